@@ -0,0 +1,109 @@
+//! Cost of building `Files` maps, and parsing throughput over a synthetic
+//! file-heavy snapshot.
+//!
+//! Run with `cargo bench --features testing --bench parse`. `RPM_QA_BENCH_FILES`
+//! sets the number of files (default 500000), split into packages of 1000.
+//!
+//! Since rpm emits the files of each package sorted by path, the parser
+//! collects them and builds each `Files` map in one go, which is compared
+//! here to inserting them one by one and to filling a `HashMap` instead.
+
+use camino::Utf8PathBuf;
+use rpm_qa::FileInfo;
+use rpm_qa::testing::FileInfoBuilder;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+const FILES_PER_PACKAGE: usize = 1000;
+const RUNS: usize = 3;
+
+fn main() {
+    let nfiles: usize = std::env::var("RPM_QA_BENCH_FILES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(500_000);
+
+    let paths: Vec<Utf8PathBuf> = (0..nfiles)
+        .map(|i| {
+            let pkg = i / FILES_PER_PACKAGE;
+            format!("/usr/share/pkg{pkg:06}/dir{:02}/file{i:08}", i % 7).into()
+        })
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let info = FileInfoBuilder::new().build();
+    let entries: Vec<(Utf8PathBuf, FileInfo)> = (paths.iter())
+        .map(|path| (path.clone(), info.clone()))
+        .collect();
+    eprintln!("{nfiles} files");
+
+    report("BTreeMap, per-line inserts", &entries, |entries| {
+        let mut files = BTreeMap::new();
+        for (path, info) in entries {
+            files.insert(path, info);
+        }
+        files.len()
+    });
+    report("BTreeMap, bulk-built", &entries, |entries| {
+        entries.into_iter().collect::<BTreeMap<_, _>>().len()
+    });
+    report("HashMap", &entries, |entries| {
+        entries.into_iter().collect::<HashMap<_, _>>().len()
+    });
+
+    let output = queryformat_output(&paths);
+    let start = Instant::now();
+    let packages = rpm_qa::load_from_reader(output.as_bytes()).unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(packages.len(), nfiles.div_ceil(FILES_PER_PACKAGE));
+    println!(
+        "{:<28} {:>8.1} ms ({:.1} MiB/s)",
+        "load_from_reader",
+        elapsed.as_secs_f64() * 1000.0,
+        output.len() as f64 / (1 << 20) as f64 / elapsed.as_secs_f64()
+    );
+}
+
+/// Time building a map from a copy of `entries` with `build`, best of
+/// [`RUNS`].
+fn report(
+    what: &str,
+    entries: &[(Utf8PathBuf, FileInfo)],
+    build: impl Fn(Vec<(Utf8PathBuf, FileInfo)>) -> usize,
+) {
+    let best = (0..RUNS)
+        .map(|_| {
+            let copy = entries.to_vec();
+            let start = Instant::now();
+            assert_eq!(build(copy), entries.len());
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or(Duration::ZERO);
+    println!("{what:<28} {:>8.1} ms", best.as_secs_f64() * 1000.0);
+}
+
+/// Format `paths` as the output of `rpm -qa` with the version 4 queryformat
+/// (see `rpm_qa::queryformat_v4`), in packages of [`FILES_PER_PACKAGE`].
+fn queryformat_output(paths: &[Utf8PathBuf]) -> String {
+    let mut output = String::new();
+    for (i, files) in paths.chunks(FILES_PER_PACKAGE).enumerate() {
+        output.push_str("@@FMT@@\x1f4\x1e");
+        write!(
+            output,
+            "@@PKG@@\x1fpkg{i:06}\x1f1.0\x1f1.fc43\x1f(none)\x1fx86_64\x1fMIT\x1f1000\
+             \x1f1700000000\x1f1700000000\x1fpkg{i:06}-1.0-1.fc43.src.rpm\x1f8\x1f1700000000\x1e"
+        )
+        .unwrap();
+        for path in files {
+            write!(
+                output,
+                "@@FILE@@\x1f{path}\x1f100\x1f33188\x1f1700000000\x1f\x1f0\x1froot\x1froot\x1f\x1f0\x1e"
+            )
+            .unwrap();
+        }
+        output.push_str("@@END@@\x1e");
+    }
+    output
+}
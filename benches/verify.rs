@@ -0,0 +1,84 @@
+//! Throughput of `verify_files` over a synthetic install.
+//!
+//! Run with `cargo bench --features testing,verify`. `RPM_QA_BENCH_MB` sets the
+//! total size of the files (default 256), and `RPM_QA_BENCH_DIR` the
+//! directory to create them in (default a temporary directory), e.g. to
+//! benchmark a real disk instead of tmpfs. Files are dropped from the page
+//! cache once hashed (see `VerifyOptions::drop_cache`), so on a real disk
+//! each run reads them back from it, while on tmpfs only hashing is measured.
+
+use camino::Utf8Path;
+use rpm_qa::testing::{self, FileInfoBuilder, PackageBuilder};
+use rpm_qa::{DigestAlgorithm, FileDigest, VerifyOptions, verify_files};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+/// Sizes of the files of each package, cycled through: mostly small files
+/// and a few large ones, roughly as in a typical install.
+const FILE_SIZES: &[usize] = &[4 << 10, 16 << 10, 1 << 10, 64 << 10, 8 << 10, 4 << 20];
+
+fn main() {
+    let total_mb: usize = std::env::var("RPM_QA_BENCH_MB")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(256);
+    let tmp;
+    let rootfs = match std::env::var("RPM_QA_BENCH_DIR") {
+        Ok(dir) => Utf8Path::new(&dir).to_owned(),
+        Err(_) => {
+            tmp = tempfile::tempdir().unwrap();
+            Utf8Path::from_path(tmp.path()).unwrap().to_owned()
+        }
+    };
+
+    let mut files: BTreeMap<String, Vec<(String, FileInfoBuilder)>> = BTreeMap::new();
+    let mut written = 0;
+    let mut i = 0;
+    while written < total_mb << 20 {
+        let name = format!("pkg{}", i / 100);
+        let path = format!("/usr/share/{name}/file{i}");
+        let size = FILE_SIZES[i % FILE_SIZES.len()];
+        let contents: Vec<u8> = (0..size).map(|j| (i + j) as u8).collect();
+        let on_disk = rootfs.join(&path[1..]);
+        std::fs::create_dir_all(on_disk.parent().unwrap()).unwrap();
+        std::fs::write(&on_disk, &contents).unwrap();
+        let digest = FileDigest::from_bytes(&Sha256::digest(&contents)).unwrap();
+        let info = FileInfoBuilder::new().size(size as u64).digest(digest);
+        files.entry(name).or_default().push((path, info));
+        written += size;
+        i += 1;
+    }
+    let packages = testing::packages(files.into_iter().map(|(name, files)| {
+        let pkg = PackageBuilder::new(name).digest_algo(Some(DigestAlgorithm::Sha256));
+        (files.into_iter())
+            .fold(pkg, |pkg, (path, info)| pkg.file(path, info))
+            .build()
+    }));
+    eprintln!("{i} files, {} MiB", written >> 20);
+
+    let mut thread_counts = vec![
+        1,
+        2,
+        std::thread::available_parallelism().map_or(4, |n| n.get()),
+    ];
+    thread_counts.sort();
+    thread_counts.dedup();
+    for threads in thread_counts {
+        for buffer_size in [64 << 10, 1 << 20] {
+            let options = VerifyOptions::new()
+                .threads(threads)
+                .buffer_size(buffer_size)
+                .drop_cache(true);
+            let start = Instant::now();
+            let report = verify_files(&packages, &rootfs, &options).unwrap();
+            let elapsed = start.elapsed();
+            assert!(report.failures.is_empty());
+            println!(
+                "threads={threads:<3} buffer={:>5}KiB {:>8.1} MiB/s",
+                buffer_size >> 10,
+                report.bytes as f64 / (1 << 20) as f64 / elapsed.as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,143 @@
+#ifndef RPM_QA_H
+#define RPM_QA_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+// A file of an [`RpmQaPackage`].
+typedef struct RpmQaFile RpmQaFile;
+
+// A package of an [`RpmQaPackages`].
+typedef struct RpmQaPackage RpmQaPackage;
+
+// A loaded snapshot of installed packages.
+typedef struct RpmQaPackages RpmQaPackages;
+
+// Load the installed packages of `rootfs` by running rpm. Returns NULL on
+// failure, setting `*error` (if `error` isn't NULL) to a message to be freed
+// with [`rpm_qa_string_free`].
+//
+// Not available if the library was built without the `exec` feature.
+//
+// # Safety
+//
+// `rootfs` must be a valid C string and `error` NULL or valid for writes.
+RpmQaPackages *rpm_qa_load_from_rootfs(const char *rootfs, char **error);
+
+// Load packages from a file containing queryformat output. Errors are
+// reported as for `rpm_qa_load_from_rootfs`.
+//
+// # Safety
+//
+// `path` must be a valid C string and `error` NULL or valid for writes.
+RpmQaPackages *rpm_qa_load_from_file(const char *path, char **error);
+
+// Free a snapshot. Pointers obtained from it become invalid.
+//
+// # Safety
+//
+// `packages` must be NULL or a snapshot which wasn't freed yet.
+void rpm_qa_packages_free(RpmQaPackages *packages);
+
+// Free an error message.
+//
+// # Safety
+//
+// `s` must be NULL or a string returned by this library which wasn't freed
+// yet.
+void rpm_qa_string_free(char *s);
+
+// Number of packages in a snapshot.
+//
+// # Safety
+//
+// `packages` must be a valid snapshot.
+size_t rpm_qa_packages_len(const RpmQaPackages *packages);
+
+// Get the package at `index`, or NULL if out of range.
+//
+// # Safety
+//
+// `packages` must be a valid snapshot.
+const RpmQaPackage *rpm_qa_packages_get(const RpmQaPackages *packages, size_t index);
+
+// Get the first package named `name`, or NULL if there's none.
+//
+// # Safety
+//
+// `packages` must be a valid snapshot and `name` a valid C string.
+const RpmQaPackage *rpm_qa_packages_find(const RpmQaPackages *packages, const char *name);
+
+// Package name.
+const char *rpm_qa_package_name(const RpmQaPackage *pkg);
+
+// Package version.
+const char *rpm_qa_package_version(const RpmQaPackage *pkg);
+
+// Package release.
+const char *rpm_qa_package_release(const RpmQaPackage *pkg);
+
+// Package architecture.
+const char *rpm_qa_package_arch(const RpmQaPackage *pkg);
+
+// Package `name-[epoch:]version-release.arch`.
+const char *rpm_qa_package_nevra(const RpmQaPackage *pkg);
+
+// Package license.
+const char *rpm_qa_package_license(const RpmQaPackage *pkg);
+
+// Source rpm file name, or NULL.
+const char *rpm_qa_package_sourcerpm(const RpmQaPackage *pkg);
+
+// Package epoch, or -1 if it has none.
+int64_t rpm_qa_package_epoch(const RpmQaPackage *pkg);
+
+// Installed size.
+uint64_t rpm_qa_package_size(const RpmQaPackage *pkg);
+
+// Unix timestamp of the package build.
+uint64_t rpm_qa_package_buildtime(const RpmQaPackage *pkg);
+
+// Unix timestamp of the package installation.
+uint64_t rpm_qa_package_installtime(const RpmQaPackage *pkg);
+
+// Number of files in the package.
+size_t rpm_qa_package_files_len(const RpmQaPackage *pkg);
+
+// Get the file at `index` of a package, or NULL if out of range.
+//
+// # Safety
+//
+// `pkg` must be a valid package.
+const RpmQaFile *rpm_qa_package_file(const RpmQaPackage *pkg, size_t index);
+
+// File path.
+const char *rpm_qa_file_path(const RpmQaFile *file);
+
+// Owner user name.
+const char *rpm_qa_file_user(const RpmQaFile *file);
+
+// Owner group name.
+const char *rpm_qa_file_group(const RpmQaFile *file);
+
+// Symlink target, or NULL if not a symlink.
+const char *rpm_qa_file_linkto(const RpmQaFile *file);
+
+// Hex digest, or NULL if the file has none.
+const char *rpm_qa_file_digest(const RpmQaFile *file);
+
+// File size.
+uint64_t rpm_qa_file_size(const RpmQaFile *file);
+
+// Unix file mode.
+uint16_t rpm_qa_file_mode(const RpmQaFile *file);
+
+// Unix modification timestamp.
+uint64_t rpm_qa_file_mtime(const RpmQaFile *file);
+
+// Raw file attribute flags (`RPMFILE_*`).
+uint32_t rpm_qa_file_flags(const RpmQaFile *file);
+
+#endif /* RPM_QA_H */
@@ -0,0 +1,212 @@
+use anyhow::{Context, bail};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+
+use crate::*;
+
+/// Where the alternatives system keeps its links, e.g. `/etc/alternatives/java`.
+const ALTERNATIVES_DIR: &str = "/etc/alternatives";
+/// How many symlinks to follow before giving up, as the kernel does.
+const MAX_SYMLINKS: usize = 40;
+
+/// The owners of a path, looked up through the alternatives system. See
+/// [`resolve_owners`].
+#[derive(Debug, Clone)]
+pub struct ResolvedOwners<'a> {
+    /// The path which was looked up, normalized.
+    pub path: Utf8PathBuf,
+    /// The real path `path` resolves to on disk, if that goes through
+    /// `/etc/alternatives`, e.g. `/usr/lib/jvm/java-21-openjdk/bin/java`
+    /// for `/usr/bin/java`.
+    pub target: Option<Utf8PathBuf>,
+    /// The symlinks followed to get to `target`, in order, e.g.
+    /// `/usr/bin/java` and `/etc/alternatives/java`.
+    pub links: Vec<Utf8PathBuf>,
+    /// The packages owning `target`, or `path` itself if it doesn't go
+    /// through alternatives, sorted by name.
+    pub owners: Vec<&'a Package>,
+}
+
+/// Look up the packages owning `path`, following the alternatives system.
+///
+/// Paths managed by `alternatives` (e.g. `/usr/bin/java`) are symlinks
+/// created at install time, so the rpmdb doesn't know about them, or only
+/// as ghost files. When `path` resolves through `/etc/alternatives`, the
+/// links are followed on disk under `rootfs` (absolute links are taken
+/// relative to it, as in a chroot) and the owners of the real target are
+/// reported instead. Otherwise, this is a plain lookup of `path`, which
+/// needn't exist on disk.
+pub fn resolve_owners<'a>(
+    packages: &'a Packages,
+    rootfs: &Utf8Path,
+    path: &Utf8Path,
+) -> Result<ResolvedOwners<'a>> {
+    Ok(resolve_owners_impl(packages, rootfs, path)
+        .with_context(|| format!("resolving {path} under {rootfs}"))?)
+}
+
+fn resolve_owners_impl<'a>(
+    packages: &'a Packages,
+    rootfs: &Utf8Path,
+    path: &Utf8Path,
+) -> anyhow::Result<ResolvedOwners<'a>> {
+    let path = Utf8PathBuf::from(&*parse::normalize_path(path.as_str()));
+    let (target, links) = match resolve(rootfs, &path)? {
+        Some((target, links)) if links.iter().any(|link| link.starts_with(ALTERNATIVES_DIR)) => {
+            (Some(target), links)
+        }
+        _ => (None, Vec::new()),
+    };
+    let lookup = target.as_deref().unwrap_or(&path);
+    let mut owners: Vec<&Package> = (packages.values())
+        .filter(|pkg| pkg.file(lookup).is_some())
+        .collect();
+    owners.sort_by_cached_key(|pkg| (pkg.name.clone(), pkg.nevra()));
+    Ok(ResolvedOwners {
+        path,
+        target,
+        links,
+        owners,
+    })
+}
+
+/// Resolve the symlinks in the absolute path `path` under `rootfs`, like
+/// `realpath`, returning the resolved path and the symlinks followed.
+/// Returns `None` if a component of the path doesn't exist.
+fn resolve(
+    rootfs: &Utf8Path,
+    path: &Utf8Path,
+) -> anyhow::Result<Option<(Utf8PathBuf, Vec<Utf8PathBuf>)>> {
+    let mut resolved = Utf8PathBuf::from("/");
+    let mut links = Vec::new();
+    // Components left to resolve, last first.
+    let mut pending: Vec<String> = components(path);
+    while let Some(component) = pending.pop() {
+        match component.as_str() {
+            "." => continue,
+            ".." => {
+                resolved.pop();
+                continue;
+            }
+            _ => {}
+        }
+        let next = resolved.join(&component);
+        let on_disk = rootfs.join(next.strip_prefix("/").unwrap_or(&next));
+        let meta = match on_disk.symlink_metadata() {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("reading {on_disk}")),
+        };
+        if !meta.is_symlink() {
+            resolved = next;
+            continue;
+        }
+        if links.len() == MAX_SYMLINKS {
+            bail!("too many levels of symbolic links at {next}");
+        }
+        let target = on_disk
+            .read_link_utf8()
+            .with_context(|| format!("reading link {on_disk}"))?;
+        if target.is_absolute() {
+            resolved = Utf8PathBuf::from("/");
+        }
+        pending.extend(components(&target));
+        links.push(next);
+    }
+    Ok(Some((resolved, links)))
+}
+
+/// Get the components of `path` to resolve, in reverse order.
+fn components(path: &Utf8Path) -> Vec<String> {
+    let mut components: Vec<String> = (path.components())
+        .filter_map(|c| match c {
+            Utf8Component::Normal(name) => Some(name.to_string()),
+            Utf8Component::ParentDir => Some("..".to_string()),
+            _ => None,
+        })
+        .collect();
+    components.reverse();
+    components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FileInfoBuilder, PackageBuilder, packages};
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_resolve_owners() {
+        let tmp = tempfile::tempdir().unwrap();
+        let rootfs = Utf8Path::from_path(tmp.path()).unwrap();
+        let jvm = rootfs.join("usr/lib/jvm/java-21-openjdk/bin");
+        std::fs::create_dir_all(&jvm).unwrap();
+        std::fs::write(jvm.join("java"), "").unwrap();
+        std::fs::create_dir_all(rootfs.join("etc/alternatives")).unwrap();
+        std::fs::create_dir_all(rootfs.join("usr/bin")).unwrap();
+        // The JVM directory is an alternative itself.
+        symlink(
+            "/etc/alternatives/jre_21",
+            rootfs.join("usr/lib/jvm/jre-21"),
+        )
+        .unwrap();
+        symlink(
+            "/usr/lib/jvm/java-21-openjdk",
+            rootfs.join("etc/alternatives/jre_21"),
+        )
+        .unwrap();
+        symlink(
+            "/usr/lib/jvm/jre-21/bin/java",
+            rootfs.join("etc/alternatives/java"),
+        )
+        .unwrap();
+        symlink("../../etc/alternatives/java", rootfs.join("usr/bin/java")).unwrap();
+        std::fs::write(rootfs.join("usr/bin/foo-1"), "").unwrap();
+        symlink("foo-1", rootfs.join("usr/bin/foo")).unwrap();
+        symlink("loop", rootfs.join("usr/bin/loop")).unwrap();
+
+        let packages = packages([
+            PackageBuilder::new("java-21-openjdk-headless")
+                .file(
+                    "/usr/lib/jvm/java-21-openjdk/bin/java",
+                    FileInfoBuilder::new(),
+                )
+                .file("/usr/bin/foo", FileInfoBuilder::new())
+                .build(),
+            PackageBuilder::new("javapackages-filesystem")
+                .dir("/usr/lib/jvm")
+                .build(),
+        ]);
+
+        let resolved = resolve_owners(&packages, rootfs, Utf8Path::new("/usr/bin/java")).unwrap();
+        assert_eq!(
+            resolved.target.as_deref(),
+            Some(Utf8Path::new("/usr/lib/jvm/java-21-openjdk/bin/java"))
+        );
+        assert_eq!(
+            resolved.links,
+            [
+                "/usr/bin/java",
+                "/etc/alternatives/java",
+                "/usr/lib/jvm/jre-21",
+                "/etc/alternatives/jre_21",
+            ]
+        );
+        let names: Vec<&str> = resolved.owners.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["java-21-openjdk-headless"]);
+
+        // Symlinks not going through alternatives aren't followed.
+        let resolved = resolve_owners(&packages, rootfs, Utf8Path::new("/usr/bin/foo")).unwrap();
+        assert_eq!(resolved.target, None);
+        assert!(resolved.links.is_empty());
+        assert_eq!(resolved.owners.len(), 1);
+
+        let resolved = resolve_owners(&packages, rootfs, Utf8Path::new("/usr/lib/jvm/")).unwrap();
+        assert_eq!(resolved.path, "/usr/lib/jvm");
+        assert_eq!(resolved.owners[0].name, "javapackages-filesystem");
+        // Paths which don't exist on disk are looked up as is.
+        let resolved = resolve_owners(&packages, rootfs, Utf8Path::new("/nonexistent")).unwrap();
+        assert!(resolved.owners.is_empty());
+
+        assert!(resolve_owners(&packages, rootfs, Utf8Path::new("/usr/bin/loop")).is_err());
+    }
+}
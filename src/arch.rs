@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use crate::*;
+
+/// 64-bit architectures and the 32-bit architectures whose packages can be
+/// installed alongside theirs (multilib), as in rpm's `arch_compat` table.
+const MULTILIB_ARCHES: &[(&str, &[&str])] = &[
+    ("x86_64", &["i686", "i586", "i486", "i386", "athlon"]),
+    ("ppc64", &["ppc"]),
+    ("s390x", &["s390"]),
+    ("sparc64", &["sparcv9", "sparc"]),
+];
+
+/// Whether `secondary` is a 32-bit architecture whose packages can be
+/// installed alongside those of the 64-bit `primary`.
+fn is_multilib(primary: &str, secondary: &str) -> bool {
+    MULTILIB_ARCHES
+        .iter()
+        .any(|(arch, compat)| *arch == primary && compat.contains(&secondary))
+}
+
+pub(crate) fn native_arch(packages: &Packages) -> Option<&str> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for pkg in packages.values().filter(|pkg| pkg.arch != "noarch") {
+        *counts.entry(&pkg.arch).or_default() += 1;
+    }
+    // Ties are broken by name to be deterministic.
+    (counts.into_iter())
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(arch, _)| arch)
+}
+
+pub(crate) fn filter_arch<'a>(packages: &'a Packages, arch: &str) -> Vec<&'a Package> {
+    let mut filtered: Vec<&Package> = (packages.values())
+        .filter(|pkg| pkg.arch == arch || pkg.arch == "noarch")
+        .collect();
+    filtered.sort_by_cached_key(|pkg| (pkg.name.clone(), pkg.nevra()));
+    filtered
+}
+
+pub(crate) fn multilib_pairs(packages: &Packages) -> Vec<(&Package, &Package)> {
+    let mut by_name: HashMap<&str, Vec<&Package>> = HashMap::new();
+    for pkg in packages.values().filter(|pkg| pkg.arch != "noarch") {
+        by_name.entry(&pkg.name).or_default().push(pkg);
+    }
+    let mut pairs = Vec::new();
+    for pkgs in by_name.values() {
+        for primary in pkgs {
+            for secondary in pkgs {
+                if is_multilib(&primary.arch, &secondary.arch) {
+                    pairs.push((*primary, *secondary));
+                }
+            }
+        }
+    }
+    pairs.sort_by_cached_key(|(a, b)| (a.name.clone(), a.nevra(), b.nevra()));
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::PackageBuilder;
+
+    fn packages(pkgs: &[(&str, &str)]) -> Packages {
+        (pkgs.iter())
+            .map(|(name, arch)| {
+                let pkg = PackageBuilder::new(*name).arch(*arch).build();
+                (pkg.nevra(), pkg)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_arches() {
+        let packages = packages(&[
+            ("glibc", "x86_64"),
+            ("glibc", "i686"),
+            ("bash", "x86_64"),
+            ("coreutils", "x86_64"),
+            ("setup", "noarch"),
+            ("wine-core", "i686"),
+            ("zlib", "x86_64"),
+            ("zlib", "i686"),
+            ("zlib", "aarch64"),
+        ]);
+        assert_eq!(packages.native_arch(), Some("x86_64"));
+        assert_eq!(Packages::default().native_arch(), None);
+        assert_eq!(
+            self::packages(&[("a", "i686"), ("b", "x86_64")]).native_arch(),
+            Some("i686")
+        );
+
+        let names = |pkgs: Vec<&Package>| -> Vec<String> {
+            pkgs.iter()
+                .map(|pkg| format!("{}.{}", pkg.name, pkg.arch))
+                .collect()
+        };
+        assert_eq!(
+            names(packages.filter_arch("i686")),
+            ["glibc.i686", "setup.noarch", "wine-core.i686", "zlib.i686"]
+        );
+        assert_eq!(names(packages.filter_arch("ppc64le")), ["setup.noarch"]);
+
+        let pairs: Vec<(&str, &str, &str)> = (packages.multilib_pairs().iter())
+            .map(|(a, b)| (a.name.as_str(), a.arch.as_str(), b.arch.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            [("glibc", "x86_64", "i686"), ("zlib", "x86_64", "i686")]
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use bumpalo::Bump;
+use bumpalo::collections::Vec as BumpVec;
+use camino::Utf8Path;
+use std::collections::HashMap;
+
+use crate::*;
+
+/// A bump arena holding file paths of packages, allocated together rather
+/// than one by one.
+///
+/// File lists deferred with [`ParseOptions::lazy_files`] are read straight
+/// from the unparsed output, so loading packages that way and getting their
+/// paths from an arena avoids allocating each path and [`FileInfo`]
+/// separately, which adds up on huge rpmdbs.
+///
+/// ```
+/// # fn main() -> rpm_qa::Result<()> {
+/// # let output = "";
+/// let options = rpm_qa::ParseOptions::new().lazy_files(true);
+/// let packages = rpm_qa::load_from_reader_with_options(output.as_bytes(), &options)?;
+/// let arena = rpm_qa::PathArena::new();
+/// for (name, paths) in arena.all_paths(&packages) {
+///     println!("{name}: {} files", paths.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct PathArena {
+    bump: Bump,
+}
+
+impl PathArena {
+    /// Create an empty arena.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the paths of the files of `pkg`, sorted, allocated in the arena.
+    pub fn paths<'a>(&'a self, pkg: &Package) -> &'a [&'a Utf8Path] {
+        let mut paths = BumpVec::new_in(&self.bump);
+        match &pkg.unparsed_files {
+            Some(raw) => paths.extend(parse::raw_file_paths(raw).map(|path| self.alloc(&path))),
+            None => paths.extend(pkg.files.keys().map(|path| self.alloc(path.as_str()))),
+        }
+        paths.sort_unstable();
+        paths.into_bump_slice()
+    }
+
+    /// Get the paths of the files of all packages, by package name. See
+    /// [`PathArena::paths`].
+    pub fn all_paths<'a>(&'a self, packages: &Packages) -> HashMap<&'a str, &'a [&'a Utf8Path]> {
+        (packages.iter())
+            .map(|(name, pkg)| (&*self.bump.alloc_str(name), self.paths(pkg)))
+            .collect()
+    }
+
+    /// Get the number of bytes allocated for the arena, including unused
+    /// capacity.
+    pub fn allocated_bytes(&self) -> usize {
+        self.bump.allocated_bytes()
+    }
+
+    fn alloc(&self, path: &str) -> &Utf8Path {
+        Utf8Path::new(self.bump.alloc_str(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_arena() {
+        let fixture = include_str!("../tests/fixtures/fedora.qf");
+        let packages = load_from_str(fixture).unwrap();
+        let options = ParseOptions::new().lazy_files(true);
+        let lazy = load_from_reader_with_options(fixture.as_bytes(), &options).unwrap();
+        let arena = PathArena::new();
+        let paths = arena.all_paths(&lazy);
+        assert_eq!(paths.len(), packages.len());
+        for (name, pkg) in &packages {
+            let expected: Vec<&Utf8Path> = pkg.files.keys().map(|p| p.as_path()).collect();
+            assert_eq!(paths[name.as_str()], expected, "{name}");
+            assert_eq!(arena.paths(pkg), expected, "{name}");
+        }
+        assert!(arena.allocated_bytes() > 0);
+    }
+}
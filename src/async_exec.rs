@@ -0,0 +1,54 @@
+use anyhow::Context;
+use std::process::Stdio;
+use tokio::io::AsyncReadExt;
+
+use crate::*;
+
+/// Load packages from a rootfs by running `rpm -qa` as a tokio subprocess,
+/// parsing its output as it's produced.
+pub(crate) async fn load_from_rootfs_async_impl(
+    rootfs: &Utf8Path,
+    options: &ParseOptions,
+) -> Result<Packages> {
+    let query = QueryBuilder::new().root(rootfs);
+    // Probing the tags rpm supports only happens once per process, but still
+    // shouldn't block the runtime.
+    let qf = {
+        let query = query.clone();
+        tokio::task::spawn_blocking(move || queryformat_for(&query))
+            .await
+            .context("probing rpm tags")??
+    };
+    let mut cmd = query.command()?;
+    options.add_rpm_args(&mut cmd);
+    cmd.args(["-qa", "--queryformat", qf]);
+    let mut cmd = tokio::process::Command::from(cmd);
+    cmd.stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    debug!("running {cmd:?}");
+    let mut child = cmd.spawn().map_err(|e| spawn_error(cmd.as_std(), e))?;
+    let stdout = child
+        .stdout
+        .take()
+        .context("failed to capture rpm stdout")?;
+    let mut stderr = child
+        .stderr
+        .take()
+        .context("failed to capture rpm stderr")?;
+
+    // Drain stderr while parsing so rpm can't block on it.
+    let (result, stderr) =
+        tokio::join!(parse::load_from_async_reader_impl(stdout, options), async {
+            let mut buf = Vec::new();
+            let _ = stderr.read_to_end(&mut buf).await;
+            String::from_utf8_lossy(&buf).into_owned()
+        });
+
+    if result.is_err() {
+        // Don't wait for rpm to notice that its output isn't read anymore.
+        let _ = child.start_kill();
+    }
+    let status = child.wait().await.context("failed to wait for rpm")?;
+    check_rpm_query(status, stderr, result)
+}
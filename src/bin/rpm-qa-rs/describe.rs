@@ -0,0 +1,75 @@
+use clap::{Arg, Command};
+use serde_json::{Value, json};
+
+use crate::output;
+
+/// What each subcommand prints with the structured formats, as keys of
+/// [`output::FIELDS`].
+const OUTPUTS: &[(&str, &str)] = &[
+    ("list", "packages"),
+    ("files", "files"),
+    ("owner", "packages"),
+    ("who-owns", "owners"),
+    ("diff", "diff"),
+    ("load", "summary"),
+];
+
+/// Describe the subcommands, options and output fields of the CLI.
+pub(crate) fn describe(cmd: &Command) -> Value {
+    let subcommands: Vec<Value> = cmd
+        .get_subcommands()
+        .map(|sub| {
+            let output = OUTPUTS
+                .iter()
+                .find(|(name, _)| *name == sub.get_name())
+                .map(|(_, output)| output);
+            json!({
+                "name": sub.get_name(),
+                "about": sub.get_about().map(|s| s.to_string()),
+                "args": sub
+                    .get_arguments()
+                    .filter(|arg| !arg.is_global_set() && !is_builtin(arg))
+                    .map(describe_arg)
+                    .collect::<Vec<_>>(),
+                "output": output,
+            })
+        })
+        .collect();
+    let fields: serde_json::Map<String, Value> = output::FIELDS
+        .iter()
+        .map(|(kind, fields)| (kind.to_string(), json!(fields)))
+        .collect();
+    json!({
+        "name": cmd.get_name(),
+        "version": cmd.get_version(),
+        "global_args": cmd
+            .get_arguments()
+            .filter(|arg| !is_builtin(arg))
+            .map(describe_arg)
+            .collect::<Vec<_>>(),
+        "subcommands": subcommands,
+        "fields": fields,
+    })
+}
+
+fn is_builtin(arg: &Arg) -> bool {
+    matches!(arg.get_id().as_str(), "help" | "version" | "describe_json")
+}
+
+fn describe_arg(arg: &Arg) -> Value {
+    let values: Vec<String> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    json!({
+        "name": arg.get_id().as_str(),
+        "long": arg.get_long(),
+        "positional": arg.is_positional(),
+        "required": arg.is_required_set(),
+        "takes_value": arg.get_num_args().is_none_or(|n| n.takes_values()),
+        "values": values,
+        "help": arg.get_help().map(|s| s.to_string()),
+    })
+}
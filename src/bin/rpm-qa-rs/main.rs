@@ -0,0 +1,521 @@
+use anyhow::{Context, Result, bail};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{CommandFactory, Parser, Subcommand};
+use rpm_qa::{Package, Packages, PathIndex};
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::process::ExitCode;
+
+use output::{FileRecord, Format, Output, Ownership};
+
+mod describe;
+mod output;
+
+/// Query the rpmdb of a rootfs, or a snapshot of it.
+#[derive(Debug, Parser)]
+#[command(name = "rpm-qa-rs", version)]
+struct Cli {
+    /// Query the rpmdb of this rootfs.
+    #[arg(long, global = true, default_value = "/")]
+    root: Utf8PathBuf,
+    /// Query a snapshot saved with `dump` instead of an rpmdb.
+    #[arg(long, global = true, conflicts_with = "root")]
+    snapshot: Option<Utf8PathBuf>,
+    /// Output format [default: table].
+    #[arg(long, global = true)]
+    format: Option<Format>,
+    /// Describe the subcommands, their options and their output fields as
+    /// JSON, and exit.
+    #[arg(long, exclusive = true)]
+    describe_json: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List installed packages.
+    List,
+    /// List the files of a package.
+    Files {
+        /// Package name.
+        package: String,
+    },
+    /// Print the packages owning a path. Fails if there are none.
+    ///
+    /// Unowned paths managed by alternatives (e.g. `/usr/bin/java`) are
+    /// resolved through `/etc/alternatives` under the rootfs, and the owners
+    /// of their targets printed.
+    Owner {
+        /// Absolute path.
+        path: Utf8PathBuf,
+    },
+    /// Print the packages owning each of the given paths.
+    ///
+    /// Exits with 0 if every path is owned, 1 if some aren't and 2 on errors.
+    WhoOwns {
+        /// Also print the owners of each path's ancestor directories.
+        #[arg(long)]
+        ancestors: bool,
+        /// Absolute paths.
+        #[arg(required = true)]
+        paths: Vec<Utf8PathBuf>,
+    },
+    /// Compare the packages of two sources.
+    ///
+    /// Sources are rootfs paths, snapshots saved with `dump`, or images as
+    /// `oci:DIR`, where DIR is an OCI image layout (e.g. as created by
+    /// `skopeo copy`). Exits with 0 if there are no differences, 1 if there are
+    /// and 2 on errors.
+    Diff {
+        /// Also compare the files of packages in both sources.
+        #[arg(long)]
+        files: bool,
+        /// The old source.
+        old: String,
+        /// The new source.
+        new: String,
+    },
+    /// Write a software bill of materials of the installed packages, with
+    /// `--format spdx` or `--format cyclonedx`.
+    Sbom {
+        /// Describe the image in this OCI image layout instead of a rootfs.
+        #[arg(long, conflicts_with_all = ["root", "snapshot"])]
+        image: Option<Utf8PathBuf>,
+        /// Vendor of the packages (e.g. `fedora`), used in package URLs.
+        #[arg(long)]
+        vendor: Option<String>,
+        /// Name of the SBOM [default: the rootfs, snapshot or image path].
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Generate shell completions.
+    Completions {
+        /// Shell to generate completions for.
+        shell: clap_complete::Shell,
+    },
+    /// Write a snapshot of the rpmdb to stdout, to be queried later with
+    /// `--snapshot`.
+    Dump,
+    /// Check that a snapshot can be loaded and summarize it.
+    Load {
+        /// Snapshot file.
+        file: Utf8PathBuf,
+    },
+}
+
+impl Cli {
+    fn packages(&self) -> Result<Packages> {
+        match &self.snapshot {
+            Some(path) => load_snapshot(path),
+            None => rpm_qa::load_from_rootfs(&self.root)
+                .with_context(|| format!("loading packages from {}", self.root)),
+        }
+    }
+}
+
+fn load_snapshot(path: &Utf8Path) -> Result<Packages> {
+    let file = File::open(path).with_context(|| format!("opening {path}"))?;
+    rpm_qa::load_from_reader(BufReader::new(file)).with_context(|| format!("loading {path}"))
+}
+
+/// Load packages from a source given on the command line. See the `diff`
+/// subcommand.
+fn load_source(source: &str) -> Result<Packages> {
+    if let Some(layout) = source.strip_prefix("oci:") {
+        return rpm_qa::load_from_oci_layout(Utf8Path::new(layout))
+            .with_context(|| format!("loading packages from image {layout}"));
+    }
+    let path = Utf8Path::new(source);
+    if path.is_dir() {
+        rpm_qa::load_from_rootfs(path).with_context(|| format!("loading packages from {path}"))
+    } else {
+        load_snapshot(path)
+    }
+}
+
+/// Packages sorted by name, then NEVRA.
+fn sorted(packages: &Packages) -> Vec<&Package> {
+    let mut sorted: Vec<&Package> = packages.values().collect();
+    sorted.sort_by_cached_key(|pkg| (pkg.name.clone(), pkg.nevra()));
+    sorted
+}
+
+fn run(cli: &Cli, out: &mut dyn Write) -> Result<ExitCode> {
+    let (packages, other);
+    let mut code = ExitCode::SUCCESS;
+    if cli.describe_json {
+        if cli.command.is_some() {
+            bail!("--describe-json can't be used with a subcommand");
+        }
+        serde_json::to_writer_pretty(&mut *out, &describe::describe(&Cli::command()))?;
+        writeln!(out)?;
+        return Ok(code);
+    }
+    let Some(command) = &cli.command else {
+        bail!("a subcommand is required");
+    };
+    let output = match command {
+        Command::List => {
+            packages = cli.packages()?;
+            Output::Packages(sorted(&packages))
+        }
+        Command::Files { package } => {
+            packages = cli.packages()?;
+            let matching: Vec<&Package> = sorted(&packages)
+                .into_iter()
+                .filter(|pkg| pkg.name == *package)
+                .collect();
+            if matching.is_empty() {
+                bail!("package '{package}' is not installed");
+            }
+            Output::Files(
+                matching
+                    .into_iter()
+                    .flat_map(|pkg| {
+                        pkg.files.iter().map(move |(path, info)| FileRecord {
+                            package: pkg,
+                            path,
+                            info,
+                        })
+                    })
+                    .collect(),
+            )
+        }
+        Command::Owner { path } => {
+            packages = cli.packages()?;
+            let mut owners: Vec<&Package> = sorted(&packages)
+                .into_iter()
+                .filter(|pkg| pkg.file(path).is_some())
+                .collect();
+            // Paths managed by alternatives are usually unowned; report the
+            // owners of what they point to instead.
+            if owners.is_empty() && cli.snapshot.is_none() {
+                let resolved = rpm_qa::resolve_owners(&packages, &cli.root, path)?;
+                if let Some(target) = &resolved.target {
+                    eprintln!("{path} resolves to {target} through alternatives");
+                    owners = resolved.owners;
+                }
+            }
+            if owners.is_empty() {
+                eprintln!("{path} is not owned by any package");
+                code = ExitCode::FAILURE;
+            }
+            Output::Packages(owners)
+        }
+        Command::WhoOwns { ancestors, paths } => {
+            packages = cli.packages()?;
+            let index = PathIndex::new(&packages)?;
+            let owners = |path: &Utf8Path| -> Vec<&Package> {
+                index.owners_of(path).map(|key| &packages[key]).collect()
+            };
+            let mut ownerships = Vec::new();
+            for path in paths {
+                let ownership = Ownership {
+                    path: path.clone(),
+                    owners: owners(path),
+                    ancestors: if *ancestors {
+                        path.ancestors()
+                            .skip(1)
+                            .map(|ancestor| Ownership {
+                                path: ancestor.to_owned(),
+                                owners: owners(ancestor),
+                                ancestors: Vec::new(),
+                            })
+                            .filter(|ancestor| !ancestor.owners.is_empty())
+                            .collect()
+                    } else {
+                        Vec::new()
+                    },
+                };
+                if ownership.owners.is_empty() {
+                    code = ExitCode::FAILURE;
+                }
+                ownerships.push(ownership);
+            }
+            Output::Owners(ownerships)
+        }
+        Command::Diff { files, old, new } => {
+            packages = load_source(old)?;
+            other = load_source(new)?;
+            let mut diff = rpm_qa::diff(&packages, &other);
+            if !*files {
+                diff.files.clear();
+            }
+            if !diff.is_empty() {
+                code = ExitCode::FAILURE;
+            }
+            Output::Diff(diff)
+        }
+        Command::Sbom {
+            image,
+            vendor,
+            name,
+        } => {
+            let (loaded, source) = match (image, &cli.snapshot) {
+                (Some(layout), _) => (
+                    rpm_qa::load_from_oci_layout(layout)
+                        .with_context(|| format!("loading packages from image {layout}"))?,
+                    layout,
+                ),
+                (None, Some(snapshot)) => (load_snapshot(snapshot)?, snapshot),
+                (None, None) => (cli.packages()?, &cli.root),
+            };
+            packages = loaded;
+            let mut sbom = rpm_qa::Sbom::new(&packages, name.as_deref().unwrap_or(source.as_str()));
+            if let Some(vendor) = vendor {
+                sbom = sbom.vendor(vendor);
+            }
+            match cli.format {
+                Some(Format::Spdx) => sbom.write_spdx(&mut *out)?,
+                Some(Format::Cyclonedx) => sbom.write_cyclonedx(&mut *out)?,
+                _ => bail!("sbom needs --format spdx or --format cyclonedx"),
+            }
+            return Ok(code);
+        }
+        Command::Completions { shell } => {
+            clap_complete::generate(*shell, &mut Cli::command(), "rpm-qa-rs", out);
+            return Ok(code);
+        }
+        Command::Dump => {
+            if cli.snapshot.is_some() {
+                bail!("dump reads from an rpmdb, not a snapshot");
+            }
+            if cli.format.is_some() {
+                bail!("dump only writes the raw snapshot format");
+            }
+            rpm_qa::dump_from_rootfs(&cli.root, &mut *out)
+                .with_context(|| format!("dumping rpmdb of {}", cli.root))?;
+            return Ok(code);
+        }
+        Command::Load { file } => {
+            packages = load_snapshot(file)?;
+            Output::Summary {
+                packages: packages.len(),
+                files: packages.values().map(|pkg| pkg.files.len()).sum(),
+            }
+        }
+    };
+    output.write(cli.format.unwrap_or_default(), out)?;
+    Ok(code)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if cli.command.is_none() && !cli.describe_json {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingSubcommand,
+                "a subcommand is required",
+            )
+            .exit();
+    }
+    let mut stdout = std::io::stdout().lock();
+    run(&cli, &mut stdout).unwrap_or_else(|e| {
+        eprintln!("error: {e:#}");
+        // Distinct from the failures of queries, e.g. unowned paths.
+        ExitCode::from(2)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = "tests/fixtures/fedora.qf";
+
+    fn run_args(args: &[&str]) -> Result<(ExitCode, String)> {
+        let cli = Cli::try_parse_from(["rpm-qa-rs", "--snapshot", FIXTURE].iter().chain(args))?;
+        let mut out = Vec::new();
+        let code = run(&cli, &mut out)?;
+        Ok((code, String::from_utf8(out)?))
+    }
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let (code, out) = run_args(&["list", "--format", "nevra"]).unwrap();
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert_eq!(out.lines().next(), Some("bash-5.3.0-2.fc43.x86_64"));
+        assert_eq!(out.lines().count(), 10);
+        let (_, out) = run_args(&["list"]).unwrap();
+        let mut lines = out.lines();
+        assert!(lines.next().unwrap().starts_with("NAME "));
+        let bash: Vec<&str> = lines.next().unwrap().split_whitespace().collect();
+        assert_eq!(bash[..5], ["bash", "-", "5.3.0", "2.fc43", "x86_64"]);
+
+        let (_, out) = run_args(&["files", "bash", "--format", "nevra"]).unwrap();
+        assert!(out.lines().any(|l| l == "/usr/bin/bash"));
+        let (_, out) = run_args(&["files", "bash"]).unwrap();
+        assert!(out.lines().any(|l| l.ends_with(" /usr/bin/bash")));
+        assert!(out.lines().any(|l| l.ends_with(" /usr/bin/sh -> bash")));
+        assert!(run_args(&["files", "nonexistent"]).is_err());
+
+        let (code, out) = run_args(&["owner", "/usr/bin/bash", "--format", "nevra"]).unwrap();
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert_eq!(out, "bash-5.3.0-2.fc43.x86_64\n");
+        let (code, _) = run_args(&["owner", "/nonexistent"]).unwrap();
+        assert_eq!(code, ExitCode::FAILURE);
+
+        let (code, out) =
+            run_args(&["who-owns", "/usr/bin/bash", "/usr/lib/rpm/macros.d/"]).unwrap();
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert_eq!(out, "/usr/bin/bash: bash\n/usr/lib/rpm/macros.d/: rpm\n");
+        let (code, out) = run_args(&[
+            "who-owns",
+            "--ancestors",
+            "/usr/lib/rpm/macros.d/macros.foo",
+        ])
+        .unwrap();
+        assert_eq!(code, ExitCode::FAILURE);
+        let mut lines = out.lines();
+        assert_eq!(
+            lines.next(),
+            Some("/usr/lib/rpm/macros.d/macros.foo: not owned by any package")
+        );
+        assert_eq!(lines.next(), Some("  /usr/lib/rpm/macros.d: rpm"));
+        assert!(run_args(&["who-owns"]).is_err());
+        let (_, out) = run_args(&["--format", "nevra", "who-owns", "/usr/bin/bash"]).unwrap();
+        assert_eq!(out, "/usr/bin/bash: bash-5.3.0-2.fc43.x86_64\n");
+
+        let (_, out) = run_args(&["load", FIXTURE]).unwrap();
+        assert!(out.starts_with("10 packages, "), "{out}");
+        assert!(run_args(&["dump"]).is_err());
+        assert!(run_args(&["list", "--format", "xml"]).is_err());
+        assert!(run_args(&["list", "--format", "spdx"]).is_err());
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let updated = tmpdir.path().join("updated.qf");
+        let fixture = std::fs::read_to_string(FIXTURE).unwrap();
+        std::fs::write(
+            &updated,
+            fixture.replace(
+                "@@PKG@@\tbash\t5.3.0\t2.fc43",
+                "@@PKG@@\tbash\t5.3.1\t1.fc43",
+            ),
+        )
+        .unwrap();
+        let updated = updated.to_str().unwrap();
+        let (code, out) = run_args(&["diff", FIXTURE, updated]).unwrap();
+        assert_eq!(code, ExitCode::FAILURE);
+        let row: Vec<&str> = out.lines().nth(1).unwrap().split_whitespace().collect();
+        assert_eq!(
+            row,
+            [
+                "upgraded",
+                "bash",
+                "5.3.0-2.fc43.x86_64",
+                "5.3.1-1.fc43.x86_64"
+            ]
+        );
+        let (_, out) = run_args(&["diff", updated, FIXTURE, "--format", "nevra"]).unwrap();
+        assert_eq!(
+            out,
+            "-bash-5.3.1-1.fc43.x86_64\n+bash-5.3.0-2.fc43.x86_64\n"
+        );
+        let (code, out) = run_args(&["diff", "--files", FIXTURE, FIXTURE]).unwrap();
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert_eq!(out.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_introspection() {
+        let cli = Cli::try_parse_from(["rpm-qa-rs", "--describe-json"]).unwrap();
+        let mut out = Vec::new();
+        run(&cli, &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["name"], "rpm-qa-rs");
+        let subcommands = json["subcommands"].as_array().unwrap();
+        let list = subcommands.iter().find(|s| s["name"] == "list").unwrap();
+        assert_eq!(list["output"], "packages");
+        assert_eq!(json["fields"]["packages"][0], "name");
+        let format = json["global_args"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|a| a["name"] == "format")
+            .unwrap();
+        assert!(
+            format["values"]
+                .as_array()
+                .unwrap()
+                .contains(&"json".into())
+        );
+        let who_owns = subcommands
+            .iter()
+            .find(|s| s["name"] == "who-owns")
+            .unwrap();
+        let paths = &who_owns["args"].as_array().unwrap()[1];
+        assert_eq!(paths["name"], "paths");
+        assert_eq!(paths["positional"], true);
+        let cli = Cli::try_parse_from(["rpm-qa-rs", "--describe-json", "list"]).unwrap();
+        assert!(run(&cli, &mut Vec::new()).is_err());
+
+        let (_, out) = run_args(&["completions", "bash"]).unwrap();
+        assert!(out.contains("who-owns"));
+        assert!(run_args(&[]).is_err());
+    }
+
+    #[test]
+    fn test_structured_formats() {
+        let (_, out) = run_args(&["list", "--format", "json"]).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 10);
+        assert_eq!(json[0]["name"], "bash");
+        assert_eq!(json[0]["epoch"], serde_json::Value::Null);
+        assert_eq!(json[0]["sourcerpm"], "bash-5.3.0-2.fc43.src.rpm");
+
+        let (_, out) = run_args(&["files", "bash", "--format", "json"]).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        let sh = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|f| f["path"] == "/usr/bin/sh")
+            .unwrap();
+        assert_eq!(sh["linkto"], "bash");
+        assert_eq!(sh["package"], "bash");
+
+        let (_, out) = run_args(&["who-owns", "/usr/bin/bash", "--format", "yaml"]).unwrap();
+        let yaml: serde_json::Value = serde_yaml_ng::from_str(&out).unwrap();
+        assert_eq!(yaml[0]["path"], "/usr/bin/bash");
+        assert_eq!(yaml[0]["owners"][0], "bash");
+
+        let (_, out) = run_args(&["sbom", "--format", "spdx", "--vendor", "fedora"]).unwrap();
+        let spdx: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(spdx["name"], FIXTURE);
+        assert_eq!(spdx["packages"].as_array().unwrap().len(), 10);
+        let (_, out) = run_args(&["sbom", "--format", "cyclonedx", "--name", "test"]).unwrap();
+        let cdx: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(cdx["metadata"]["component"]["name"], "test");
+        assert!(run_args(&["sbom"]).is_err());
+
+        // The fields match the introspection output.
+        let fields = |kind: &str| {
+            let (_, fields) = output::FIELDS.iter().find(|(k, _)| *k == kind).unwrap();
+            let mut fields = fields.to_vec();
+            fields.sort_unstable();
+            fields
+        };
+        let keys = |value: &serde_json::Value| {
+            let mut keys: Vec<String> = value.as_object().unwrap().keys().cloned().collect();
+            keys.sort_unstable();
+            keys
+        };
+        let (_, out) = run_args(&["list", "--format", "json"]).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(keys(&json[0]), fields("packages"));
+        let (_, out) = run_args(&["files", "bash", "--format", "json"]).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(keys(&json[0]), fields("files"));
+        let (_, out) = run_args(&["diff", FIXTURE, FIXTURE, "--format", "json"]).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(keys(&json), fields("diff"));
+
+        let (_, out) = run_args(&["load", FIXTURE, "--format", "json"]).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(json["packages"], 10);
+        assert!(run_args(&["--root", "/", "list"]).is_err());
+    }
+}
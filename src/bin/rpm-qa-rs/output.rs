@@ -0,0 +1,293 @@
+use anyhow::{Result, bail};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::ValueEnum;
+use rpm_qa::{FileInfo, Package, PackageDiff};
+use serde::{Serialize, Serializer};
+use std::io::Write;
+
+/// Output format of the subcommands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Format {
+    /// Aligned columns, or plain text for non-tabular output.
+    #[default]
+    Table,
+    /// JSON, with the field names of the library's types.
+    Json,
+    /// YAML, with the same structure as JSON.
+    Yaml,
+    /// Packages as NEVRAs and files as paths, one per line.
+    Nevra,
+    /// SPDX 2.3 JSON (`sbom` only).
+    Spdx,
+    /// CycloneDX 1.5 JSON (`sbom` only).
+    Cyclonedx,
+}
+
+/// Fields of the records of each kind of output in the structured formats.
+pub(crate) const FIELDS: &[(&str, &[&str])] = &[
+    (
+        "packages",
+        &[
+            "name",
+            "version",
+            "release",
+            "epoch",
+            "arch",
+            "license",
+            "size",
+            "buildtime",
+            "installtime",
+            "installtid",
+            "sourcerpm",
+            "digest_algo",
+            "signing_key",
+            "changelog",
+            "requires",
+            "provides",
+            "recommends",
+            "suggests",
+            "obsoletes",
+            "conflicts",
+            "pre",
+            "post",
+            "preun",
+            "postun",
+            "triggers",
+            "files",
+        ],
+    ),
+    (
+        "files",
+        &[
+            "package", "path", "size", "mode", "mtime", "digest", "flags", "user", "group",
+            "linkto", "state", "lang", "color", "caps",
+        ],
+    ),
+    ("owners", &["path", "owners", "ancestors"]),
+    ("summary", &["packages", "files"]),
+    (
+        "diff",
+        &[
+            "added",
+            "removed",
+            "upgraded",
+            "downgraded",
+            "unchanged",
+            "files",
+        ],
+    ),
+];
+
+/// The owners of a path. See the `who-owns` subcommand.
+#[derive(Serialize)]
+pub(crate) struct Ownership<'a> {
+    pub(crate) path: Utf8PathBuf,
+    /// Serialized as package names.
+    #[serde(serialize_with = "serialize_names")]
+    pub(crate) owners: Vec<&'a Package>,
+    /// Owned ancestor directories, closest first.
+    pub(crate) ancestors: Vec<Ownership<'a>>,
+}
+
+/// A file of a package, serialized as the fields of its [`FileInfo`] along
+/// with its package name and path.
+#[derive(Serialize)]
+pub(crate) struct FileRecord<'a> {
+    #[serde(serialize_with = "serialize_name")]
+    pub(crate) package: &'a Package,
+    pub(crate) path: &'a Utf8Path,
+    #[serde(flatten)]
+    pub(crate) info: &'a FileInfo,
+}
+
+/// What a subcommand prints. The structured formats serialize the library's
+/// types as they are.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub(crate) enum Output<'a> {
+    Packages(Vec<&'a Package>),
+    Files(Vec<FileRecord<'a>>),
+    Owners(Vec<Ownership<'a>>),
+    Summary { packages: usize, files: usize },
+    Diff(PackageDiff<'a>),
+}
+
+impl Output<'_> {
+    pub(crate) fn write(&self, format: Format, out: &mut dyn Write) -> Result<()> {
+        match format {
+            Format::Json => {
+                serde_json::to_writer_pretty(&mut *out, self)?;
+                writeln!(out)?;
+            }
+            Format::Yaml => serde_yaml_ng::to_writer(&mut *out, self)?,
+            Format::Table | Format::Nevra => self.write_text(format == Format::Nevra, out)?,
+            Format::Spdx | Format::Cyclonedx => bail!("SBOM formats only apply to sbom"),
+        }
+        Ok(())
+    }
+
+    fn write_text(&self, nevra: bool, out: &mut dyn Write) -> Result<()> {
+        match self {
+            Output::Packages(packages) if nevra => {
+                for pkg in packages {
+                    writeln!(out, "{}", pkg.nevra())?;
+                }
+            }
+            Output::Packages(packages) => {
+                let rows = packages.iter().map(|pkg| {
+                    vec![
+                        pkg.name.clone(),
+                        pkg.epoch.map_or("-".into(), |e| e.to_string()),
+                        pkg.version.clone(),
+                        pkg.release.clone(),
+                        pkg.arch.clone(),
+                        pkg.size.to_string(),
+                    ]
+                });
+                write_table(
+                    out,
+                    &["NAME", "EPOCH", "VERSION", "RELEASE", "ARCH", "SIZE"],
+                    rows,
+                )?;
+            }
+            Output::Files(files) if nevra => {
+                for file in files {
+                    writeln!(out, "{}", file.path)?;
+                }
+            }
+            Output::Files(files) => {
+                let rows = files.iter().map(|FileRecord { path, info, .. }| {
+                    vec![
+                        format!("{:o}", info.mode),
+                        info.user.clone(),
+                        info.group.clone(),
+                        info.size.to_string(),
+                        match &info.linkto {
+                            Some(target) => format!("{path} -> {target}"),
+                            None => path.to_string(),
+                        },
+                    ]
+                });
+                write_table(out, &["MODE", "USER", "GROUP", "SIZE", "PATH"], rows)?;
+            }
+            Output::Owners(owners) => {
+                let names = |owners: &[&Package]| {
+                    let names: Vec<String> = owners
+                        .iter()
+                        .map(|pkg| if nevra { pkg.nevra() } else { pkg.name.clone() })
+                        .collect();
+                    names.join(" ")
+                };
+                for ownership in owners {
+                    match ownership.owners.as_slice() {
+                        [] => writeln!(out, "{}: not owned by any package", ownership.path)?,
+                        o => writeln!(out, "{}: {}", ownership.path, names(o))?,
+                    }
+                    for ancestor in &ownership.ancestors {
+                        writeln!(out, "  {}: {}", ancestor.path, names(&ancestor.owners))?;
+                    }
+                }
+            }
+            Output::Summary { packages, files } => {
+                writeln!(out, "{packages} packages, {files} files")?
+            }
+            Output::Diff(diff) if nevra => {
+                for pkg in &diff.removed {
+                    writeln!(out, "-{}", pkg.nevra())?;
+                }
+                for pkg in &diff.added {
+                    writeln!(out, "+{}", pkg.nevra())?;
+                }
+                for (old, new) in diff.upgraded.iter().chain(&diff.downgraded) {
+                    writeln!(out, "-{}\n+{}", old.nevra(), new.nevra())?;
+                }
+            }
+            Output::Diff(diff) => {
+                let evra = |pkg: &Package| match pkg.epoch {
+                    Some(epoch) => format!("{epoch}:{}-{}.{}", pkg.version, pkg.release, pkg.arch),
+                    None => format!("{}-{}.{}", pkg.version, pkg.release, pkg.arch),
+                };
+                let row = |change: &str, name: &str, old: String, new: String| {
+                    vec![change.to_string(), name.to_string(), old, new]
+                };
+                let rows = (diff.added.iter())
+                    .map(|pkg| row("added", &pkg.name, "-".into(), evra(pkg)))
+                    .chain(
+                        diff.removed
+                            .iter()
+                            .map(|pkg| row("removed", &pkg.name, evra(pkg), "-".into())),
+                    )
+                    .chain(
+                        diff.upgraded
+                            .iter()
+                            .map(|(old, new)| row("upgraded", &new.name, evra(old), evra(new))),
+                    )
+                    .chain(
+                        diff.downgraded
+                            .iter()
+                            .map(|(old, new)| row("downgraded", &new.name, evra(old), evra(new))),
+                    );
+                write_table(out, &["CHANGE", "PACKAGE", "OLD", "NEW"], rows)?;
+                if !diff.files.is_empty() {
+                    writeln!(out)?;
+                    let rows = diff.files.iter().flat_map(|changes| {
+                        let row = move |change: &str, path: &Utf8Path| {
+                            vec![
+                                change.to_string(),
+                                changes.package.to_string(),
+                                path.to_string(),
+                            ]
+                        };
+                        (changes.added.iter().map(move |path| row("added", path)))
+                            .chain(changes.removed.iter().map(move |path| row("removed", path)))
+                            .chain(
+                                changes
+                                    .modified
+                                    .iter()
+                                    .map(move |path| row("modified", path)),
+                            )
+                    });
+                    write_table(out, &["CHANGE", "PACKAGE", "PATH"], rows)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write rows as left-aligned columns, except for the last one which isn't
+/// padded.
+fn write_table(
+    out: &mut dyn Write,
+    header: &[&str],
+    rows: impl Iterator<Item = Vec<String>>,
+) -> Result<()> {
+    let rows: Vec<Vec<String>> = std::iter::once(header.iter().map(|h| h.to_string()).collect())
+        .chain(rows)
+        .collect();
+    let mut widths = vec![0; header.len()];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in rows {
+        let last = row.len() - 1;
+        for (i, cell) in row.iter().enumerate() {
+            if i == last {
+                writeln!(out, "{cell}")?;
+            } else {
+                write!(out, "{cell:<width$}  ", width = widths[i])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn serialize_name<S: Serializer>(pkg: &&Package, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&pkg.name)
+}
+
+fn serialize_names<S: Serializer>(pkgs: &[&Package], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(pkgs.iter().map(|pkg| &pkg.name))
+}
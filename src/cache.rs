@@ -0,0 +1,71 @@
+use anyhow::Context;
+use camino::{Utf8Path, Utf8PathBuf};
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::sync::{Arc, LazyLock, Mutex};
+
+use crate::*;
+
+/// Identifies a particular state of an rpmdb. Any rpm transaction rewrites at
+/// least one of the database files, which changes their size or mtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DbCookie(Vec<(String, u64, u64, i64, i64)>);
+
+/// Snapshots keyed by rootfs path.
+type Cache = HashMap<Utf8PathBuf, (DbCookie, Arc<Packages>)>;
+
+static CACHE: LazyLock<Mutex<Cache>> = LazyLock::new(Default::default);
+
+/// Compute the cookie for the rpmdb in `rootfs`, if there is one.
+fn db_cookie(rootfs: &Utf8Path) -> Result<Option<DbCookie>> {
+    let Some(dbpath) = find_dbpath(rootfs.as_std_path())? else {
+        return Ok(None);
+    };
+    let dbdir = rootfs.join(dbpath);
+    let mut entries = Vec::new();
+    for entry in dbdir
+        .read_dir_utf8()
+        .with_context(|| format!("reading {dbdir}"))?
+    {
+        let entry = entry.with_context(|| format!("reading {dbdir}"))?;
+        let meta = entry
+            .metadata()
+            .with_context(|| format!("querying {}", entry.path()))?;
+        entries.push((
+            entry.file_name().to_string(),
+            meta.ino(),
+            meta.size(),
+            meta.mtime(),
+            meta.mtime_nsec(),
+        ));
+    }
+    entries.sort();
+    Ok(Some(DbCookie(entries)))
+}
+
+pub(crate) fn load_from_rootfs_cached_impl(rootfs: &Utf8Path) -> Result<Arc<Packages>> {
+    let Some(cookie) = db_cookie(rootfs)? else {
+        // Nothing to key the cache on; just load directly.
+        return Ok(Arc::new(load_from_rootfs(rootfs)?));
+    };
+    let cached = match CACHE.lock().unwrap().get(rootfs) {
+        Some((cached_cookie, packages)) if *cached_cookie == cookie => Some(Arc::clone(packages)),
+        _ => None,
+    };
+    observer::notify(|observer| observer.on_cache_lookup(rootfs, cached.is_some()));
+    if let Some(packages) = cached {
+        return Ok(packages);
+    }
+    // Don't hold the lock while loading. The cookie was computed before
+    // loading, so if the rpmdb changes meanwhile, the next call reloads.
+    let packages = Arc::new(load_from_rootfs(rootfs)?);
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(rootfs.to_path_buf(), (cookie, Arc::clone(&packages)));
+    Ok(packages)
+}
+
+pub(crate) fn clear_cache_impl() {
+    CACHE.lock().unwrap().clear();
+}
@@ -0,0 +1,109 @@
+/// An entry of a package's changelog.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChangelogEntry {
+    /// Unix timestamp of the entry.
+    pub time: u64,
+    /// Header line of the entry (rpm's `CHANGELOGNAME`), e.g. `Jane Doe
+    /// <jane@example.com> - 1.0-1`. See [`ChangelogEntry::header`]. Empty when
+    /// parsing output from older versions of this crate.
+    pub author: String,
+    /// Text of the entry, usually a list of `- ` items. Empty when parsing
+    /// output from older versions of this crate.
+    pub text: String,
+}
+
+impl ChangelogEntry {
+    /// Parse the header line of the entry into its parts.
+    pub fn header(&self) -> ChangelogHeader {
+        ChangelogHeader::parse(&self.author)
+    }
+}
+
+/// The parsed header line of a changelog entry (rpm's `CHANGELOGNAME`), by
+/// convention `Name <email> - version-release`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangelogHeader {
+    /// Author name. Empty if the header only has an email.
+    pub author: String,
+    /// Author email, as written (it's sometimes obfuscated, e.g.
+    /// `jane at example.com`).
+    pub email: Option<String>,
+    /// `[epoch:]version-release` the entry is for, if stated.
+    pub evr: Option<String>,
+}
+
+impl ChangelogHeader {
+    /// Parse a changelog header. This never fails: parts which don't follow
+    /// the convention are left as part of the author or dropped.
+    pub fn parse(header: &str) -> Self {
+        let header = header.trim();
+        let (author, email, rest) = match header.split_once('<') {
+            Some((author, tail)) => match tail.split_once('>') {
+                Some((email, rest)) => (author, Some(email.trim()), rest),
+                None => (header, None, ""),
+            },
+            // Without an email, the EVR can only be told apart by the dash.
+            None => match header.rsplit_once(" - ") {
+                Some((author, evr)) => (author, None, evr),
+                None => (header, None, ""),
+            },
+        };
+        let rest = rest.trim_start();
+        let rest = rest.strip_prefix('-').unwrap_or(rest);
+        let evr = rest.split_whitespace().next();
+        Self {
+            author: author.trim().to_string(),
+            email: email.filter(|e| !e.is_empty()).map(String::from),
+            evr: evr.map(String::from),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(author: &str, email: Option<&str>, evr: Option<&str>) -> ChangelogHeader {
+        ChangelogHeader {
+            author: author.into(),
+            email: email.map(String::from),
+            evr: evr.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        for (input, expected) in [
+            (
+                "Fedora Release Engineering <releng@fedoraproject.org> - 5.3.0-2",
+                header(
+                    "Fedora Release Engineering",
+                    Some("releng@fedoraproject.org"),
+                    Some("5.3.0-2"),
+                ),
+            ),
+            (
+                "Jane Doe <jane@example.com> 1:2.0-1",
+                header("Jane Doe", Some("jane@example.com"), Some("1:2.0-1")),
+            ),
+            (
+                "Jane Doe <jane at example dot com> -1.0-1 ",
+                header("Jane Doe", Some("jane at example dot com"), Some("1.0-1")),
+            ),
+            (
+                "<jane@example.com> - 1.0-1",
+                header("", Some("jane@example.com"), Some("1.0-1")),
+            ),
+            (
+                "Jane Doe <jane@example.com>",
+                header("Jane Doe", Some("jane@example.com"), None),
+            ),
+            ("Jane Doe - 1.0-1", header("Jane Doe", None, Some("1.0-1"))),
+            ("Jane Doe", header("Jane Doe", None, None)),
+            ("Jane <oops", header("Jane <oops", None, None)),
+        ] {
+            assert_eq!(ChangelogHeader::parse(input), expected, "{input}");
+        }
+    }
+}
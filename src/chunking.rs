@@ -0,0 +1,247 @@
+use anyhow::anyhow;
+use std::collections::HashMap;
+
+use crate::*;
+
+/// Partitions packages into a fixed number of bins, e.g. the layers of a
+/// container image.
+///
+/// Packages are ordered by how often they're updated, as estimated by
+/// [`Package::update_frequency`] (packages without enough changelog history
+/// count as never updated), and then split into runs of roughly equal installed size. Packages updated
+/// at similar rates thus end up in the same bins, so that an update of a
+/// frequently changing package doesn't invalidate the layers of stable ones.
+#[derive(Debug, Clone)]
+pub struct Chunker {
+    bins: usize,
+    pins: Vec<(String, usize)>,
+    affinity: Vec<Vec<String>>,
+}
+
+/// A bin of packages produced by [`Chunker::chunk`].
+#[derive(Debug, Clone, Default)]
+pub struct Chunk<'a> {
+    /// Packages in this bin, sorted by name.
+    pub packages: Vec<&'a Package>,
+    /// Total installed size of the packages.
+    pub size: u64,
+}
+
+/// Packages which must end up in the same bin.
+#[derive(Default)]
+struct Unit<'a> {
+    packages: Vec<&'a Package>,
+    pin: Option<usize>,
+}
+
+impl Chunker {
+    /// Partition packages into `bins` bins.
+    pub fn new(bins: usize) -> Self {
+        Self {
+            bins,
+            pins: Vec::new(),
+            affinity: Vec::new(),
+        }
+    }
+
+    /// Put the package `name` into the bin with index `bin`. Bins with pinned
+    /// packages only hold those (and the packages they must be kept with, see
+    /// [`Chunker::together`]). Pins of packages which aren't installed are
+    /// ignored.
+    pub fn pin(mut self, name: impl Into<String>, bin: usize) -> Self {
+        self.pins.push((name.into(), bin));
+        self
+    }
+
+    /// Keep the given packages in the same bin, e.g. because they're always
+    /// updated together.
+    pub fn together<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.affinity
+            .push(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Partition `packages`. The returned bins are indexed as for
+    /// [`Chunker::pin`]; some may be empty if there are fewer packages than
+    /// bins.
+    pub fn chunk<'a>(&self, packages: &'a Packages) -> Result<Vec<Chunk<'a>>> {
+        if self.bins == 0 {
+            return Err(anyhow!("number of bins must be at least 1").into());
+        }
+
+        let mut units: Vec<Unit<'a>> = Vec::new();
+        let mut unit_of: HashMap<&str, usize> = HashMap::new();
+        for group in &self.affinity {
+            let id = units.len();
+            units.push(Unit::default());
+            for name in group {
+                if unit_of.insert(name, id).is_some() {
+                    return Err(anyhow!("package '{name}' is in several affinity groups").into());
+                }
+            }
+        }
+        let mut sorted: Vec<&Package> = packages.values().collect();
+        sorted.sort_by(|a, b| a.name.cmp(&b.name));
+        for pkg in sorted {
+            let id = *unit_of.entry(&pkg.name).or_insert_with(|| {
+                units.push(Unit::default());
+                units.len() - 1
+            });
+            units[id].packages.push(pkg);
+        }
+        for (name, bin) in &self.pins {
+            if *bin >= self.bins {
+                return Err(anyhow!(
+                    "package '{name}' pinned to bin {bin}, but there are only {} bins",
+                    self.bins
+                )
+                .into());
+            }
+            let Some(unit) = unit_of.get(name.as_str()).map(|&id| &mut units[id]) else {
+                continue;
+            };
+            match unit.pin {
+                Some(other) if other != *bin => {
+                    return Err(anyhow!(
+                        "package '{name}' pinned to both bin {other} and bin {bin}"
+                    )
+                    .into());
+                }
+                _ => unit.pin = Some(*bin),
+            }
+        }
+        units.retain(|unit| !unit.packages.is_empty());
+
+        let mut chunks = vec![Chunk::default(); self.bins];
+        let mut free: Vec<Unit<'a>> = Vec::new();
+        for unit in units {
+            match unit.pin {
+                Some(bin) => add_unit(&mut chunks[bin], unit),
+                None => free.push(unit),
+            }
+        }
+        let free_bins: Vec<usize> = (0..self.bins)
+            .filter(|&bin| chunks[bin].packages.is_empty())
+            .collect();
+        if free_bins.is_empty() && !free.is_empty() {
+            return Err(anyhow!("all bins are pinned, with packages left to place").into());
+        }
+
+        let churn = |unit: &Unit| -> f64 {
+            (unit.packages.iter())
+                .filter_map(|pkg| pkg.update_frequency())
+                .sum()
+        };
+        let size = |unit: &Unit| -> u64 { unit.packages.iter().map(|pkg| pkg.size).sum() };
+        // Most frequently updated first; the order within a run is irrelevant.
+        let mut keyed: Vec<(f64, u64, Unit<'a>)> = (free.into_iter())
+            .map(|unit| (churn(&unit), size(&unit), unit))
+            .collect();
+        keyed.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.1.cmp(&a.1)));
+        let free: Vec<Unit<'a>> = keyed.into_iter().map(|(_, _, unit)| unit).collect();
+
+        let mut remaining: u64 = free.iter().map(size).sum();
+        let mut free = free.into_iter().peekable();
+        for (i, &bin) in free_bins.iter().enumerate() {
+            let bins_left = free_bins.len() - i;
+            let target = remaining.div_ceil(bins_left as u64);
+            let chunk = &mut chunks[bin];
+            loop {
+                // Take at least one unit, but leave one for each later bin.
+                let more = bins_left == 1
+                    || chunk.packages.is_empty()
+                    || (chunk.size < target && free.len() >= bins_left);
+                let Some(unit) = free.next_if(|_| more) else {
+                    break;
+                };
+                add_unit(chunk, unit);
+            }
+            remaining -= chunk.size;
+        }
+
+        for chunk in &mut chunks {
+            chunk.packages.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        Ok(chunks)
+    }
+}
+
+fn add_unit<'a>(chunk: &mut Chunk<'a>, unit: Unit<'a>) {
+    chunk.size += unit.packages.iter().map(|pkg| pkg.size).sum::<u64>();
+    chunk.packages.extend(unit.packages);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/fedora.qf");
+
+    fn names<'a>(chunk: &Chunk<'a>) -> Vec<&'a str> {
+        chunk.packages.iter().map(|pkg| pkg.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_chunk() {
+        let packages = load_from_str(FIXTURE).unwrap();
+        let chunks = Chunker::new(3).chunk(&packages).unwrap();
+        assert_eq!(chunks.len(), 3);
+        let mut all: Vec<&str> = chunks.iter().flat_map(names).collect();
+        all.sort_unstable();
+        let mut expected: Vec<&str> = packages.keys().map(String::as_str).collect();
+        expected.sort_unstable();
+        assert_eq!(all, expected);
+        let total: u64 = packages.values().map(|pkg| pkg.size).sum();
+        assert_eq!(chunks.iter().map(|c| c.size).sum::<u64>(), total);
+        assert!(chunks.iter().all(|c| !c.packages.is_empty()));
+
+        // The most frequently updated packages go first.
+        assert!(names(&chunks[0]).contains(&"fedora-release-common"));
+        assert!(!names(&chunks[0]).contains(&"langpacks-core-en"));
+
+        let chunks = Chunker::new(20).chunk(&packages).unwrap();
+        assert_eq!(
+            chunks.iter().filter(|c| !c.packages.is_empty()).count(),
+            packages.len()
+        );
+        let chunks = Chunker::new(1).chunk(&packages).unwrap();
+        assert_eq!(chunks[0].packages.len(), packages.len());
+    }
+
+    #[test]
+    fn test_pins_and_affinity() {
+        let packages = load_from_str(FIXTURE).unwrap();
+        let chunks = Chunker::new(3)
+            .pin("glibc", 2)
+            .together(["glibc", "langpacks-core-en"])
+            .together(["bash", "coreutils"])
+            .pin("nonexistent", 0)
+            .chunk(&packages)
+            .unwrap();
+        assert_eq!(names(&chunks[2]), ["glibc", "langpacks-core-en"]);
+        let bash = chunks
+            .iter()
+            .position(|c| names(c).contains(&"bash"))
+            .unwrap();
+        assert!(names(&chunks[bash]).contains(&"coreutils"));
+
+        assert!(Chunker::new(0).chunk(&packages).is_err());
+        assert!(Chunker::new(2).pin("bash", 2).chunk(&packages).is_err());
+        assert!(
+            Chunker::new(2)
+                .pin("bash", 0)
+                .pin("bash", 1)
+                .chunk(&packages)
+                .is_err()
+        );
+        assert!(Chunker::new(1).pin("bash", 0).chunk(&packages).is_err());
+        let overlapping = Chunker::new(2)
+            .together(["bash", "rpm"])
+            .together(["rpm", "setup"]);
+        assert!(overlapping.chunk(&packages).is_err());
+    }
+}
@@ -0,0 +1,312 @@
+use anyhow::{Context, bail};
+use std::collections::HashSet;
+
+use crate::*;
+
+/// Package groups and environments from a comps.xml file.
+#[derive(Debug, Clone, Default)]
+pub struct Comps {
+    /// Groups, in file order.
+    pub groups: Vec<Group>,
+    /// Environments, in file order.
+    pub environments: Vec<Environment>,
+}
+
+/// A package group, e.g. `core`.
+#[derive(Debug, Clone)]
+pub struct Group {
+    /// Group ID.
+    pub id: String,
+    /// Untranslated display name.
+    pub name: String,
+    /// Packages in the group.
+    pub packages: Vec<GroupPackage>,
+}
+
+/// A package listed in a [`Group`].
+#[derive(Debug, Clone)]
+pub struct GroupPackage {
+    /// Package name.
+    pub name: String,
+    /// How the package is pulled in when the group is installed.
+    pub kind: GroupPackageKind,
+    /// For [`GroupPackageKind::Conditional`] packages, the package whose
+    /// presence pulls this one in.
+    pub requires: Option<String>,
+}
+
+/// How a package in a [`Group`] is pulled in when the group is installed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupPackageKind {
+    /// Always installed.
+    Mandatory,
+    /// Installed unless excluded.
+    Default,
+    /// Only installed if asked for.
+    Optional,
+    /// Installed if the package it requires is.
+    Conditional,
+}
+
+/// An environment, i.e. a set of groups, e.g. `server-product-environment`.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    /// Environment ID.
+    pub id: String,
+    /// Untranslated display name.
+    pub name: String,
+    /// IDs of the groups making up the environment.
+    pub groups: Vec<String>,
+    /// IDs of groups which may optionally be added.
+    pub options: Vec<String>,
+}
+
+/// How much of a [`Group`] is installed. See [`Comps::group_coverage`].
+#[derive(Debug, Clone)]
+pub struct GroupCoverage<'a> {
+    /// The group.
+    pub group: &'a Group,
+    /// Names of the group's packages which are installed, of any kind.
+    pub installed: Vec<&'a str>,
+    /// Names of the group's mandatory packages which aren't installed.
+    pub missing_mandatory: Vec<&'a str>,
+}
+
+impl GroupCoverage<'_> {
+    /// Whether all the group's mandatory packages are installed.
+    pub fn is_complete(&self) -> bool {
+        self.missing_mandatory.is_empty()
+    }
+}
+
+impl Comps {
+    /// Parse the contents of a comps.xml file. Categories and translations are
+    /// ignored.
+    pub fn parse(xml: &str) -> Result<Self> {
+        Ok(Self::parse_impl(xml).context("parsing comps")?)
+    }
+
+    fn parse_impl(xml: &str) -> anyhow::Result<Self> {
+        // comps files usually have a DOCTYPE declaration.
+        let options = roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        };
+        let doc = roxmltree::Document::parse_with_options(xml, options)?;
+        let root = doc.root_element();
+        if !root.has_tag_name("comps") {
+            bail!("unexpected root element <{}>", root.tag_name().name());
+        }
+        let mut comps = Comps::default();
+        for node in root.children().filter(|n| n.is_element()) {
+            match node.tag_name().name() {
+                "group" => comps.groups.push(parse_group(node)?),
+                "environment" => comps.environments.push(parse_environment(node)?),
+                _ => {}
+            }
+        }
+        Ok(comps)
+    }
+
+    /// Look up a group by ID.
+    pub fn group(&self, id: &str) -> Option<&Group> {
+        self.groups.iter().find(|group| group.id == id)
+    }
+
+    /// Get the groups listing the package `name`, of any kind.
+    pub fn groups_of<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Group> + 'a {
+        self.groups
+            .iter()
+            .filter(move |group| group.packages.iter().any(|pkg| pkg.name == name))
+    }
+
+    /// Get the environments including the group `id`, as a member or an
+    /// option.
+    pub fn environments_of<'a>(
+        &'a self,
+        id: &'a str,
+    ) -> impl Iterator<Item = &'a Environment> + 'a {
+        self.environments.iter().filter(move |env| {
+            env.groups
+                .iter()
+                .chain(&env.options)
+                .any(|group| group == id)
+        })
+    }
+
+    /// Check which packages of each group are installed, and which mandatory
+    /// ones are missing. Groups none of whose packages are installed are
+    /// included too, in file order.
+    pub fn group_coverage<'a>(&'a self, packages: &Packages) -> Vec<GroupCoverage<'a>> {
+        // Keys aren't necessarily names (see DuplicatePolicy::Merge).
+        let installed: HashSet<&str> = packages.values().map(|pkg| pkg.name.as_str()).collect();
+        self.groups
+            .iter()
+            .map(|group| {
+                let mut coverage = GroupCoverage {
+                    group,
+                    installed: Vec::new(),
+                    missing_mandatory: Vec::new(),
+                };
+                for pkg in &group.packages {
+                    if installed.contains(pkg.name.as_str()) {
+                        coverage.installed.push(&pkg.name);
+                    } else if pkg.kind == GroupPackageKind::Mandatory {
+                        coverage.missing_mandatory.push(&pkg.name);
+                    }
+                }
+                coverage
+            })
+            .collect()
+    }
+}
+
+fn parse_group(node: roxmltree::Node) -> anyhow::Result<Group> {
+    let id = child_text(node, "id").context("group without id")?;
+    let mut packages = Vec::new();
+    let reqs = node
+        .children()
+        .filter(|n| n.has_tag_name("packagelist"))
+        .flat_map(|n| n.children())
+        .filter(|n| n.has_tag_name("packagereq"));
+    for req in reqs {
+        let name = req
+            .text()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .with_context(|| format!("group '{id}': empty packagereq"))?;
+        let kind = match req.attribute("type") {
+            // Missing types default to mandatory, as in dnf.
+            None | Some("mandatory") => GroupPackageKind::Mandatory,
+            Some("default") => GroupPackageKind::Default,
+            Some("optional") => GroupPackageKind::Optional,
+            Some("conditional") => GroupPackageKind::Conditional,
+            Some(t) => bail!("group '{id}': unknown packagereq type '{t}'"),
+        };
+        packages.push(GroupPackage {
+            name: name.to_string(),
+            kind,
+            requires: req.attribute("requires").map(str::to_string),
+        });
+    }
+    Ok(Group {
+        name: child_text(node, "name").unwrap_or(id).to_string(),
+        id: id.to_string(),
+        packages,
+    })
+}
+
+fn parse_environment(node: roxmltree::Node) -> anyhow::Result<Environment> {
+    let id = child_text(node, "id").context("environment without id")?;
+    let group_ids = |list: &str| -> Vec<String> {
+        node.children()
+            .filter(|n| n.has_tag_name(list))
+            .flat_map(|n| n.children())
+            .filter(|n| n.has_tag_name("groupid"))
+            .filter_map(|n| n.text())
+            .map(|id| id.trim().to_string())
+            .collect()
+    };
+    Ok(Environment {
+        name: child_text(node, "name").unwrap_or(id).to_string(),
+        id: id.to_string(),
+        groups: group_ids("grouplist"),
+        options: group_ids("optionlist"),
+    })
+}
+
+/// Get the trimmed text of the first untranslated `tag` child of `node`.
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, tag: &str) -> Option<&'a str> {
+    node.children()
+        .filter(|n| n.has_tag_name(tag))
+        .find(|n| n.attributes().all(|attr| attr.name() != "lang"))
+        .and_then(|n| n.text())
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/fedora.qf");
+
+    const COMPS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE comps PUBLIC "-//Red Hat, Inc.//DTD Comps info//EN" "comps.dtd">
+<comps>
+  <group>
+    <id>core</id>
+    <name>Core</name>
+    <name xml:lang="de">Kern</name>
+    <packagelist>
+      <packagereq type="mandatory">bash</packagereq>
+      <packagereq>coreutils</packagereq>
+      <packagereq type="mandatory">systemd</packagereq>
+      <packagereq type="default">util-linux-core</packagereq>
+      <packagereq type="conditional" requires="glibc">glibc-langpack-en</packagereq>
+    </packagelist>
+  </group>
+  <group>
+    <id>editors</id>
+    <name>Editors</name>
+    <packagelist>
+      <packagereq type="optional">vim-enhanced</packagereq>
+    </packagelist>
+  </group>
+  <environment>
+    <id>minimal-environment</id>
+    <name>Minimal Install</name>
+    <grouplist>
+      <groupid>core</groupid>
+    </grouplist>
+    <optionlist>
+      <groupid>editors</groupid>
+    </optionlist>
+  </environment>
+  <category>
+    <id>base-system</id>
+  </category>
+</comps>
+"#;
+
+    #[test]
+    fn test_parse() {
+        let comps = Comps::parse(COMPS).unwrap();
+        assert_eq!(comps.groups.len(), 2);
+        let core = comps.group("core").unwrap();
+        assert_eq!(core.name, "Core");
+        assert_eq!(core.packages.len(), 5);
+        assert_eq!(core.packages[1].kind, GroupPackageKind::Mandatory);
+        assert_eq!(core.packages[4].kind, GroupPackageKind::Conditional);
+        assert_eq!(core.packages[4].requires.as_deref(), Some("glibc"));
+        let env = &comps.environments[0];
+        assert_eq!(env.groups, ["core"]);
+        assert_eq!(env.options, ["editors"]);
+
+        let groups: Vec<&str> = comps.groups_of("bash").map(|g| g.id.as_str()).collect();
+        assert_eq!(groups, ["core"]);
+        assert_eq!(comps.environments_of("editors").count(), 1);
+        assert_eq!(comps.environments_of("nonexistent").count(), 0);
+
+        assert!(Comps::parse("<notcomps/>").is_err());
+        assert!(Comps::parse("<comps><group><id>x").is_err());
+        let bad = "<comps><group><id>x</id><packagelist><packagereq type=\"bogus\">a</packagereq></packagelist></group></comps>";
+        assert!(Comps::parse(bad).is_err());
+    }
+
+    #[test]
+    fn test_group_coverage() {
+        let packages = load_from_str(FIXTURE).unwrap();
+        let comps = Comps::parse(COMPS).unwrap();
+        let coverage = comps.group_coverage(&packages);
+        assert_eq!(coverage.len(), 2);
+        assert_eq!(coverage[0].group.id, "core");
+        assert_eq!(
+            coverage[0].installed,
+            ["bash", "coreutils", "util-linux-core"]
+        );
+        assert_eq!(coverage[0].missing_mandatory, ["systemd"]);
+        assert!(!coverage[0].is_complete());
+        assert!(coverage[1].installed.is_empty());
+        assert!(coverage[1].is_complete());
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+
+use crate::*;
+
+/// How the two files of a [`FileConflict`] differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// The files are of different types, e.g. a directory and a symlink.
+    Type,
+    /// Regular files with different contents (size or digest).
+    Contents,
+    /// Symlinks to different targets.
+    LinkTarget,
+    /// Files with the same contents but different permissions.
+    Mode,
+}
+
+/// A path which two packages would install differently. See
+/// [`file_conflicts`].
+#[derive(Debug, Clone)]
+pub struct FileConflict<'a> {
+    /// The conflicting path.
+    pub path: &'a Utf8Path,
+    /// How the files differ.
+    pub kind: ConflictKind,
+    /// The package already owning the path: an installed package, or another
+    /// incoming one.
+    pub existing: &'a Package,
+    /// The file of `existing`.
+    pub existing_file: &'a FileInfo,
+    /// The incoming package.
+    pub incoming: &'a Package,
+    /// The file of `incoming`.
+    pub incoming_file: &'a FileInfo,
+}
+
+/// Find the file conflicts rpm would report when installing the `incoming`
+/// packages (e.g. loaded from `rpm -qp --queryformat` output of package
+/// files) on top of the `installed` ones, sorted by path.
+///
+/// As in rpm, incoming packages replace the installed packages of the same
+/// name and architecture, which don't conflict with them, and files
+/// identical in type, permissions and contents may be shared. Directories
+/// don't conflict with each other, nor do ghost files, nor multilib files of
+/// different colors (e.g. 32-bit and 64-bit ELF files), for which rpm picks
+/// the preferred one. Incoming packages are also checked against each other.
+pub fn file_conflicts<'a>(
+    installed: &'a Packages,
+    incoming: &'a Packages,
+) -> Vec<FileConflict<'a>> {
+    let mut incoming_arches: HashMap<&str, Vec<&str>> = HashMap::new();
+    for pkg in incoming.values() {
+        incoming_arches
+            .entry(&pkg.name)
+            .or_default()
+            .push(&pkg.arch);
+    }
+    let replaced = |pkg: &Package| {
+        incoming_arches
+            .get(pkg.name.as_str())
+            .is_some_and(|arches| {
+                (arches.iter())
+                    .any(|&arch| arch == pkg.arch || arch == "noarch" || pkg.arch == "noarch")
+            })
+    };
+    // Files of the incoming packages, by path.
+    let mut incoming_files: HashMap<&Utf8Path, Vec<(&Package, &FileInfo)>> = HashMap::new();
+    for pkg in incoming.values() {
+        for (path, info) in &pkg.files {
+            if !info.flags.is_ghost() {
+                incoming_files
+                    .entry(path.as_path())
+                    .or_default()
+                    .push((pkg, info));
+            }
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    for pkg in installed.values().filter(|pkg| !replaced(pkg)) {
+        for (path, info) in &pkg.files {
+            if info.flags.is_ghost() || !info.state.is_none_or(|s| s == FileState::Normal) {
+                continue;
+            }
+            let Some(files) = incoming_files.get(path.as_path()) else {
+                continue;
+            };
+            for &(new, new_info) in files {
+                if let Some(kind) = compare(info, new_info) {
+                    conflicts.push(FileConflict {
+                        path,
+                        kind,
+                        existing: pkg,
+                        existing_file: info,
+                        incoming: new,
+                        incoming_file: new_info,
+                    });
+                }
+            }
+        }
+    }
+    for (path, files) in &incoming_files {
+        for (i, &a) in files.iter().enumerate() {
+            for &b in &files[i + 1..] {
+                // Report pairs in a stable order.
+                let ((pkg, info), (new, new_info)) =
+                    if a.0.name <= b.0.name { (a, b) } else { (b, a) };
+                if let Some(kind) = compare(info, new_info) {
+                    conflicts.push(FileConflict {
+                        path,
+                        kind,
+                        existing: pkg,
+                        existing_file: info,
+                        incoming: new,
+                        incoming_file: new_info,
+                    });
+                }
+            }
+        }
+    }
+    conflicts.sort_by(|a, b| {
+        (a.path, &a.existing.name, &a.incoming.name).cmp(&(
+            b.path,
+            &b.existing.name,
+            &b.incoming.name,
+        ))
+    });
+    conflicts
+}
+
+/// Get how two files at the same path conflict, if they do.
+fn compare(a: &FileInfo, b: &FileInfo) -> Option<ConflictKind> {
+    if let (Some(a), Some(b)) = (a.color, b.color)
+        && a != 0
+        && b != 0
+        && a & b == 0
+    {
+        return None;
+    }
+    let file_type = a.file_type();
+    if file_type != b.file_type() {
+        return Some(ConflictKind::Type);
+    }
+    match file_type {
+        FileInfo::S_IFDIR => return None,
+        FileInfo::S_IFREG if a.size != b.size || a.digest != b.digest => {
+            return Some(ConflictKind::Contents);
+        }
+        FileInfo::S_IFLNK if a.linkto != b.linkto => return Some(ConflictKind::LinkTarget),
+        _ => {}
+    }
+    (a.mode != b.mode).then_some(ConflictKind::Mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FileInfoBuilder, PackageBuilder};
+
+    fn file(mode: u16, digest: &str, color: u32) -> FileInfoBuilder {
+        (FileInfoBuilder::new().size(100).mode(mode).color(color))
+            .digest(FileDigest::from_hex(digest).unwrap())
+    }
+
+    fn package(name: &str, arch: &str, files: Vec<(&str, FileInfoBuilder)>) -> Package {
+        (files.into_iter())
+            .fold(PackageBuilder::new(name).arch(arch), |pkg, (path, file)| {
+                pkg.file(path, file)
+            })
+            .build()
+    }
+
+    fn packages(pkgs: Vec<Package>) -> Packages {
+        pkgs.into_iter()
+            .map(|pkg| (format!("{}.{}", pkg.name, pkg.arch), pkg))
+            .collect()
+    }
+
+    #[test]
+    fn test_compare() {
+        let reg = file(0o644, "aa", 0).build();
+        assert_eq!(compare(&reg, &reg), None);
+        assert_eq!(
+            compare(&reg, &file(0o644, "bb", 0).build()),
+            Some(ConflictKind::Contents)
+        );
+        assert_eq!(
+            compare(&reg, &file(0o755, "aa", 0).build()),
+            Some(ConflictKind::Mode)
+        );
+        let dir = FileInfoBuilder::dir().build();
+        assert_eq!(compare(&reg, &dir), Some(ConflictKind::Type));
+        assert_eq!(
+            compare(&dir, &FileInfoBuilder::dir().mode(0o700).build()),
+            None
+        );
+        let link = FileInfoBuilder::symlink("a").build();
+        let other = FileInfoBuilder::symlink("b").build();
+        assert_eq!(compare(&link, &other), Some(ConflictKind::LinkTarget));
+        // Multilib files of different colors are exempt.
+        assert_eq!(
+            compare(&file(0o755, "aa", 1).build(), &file(0o755, "bb", 2).build()),
+            None
+        );
+        assert_eq!(
+            compare(&file(0o755, "aa", 2).build(), &file(0o755, "bb", 2).build()),
+            Some(ConflictKind::Contents)
+        );
+        let mut unknown = file(0o755, "bb", 0).build();
+        unknown.color = None;
+        assert!(compare(&file(0o755, "aa", 1).build(), &unknown).is_some());
+    }
+
+    #[test]
+    fn test_file_conflicts() {
+        let installed = packages(vec![
+            package(
+                "foo",
+                "x86_64",
+                vec![
+                    ("/usr/bin/foo", file(0o755, "aa", 2)),
+                    ("/usr/share/foo", FileInfoBuilder::dir()),
+                ],
+            ),
+            package(
+                "bar",
+                "x86_64",
+                vec![("/etc/bar.conf", file(0o644, "aa", 0))],
+            ),
+            package(
+                "libbaz",
+                "x86_64",
+                vec![("/usr/lib/baz", file(0o755, "aa", 2))],
+            ),
+        ]);
+        let incoming = packages(vec![
+            // An upgrade of foo, replacing its files.
+            package(
+                "foo",
+                "x86_64",
+                vec![("/usr/bin/foo", file(0o755, "bb", 2))],
+            ),
+            package(
+                "qux",
+                "x86_64",
+                vec![
+                    ("/etc/bar.conf", file(0o644, "cc", 0)),
+                    ("/usr/share/foo", FileInfoBuilder::dir()),
+                    ("/usr/bin/qux", file(0o755, "dd", 2)),
+                ],
+            ),
+            package(
+                "libbaz",
+                "i686",
+                vec![("/usr/lib/baz", file(0o755, "ee", 1))],
+            ),
+            package(
+                "quux",
+                "x86_64",
+                vec![("/usr/bin/qux", file(0o755, "ff", 2))],
+            ),
+        ]);
+        let conflicts = file_conflicts(&installed, &incoming);
+        let summary: Vec<_> = (conflicts.iter())
+            .map(|c| {
+                (
+                    c.path.as_str(),
+                    c.existing.name.as_str(),
+                    c.incoming.name.as_str(),
+                    c.kind,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("/etc/bar.conf", "bar", "qux", ConflictKind::Contents),
+                ("/usr/bin/qux", "quux", "qux", ConflictKind::Contents),
+            ]
+        );
+        assert!(file_conflicts(&installed, &Packages::default()).is_empty());
+    }
+}
@@ -0,0 +1,251 @@
+use anyhow::Context;
+use std::collections::HashMap;
+use std::time::Duration;
+use zbus::blocking::Connection;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::Value;
+use zbus::{fdo, interface};
+
+use crate::*;
+
+/// Well-known bus name requested by [`DbusService::run`].
+pub const DBUS_NAME: &str = "io.github.jlebon.RpmQa";
+
+/// Object path of the packages object.
+pub const DBUS_PATH: &str = "/io/github/jlebon/RpmQa";
+
+/// A D-Bus service exposing the packages installed in a rootfs, so that other
+/// processes can query them without linking this crate or spawning rpm.
+///
+/// The `io.github.jlebon.RpmQa1` interface at [`DBUS_PATH`] has the methods
+/// `ListPackages() -> as` (NEVRAs), `GetPackage(s name) -> a{sv}` and
+/// `WhoOwns(s path) -> as` (package names), and the signal
+/// `PackagesChanged(as added, as removed, as changed)` (package names),
+/// emitted when the rpmdb changes.
+///
+/// The rpmdb is checked for changes periodically, as with
+/// [`load_from_rootfs_cached`]. If reloading it fails, e.g. because a
+/// transaction holds the rpmdb lock, the service keeps serving the last
+/// snapshot and tries again at the next check.
+#[derive(Debug, Clone)]
+pub struct DbusService {
+    rootfs: Utf8PathBuf,
+    poll_interval: Duration,
+}
+
+impl DbusService {
+    /// Create a service for the packages installed in `rootfs`.
+    pub fn new(rootfs: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            rootfs: rootfs.into(),
+            poll_interval: Duration::from_secs(5),
+        }
+    }
+
+    /// Set how often to check the rpmdb for changes (every 5 seconds by
+    /// default).
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Serve on `connection` (typically [`Connection::system`]), requesting
+    /// [`DBUS_NAME`]. This only returns on error.
+    pub fn run(&self, connection: &Connection) -> Result<()> {
+        Ok(self.run_impl(connection).context("running D-Bus service")?)
+    }
+
+    fn run_impl(&self, connection: &Connection) -> anyhow::Result<()> {
+        let object = PackagesObject::new(load_from_rootfs_cached(&self.rootfs)?)?;
+        connection.object_server().at(DBUS_PATH, object)?;
+        connection.request_name(DBUS_NAME)?;
+        let iface = connection
+            .object_server()
+            .interface::<_, PackagesObject>(DBUS_PATH)?;
+        loop {
+            std::thread::sleep(self.poll_interval);
+            let Some((added, removed, changed)) = self.reload(&mut iface.get_mut()) else {
+                continue;
+            };
+            if !(added.is_empty() && removed.is_empty() && changed.is_empty()) {
+                zbus::block_on(PackagesObject::packages_changed(
+                    iface.signal_emitter(),
+                    added,
+                    removed,
+                    changed,
+                ))?;
+            }
+        }
+    }
+
+    /// Reload the packages served by `object` if the rpmdb changed, and get
+    /// the names of the packages added, removed and changed. Errors are only
+    /// logged, keeping the current packages.
+    fn reload(
+        &self,
+        object: &mut PackagesObject,
+    ) -> Option<(Vec<String>, Vec<String>, Vec<String>)> {
+        let reloaded = load_from_rootfs_cached(&self.rootfs).and_then(|packages| {
+            if Arc::ptr_eq(&object.packages, &packages) {
+                return Ok(None);
+            }
+            PackagesObject::new(packages).map(Some)
+        });
+        let new = match reloaded {
+            Ok(new) => new?,
+            Err(e) => {
+                warn!("failed to reload packages of {}: {e:#}", self.rootfs);
+                return None;
+            }
+        };
+        let changes = changes(&object.packages, &new.packages);
+        *object = new;
+        Some(changes)
+    }
+}
+
+/// The object served at [`DBUS_PATH`].
+struct PackagesObject {
+    packages: Arc<Packages>,
+    /// Index of the files of `packages`, for `WhoOwns`.
+    index: PathIndex,
+}
+
+impl PackagesObject {
+    fn new(packages: Arc<Packages>) -> Result<Self> {
+        let index = PathIndex::new(&packages)?;
+        Ok(Self { packages, index })
+    }
+}
+
+#[interface(name = "io.github.jlebon.RpmQa1")]
+impl PackagesObject {
+    fn list_packages(&self) -> Vec<String> {
+        let mut nevras: Vec<String> = self.packages.values().map(|pkg| pkg.nevra()).collect();
+        nevras.sort();
+        nevras
+    }
+
+    fn get_package(&self, name: &str) -> fdo::Result<HashMap<&str, Value<'_>>> {
+        let pkg = self
+            .packages
+            .get(name)
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("package '{name}' not found")))?;
+        let mut props = HashMap::from([
+            ("name", Value::from(pkg.name.as_str())),
+            ("version", pkg.version.as_str().into()),
+            ("release", pkg.release.as_str().into()),
+            ("arch", pkg.arch.as_str().into()),
+            ("license", pkg.license.as_str().into()),
+            ("size", pkg.size.into()),
+            ("buildtime", pkg.buildtime.into()),
+            ("installtime", pkg.installtime.into()),
+        ]);
+        // D-Bus has no null, so leave out what's missing.
+        if let Some(epoch) = pkg.epoch {
+            props.insert("epoch", epoch.into());
+        }
+        if let Some(installtid) = pkg.installtid {
+            props.insert("installtid", installtid.into());
+        }
+        if let Some(sourcerpm) = &pkg.sourcerpm {
+            props.insert("sourcerpm", sourcerpm.as_str().into());
+        }
+        Ok(props)
+    }
+
+    fn who_owns(&self, path: &str) -> Vec<String> {
+        (self.index.owners_of(Utf8Path::new(path)))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[zbus(signal)]
+    async fn packages_changed(
+        emitter: &SignalEmitter<'_>,
+        added: Vec<String>,
+        removed: Vec<String>,
+        changed: Vec<String>,
+    ) -> zbus::Result<()>;
+}
+
+/// Get the names of the packages added, removed and changed (e.g. upgraded or
+/// reinstalled) between two snapshots, sorted.
+fn changes(old: &Packages, new: &Packages) -> (Vec<String>, Vec<String>, Vec<String>) {
+    let (mut added, mut changed) = (Vec::new(), Vec::new());
+    for (name, pkg) in new {
+        match old.get(name) {
+            None => added.push(name.clone()),
+            Some(prev) if prev.nevra() != pkg.nevra() || prev.installtid != pkg.installtid => {
+                changed.push(name.clone())
+            }
+            Some(_) => {}
+        }
+    }
+    let mut removed: Vec<String> = (old.keys())
+        .filter(|name| !new.contains_key(*name))
+        .cloned()
+        .collect();
+    added.sort();
+    removed.sort();
+    changed.sort();
+    (added, removed, changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/fedora.qf");
+
+    #[test]
+    fn test_methods() {
+        let packages = Arc::new(load_from_str(FIXTURE).unwrap());
+        let object = PackagesObject::new(Arc::clone(&packages)).unwrap();
+        let nevras = object.list_packages();
+        assert_eq!(nevras.len(), packages.len());
+        assert!(nevras.contains(&"bash-5.3.0-2.fc43.x86_64".to_string()));
+
+        let bash = object.get_package("bash").unwrap();
+        assert_eq!(bash["version"], Value::from("5.3.0"));
+        assert_eq!(bash["sourcerpm"], Value::from("bash-5.3.0-2.fc43.src.rpm"));
+        assert!(!bash.contains_key("epoch"));
+        assert!(matches!(
+            object.get_package("nonexistent"),
+            Err(fdo::Error::InvalidArgs(_))
+        ));
+
+        assert_eq!(object.who_owns("/usr/lib/rpm/macros.d/"), ["rpm"]);
+        assert!(object.who_owns("/nonexistent").is_empty());
+    }
+
+    #[test]
+    fn test_changes() {
+        let old = load_from_str(FIXTURE).unwrap();
+        let mut new = old.clone();
+        new.remove("rpm");
+        new.get_mut("bash").unwrap().release = "3.fc43".into();
+        let mut foo = old["setup"].clone();
+        foo.name = "foo".into();
+        new.insert("foo".into(), foo);
+
+        let (added, removed, changed) = changes(&old, &new);
+        assert_eq!(added, ["foo"]);
+        assert_eq!(removed, ["rpm"]);
+        assert_eq!(changed, ["bash"]);
+        assert_eq!(changes(&old, &old), Default::default());
+    }
+
+    #[test]
+    fn test_reload_error() {
+        let packages = Arc::new(load_from_str(FIXTURE).unwrap());
+        let mut object = PackagesObject::new(Arc::clone(&packages)).unwrap();
+        let tmpdir = tempfile::tempdir().unwrap();
+        let service = DbusService::new(Utf8Path::from_path(tmpdir.path()).unwrap());
+        // The rootfs has no rpmdb, so reloading fails, but the packages are
+        // kept rather than the service stopping.
+        assert!(service.reload(&mut object).is_none());
+        assert!(Arc::ptr_eq(&object.packages, &packages));
+        assert_eq!(object.who_owns("/usr/bin/bash"), ["bash"]);
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::{Context, bail};
+use std::io::{Read, Write};
+
+use crate::*;
+
+/// Version of the format written by [`PackagesDelta::write`].
+const DELTA_VERSION: u32 = 1;
+
+/// The changes between two snapshots of the installed packages, e.g. to ship
+/// only what a transaction changed rather than the whole new snapshot.
+///
+/// A package counts as changed if its NEVRA, build time, install time or
+/// install transaction differ, i.e. if it was upgraded, downgraded or
+/// reinstalled. The rest of its metadata is assumed to follow.
+#[derive(Debug, Clone, Default)]
+pub struct PackagesDelta {
+    /// Keys of the packages which are in the old snapshot but not in the new
+    /// one, sorted.
+    pub removed: Vec<String>,
+    /// Packages which are new or changed in the new snapshot, with their key
+    /// and sorted by it.
+    pub changed: Vec<(String, Package)>,
+}
+
+impl PackagesDelta {
+    /// Compute the changes from `old` to `new`.
+    pub fn between(old: &Packages, new: &Packages) -> Self {
+        let mut removed: Vec<String> = (old.keys())
+            .filter(|key| !new.contains_key(*key))
+            .cloned()
+            .collect();
+        removed.sort();
+        let mut changed: Vec<(String, Package)> = (new.iter())
+            .filter(|(key, pkg)| old.get(*key).is_none_or(|prev| !same_install(prev, pkg)))
+            .map(|(key, pkg)| (key.clone(), pkg.clone()))
+            .collect();
+        changed.sort_by(|a, b| a.0.cmp(&b.0));
+        Self { removed, changed }
+    }
+
+    /// Whether the snapshots are the same.
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Apply the changes to `packages`, turning the old snapshot into the new
+    /// one.
+    pub fn apply(&self, packages: &mut Packages) {
+        for key in &self.removed {
+            packages.remove(key);
+        }
+        for (key, pkg) in &self.changed {
+            packages.insert(key.clone(), pkg.clone());
+        }
+    }
+
+    /// Serialize the delta to `writer`, to be read back with
+    /// [`PackagesDelta::read`].
+    ///
+    /// The format extends that of [`QUERYFORMAT`]: after a @@DELTA@@ record
+    /// stating its version come a @@DEL@@ record with the key of each removed
+    /// package, and a @@SET@@ record with the key of each changed package
+    /// followed by the records rpm would output for it.
+    pub fn write<W: Write>(&self, writer: W) -> Result<()> {
+        Ok(self.write_impl(writer).context("writing delta")?)
+    }
+
+    fn write_impl<W: Write>(&self, writer: W) -> anyhow::Result<()> {
+        let mut writer = std::io::BufWriter::new(writer);
+        parse::write_record(&mut writer, "DELTA", &[DELTA_VERSION.to_string()])?;
+        for key in &self.removed {
+            parse::write_record(&mut writer, "DEL", std::slice::from_ref(key))?;
+        }
+        for (key, pkg) in &self.changed {
+            parse::write_record(&mut writer, "SET", std::slice::from_ref(key))?;
+            parse::write_package(&mut writer, pkg)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Deserialize a delta written by [`PackagesDelta::write`].
+    pub fn read<R: Read>(reader: R) -> Result<Self> {
+        Ok(Self::read_impl(reader).context("reading delta")?)
+    }
+
+    fn read_impl<R: Read>(mut reader: R) -> anyhow::Result<Self> {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+        let mut records = input.split_terminator('\x1e');
+        match records.next().and_then(|r| r.strip_prefix("@@DELTA@@\x1f")) {
+            Some(v) if v == DELTA_VERSION.to_string() => {}
+            Some(v) => bail!("unsupported delta version {v} (expected {DELTA_VERSION})"),
+            None => bail!("missing @@DELTA@@ record"),
+        }
+        let mut delta = Self::default();
+        while let Some(record) = records.next() {
+            if let Some(key) = record.strip_prefix("@@DEL@@\x1f") {
+                delta.removed.push(key.to_string());
+                continue;
+            }
+            let Some(key) = record.strip_prefix("@@SET@@\x1f") else {
+                let snippet: String = record.chars().take(80).collect();
+                bail!("unexpected record: {snippet}");
+            };
+            // The package's records run up to and including its @@END@@.
+            let mut package = String::new();
+            for record in records.by_ref() {
+                package.push_str(record);
+                package.push('\x1e');
+                if record == "@@END@@" {
+                    break;
+                }
+            }
+            let pkg = (load_from_str(&package)?.into_values().next())
+                .with_context(|| format!("no package for '{key}'"))?;
+            delta.changed.push((key.to_string(), pkg));
+        }
+        Ok(delta)
+    }
+}
+
+/// Whether two packages are the same installation of the same build.
+fn same_install(a: &Package, b: &Package) -> bool {
+    a.name == b.name
+        && a.epoch.unwrap_or(0) == b.epoch.unwrap_or(0)
+        && a.version == b.version
+        && a.release == b.release
+        && a.arch == b.arch
+        && a.buildtime == b.buildtime
+        && a.installtime == b.installtime
+        && a.installtid == b.installtid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta() {
+        let old = load_from_str(include_str!("../tests/fixtures/fedora.qf")).unwrap();
+        let mut new = old.clone();
+        new.remove("setup");
+        let bash = new.get_mut("bash").unwrap();
+        bash.release = "3.fc43".into();
+        bash.installtime += 1;
+        bash.signing_key = Some("dbfcf71c6d9f90a6".into());
+        let mut foo = old["setup"].clone();
+        foo.name = "foo".into();
+        foo.recommends.push(Dependency {
+            name: "bar".into(),
+            flags: DependencyFlags::default(),
+            version: None,
+        });
+        new.insert("foo".into(), foo);
+
+        let delta = PackagesDelta::between(&old, &new);
+        assert_eq!(delta.removed, ["setup"]);
+        let keys: Vec<&str> = delta.changed.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["bash", "foo"]);
+        assert!(PackagesDelta::between(&new, &new).is_empty());
+
+        let mut buf = Vec::new();
+        delta.write(&mut buf).unwrap();
+        let read = PackagesDelta::read(buf.as_slice()).unwrap();
+        assert_eq!(read.removed, delta.removed);
+        let mut applied = old.clone();
+        read.apply(&mut applied);
+        assert_eq!(applied.len(), new.len());
+        assert!(PackagesDelta::between(&applied, &new).is_empty());
+        let (bash, foo) = (&applied["bash"], &applied["foo"]);
+        assert_eq!(bash.release, "3.fc43");
+        assert_eq!(bash.signing_key.as_deref(), Some("dbfcf71c6d9f90a6"));
+        assert_eq!(bash.files.len(), new["bash"].files.len());
+        let (path, info) = bash.files.iter().next().unwrap();
+        let orig = &new["bash"].files[path];
+        assert_eq!(
+            (info.digest, info.mode, info.state),
+            (orig.digest, orig.mode, orig.state)
+        );
+        assert_eq!(bash.changelog, new["bash"].changelog);
+        assert_eq!(foo.recommends[0].name, "bar");
+
+        assert!(PackagesDelta::read(&b"@@DELTA@@\x1f2\x1e"[..]).is_err());
+        assert!(PackagesDelta::read(&b"@@DEL@@\x1fsetup\x1e"[..]).is_err());
+    }
+}
@@ -0,0 +1,632 @@
+#[cfg(feature = "dnf")]
+use anyhow::{Context, bail};
+use std::collections::{HashMap, HashSet};
+
+use crate::*;
+
+/// Which installed packages the seed packages of [`dependency_closure`]
+/// need.
+#[derive(Debug, Clone, Default)]
+pub struct DependencyClosure<'a> {
+    /// The seed packages and the packages they require, directly or
+    /// transitively, sorted by name.
+    pub required: Vec<&'a Package>,
+    /// The other installed packages, sorted by name. These could be removed
+    /// without breaking the seed packages.
+    pub outside: Vec<&'a Package>,
+    /// Seed names which aren't installed.
+    pub missing: Vec<String>,
+    /// Requirements of packages in the closure which no installed package
+    /// satisfies.
+    pub unsatisfied: Vec<(&'a Package, &'a Dependency)>,
+}
+
+/// Compute the transitive closure of the Requires of the packages named in
+/// `seeds` within the installed `packages`.
+///
+/// Requirements are resolved by name against the Provides of installed
+/// packages and, for file paths, against the files they own. Versions aren't
+/// compared, as the installed set is normally consistent, and `rpmlib(...)`
+/// requirements are skipped. Every package providing a capability is pulled
+/// in, as is every alternative of rich dependencies like `(a or b)`, so the
+/// closure errs on the side of keeping packages.
+///
+/// Only parsed file lists are considered; deferred ones (see
+/// [`ParseOptions::lazy_files`]) must be parsed first.
+pub fn dependency_closure<'a, S: AsRef<str>>(
+    packages: &'a Packages,
+    seeds: &[S],
+) -> DependencyClosure<'a> {
+    let providers = Providers::new(packages);
+    let mut closure = DependencyClosure::default();
+    let mut queue = Vec::new();
+    for seed in seeds {
+        let seed = seed.as_ref();
+        let matching = (providers.packages.iter().enumerate())
+            .filter(|(_, pkg)| pkg.name == seed)
+            .map(|(i, _)| i);
+        let len = queue.len();
+        queue.extend(matching);
+        if queue.len() == len {
+            closure.missing.push(seed.to_string());
+        }
+    }
+    let (included, unsatisfied) = providers.closure(queue);
+    closure.unsatisfied = unsatisfied;
+    for (pkg, included) in providers.packages.iter().zip(included) {
+        if included {
+            closure.required.push(pkg);
+        } else {
+            closure.outside.push(pkg);
+        }
+    }
+    closure.required.sort_by(|a, b| a.name.cmp(&b.name));
+    closure.outside.sort_by(|a, b| a.name.cmp(&b.name));
+    closure
+        .unsatisfied
+        .sort_by(|a, b| (&a.0.name, &a.1.name).cmp(&(&b.0.name, &b.1.name)));
+    closure
+}
+
+/// Why a package was installed, as recorded by dnf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallReason {
+    /// Explicitly installed by the user.
+    User,
+    /// Pulled in as a dependency.
+    Dependency,
+    /// Pulled in as a weak dependency (Recommends or Supplements).
+    WeakDependency,
+    /// Installed as part of a group.
+    Group,
+    /// Kept after the packages depending on it were removed, to be cleaned
+    /// up.
+    Clean,
+    /// Installed outside of dnf, e.g. by rpm directly.
+    External,
+}
+
+impl InstallReason {
+    /// Whether the package only stays installed as long as something needs
+    /// it.
+    pub fn is_dependency(&self) -> bool {
+        matches!(self, Self::Dependency | Self::WeakDependency | Self::Clean)
+    }
+}
+
+/// Install reasons of packages, keyed by name and architecture.
+#[derive(Debug, Clone, Default)]
+pub struct InstallReasons {
+    /// Reason of each architecture of a package, by name.
+    reasons: HashMap<String, Vec<(String, InstallReason)>>,
+}
+
+impl InstallReasons {
+    /// Create an empty set of install reasons.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the install reason of the package `name` of `arch`.
+    pub fn insert(
+        &mut self,
+        name: impl Into<String>,
+        arch: impl Into<String>,
+        reason: InstallReason,
+    ) {
+        let arch = arch.into();
+        let arches = self.reasons.entry(name.into()).or_default();
+        match arches.iter_mut().find(|(a, _)| *a == arch) {
+            Some((_, r)) => *r = reason,
+            None => arches.push((arch, reason)),
+        }
+    }
+
+    /// Get the install reason of `pkg`, if recorded.
+    pub fn get(&self, pkg: &Package) -> Option<InstallReason> {
+        (self.reasons.get(&pkg.name)?.iter())
+            .find(|(arch, _)| *arch == pkg.arch)
+            .map(|(_, reason)| *reason)
+    }
+
+    /// Load the install reasons recorded by dnf5 in `rootfs`, from
+    /// `/usr/lib/sysimage/libdnf5/packages.toml`. The history database of
+    /// dnf4 isn't supported.
+    #[cfg(feature = "dnf")]
+    pub fn load_dnf5(rootfs: &Utf8Path) -> Result<Self> {
+        let path = rootfs.join(DNF5_PACKAGES);
+        let contents = std::fs::read_to_string(&path).with_context(|| format!("reading {path}"))?;
+        Self::parse_dnf5(&contents)
+    }
+
+    /// Parse the contents of dnf5's `packages.toml`.
+    #[cfg(feature = "dnf")]
+    pub fn parse_dnf5(toml: &str) -> Result<Self> {
+        Ok(Self::parse_dnf5_impl(toml).context("parsing dnf5 packages.toml")?)
+    }
+
+    #[cfg(feature = "dnf")]
+    fn parse_dnf5_impl(toml: &str) -> anyhow::Result<Self> {
+        let table: toml::Table = toml.parse()?;
+        let mut reasons = Self::new();
+        let Some(packages) = table.get("packages") else {
+            return Ok(reasons);
+        };
+        let packages = packages
+            .as_table()
+            .ok_or_else(|| anyhow::anyhow!("'packages' isn't a table"))?;
+        for (key, value) in packages {
+            let (name, arch) = key
+                .rsplit_once('.')
+                .ok_or_else(|| anyhow::anyhow!("invalid package key '{key}'"))?;
+            let reason = value.get("reason").and_then(|r| r.as_str());
+            let reason = match reason {
+                Some("User") => InstallReason::User,
+                Some("Dependency") => InstallReason::Dependency,
+                Some("Weak Dependency") => InstallReason::WeakDependency,
+                Some("Group") => InstallReason::Group,
+                Some("Clean") => InstallReason::Clean,
+                Some("External") => InstallReason::External,
+                // Nothing known; as if it wasn't recorded.
+                Some("None") | None => continue,
+                Some(other) => bail!("unknown reason '{other}' for '{key}'"),
+            };
+            reasons.insert(name, arch, reason);
+        }
+        Ok(reasons)
+    }
+}
+
+/// Path of dnf5's package state, relative to the rootfs.
+#[cfg(feature = "dnf")]
+const DNF5_PACKAGES: &str = "usr/lib/sysimage/libdnf5/packages.toml";
+
+/// Find the packages installed as dependencies which nothing installed
+/// otherwise still requires, like `dnf autoremove --assumeno` lists them.
+/// The result is sorted by name.
+///
+/// The packages kept are the closure (see [`dependency_closure`]) of those
+/// not installed as dependencies, including those with no recorded reason.
+/// Weak dependencies of kept packages aren't followed, so weak dependencies
+/// which dnf would keep are reported too.
+pub fn autoremove_candidates<'a>(
+    packages: &'a Packages,
+    reasons: &InstallReasons,
+) -> Vec<&'a Package> {
+    let providers = Providers::new(packages);
+    let is_dependency = |pkg: &Package| reasons.get(pkg).is_some_and(|r| r.is_dependency());
+    let seeds = (providers.packages.iter().enumerate())
+        .filter(|(_, pkg)| !is_dependency(pkg))
+        .map(|(i, _)| i)
+        .collect();
+    let (included, _) = providers.closure(seeds);
+    let mut candidates: Vec<&Package> = (providers.packages.iter().zip(included))
+        .filter(|(pkg, included)| !included && is_dependency(pkg))
+        .map(|(pkg, _)| *pkg)
+        .collect();
+    candidates.sort_by(|a, b| (&a.name, &a.arch).cmp(&(&b.name, &b.arch)));
+    candidates
+}
+
+/// Kind of a weak dependency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeakDependencyKind {
+    /// Installed by default along with the package (`Recommends`).
+    Recommends,
+    /// Only a hint (`Suggests`); not installed by default.
+    Suggests,
+}
+
+/// A weak dependency of an installed package and how it's satisfied.
+#[derive(Debug, Clone)]
+pub struct WeakDependency<'a> {
+    /// The package with the weak dependency.
+    pub package: &'a Package,
+    /// Whether it's a Recommends or a Suggests.
+    pub kind: WeakDependencyKind,
+    /// The dependency.
+    pub dependency: &'a Dependency,
+    /// The installed packages satisfying it, sorted by name.
+    pub providers: Vec<&'a Package>,
+}
+
+/// Whether the weak dependencies of the installed packages are satisfied.
+/// See [`weak_dependencies`].
+#[derive(Debug, Clone, Default)]
+pub struct WeakDependencies<'a> {
+    /// Weak dependencies satisfied by the package they name.
+    pub satisfied: Vec<WeakDependency<'a>>,
+    /// Weak dependencies on a package which isn't installed, but which other
+    /// installed packages satisfy, e.g. `Recommends: foo` satisfied by a
+    /// package providing `foo`.
+    pub other_provider: Vec<WeakDependency<'a>>,
+    /// Weak dependencies no installed package satisfies. Installing with
+    /// weak dependencies would pull in the Recommends among them.
+    pub unsatisfied: Vec<WeakDependency<'a>>,
+}
+
+/// Check which weak dependencies (Recommends and Suggests) of the installed
+/// `packages` are satisfied, and by what.
+///
+/// Dependencies are resolved as for [`dependency_closure`]. One naming a
+/// package (rather than a virtual capability like `font(dejavusans)` or a
+/// file path) is satisfied by default by that package; if only other
+/// packages satisfy it, it's reported in
+/// [`WeakDependencies::other_provider`]. The result is sorted by package
+/// name.
+pub fn weak_dependencies(packages: &Packages) -> WeakDependencies<'_> {
+    let providers = Providers::new(packages);
+    let mut sorted = providers.packages.clone();
+    sorted.sort_by_cached_key(|pkg| (pkg.name.clone(), pkg.nevra()));
+    let mut report = WeakDependencies::default();
+    for pkg in sorted {
+        let deps = (pkg
+            .recommends
+            .iter()
+            .map(|dep| (WeakDependencyKind::Recommends, dep)))
+        .chain(
+            pkg.suggests
+                .iter()
+                .map(|dep| (WeakDependencyKind::Suggests, dep)),
+        );
+        for (kind, dep) in deps {
+            let Some(found) = providers.resolve(dep) else {
+                continue;
+            };
+            let mut found: Vec<&Package> =
+                found.into_iter().map(|i| providers.packages[i]).collect();
+            found.sort_by(|a, b| a.name.cmp(&b.name));
+            // Names of packages the dependency could mean; if it names none,
+            // any provider is as good as another.
+            let mut names = capabilities(&dep.name)
+                .filter(|name| !name.starts_with('/') && !name.contains('('))
+                .peekable();
+            let is_default =
+                names.peek().is_none() || names.any(|name| found.iter().any(|p| p.name == name));
+            let list = if found.is_empty() {
+                &mut report.unsatisfied
+            } else if is_default {
+                &mut report.satisfied
+            } else {
+                &mut report.other_provider
+            };
+            list.push(WeakDependency {
+                package: pkg,
+                kind,
+                dependency: dep,
+                providers: found,
+            });
+        }
+    }
+    report
+}
+
+/// Keywords of rich dependencies, which aren't capabilities.
+const RICH_KEYWORDS: &[&str] = &["and", "or", "if", "else", "with", "without", "unless"];
+
+/// Index of the installed packages providing each capability.
+pub(crate) struct Providers<'a> {
+    /// The installed packages, indexed by the maps below.
+    pub(crate) packages: Vec<&'a Package>,
+    /// Packages providing each capability by name, including the file paths
+    /// anything requires.
+    by_name: HashMap<&'a str, Vec<usize>>,
+}
+
+impl<'a> Providers<'a> {
+    pub(crate) fn new(packages: &'a Packages) -> Self {
+        let packages: Vec<&Package> = packages.values().collect();
+        let mut by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, pkg) in packages.iter().enumerate() {
+            // rpm makes packages provide their own name, but be robust to
+            // headers lacking it.
+            let names = std::iter::once(pkg.name.as_str())
+                .chain(pkg.provides.iter().map(|dep| dep.name.as_str()));
+            for name in names {
+                let providers = by_name.entry(name).or_default();
+                if providers.last() != Some(&i) {
+                    providers.push(i);
+                }
+            }
+        }
+        // Files are implicitly provided, but indexing them all would be
+        // wasteful: only look up the paths something requires.
+        let paths: HashSet<&str> = (packages.iter())
+            .flat_map(|pkg| {
+                pkg.requires
+                    .iter()
+                    .chain(&pkg.recommends)
+                    .chain(&pkg.suggests)
+            })
+            .flat_map(|dep| capabilities(&dep.name))
+            .filter(|name| name.starts_with('/'))
+            .collect();
+        for path in paths {
+            for (i, pkg) in packages.iter().enumerate() {
+                if pkg.file(Utf8Path::new(path)).is_some() {
+                    let providers = by_name.entry(path).or_default();
+                    if !providers.contains(&i) {
+                        providers.push(i);
+                    }
+                }
+            }
+        }
+        Self { packages, by_name }
+    }
+
+    /// Compute which packages the packages at indices `queue` require,
+    /// directly or transitively, including themselves. Also get the
+    /// requirements found unsatisfied along the way.
+    pub(crate) fn closure(
+        &self,
+        mut queue: Vec<usize>,
+    ) -> (Vec<bool>, Vec<(&'a Package, &'a Dependency)>) {
+        let mut included = vec![false; self.packages.len()];
+        for &i in &queue {
+            included[i] = true;
+        }
+        let mut unsatisfied = Vec::new();
+        while let Some(i) = queue.pop() {
+            let pkg = self.packages[i];
+            for dep in &pkg.requires {
+                let Some(found) = self.resolve(dep) else {
+                    continue;
+                };
+                if found.is_empty() {
+                    unsatisfied.push((pkg, dep));
+                }
+                for j in found {
+                    if !std::mem::replace(&mut included[j], true) {
+                        queue.push(j);
+                    }
+                }
+            }
+        }
+        (included, unsatisfied)
+    }
+
+    /// Get the indices of the packages satisfying `dep`, or `None` if it's
+    /// satisfied by rpm itself.
+    pub(crate) fn resolve(&self, dep: &Dependency) -> Option<Vec<usize>> {
+        if dep.flags.is_rpmlib() || dep.name.starts_with("rpmlib(") {
+            return None;
+        }
+        let mut found: Vec<usize> = capabilities(&dep.name)
+            .filter_map(|name| self.by_name.get(name))
+            .flatten()
+            .copied()
+            .collect();
+        found.sort_unstable();
+        found.dedup();
+        Some(found)
+    }
+}
+
+/// Get the capabilities named in a dependency: the dependency itself, or the
+/// operands of a rich dependency.
+pub(crate) fn capabilities(name: &str) -> impl Iterator<Item = &str> {
+    let rich = name.starts_with('(');
+    let mut tokens = name
+        .split(move |c: char| c.is_whitespace() || (rich && (c == '(' || c == ')')))
+        .filter(|token| !token.is_empty());
+    let mut after_operator = false;
+    std::iter::from_fn(move || {
+        for token in tokens.by_ref() {
+            if !rich {
+                return Some(token);
+            }
+            if token.starts_with(['<', '>', '=']) {
+                // The version follows.
+                after_operator = true;
+            } else if std::mem::take(&mut after_operator) || RICH_KEYWORDS.contains(&token) {
+                continue;
+            } else {
+                return Some(token);
+            }
+        }
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FileInfoBuilder, PackageBuilder};
+
+    fn dep(name: &str) -> Dependency {
+        Dependency {
+            name: name.into(),
+            flags: DependencyFlags::default(),
+            version: None,
+        }
+    }
+
+    fn package(name: &str, requires: &[&str], provides: &[&str], files: &[&str]) -> Package {
+        (files.iter())
+            .fold(PackageBuilder::new(name), |pkg, path| {
+                pkg.file(path, FileInfoBuilder::new())
+            })
+            .requires(requires.iter().map(|name| dep(name)))
+            .provides(provides.iter().map(|name| dep(name)))
+            .build()
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let caps = |name| capabilities(name).collect::<Vec<_>>();
+        assert_eq!(caps("libc.so.6()(64bit)"), ["libc.so.6()(64bit)"]);
+        assert_eq!(caps("(foo >= 1.0 or (bar if baz))"), ["foo", "bar", "baz"]);
+        assert_eq!(caps("(foo with foo-libs = 2:1.0-1)"), ["foo", "foo-libs"]);
+    }
+
+    #[test]
+    fn test_dependency_closure() {
+        let mut rpmlib = dep("rpmlib(PayloadIsZstd)");
+        rpmlib.flags = DependencyFlags::from_raw(DependencyFlags::RPMLIB);
+        let mut app = package(
+            "app",
+            &["libfoo.so.1()(64bit)", "/bin/sh", "(theme-a or theme-b)"],
+            &[],
+            &["/usr/bin/app"],
+        );
+        app.requires.push(rpmlib);
+        let packages: Packages = [
+            app,
+            package("foo-libs", &["missing-cap"], &["libfoo.so.1()(64bit)"], &[]),
+            package("bash", &["filesystem"], &[], &["/usr/bin/bash", "/bin/sh"]),
+            package("filesystem", &[], &[], &["/usr"]),
+            package("theme-b", &[], &[], &[]),
+            package("unrelated", &["app"], &[], &[]),
+        ]
+        .into_iter()
+        .map(|pkg| (pkg.name.clone(), pkg))
+        .collect();
+
+        let closure = dependency_closure(&packages, &["app", "nonexistent"]);
+        let names = |pkgs: &[&Package]| pkgs.iter().map(|pkg| pkg.name.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            names(&closure.required),
+            ["app", "bash", "filesystem", "foo-libs", "theme-b"]
+        );
+        assert_eq!(names(&closure.outside), ["unrelated"]);
+        assert_eq!(closure.missing, ["nonexistent"]);
+        assert_eq!(closure.unsatisfied.len(), 1);
+        assert_eq!(closure.unsatisfied[0].0.name, "foo-libs");
+        assert_eq!(closure.unsatisfied[0].1.name, "missing-cap");
+
+        let closure = dependency_closure::<&str>(&packages, &[]);
+        assert!(closure.required.is_empty());
+        assert_eq!(closure.outside.len(), packages.len());
+    }
+
+    #[test]
+    fn test_autoremove_candidates() {
+        let packages: Packages = [
+            package("app", &["libfoo.so.1"], &[], &[]),
+            package("foo-libs", &[], &["libfoo.so.1"], &[]),
+            package("old-libs", &["bar"], &[], &[]),
+            package("bar", &[], &[], &[]),
+            package("manual", &["bar"], &[], &[]),
+            package("leftover", &[], &[], &[]),
+        ]
+        .into_iter()
+        .map(|pkg| (pkg.name.clone(), pkg))
+        .collect();
+        let arch = &packages["app"].arch;
+        let mut reasons = InstallReasons::new();
+        reasons.insert("app", arch, InstallReason::User);
+        reasons.insert("foo-libs", arch, InstallReason::Dependency);
+        reasons.insert("old-libs", arch, InstallReason::Dependency);
+        reasons.insert("bar", arch, InstallReason::WeakDependency);
+        reasons.insert("leftover", arch, InstallReason::Clean);
+        // Another arch of a package doesn't count.
+        reasons.insert("manual", "i686", InstallReason::Dependency);
+        assert_eq!(
+            reasons.get(&packages["foo-libs"]),
+            Some(InstallReason::Dependency)
+        );
+        assert_eq!(reasons.get(&packages["manual"]), None);
+
+        let names: Vec<&str> = autoremove_candidates(&packages, &reasons)
+            .iter()
+            .map(|pkg| pkg.name.as_str())
+            .collect();
+        // bar is still required by manual, which has no recorded reason.
+        assert_eq!(names, ["leftover", "old-libs"]);
+    }
+
+    #[test]
+    fn test_weak_dependencies() {
+        let mut app = package("app", &[], &[], &[]);
+        app.recommends = ["app-docs", "sendmail", "font(dejavusans)", "/usr/bin/sh"]
+            .map(dep)
+            .to_vec();
+        app.suggests = vec![dep("app-extras"), dep("(app-gui if desktop)")];
+        let packages: Packages = [
+            app,
+            package("app-docs", &[], &[], &[]),
+            package("postfix", &[], &["sendmail"], &[]),
+            package("dejavu-sans-fonts", &[], &["font(dejavusans)"], &[]),
+            package("desktop", &[], &[], &[]),
+        ]
+        .into_iter()
+        .map(|pkg| (pkg.name.clone(), pkg))
+        .collect();
+
+        let report = weak_dependencies(&packages);
+        let deps = |deps: &[WeakDependency]| {
+            deps.iter()
+                .map(|d| {
+                    let providers: Vec<&str> =
+                        d.providers.iter().map(|p| p.name.as_str()).collect();
+                    (d.dependency.name.clone(), providers.join(","))
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            deps(&report.satisfied),
+            [
+                ("app-docs".into(), "app-docs".into()),
+                ("font(dejavusans)".into(), "dejavu-sans-fonts".into()),
+                // Rich dependencies are resolved loosely, conditions included.
+                ("(app-gui if desktop)".into(), "desktop".into()),
+            ]
+        );
+        assert_eq!(
+            deps(&report.other_provider),
+            [("sendmail".into(), "postfix".into())]
+        );
+        assert_eq!(
+            report.other_provider[0].kind,
+            WeakDependencyKind::Recommends
+        );
+        assert_eq!(report.unsatisfied.len(), 2);
+        assert_eq!(report.unsatisfied[0].dependency.name, "/usr/bin/sh");
+        assert_eq!(report.unsatisfied[1].dependency.name, "app-extras");
+        assert_eq!(report.unsatisfied[1].kind, WeakDependencyKind::Suggests);
+    }
+
+    #[test]
+    #[cfg(feature = "dnf")]
+    fn test_parse_dnf5() {
+        let toml = r#"
+version = "1.0"
+
+[packages]
+"bash.x86_64" = { reason = "User" }
+"glibc.i686" = { reason = "Dependency" }
+"glibc.x86_64" = { reason = "Weak Dependency" }
+"kernel-core.x86_64" = { reason = "None" }
+
+[packages."python3.12.x86_64"]
+reason = "External"
+"#;
+        let reasons = InstallReasons::parse_dnf5(toml).unwrap();
+        let reason = |name: &str, arch: &str| {
+            (reasons.reasons.get(name)?.iter())
+                .find(|(a, _)| a == arch)
+                .map(|(_, r)| *r)
+        };
+        assert_eq!(reason("bash", "x86_64"), Some(InstallReason::User));
+        assert_eq!(reason("glibc", "i686"), Some(InstallReason::Dependency));
+        assert_eq!(
+            reason("glibc", "x86_64"),
+            Some(InstallReason::WeakDependency)
+        );
+        assert_eq!(reason("kernel-core", "x86_64"), None);
+        assert_eq!(
+            reason("python3.12", "x86_64"),
+            Some(InstallReason::External)
+        );
+
+        assert!(InstallReasons::parse_dnf5("[packages]\n\"bash\" = {}\n").is_err());
+        let toml = "[packages]\n\"bash.x86_64\" = { reason = \"Bogus\" }\n";
+        assert!(InstallReasons::parse_dnf5(toml).is_err());
+        assert!(
+            InstallReasons::parse_dnf5("version = \"1.0\"\n")
+                .unwrap()
+                .reasons
+                .is_empty()
+        );
+    }
+}
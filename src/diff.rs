@@ -0,0 +1,186 @@
+use camino::Utf8Path;
+use std::cmp::Ordering;
+
+use crate::*;
+
+/// How two snapshots of the installed packages differ. See [`diff`].
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PackageDiff<'a> {
+    /// Packages only in the new snapshot, sorted by name.
+    pub added: Vec<&'a Package>,
+    /// Packages only in the old snapshot, sorted by name.
+    pub removed: Vec<&'a Package>,
+    /// Packages with a newer EVR in the new snapshot, as (old, new) pairs
+    /// sorted by name.
+    pub upgraded: Vec<(&'a Package, &'a Package)>,
+    /// Packages with an older EVR in the new snapshot, as (old, new) pairs
+    /// sorted by name.
+    pub downgraded: Vec<(&'a Package, &'a Package)>,
+    /// Packages with the same EVR in both snapshots, from the new one and
+    /// sorted by name.
+    pub unchanged: Vec<&'a Package>,
+    /// File changes of the packages in both snapshots, sorted by package name.
+    /// Packages whose files didn't change are left out.
+    pub files: Vec<FileDiff<'a>>,
+}
+
+impl PackageDiff<'_> {
+    /// Whether the snapshots have the same packages, with the same EVRs and
+    /// files.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.upgraded.is_empty()
+            && self.downgraded.is_empty()
+            && self.files.is_empty()
+    }
+}
+
+/// How the files of a package differ between two snapshots.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FileDiff<'a> {
+    /// Name of the package.
+    pub package: &'a str,
+    /// Files only in the new package, sorted.
+    pub added: Vec<&'a Utf8Path>,
+    /// Files only in the old package, sorted.
+    pub removed: Vec<&'a Utf8Path>,
+    /// Files whose content, type, mode, ownership, capabilities or symlink
+    /// target changed, sorted.
+    pub modified: Vec<&'a Utf8Path>,
+}
+
+impl<'a> FileDiff<'a> {
+    /// Compare the files of two versions of a package. Their file lists must
+    /// have been parsed (see [`ParseOptions::lazy_files`]).
+    pub fn between(old: &'a Package, new: &'a Package) -> Self {
+        let modified = |a: &FileInfo, b: &FileInfo| {
+            a.digest != b.digest
+                || a.mode != b.mode
+                || a.linkto != b.linkto
+                || a.user != b.user
+                || a.group != b.group
+                || a.caps != b.caps
+        };
+        Self {
+            package: &new.name,
+            added: (new.files.keys())
+                .filter(|path| !old.files.contains_key(*path))
+                .map(|path| path.as_path())
+                .collect(),
+            removed: (old.files.keys())
+                .filter(|path| !new.files.contains_key(*path))
+                .map(|path| path.as_path())
+                .collect(),
+            modified: (new.files.iter())
+                .filter(|(path, info)| {
+                    old.files
+                        .get(*path)
+                        .is_some_and(|prev| modified(prev, info))
+                })
+                .map(|(path, _)| path.as_path())
+                .collect(),
+        }
+    }
+
+    /// Whether the package has the same files in both snapshots.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Compare two snapshots of the installed packages, matching packages by key
+/// and comparing their EVRs as rpm does (see [`Package::cmp_evr`]).
+pub fn diff<'a>(old: &'a Packages, new: &'a Packages) -> PackageDiff<'a> {
+    let mut diff = PackageDiff::default();
+    for (key, pkg) in new {
+        let Some(prev) = old.get(key) else {
+            diff.added.push(pkg);
+            continue;
+        };
+        match prev.cmp_evr(pkg) {
+            Ordering::Less => diff.upgraded.push((prev, pkg)),
+            Ordering::Greater => diff.downgraded.push((prev, pkg)),
+            Ordering::Equal => diff.unchanged.push(pkg),
+        }
+        let files = FileDiff::between(prev, pkg);
+        if !files.is_empty() {
+            diff.files.push(files);
+        }
+    }
+    diff.removed = (old.iter())
+        .filter(|(key, _)| !new.contains_key(*key))
+        .map(|(_, pkg)| pkg)
+        .collect();
+    for pkgs in [&mut diff.added, &mut diff.removed, &mut diff.unchanged] {
+        pkgs.sort_by_cached_key(|pkg| (pkg.name.clone(), pkg.nevra()));
+    }
+    for pairs in [&mut diff.upgraded, &mut diff.downgraded] {
+        pairs.sort_by_cached_key(|(_, pkg)| (pkg.name.clone(), pkg.nevra()));
+    }
+    diff.files.sort_by(|a, b| a.package.cmp(b.package));
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let old = load_from_str(include_str!("../tests/fixtures/fedora.qf")).unwrap();
+        let mut new = old.clone();
+        new.remove("rpm");
+        new.get_mut("bash").unwrap().release = "10.fc43".into();
+        // Same as no epoch.
+        new.get_mut("glibc").unwrap().epoch = Some(0);
+        let setup = new.get_mut("setup").unwrap();
+        setup.version = "2.1".into();
+        setup.files.pop_first();
+        let mut foo = old["setup"].clone();
+        foo.name = "foo".into();
+        new.insert("foo".into(), foo);
+
+        let diff = diff(&old, &new);
+        let names = |pkgs: &[&Package]| pkgs.iter().map(|pkg| pkg.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&diff.added), ["foo"]);
+        assert_eq!(names(&diff.removed), ["rpm"]);
+        let upgraded: Vec<&str> = diff.upgraded.iter().map(|(_, p)| p.name.as_str()).collect();
+        assert_eq!(upgraded, ["bash"]);
+        let downgraded: Vec<&str> = (diff.downgraded.iter())
+            .map(|(_, p)| p.name.as_str())
+            .collect();
+        assert_eq!(downgraded, ["setup"]);
+        assert_eq!(diff.unchanged.len(), old.len() - 3);
+        assert!(names(&diff.unchanged).contains(&"glibc".to_string()));
+        assert_eq!(diff.files.len(), 1);
+        assert_eq!(diff.files[0].package, "setup");
+        assert_eq!(diff.files[0].removed.len(), 1);
+        assert!(!diff.is_empty());
+
+        let same = super::diff(&old, &old);
+        assert!(same.is_empty());
+        assert_eq!(same.unchanged.len(), old.len());
+    }
+
+    #[test]
+    fn test_file_diff() {
+        let old =
+            load_from_str(include_str!("../tests/fixtures/fedora.qf")).unwrap()["bash"].clone();
+        let mut new = old.clone();
+        let (path, _) = new.files.pop_last().unwrap();
+        let modified = new.files.keys().next().unwrap().clone();
+        new.files.get_mut(&modified).unwrap().mode ^= 0o100;
+        let added = new.files[&modified].clone();
+        new.files.insert("/usr/bin/newfile".into(), added);
+
+        let diff = FileDiff::between(&old, &new);
+        assert_eq!(diff.package, "bash");
+        assert_eq!(diff.added, ["/usr/bin/newfile"]);
+        assert_eq!(diff.removed, [path.as_path()]);
+        assert_eq!(diff.modified, [modified.as_path()]);
+        assert!(FileDiff::between(&old, &old).is_empty());
+    }
+}
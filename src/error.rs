@@ -0,0 +1,227 @@
+/// Errors returned by this crate.
+///
+/// Failures which don't fit any of the specific variants are wrapped in
+/// [`Error::Other`]. `Error` implements [`std::error::Error`], so it converts
+/// into an [`anyhow::Error`] with `?` as usual.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The `rpm` binary could not be found, i.e. rpm isn't installed or isn't
+    /// on `PATH`.
+    #[error("rpm not found (tried {}); is rpm installed and on PATH?", .path.display())]
+    RpmNotFound {
+        /// The program we tried to run.
+        path: std::path::PathBuf,
+    },
+    /// `rpm` exited unsuccessfully or was killed by a signal.
+    #[error("rpm command failed ({}){}", exit_reason(*.code, *.signal), stderr_suffix(.stderr))]
+    RpmFailed {
+        /// Exit code, or `None` if `rpm` was killed by a signal.
+        code: Option<i32>,
+        /// The signal which killed `rpm`, if any.
+        signal: Option<i32>,
+        /// What `rpm` printed to stderr.
+        stderr: String,
+    },
+    /// The rpmdb is locked by another process, e.g. an ongoing transaction.
+    /// Retrying later may succeed.
+    #[error("rpm database is locked{}", stderr_suffix(.stderr))]
+    DbLocked {
+        /// What `rpm` printed to stderr.
+        stderr: String,
+    },
+    /// The rpmdb appears to be corrupted. Unlike [`Error::DbLocked`],
+    /// retrying won't help.
+    #[error("rpm database is corrupted{}; {remediation}", stderr_suffix(.stderr))]
+    DbCorrupt {
+        /// What `rpm` printed to stderr, or what's wrong with the rpmdb when
+        /// reading it without rpm (see `load_from_rpmdb`).
+        stderr: String,
+        /// Suggested fix.
+        remediation: &'static str,
+    },
+    /// The queryformat output could not be parsed.
+    #[error("line {line}: {message}")]
+    Parse {
+        /// Line (or record) number, 1-based, in the queryformat output.
+        line: usize,
+        /// What went wrong.
+        message: String,
+    },
+    /// The queryformat output ended in the middle of a package, e.g. because
+    /// rpm was killed or a pipe broke.
+    #[error("truncated input{}", match .last_complete {
+        Some(name) => format!(" after package '{name}'"),
+        None => String::new(),
+    })]
+    Truncated {
+        /// Name of the last package which was read completely, if any.
+        last_complete: Option<String>,
+    },
+    /// Several packages share a name. See [`crate::DuplicatePolicy`].
+    #[error("duplicate package name '{0}'")]
+    DuplicatePackage(String),
+    /// The `rpm` binary is too old (or otherwise unsuitable) to query.
+    #[error("unsupported rpm version: {0}")]
+    UnsupportedRpmVersion(String),
+    /// Packages use file digest algorithms rejected by a
+    /// [`crate::DigestPolicy`], e.g. MD5 in FIPS mode.
+    #[error("packages with digests not approved for FIPS: {}", .0.join(", "))]
+    WeakDigest(Vec<String>),
+    /// Reading or writing failed, e.g. reading queryformat output from a
+    /// reader, or rpm's output from its pipe.
+    #[error(transparent)]
+    Io(std::io::Error),
+    /// An error of this crate with context added to it, e.g. which rootfs
+    /// was being loaded. See [`Error::inner`] to match on its kind.
+    #[error("{context}: {error}")]
+    Context {
+        /// What was being done, outermost first.
+        context: String,
+        /// The underlying error.
+        error: Box<Error>,
+    },
+    /// Any other error.
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl Error {
+    /// The underlying error, without any [`Error::Context`] around it.
+    pub fn inner(&self) -> &Error {
+        match self {
+            Error::Context { error, .. } => error.inner(),
+            e => e,
+        }
+    }
+}
+
+/// A `Result` defaulting to this crate's [`Error`].
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl From<anyhow::Error> for Error {
+    /// Errors of this crate passed through `anyhow` are unwrapped back to
+    /// their original variant, and I/O errors become [`Error::Io`]. Context
+    /// added on the way is kept with [`Error::Context`]. Anything else
+    /// becomes [`Error::Other`].
+    fn from(e: anyhow::Error) -> Self {
+        let context: Vec<String> = (e.chain())
+            .take_while(|cause| !cause.is::<Error>() && !cause.is::<std::io::Error>())
+            .map(|cause| cause.to_string())
+            .collect();
+        let error = match e.downcast::<Error>() {
+            Ok(error) => error,
+            Err(e) => match e.downcast::<std::io::Error>() {
+                Ok(error) => Error::from(error),
+                Err(e) => return Error::Other(e),
+            },
+        };
+        if context.is_empty() {
+            return error;
+        }
+        Error::Context {
+            context: context.join(": "),
+            error: Box::new(error),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    /// Errors of this crate passed through I/O (e.g. by a reader decoding an
+    /// rpmdb) are unwrapped back to their original variant; anything else
+    /// becomes [`Error::Io`].
+    fn from(e: std::io::Error) -> Self {
+        e.downcast::<Error>().unwrap_or_else(Error::Io)
+    }
+}
+
+/// How to recover from a corrupted rpmdb.
+#[cfg(any(feature = "exec", feature = "native-db"))]
+pub(crate) const DB_CORRUPT_REMEDIATION: &str =
+    "try rebuilding it with `rpm --rebuilddb` (passing --root for a rootfs)";
+
+fn exit_reason(code: Option<i32>, signal: Option<i32>) -> String {
+    match (code, signal) {
+        (Some(code), _) => format!("exit code {code}"),
+        (None, Some(signal)) => format!("killed by signal {signal}"),
+        (None, None) => "killed by signal".to_string(),
+    }
+}
+
+fn stderr_suffix(stderr: &str) -> String {
+    match stderr.trim() {
+        "" => String::new(),
+        s => format!(": {s}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anyhow_roundtrip() {
+        let e = Error::DbLocked {
+            stderr: "error: database is locked\n".into(),
+        };
+        assert_eq!(
+            e.to_string(),
+            "rpm database is locked: error: database is locked"
+        );
+        let e = anyhow::Error::from(e)
+            .context("loading rootfs")
+            .context("loading packages");
+        let e = Error::from(e);
+        assert!(matches!(e.inner(), Error::DbLocked { .. }));
+        assert_eq!(
+            e.to_string(),
+            "loading packages: loading rootfs: rpm database is locked: error: database is locked"
+        );
+        // Context added again is kept too.
+        let e = Error::from(anyhow::Error::from(e).context("serving"));
+        assert!(e.to_string().starts_with("serving: loading packages: "));
+        assert!(matches!(e.inner(), Error::DbLocked { .. }));
+
+        let e = Error::from(anyhow::anyhow!("boom"));
+        assert!(matches!(e, Error::Other(_)));
+        assert_eq!(e.to_string(), "boom");
+
+        let e = Error::RpmFailed {
+            code: Some(1),
+            signal: None,
+            stderr: String::new(),
+        };
+        assert_eq!(e.to_string(), "rpm command failed (exit code 1)");
+        let e = Error::RpmFailed {
+            code: None,
+            signal: Some(9),
+            stderr: String::new(),
+        };
+        assert_eq!(e.to_string(), "rpm command failed (killed by signal 9)");
+    }
+
+    #[test]
+    fn test_io_roundtrip() {
+        let io = || std::io::Error::new(std::io::ErrorKind::BrokenPipe, "pipe closed");
+        let e = Error::from(io());
+        assert!(matches!(&e, Error::Io(e) if e.kind() == std::io::ErrorKind::BrokenPipe));
+        assert!(matches!(
+            Error::from(anyhow::Error::from(io())),
+            Error::Io(_)
+        ));
+        // Context isn't dropped.
+        let e = Error::from(anyhow::Error::from(io()).context("reading rpmdb"));
+        assert!(matches!(e.inner(), Error::Io(_)));
+        assert_eq!(e.to_string(), "reading rpmdb: pipe closed");
+        let e = Error::DbCorrupt {
+            stderr: String::new(),
+            remediation: "rebuild it",
+        };
+        let e = Error::from(anyhow::Error::from(std::io::Error::other(e)).context("reading rpmdb"));
+        assert!(matches!(e.inner(), Error::DbCorrupt { .. }));
+        assert!(e.to_string().starts_with("reading rpmdb: "));
+
+        let e = std::io::Error::other(Error::DuplicatePackage("foo".into()));
+        assert!(matches!(Error::from(e), Error::DuplicatePackage(_)));
+    }
+}
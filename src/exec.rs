@@ -0,0 +1,190 @@
+use anyhow::Context;
+use cap_std_ext::cap_std::fs::Dir;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::*;
+
+/// Note the host `rpm` resolves `%_dbpath` from its own macro context, not the
+/// target rootfs's. We probe the rootfs for [`RPMDB_PATHS`] to find where the
+/// rpmdb actually is and pass `--dbpath` explicitly to avoid mismatches (e.g.
+/// Fedora host reading a RHEL 9 rootfs).
+pub(crate) fn find_dbpath(rootfs: &Path) -> Result<Option<&'static str>> {
+    for dbpath in RPMDB_PATHS {
+        if std::fs::exists(rootfs.join(dbpath)).context("failed to probe rpmdb path")? {
+            return Ok(Some(dbpath));
+        }
+    }
+    Ok(None)
+}
+
+/// Like [`find_dbpath`], but probing through a directory fd.
+pub(crate) fn find_dbpath_dir(rootfs: &Dir) -> Result<Option<&'static str>> {
+    for dbpath in RPMDB_PATHS {
+        if rootfs
+            .try_exists(dbpath)
+            .context("failed to probe rpmdb path")?
+        {
+            return Ok(Some(dbpath));
+        }
+    }
+    Ok(None)
+}
+
+/// Get the queryformat to use with the `rpm` run by `query`.
+///
+/// Older rpm versions don't know about some of the tags we query and fail
+/// with an error on them, so we check which tags are supported and reduce
+/// the queryformat accordingly, leaving the corresponding fields absent. The
+/// result is computed once per process for each rpm executable.
+pub(crate) fn queryformat_for(query: &QueryBuilder) -> Result<&'static str> {
+    static QUERYFORMATS: std::sync::Mutex<BTreeMap<PathBuf, &'static str>> =
+        std::sync::Mutex::new(BTreeMap::new());
+    if let Some(qf) = QUERYFORMATS.lock().unwrap().get(query.program()) {
+        return Ok(qf);
+    }
+    let mut cmd = query.bare_command();
+    cmd.arg("--querytags");
+    let tags = rpm_output(cmd).context("querying supported rpm tags")?;
+    let tags: std::collections::HashSet<&str> = tags.lines().map(str::trim).collect();
+    let qf = parse::queryformat_for_tags(|tag| tags.contains(tag))?;
+    // Only a handful of rpm executables are ever used, so leaking one string
+    // each is fine.
+    let mut queryformats = QUERYFORMATS.lock().unwrap();
+    let qf = (queryformats.entry(query.program().to_path_buf()))
+        .or_insert_with(|| Box::leak(qf.into_boxed_str()));
+    Ok(qf)
+}
+
+/// Run an `rpm` query whose output is in our queryformat and parse it.
+pub(crate) fn run_rpm_query(cmd: Command, options: &ParseOptions) -> Result<Packages> {
+    run_rpm_query_with(cmd, |stdout| load_from_reader_with_options(stdout, options))
+}
+
+/// Run an `rpm` command, parsing its stdout with `parse` as it's produced
+/// rather than buffering it all first: on huge rpmdbs the raw dump is much
+/// larger than the parsed result.
+pub(crate) fn run_rpm_query_with<T>(
+    mut cmd: Command,
+    parse: impl FnOnce(std::process::ChildStdout) -> Result<T>,
+) -> Result<T> {
+    cmd.stdout(std::process::Stdio::piped());
+    cmd.stderr(std::process::Stdio::piped());
+    debug!("running {cmd:?}");
+    let mut child = cmd.spawn().map_err(|e| spawn_error(&cmd, e))?;
+    let stdout = child
+        .stdout
+        .take()
+        .context("failed to capture rpm stdout")?;
+    let mut stderr = child
+        .stderr
+        .take()
+        .context("failed to capture rpm stderr")?;
+
+    // Drain stderr while parsing so rpm can't block on it.
+    let (result, stderr) = std::thread::scope(|s| {
+        let stderr = s.spawn(move || {
+            let mut buf = Vec::new();
+            let _ = stderr.read_to_end(&mut buf);
+            String::from_utf8_lossy(&buf).into_owned()
+        });
+        let result = parse(stdout);
+        if result.is_err() {
+            // Don't wait for rpm to notice that its output isn't read anymore.
+            let _ = child.kill();
+        }
+        (result, stderr.join().unwrap())
+    });
+
+    let status = child.wait().context("failed to wait for rpm")?;
+    check_rpm_query(status, stderr, result)
+}
+
+/// Check how rpm exited, once its output was parsed into `result`.
+///
+/// When parsing stops early, e.g. at [`ParseOptions::max_packages`], rpm is
+/// killed or dies of SIGPIPE, and the parse error is what matters. Only
+/// failures of rpm itself, which may have cut its output short, are reported
+/// over it.
+pub(crate) fn check_rpm_query<T>(
+    status: std::process::ExitStatus,
+    stderr: String,
+    result: Result<T>,
+) -> Result<T> {
+    use std::os::unix::process::ExitStatusExt;
+    // How shells exit when a command dies of SIGPIPE (13).
+    const SIGPIPE_EXIT_CODE: i32 = 128 + 13;
+    if result.is_err() && (status.signal().is_some() || status.code() == Some(SIGPIPE_EXIT_CODE)) {
+        return result;
+    }
+    check_rpm_status(status, stderr)?;
+    result
+}
+
+/// Run an `rpm` command and return its stdout.
+pub(crate) fn rpm_output(mut cmd: Command) -> Result<String> {
+    debug!("running {cmd:?}");
+    let output = cmd.output().map_err(|e| spawn_error(&cmd, e))?;
+    check_rpm_status(
+        output.status,
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )?;
+    Ok(String::from_utf8(output.stdout).context("rpm output is not UTF-8")?)
+}
+
+/// Convert an error spawning `rpm`.
+pub(crate) fn spawn_error(cmd: &Command, e: std::io::Error) -> Error {
+    let program = Path::new(cmd.get_program());
+    if e.kind() == std::io::ErrorKind::NotFound {
+        Error::RpmNotFound {
+            path: program.to_path_buf(),
+        }
+    } else {
+        anyhow::Error::new(e)
+            .context(format!("failed to run {}", program.display()))
+            .into()
+    }
+}
+
+/// Messages from rpm which indicate that the rpmdb is locked, across the
+/// sqlite, ndb and bdb backends.
+const DB_LOCKED_MESSAGES: &[&str] = &[
+    "database is locked",
+    "transaction lock",
+    "Resource temporarily unavailable",
+];
+
+/// Messages from rpm which indicate that the rpmdb is corrupted, across the
+/// sqlite and bdb backends.
+const DB_CORRUPT_MESSAGES: &[&str] = &[
+    "Thread died in Berkeley DB library",
+    "DB_RUNRECOVERY",
+    "cannot open Packages index",
+    "database disk image is malformed",
+    "file is not a database",
+];
+
+pub(crate) fn check_rpm_status(status: std::process::ExitStatus, stderr: String) -> Result<()> {
+    if status.success() {
+        for line in stderr.lines().filter(|l| !l.trim().is_empty()) {
+            warn!("rpm: {line}");
+        }
+        return Ok(());
+    }
+    if DB_CORRUPT_MESSAGES.iter().any(|m| stderr.contains(m)) {
+        return Err(Error::DbCorrupt {
+            stderr,
+            remediation: error::DB_CORRUPT_REMEDIATION,
+        });
+    }
+    if DB_LOCKED_MESSAGES.iter().any(|m| stderr.contains(m)) {
+        return Err(Error::DbLocked { stderr });
+    }
+    Err(Error::RpmFailed {
+        code: status.code(),
+        signal: std::os::unix::process::ExitStatusExt::signal(&status),
+        stderr,
+    })
+}
@@ -0,0 +1,408 @@
+//! C API, see `include/rpm_qa.h`.
+//!
+//! A loaded snapshot is converted upfront into NUL-terminated strings so that
+//! accessors can hand out borrowed pointers which stay valid until the
+//! snapshot is freed. Packages are ordered by key and files by path.
+
+use anyhow::Context;
+use std::ffi::{CStr, CString, c_char};
+use std::fs::File;
+use std::io::BufReader;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::ptr;
+
+use crate::*;
+
+/// A loaded snapshot of installed packages.
+pub struct RpmQaPackages {
+    packages: Vec<RpmQaPackage>,
+}
+
+/// A package of an [`RpmQaPackages`].
+pub struct RpmQaPackage {
+    name: CString,
+    version: CString,
+    release: CString,
+    arch: CString,
+    nevra: CString,
+    license: CString,
+    sourcerpm: Option<CString>,
+    epoch: Option<u32>,
+    size: u64,
+    buildtime: u64,
+    installtime: u64,
+    files: Vec<RpmQaFile>,
+}
+
+/// A file of an [`RpmQaPackage`].
+pub struct RpmQaFile {
+    path: CString,
+    user: CString,
+    group: CString,
+    linkto: Option<CString>,
+    digest: Option<CString>,
+    size: u64,
+    mode: u16,
+    mtime: u64,
+    flags: u32,
+}
+
+fn cstring(s: &str) -> anyhow::Result<CString> {
+    CString::new(s).with_context(|| format!("NUL byte in '{}'", s.escape_debug()))
+}
+
+impl RpmQaPackages {
+    fn convert(packages: Packages) -> anyhow::Result<Self> {
+        let mut sorted: Vec<(String, Package)> = packages.into_iter().collect();
+        sorted.sort_by(|a, b| a.0.cmp(&b.0));
+        let packages = sorted
+            .into_iter()
+            .map(|(_, mut pkg)| {
+                pkg.parse_files()?;
+                let files = pkg
+                    .files
+                    .iter()
+                    .map(|(path, info)| {
+                        Ok(RpmQaFile {
+                            path: cstring(path.as_str())?,
+                            user: cstring(&info.user)?,
+                            group: cstring(&info.group)?,
+                            linkto: info
+                                .linkto
+                                .as_ref()
+                                .map(|l| cstring(l.as_str()))
+                                .transpose()?,
+                            digest: info.digest.map(|d| cstring(&d.to_hex())).transpose()?,
+                            size: info.size,
+                            mode: info.mode,
+                            mtime: info.mtime,
+                            flags: info.flags.raw(),
+                        })
+                    })
+                    .collect::<anyhow::Result<_>>()
+                    .with_context(|| format!("converting files of '{}'", pkg.nevra()))?;
+                Ok(RpmQaPackage {
+                    name: cstring(&pkg.name)?,
+                    version: cstring(&pkg.version)?,
+                    release: cstring(&pkg.release)?,
+                    arch: cstring(&pkg.arch)?,
+                    nevra: cstring(&pkg.nevra())?,
+                    license: cstring(&pkg.license)?,
+                    sourcerpm: pkg.sourcerpm.as_deref().map(cstring).transpose()?,
+                    epoch: pkg.epoch,
+                    size: pkg.size,
+                    buildtime: pkg.buildtime,
+                    installtime: pkg.installtime,
+                    files,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { packages })
+    }
+}
+
+/// Run a loading function, converting its result to a snapshot pointer and
+/// reporting failures (including panics) through `error`.
+///
+/// # Safety
+///
+/// `error` must be NULL or valid for writes.
+unsafe fn load(
+    error: *mut *mut c_char,
+    f: impl FnOnce() -> anyhow::Result<Packages>,
+) -> *mut RpmQaPackages {
+    let result = catch_unwind(AssertUnwindSafe(|| f().and_then(RpmQaPackages::convert)))
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("panicked while loading packages")));
+    match result {
+        Ok(packages) => Box::into_raw(Box::new(packages)),
+        Err(e) => {
+            if !error.is_null() {
+                let message = CString::new(format!("{e:#}").replace('\0', "\\0"))
+                    .expect("NUL bytes were escaped");
+                // SAFETY: The caller guarantees `error` is valid for writes.
+                unsafe { *error = message.into_raw() };
+            }
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Read a C string argument as UTF-8.
+///
+/// # Safety
+///
+/// `s` must be a valid NUL-terminated string.
+unsafe fn str_arg<'a>(s: *const c_char, what: &str) -> anyhow::Result<&'a str> {
+    anyhow::ensure!(!s.is_null(), "{what} is NULL");
+    // SAFETY: The caller guarantees `s` is a valid C string.
+    let s = unsafe { CStr::from_ptr(s) };
+    s.to_str().with_context(|| format!("{what} isn't UTF-8"))
+}
+
+fn opt_ptr(s: &Option<CString>) -> *const c_char {
+    s.as_ref().map_or(ptr::null(), |s| s.as_ptr())
+}
+
+/// Load the installed packages of `rootfs` by running rpm. Returns NULL on
+/// failure, setting `*error` (if `error` isn't NULL) to a message to be freed
+/// with [`rpm_qa_string_free`].
+///
+/// Not available if the library was built without the `exec` feature.
+///
+/// # Safety
+///
+/// `rootfs` must be a valid C string and `error` NULL or valid for writes.
+#[cfg(feature = "exec")]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rpm_qa_load_from_rootfs(
+    rootfs: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut RpmQaPackages {
+    // SAFETY: Forwarded from the caller.
+    unsafe {
+        load(error, || {
+            let rootfs = str_arg(rootfs, "rootfs")?;
+            Ok(load_from_rootfs(Utf8Path::new(rootfs))?)
+        })
+    }
+}
+
+/// Load packages from a file containing queryformat output. Errors are
+/// reported as for `rpm_qa_load_from_rootfs`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string and `error` NULL or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rpm_qa_load_from_file(
+    path: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut RpmQaPackages {
+    // SAFETY: Forwarded from the caller.
+    unsafe {
+        load(error, || {
+            let path = str_arg(path, "path")?;
+            let file = File::open(path).with_context(|| format!("opening {path}"))?;
+            Ok(load_from_reader(BufReader::new(file))?)
+        })
+    }
+}
+
+/// Free a snapshot. Pointers obtained from it become invalid.
+///
+/// # Safety
+///
+/// `packages` must be NULL or a snapshot which wasn't freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rpm_qa_packages_free(packages: *mut RpmQaPackages) {
+    if !packages.is_null() {
+        // SAFETY: The caller guarantees this came from Box::into_raw.
+        drop(unsafe { Box::from_raw(packages) });
+    }
+}
+
+/// Free an error message.
+///
+/// # Safety
+///
+/// `s` must be NULL or a string returned by this library which wasn't freed
+/// yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rpm_qa_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        // SAFETY: The caller guarantees this came from CString::into_raw.
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
+
+/// Number of packages in a snapshot.
+///
+/// # Safety
+///
+/// `packages` must be a valid snapshot.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rpm_qa_packages_len(packages: *const RpmQaPackages) -> usize {
+    // SAFETY: The caller guarantees the pointer is valid.
+    unsafe { &*packages }.packages.len()
+}
+
+/// Get the package at `index`, or NULL if out of range.
+///
+/// # Safety
+///
+/// `packages` must be a valid snapshot.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rpm_qa_packages_get(
+    packages: *const RpmQaPackages,
+    index: usize,
+) -> *const RpmQaPackage {
+    // SAFETY: The caller guarantees the pointer is valid.
+    let packages = unsafe { &*packages };
+    packages
+        .packages
+        .get(index)
+        .map_or(ptr::null(), ptr::from_ref)
+}
+
+/// Get the first package named `name`, or NULL if there's none.
+///
+/// # Safety
+///
+/// `packages` must be a valid snapshot and `name` a valid C string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rpm_qa_packages_find(
+    packages: *const RpmQaPackages,
+    name: *const c_char,
+) -> *const RpmQaPackage {
+    // SAFETY: The caller guarantees both pointers are valid.
+    let (packages, name) = unsafe { (&*packages, CStr::from_ptr(name)) };
+    packages
+        .packages
+        .iter()
+        .find(|pkg| pkg.name.as_c_str() == name)
+        .map_or(ptr::null(), ptr::from_ref)
+}
+
+macro_rules! accessors {
+    ($type:ty { $($(#[$doc:meta])* $fn:ident($self:ident) -> $ret:ty $body:block)* }) => {
+        $(
+            $(#[$doc])*
+            ///
+            /// # Safety
+            ///
+            /// The pointer must be valid, i.e. come from a snapshot which
+            /// wasn't freed yet.
+            #[unsafe(no_mangle)]
+            pub unsafe extern "C" fn $fn($self: *const $type) -> $ret {
+                // SAFETY: The caller guarantees the pointer is valid.
+                let $self = unsafe { &*$self };
+                $body
+            }
+        )*
+    };
+}
+
+accessors!(RpmQaPackage {
+    /// Package name.
+    rpm_qa_package_name(pkg) -> *const c_char { pkg.name.as_ptr() }
+    /// Package version.
+    rpm_qa_package_version(pkg) -> *const c_char { pkg.version.as_ptr() }
+    /// Package release.
+    rpm_qa_package_release(pkg) -> *const c_char { pkg.release.as_ptr() }
+    /// Package architecture.
+    rpm_qa_package_arch(pkg) -> *const c_char { pkg.arch.as_ptr() }
+    /// Package `name-[epoch:]version-release.arch`.
+    rpm_qa_package_nevra(pkg) -> *const c_char { pkg.nevra.as_ptr() }
+    /// Package license.
+    rpm_qa_package_license(pkg) -> *const c_char { pkg.license.as_ptr() }
+    /// Source rpm file name, or NULL.
+    rpm_qa_package_sourcerpm(pkg) -> *const c_char { opt_ptr(&pkg.sourcerpm) }
+    /// Package epoch, or -1 if it has none.
+    rpm_qa_package_epoch(pkg) -> i64 { pkg.epoch.map_or(-1, i64::from) }
+    /// Installed size.
+    rpm_qa_package_size(pkg) -> u64 { pkg.size }
+    /// Unix timestamp of the package build.
+    rpm_qa_package_buildtime(pkg) -> u64 { pkg.buildtime }
+    /// Unix timestamp of the package installation.
+    rpm_qa_package_installtime(pkg) -> u64 { pkg.installtime }
+    /// Number of files in the package.
+    rpm_qa_package_files_len(pkg) -> usize { pkg.files.len() }
+});
+
+/// Get the file at `index` of a package, or NULL if out of range.
+///
+/// # Safety
+///
+/// `pkg` must be a valid package.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rpm_qa_package_file(
+    pkg: *const RpmQaPackage,
+    index: usize,
+) -> *const RpmQaFile {
+    // SAFETY: The caller guarantees the pointer is valid.
+    let pkg = unsafe { &*pkg };
+    pkg.files.get(index).map_or(ptr::null(), ptr::from_ref)
+}
+
+accessors!(RpmQaFile {
+    /// File path.
+    rpm_qa_file_path(file) -> *const c_char { file.path.as_ptr() }
+    /// Owner user name.
+    rpm_qa_file_user(file) -> *const c_char { file.user.as_ptr() }
+    /// Owner group name.
+    rpm_qa_file_group(file) -> *const c_char { file.group.as_ptr() }
+    /// Symlink target, or NULL if not a symlink.
+    rpm_qa_file_linkto(file) -> *const c_char { opt_ptr(&file.linkto) }
+    /// Hex digest, or NULL if the file has none.
+    rpm_qa_file_digest(file) -> *const c_char { opt_ptr(&file.digest) }
+    /// File size.
+    rpm_qa_file_size(file) -> u64 { file.size }
+    /// Unix file mode.
+    rpm_qa_file_mode(file) -> u16 { file.mode }
+    /// Unix modification timestamp.
+    rpm_qa_file_mtime(file) -> u64 { file.mtime }
+    /// Raw file attribute flags (`RPMFILE_*`).
+    rpm_qa_file_flags(file) -> u32 { file.flags }
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_str<'a>(s: *const c_char) -> &'a str {
+        assert!(!s.is_null());
+        unsafe { CStr::from_ptr(s) }.to_str().unwrap()
+    }
+
+    #[test]
+    fn test_ffi() {
+        let path = c"tests/fixtures/fedora.qf";
+        let mut error = ptr::null_mut();
+        unsafe {
+            let packages = rpm_qa_load_from_file(path.as_ptr(), &mut error);
+            assert!(error.is_null());
+            assert!(!packages.is_null());
+            let len = rpm_qa_packages_len(packages);
+            assert_eq!(
+                len,
+                load_from_str(include_str!("../tests/fixtures/fedora.qf"))
+                    .unwrap()
+                    .len()
+            );
+            assert_eq!(
+                to_str(rpm_qa_package_name(rpm_qa_packages_get(packages, 0))),
+                "bash"
+            );
+            assert!(rpm_qa_packages_get(packages, len).is_null());
+
+            let bash = rpm_qa_packages_find(packages, c"bash".as_ptr());
+            assert_eq!(
+                to_str(rpm_qa_package_nevra(bash)),
+                "bash-5.3.0-2.fc43.x86_64"
+            );
+            assert_eq!(rpm_qa_package_epoch(bash), -1);
+            assert_eq!(
+                to_str(rpm_qa_package_sourcerpm(bash)),
+                "bash-5.3.0-2.fc43.src.rpm"
+            );
+            let nfiles = rpm_qa_package_files_len(bash);
+            assert!(nfiles > 0);
+            let file = rpm_qa_package_file(bash, 0);
+            assert_eq!(to_str(rpm_qa_file_path(file)), "/etc/skel/.bash_logout");
+            assert_eq!(rpm_qa_file_size(file), 18);
+            assert_eq!(to_str(rpm_qa_file_user(file)), "root");
+            assert_eq!(to_str(rpm_qa_file_digest(file)).len(), 64);
+            assert!(rpm_qa_file_linkto(file).is_null());
+            assert!(rpm_qa_package_file(bash, nfiles).is_null());
+            assert!(rpm_qa_packages_find(packages, c"nonexistent".as_ptr()).is_null());
+            rpm_qa_packages_free(packages);
+
+            let packages = rpm_qa_load_from_file(c"/nonexistent".as_ptr(), &mut error);
+            assert!(packages.is_null());
+            assert!(to_str(error).contains("/nonexistent"));
+            rpm_qa_string_free(error);
+            let packages = rpm_qa_load_from_file(ptr::null(), ptr::null_mut());
+            assert!(packages.is_null());
+        }
+    }
+}
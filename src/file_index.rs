@@ -0,0 +1,116 @@
+use camino::Utf8Path;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use crate::*;
+
+/// An index from file paths to the packages owning them, like `rpm -qf`.
+///
+/// Unlike [`PathIndex`](crate::PathIndex) (with the `path-index` feature),
+/// this borrows the packages and so is cheap to build, but takes memory
+/// proportional to the number of files. Paths are ordered component-wise, so
+/// the files under a directory can be looked up too.
+#[derive(Debug, Clone, Default)]
+pub struct FileIndex<'a> {
+    owners: BTreeMap<&'a Utf8Path, Vec<&'a Package>>,
+}
+
+impl<'a> FileIndex<'a> {
+    /// Build an index over all the parsed files of `packages`. Deferred file
+    /// lists (see [`ParseOptions::lazy_files`]) must be parsed first.
+    pub fn new(packages: &'a Packages) -> Self {
+        let mut owners: BTreeMap<&Utf8Path, Vec<&Package>> = BTreeMap::new();
+        for pkg in packages.values() {
+            for path in pkg.files.keys() {
+                owners.entry(path).or_default().push(pkg);
+            }
+        }
+        for pkgs in owners.values_mut() {
+            pkgs.sort_by_cached_key(|pkg| (pkg.name.clone(), pkg.nevra()));
+        }
+        Self { owners }
+    }
+
+    /// Number of distinct paths in the index.
+    pub fn len(&self) -> usize {
+        self.owners.len()
+    }
+
+    /// Whether the index contains no paths.
+    pub fn is_empty(&self) -> bool {
+        self.owners.is_empty()
+    }
+
+    /// Get the packages owning `path`, sorted by name, like
+    /// [`PathIndex::owners_of`](crate::PathIndex::owners_of) gets their names.
+    /// Returns an empty slice if no package owns it. `path` is normalized as
+    /// for [`Package::file`].
+    pub fn owners_of(&self, path: &Utf8Path) -> &[&'a Package] {
+        let path = parse::normalize_path(path.as_str());
+        self.owners
+            .get(Utf8Path::new(&*path))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Iterate over `dir` and the paths under it, in order, with the packages
+    /// owning each. `dir` is normalized as for [`Package::file`].
+    pub fn files_under(
+        &self,
+        dir: &Utf8Path,
+    ) -> impl Iterator<Item = (&'a Utf8Path, &[&'a Package])> + '_ {
+        let dir = Utf8Path::new(&*parse::normalize_path(dir.as_str())).to_owned();
+        let start = (Bound::Included(dir.as_path()), Bound::Unbounded);
+        (self.owners.range::<Utf8Path, _>(start))
+            .take_while(move |(path, _)| path.starts_with(&dir))
+            .map(|(path, pkgs)| (*path, pkgs.as_slice()))
+    }
+
+    /// Get the packages owning `dir` or anything under it, sorted by name.
+    pub fn owners_of_prefix(&self, dir: &Utf8Path) -> Vec<&'a Package> {
+        let mut owners: Vec<&Package> = (self.files_under(dir))
+            .flat_map(|(_, pkgs)| pkgs.iter().copied())
+            .collect();
+        owners.sort_by_cached_key(|pkg| (pkg.name.clone(), pkg.nevra()));
+        owners.dedup_by(|a, b| std::ptr::eq(*a, *b));
+        owners
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_index() {
+        let packages = load_from_str(include_str!("../tests/fixtures/fedora.qf")).unwrap();
+        let index = FileIndex::new(&packages);
+        assert_eq!(
+            index.len(),
+            packages
+                .files()
+                .map(|(_, path, _)| path)
+                .collect::<std::collections::HashSet<_>>()
+                .len()
+        );
+
+        let names = |pkgs: &[&Package]| pkgs.iter().map(|pkg| pkg.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(index.owners_of("/usr/bin/bash".into())), ["bash"]);
+        assert_eq!(names(index.owners_of("/usr/bin//bash/".into())), ["bash"]);
+        assert!(index.owners_of("/nonexistent".into()).is_empty());
+
+        let under: Vec<&Utf8Path> = index
+            .files_under("/etc/skel".into())
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(under.len(), 3);
+        assert!(
+            under
+                .iter()
+                .all(|path| path.parent() == Some("/etc/skel".into()))
+        );
+        assert_eq!(names(&index.owners_of_prefix("/etc/skel".into())), ["bash"]);
+        // Prefixes match whole components.
+        assert!(index.owners_of_prefix("/etc/sk".into()).is_empty());
+        assert!(index.owners_of_prefix("/usr".into()).len() > 1);
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::Context;
+
+use crate::*;
+
+/// Where the kernel reports whether FIPS mode is enabled.
+const FIPS_ENABLED_PATH: &str = "/proc/sys/crypto/fips_enabled";
+
+/// What to do about packages whose file digests use an algorithm not
+/// approved for FIPS 140 (e.g. MD5 or SHA-1), when exporting them (e.g.
+/// with `Sbom::digest_policy`) or checking them with
+/// [`DigestPolicy::check`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DigestPolicy {
+    /// Accept them.
+    #[default]
+    Allow,
+    /// Accept them, but report them.
+    Flag,
+    /// Fail with [`Error::WeakDigest`].
+    Reject,
+}
+
+impl DigestPolicy {
+    /// Get the policy for this host: [`DigestPolicy::Reject`] if it's in FIPS
+    /// mode (see [`fips_enabled`]), [`DigestPolicy::Allow`] otherwise.
+    pub fn from_host() -> Result<Self> {
+        Ok(if fips_enabled()? {
+            Self::Reject
+        } else {
+            Self::Allow
+        })
+    }
+
+    /// Apply the policy to `packages`, returning the packages to report,
+    /// i.e. those found by [`weak_digests`] if flagging them, or none.
+    pub fn check<'a>(&self, packages: &'a Packages) -> Result<Vec<&'a Package>> {
+        match self {
+            Self::Allow => Ok(Vec::new()),
+            Self::Flag => Ok(weak_digests(packages)),
+            Self::Reject => match &weak_digests(packages)[..] {
+                [] => Ok(Vec::new()),
+                weak => Err(Error::WeakDigest(
+                    weak.iter().map(|pkg| pkg.nevra()).collect(),
+                )),
+            },
+        }
+    }
+}
+
+/// Whether the host is in FIPS mode, per the kernel. Kernels without FIPS
+/// support are never in FIPS mode.
+pub fn fips_enabled() -> Result<bool> {
+    Ok(parse_fips_enabled(FIPS_ENABLED_PATH)?)
+}
+
+fn parse_fips_enabled(path: &str) -> anyhow::Result<bool> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Ok(s.trim() != "0"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("reading {path}")),
+    }
+}
+
+/// Find the packages whose file digests use an algorithm not approved for
+/// FIPS 140 (see [`DigestAlgorithm::is_fips_approved`]), sorted by name.
+/// Packages without a digest algorithm predate rpm recording it and use MD5.
+/// Packages without any file digests (e.g. with only directories) are left
+/// out.
+pub fn weak_digests(packages: &Packages) -> Vec<&Package> {
+    let mut weak: Vec<&Package> = packages.values().filter(|pkg| is_weak(pkg)).collect();
+    weak.sort_by_cached_key(|pkg| (pkg.name.clone(), pkg.nevra()));
+    weak
+}
+
+/// Whether a package has file digests using an algorithm not approved for
+/// FIPS 140. See [`weak_digests`].
+pub(crate) fn is_weak(pkg: &Package) -> bool {
+    !digest_algo(pkg).is_fips_approved()
+        && (pkg.has_unparsed_files() || pkg.files.values().any(|info| info.digest.is_some()))
+}
+
+/// Get the digest algorithm of a package's files.
+pub(crate) fn digest_algo(pkg: &Package) -> DigestAlgorithm {
+    pkg.digest_algo.unwrap_or(DigestAlgorithm::Md5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packages() -> Packages {
+        let mut packages = load_from_str(include_str!("../tests/fixtures/fedora.qf")).unwrap();
+        let mut md5 = packages["bash"].clone();
+        md5.name = "old".into();
+        md5.digest_algo = None;
+        let mut sha1 = packages["bash"].clone();
+        sha1.name = "older".into();
+        sha1.digest_algo = Some(DigestAlgorithm::Sha1);
+        let mut empty = packages["bash"].clone();
+        empty.name = "empty".into();
+        empty.digest_algo = Some(DigestAlgorithm::Md5);
+        empty.files.clear();
+        for pkg in [md5, sha1, empty] {
+            packages.insert(pkg.name.clone(), pkg);
+        }
+        packages
+    }
+
+    #[test]
+    fn test_weak_digests() {
+        let mut packages = packages();
+        let names: Vec<&str> = (weak_digests(&packages).iter())
+            .map(|pkg| pkg.name.as_str())
+            .collect();
+        assert_eq!(names, ["old", "older"]);
+
+        assert!(DigestPolicy::Allow.check(&packages).unwrap().is_empty());
+        assert_eq!(DigestPolicy::Flag.check(&packages).unwrap().len(), 2);
+        let err = DigestPolicy::Reject.check(&packages).unwrap_err();
+        assert!(matches!(&err, Error::WeakDigest(nevras) if nevras.len() == 2));
+        assert!(
+            err.to_string()
+                .starts_with("packages with digests not approved for FIPS: old-")
+        );
+
+        packages.retain(|_, pkg| pkg.digest_algo.is_some_and(|a| a.is_fips_approved()));
+        assert!(DigestPolicy::Reject.check(&packages).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fips_enabled() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("fips_enabled");
+        let path = path.to_str().unwrap();
+        assert!(!parse_fips_enabled(path).unwrap());
+        std::fs::write(path, "0\n").unwrap();
+        assert!(!parse_fips_enabled(path).unwrap());
+        std::fs::write(path, "1\n").unwrap();
+        assert!(parse_fips_enabled(path).unwrap());
+    }
+}
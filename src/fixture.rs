@@ -0,0 +1,160 @@
+use anyhow::Context;
+use std::collections::HashSet;
+use std::io::Write;
+
+use crate::parse::Delimiters;
+use crate::*;
+
+/// Options for [`capture_fixture`].
+#[derive(Debug, Clone, Default)]
+pub struct FixtureOptions {
+    packages: Option<HashSet<String>>,
+    scrub: Vec<(String, String)>,
+}
+
+impl FixtureOptions {
+    /// Create options capturing every package as is.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only capture the named packages.
+    pub fn packages<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.packages = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Replace every occurrence of `needle` (e.g. a hostname or username
+    /// leaking into file paths or releases of locally built packages) with
+    /// `replacement`. Occurrences are replaced anywhere in the output, so
+    /// `needle` should be distinctive enough not to match unrelated text.
+    pub fn scrub(mut self, needle: impl Into<String>, replacement: impl Into<String>) -> Self {
+        self.scrub.push((needle.into(), replacement.into()));
+        self
+    }
+}
+
+pub(crate) fn capture_fixture_impl<W: Write>(
+    rootfs: &Utf8Path,
+    options: &FixtureOptions,
+    writer: W,
+) -> anyhow::Result<()> {
+    for (needle, replacement) in &options.scrub {
+        if needle.is_empty() {
+            anyhow::bail!("empty scrub pattern");
+        }
+        if replacement.contains(['\t', '\n', '\x1e', '\x1f']) {
+            anyhow::bail!("scrub replacement for '{needle}' contains a delimiter");
+        }
+    }
+    let mut dump = Vec::new();
+    dump_from_rootfs(rootfs, &mut dump)?;
+    write_fixture(&dump, options, writer)
+}
+
+/// Write the packages of queryformat output selected by `options`, scrubbed.
+fn write_fixture<W: Write>(
+    dump: &[u8],
+    options: &FixtureOptions,
+    mut writer: W,
+) -> anyhow::Result<()> {
+    let delimiters = Delimiters::detect(dump);
+    let record = delimiters.record();
+    let field = delimiters.field() as u8;
+
+    // Split the output into packages: each starts with a FMT or PKG record,
+    // unless the FMT record of the same package precedes it.
+    let mut flush = |chunk: &[u8], name: Option<&[u8]>| -> anyhow::Result<()> {
+        let keep = match (&options.packages, name) {
+            (Some(names), Some(name)) => std::str::from_utf8(name).is_ok_and(|n| names.contains(n)),
+            _ => true,
+        };
+        if keep {
+            writer
+                .write_all(&scrub(chunk, &options.scrub))
+                .context("writing fixture")?;
+        }
+        Ok(())
+    };
+    let (mut start, mut name) = (0, None);
+    let mut pos = 0;
+    while pos < dump.len() {
+        let end = dump[pos..]
+            .iter()
+            .position(|&b| b == record)
+            .map_or(dump.len(), |i| pos + i + 1);
+        let rec = &dump[pos..end];
+        let is_pkg = rec.starts_with(b"@@PKG@@");
+        if (is_pkg || rec.starts_with(b"@@FMT@@")) && name.is_some() {
+            flush(&dump[start..pos], name)?;
+            (start, name) = (pos, None);
+        }
+        if is_pkg {
+            name = rec.split(|&b| b == field).nth(1);
+        }
+        pos = end;
+    }
+    flush(&dump[start..], name)?;
+    Ok(())
+}
+
+fn scrub(chunk: &[u8], patterns: &[(String, String)]) -> Vec<u8> {
+    let mut out = chunk.to_vec();
+    for (needle, replacement) in patterns {
+        let needle = needle.as_bytes();
+        let mut scrubbed = Vec::with_capacity(out.len());
+        let mut rest = out.as_slice();
+        while let Some(i) = rest.windows(needle.len()).position(|w| w == needle) {
+            scrubbed.extend_from_slice(&rest[..i]);
+            scrubbed.extend_from_slice(replacement.as_bytes());
+            rest = &rest[i + needle.len()..];
+        }
+        scrubbed.extend_from_slice(rest);
+        out = scrubbed;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(input: &str, options: &FixtureOptions) -> String {
+        let mut out = Vec::new();
+        write_fixture(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_fixture() {
+        for input in [
+            include_str!("../tests/fixtures/fedora.qf"),
+            include_str!("../tests/fixtures/multiline.qf"),
+        ] {
+            assert_eq!(fixture(input, &FixtureOptions::new()), input);
+        }
+
+        let input = include_str!("../tests/fixtures/fedora.qf");
+        let options = FixtureOptions::new()
+            .packages(["bash", "setup"])
+            .scrub("fc43", "fcXX");
+        let packages = load_from_str(&fixture(input, &options)).unwrap();
+        let mut names: Vec<&str> = packages.keys().map(|n| n.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["bash", "setup"]);
+        assert_eq!(packages["bash"].release, "2.fcXX");
+        assert_eq!(
+            packages["bash"].files.len(),
+            load_from_str(input).unwrap()["bash"].files.len()
+        );
+
+        let input = include_str!("../tests/fixtures/multiline.qf");
+        let options = FixtureOptions::new().packages(["exotic"]);
+        let packages = load_from_str(&fixture(input, &options)).unwrap();
+        assert_eq!(packages.keys().collect::<Vec<_>>(), ["exotic"]);
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::Context;
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::*;
+
+/// Where the rpmdb may be in a runtime's files, which are mounted on `/usr`.
+const RUNTIME_RPMDB_PATHS: &[&str] = &["lib/sysimage/rpm", "share/rpm"];
+
+/// An installed Flatpak runtime, e.g. `org.fedoraproject.Platform`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlatpakRuntime {
+    /// Runtime ID.
+    pub id: String,
+    /// Architecture.
+    pub arch: String,
+    /// Branch, e.g. `f43`.
+    pub branch: String,
+    /// Path to the runtime's files, i.e. what's mounted on `/usr`.
+    pub path: Utf8PathBuf,
+}
+
+impl FlatpakRuntime {
+    /// Load the runtime's installed packages. Returns `None` if the runtime
+    /// has no rpmdb, e.g. because it wasn't built from RPMs.
+    pub fn load(&self) -> Result<Option<Packages>> {
+        let Some(dbpath) = RUNTIME_RPMDB_PATHS
+            .iter()
+            .find(|dbpath| self.path.join(dbpath).is_dir())
+        else {
+            return Ok(None);
+        };
+        (QueryBuilder::new().root(&self.path))
+            .found_dbpath(Some(dbpath))
+            .load()
+            .map(Some)
+    }
+}
+
+/// Find the active runtimes of a Flatpak installation, e.g.
+/// `/var/lib/flatpak` or `~/.local/share/flatpak`, sorted by ID, arch and
+/// branch.
+pub fn find_flatpak_runtimes(installation: &Utf8Path) -> Result<Vec<FlatpakRuntime>> {
+    let mut runtimes = Vec::new();
+    let dir = installation.join("runtime");
+    for id in subdirs(&dir)? {
+        for arch in subdirs(&dir.join(&id))? {
+            for branch in subdirs(&dir.join(&id).join(&arch))? {
+                let path = dir.join(&id).join(&arch).join(&branch).join("active/files");
+                if path.is_dir() {
+                    runtimes.push(FlatpakRuntime {
+                        id: id.clone(),
+                        arch: arch.clone(),
+                        branch,
+                        path,
+                    });
+                }
+            }
+        }
+    }
+    Ok(runtimes)
+}
+
+/// Load the packages of each runtime of a Flatpak installation which has an
+/// rpmdb. See [`find_flatpak_runtimes`].
+pub fn load_flatpak_runtimes(installation: &Utf8Path) -> Result<Vec<(FlatpakRuntime, Packages)>> {
+    let mut loaded = Vec::new();
+    for runtime in find_flatpak_runtimes(installation)? {
+        let packages = runtime
+            .load()
+            .map_err(anyhow::Error::from)
+            .with_context(|| {
+                format!(
+                    "loading runtime {}/{}/{}",
+                    runtime.id, runtime.arch, runtime.branch
+                )
+            })?;
+        if let Some(packages) = packages {
+            loaded.push((runtime, packages));
+        }
+    }
+    Ok(loaded)
+}
+
+/// List the names of the subdirectories of `dir`, sorted. A missing `dir` has
+/// none.
+fn subdirs(dir: &Utf8Path) -> Result<Vec<String>> {
+    let entries = match dir.read_dir_utf8() {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        r => r.with_context(|| format!("reading {dir}"))?,
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|| format!("reading {dir}"))?;
+        if entry
+            .file_type()
+            .with_context(|| format!("reading {dir}"))?
+            .is_dir()
+        {
+            names.push(entry.file_name().to_string());
+        }
+    }
+    names.sort_unstable();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flatpak_runtimes() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let installation = Utf8Path::from_path(tmpdir.path()).unwrap();
+        assert!(find_flatpak_runtimes(installation).unwrap().is_empty());
+
+        let runtime = installation.join("runtime/org.fedoraproject.Platform/x86_64/f43");
+        let files = runtime.join("active/files");
+        std::fs::create_dir_all(files.join("lib/sysimage/rpm")).unwrap();
+        std::fs::copy(
+            "tests/fixtures/rpmdb.sqlite",
+            files.join("lib/sysimage/rpm/rpmdb.sqlite"),
+        )
+        .unwrap();
+        // Not built from RPMs.
+        let other = installation.join("runtime/org.freedesktop.Platform/x86_64/24.08/active/files");
+        std::fs::create_dir_all(&other).unwrap();
+        // Not deployed.
+        std::fs::create_dir_all(installation.join("runtime/org.gnome.Platform/x86_64/48")).unwrap();
+
+        let runtimes = find_flatpak_runtimes(installation).unwrap();
+        assert_eq!(runtimes.len(), 2);
+        assert_eq!(runtimes[0].id, "org.fedoraproject.Platform");
+        assert_eq!(runtimes[0].branch, "f43");
+        assert_eq!(runtimes[0].path, files);
+        assert!(runtimes[1].load().unwrap().is_none());
+
+        let loaded = load_flatpak_runtimes(installation).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].0, runtimes[0]);
+        assert!(loaded[0].1.contains_key("setup"));
+    }
+}
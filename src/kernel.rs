@@ -0,0 +1,244 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::*;
+
+/// Directories of kernel module trees, each holding a directory per kernel
+/// release. Packages list them under either, depending on whether they were
+/// built before or after the move of `/lib` to `/usr/lib`.
+const MODULES_DIRS: &[&str] = &["/lib/modules", "/usr/lib/modules"];
+
+/// An installed kernel. See [`kernel_inventory`].
+#[derive(Debug, Clone)]
+pub struct Kernel<'a> {
+    /// Kernel release, as in `uname -r` and the module tree under
+    /// `/lib/modules`, e.g. `6.11.4-301.fc41.x86_64` or
+    /// `6.11.4-301.fc41.x86_64+debug`.
+    pub release: String,
+    /// The package shipping the kernel image, e.g. `kernel-core` or
+    /// `kernel-debug-core`.
+    pub core: &'a Package,
+    /// The packages of this kernel built alongside the image, e.g. `kernel`,
+    /// `kernel-modules` and `kernel-devel`, sorted by name. This includes
+    /// `core`.
+    pub packages: Vec<&'a Package>,
+    /// Other packages with files in the module tree, e.g. out-of-tree kmods,
+    /// sorted by name.
+    pub module_packages: Vec<&'a Package>,
+}
+
+/// The installed kernels, newest first. See [`kernel_inventory`].
+#[derive(Debug, Clone, Default)]
+pub struct KernelInventory<'a> {
+    /// The kernels, newest first by the EVR of their core package.
+    pub kernels: Vec<Kernel<'a>>,
+}
+
+impl<'a> KernelInventory<'a> {
+    /// Get the newest kernel. Several variants of it (e.g. `+debug`) may be
+    /// installed, in which case the one without a variant is preferred.
+    pub fn newest(&self) -> Option<&Kernel<'a>> {
+        self.kernels.first()
+    }
+
+    /// Look up a kernel by release.
+    pub fn kernel(&self, release: &str) -> Option<&Kernel<'a>> {
+        self.kernels.iter().find(|k| k.release == release)
+    }
+}
+
+/// Find the installed kernels, with the packages making them up and the
+/// packages owning their module trees.
+///
+/// Kernels are identified by their module trees (`/lib/modules/<release>`)
+/// with a `vmlinuz` image in them. Several versions of a kernel are usually
+/// installed, so `packages` should be loaded with [`DuplicatePolicy::Merge`]
+/// for all of them to be seen.
+pub fn kernel_inventory<'a>(packages: &'a Packages) -> KernelInventory<'a> {
+    // Owners of each module tree, by release.
+    let mut trees: BTreeMap<&str, Vec<&Package>> = BTreeMap::new();
+    let mut cores: Vec<(&str, &Package)> = Vec::new();
+    for pkg in packages.values() {
+        let mut releases = HashSet::new();
+        for path in pkg.files.keys() {
+            let Some((release, rest)) = modules_path(path) else {
+                continue;
+            };
+            if releases.insert(release) {
+                trees.entry(release).or_default().push(pkg);
+            }
+            if rest == "vmlinuz" {
+                cores.push((release, pkg));
+            }
+        }
+    }
+
+    // Variants (e.g. `debug`) are suffixed to the release of their kernels
+    // and follow the base name in the names of their packages, e.g.
+    // `kernel-debug-devel`.
+    let variants: HashSet<&str> = (cores.iter())
+        .filter_map(|(release, _)| Some(release.split_once('+')?.1))
+        .collect();
+    let name_variant = |name: &'a str| -> Option<&'a str> {
+        let variant = name.split('-').nth(1)?;
+        variants.contains(variant).then_some(variant)
+    };
+
+    let mut inventory = KernelInventory::default();
+    for &(release, core) in &cores {
+        let variant = release.split_once('+').map(|(_, variant)| variant);
+        let mut kernel_packages: Vec<&Package> = (packages.values())
+            .filter(|pkg| {
+                pkg.sourcerpm == core.sourcerpm
+                    && pkg.version == core.version
+                    && pkg.release == core.release
+                    && pkg.arch == core.arch
+                    && name_variant(&pkg.name) == variant
+            })
+            .collect();
+        kernel_packages.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut module_packages: Vec<&Package> = (trees.get(release).into_iter().flatten())
+            .filter(|pkg| !kernel_packages.iter().any(|k| std::ptr::eq(*k, **pkg)))
+            .copied()
+            .collect();
+        module_packages.sort_by(|a, b| a.name.cmp(&b.name));
+        inventory.kernels.push(Kernel {
+            release: release.to_string(),
+            core,
+            packages: kernel_packages,
+            module_packages,
+        });
+    }
+    inventory.kernels.sort_by(|a, b| {
+        (b.core.cmp_evr(a.core))
+            .then_with(|| a.release.contains('+').cmp(&b.release.contains('+')))
+            .then_with(|| a.release.cmp(&b.release))
+    });
+    inventory
+}
+
+/// Split a path in a module tree into the kernel release and the path
+/// within the tree.
+fn modules_path(path: &Utf8Path) -> Option<(&str, &str)> {
+    let rest = MODULES_DIRS
+        .iter()
+        .find_map(|dir| path.as_str().strip_prefix(dir)?.strip_prefix('/'))?;
+    Some(rest.split_once('/').unwrap_or((rest, "")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FileInfoBuilder, PackageBuilder};
+
+    fn package(name: &str, version: &str, files: &[&str]) -> Package {
+        (files.iter())
+            .fold(PackageBuilder::new(name), |pkg, path| {
+                pkg.file(path, FileInfoBuilder::new())
+            })
+            .version(version)
+            .release("300.fc41")
+            .sourcerpm(Some(format!("kernel-{version}-300.fc41.src.rpm")))
+            .build()
+    }
+
+    #[test]
+    fn test_modules_path() {
+        let path = |p| modules_path(Utf8Path::new(p));
+        assert_eq!(
+            path("/lib/modules/6.1-1.x86_64/vmlinuz"),
+            Some(("6.1-1.x86_64", "vmlinuz"))
+        );
+        assert_eq!(
+            path("/usr/lib/modules/6.1-1.x86_64/kernel/fs/a.ko.xz"),
+            Some(("6.1-1.x86_64", "kernel/fs/a.ko.xz"))
+        );
+        assert_eq!(
+            path("/lib/modules/6.1-1.x86_64"),
+            Some(("6.1-1.x86_64", ""))
+        );
+        assert_eq!(path("/lib/modules"), None);
+        assert_eq!(path("/lib/modules-load.d/foo.conf"), None);
+    }
+
+    #[test]
+    fn test_kernel_inventory() {
+        let old = "6.10.12-300.fc41.x86_64";
+        let new = "6.11.4-300.fc41.x86_64";
+        let debug = "6.11.4-300.fc41.x86_64+debug";
+        let mut kmod = package(
+            "kmod-nvidia-6.11.4-300.fc41.x86_64",
+            "560.35.03",
+            &["/usr/lib/modules/6.11.4-300.fc41.x86_64/extra/nvidia.ko"],
+        );
+        kmod.sourcerpm = Some("nvidia-kmod-560.35.03-1.fc41.src.rpm".into());
+        let pkgs = [
+            package("kernel", "6.10.12", &[]),
+            package(
+                "kernel-core",
+                "6.10.12",
+                &[&format!("/lib/modules/{old}/vmlinuz")],
+            ),
+            package("kernel", "6.11.4", &[]),
+            package(
+                "kernel-core",
+                "6.11.4",
+                &[
+                    &format!("/usr/lib/modules/{new}"),
+                    &format!("/usr/lib/modules/{new}/vmlinuz"),
+                ],
+            ),
+            package(
+                "kernel-modules",
+                "6.11.4",
+                &[&format!("/usr/lib/modules/{new}/kernel/fs/a.ko.xz")],
+            ),
+            package(
+                "kernel-devel",
+                "6.11.4",
+                &[&format!("/usr/src/kernels/{new}/Makefile")],
+            ),
+            package(
+                "kernel-debug-core",
+                "6.11.4",
+                &[&format!("/usr/lib/modules/{debug}/vmlinuz")],
+            ),
+            package("kernel-debug-devel", "6.11.4", &[]),
+            kmod,
+        ];
+        let packages: Packages = pkgs.into_iter().map(|pkg| (pkg.nevra(), pkg)).collect();
+
+        let inventory = kernel_inventory(&packages);
+        let releases: Vec<&str> = inventory
+            .kernels
+            .iter()
+            .map(|k| k.release.as_str())
+            .collect();
+        assert_eq!(releases, [new, debug, old]);
+        let names = |pkgs: &[&Package]| pkgs.iter().map(|pkg| pkg.name.clone()).collect::<Vec<_>>();
+
+        let newest = inventory.newest().unwrap();
+        assert_eq!(newest.release, new);
+        assert_eq!(newest.core.name, "kernel-core");
+        assert_eq!(
+            names(&newest.packages),
+            ["kernel", "kernel-core", "kernel-devel", "kernel-modules"]
+        );
+        assert_eq!(
+            names(&newest.module_packages),
+            ["kmod-nvidia-6.11.4-300.fc41.x86_64"]
+        );
+
+        let debug = inventory.kernel(debug).unwrap();
+        assert_eq!(
+            names(&debug.packages),
+            ["kernel-debug-core", "kernel-debug-devel"]
+        );
+        assert!(debug.module_packages.is_empty());
+
+        let old = inventory.kernel(old).unwrap();
+        assert_eq!(old.core.version, "6.10.12");
+        assert_eq!(names(&old.packages), ["kernel", "kernel-core"]);
+
+        assert!(kernel_inventory(&Packages::default()).newest().is_none());
+    }
+}
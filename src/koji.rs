@@ -0,0 +1,557 @@
+use anyhow::{Context, anyhow, bail};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::*;
+
+/// Where a package was built, as known to Koji (or Brew). See
+/// [`KojiClient::enrich`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+    /// Koji build ID.
+    pub build_id: u64,
+    /// NVR of the build, i.e. of its source RPM.
+    pub nvr: String,
+    /// URL of the build's page.
+    pub build_url: String,
+    /// ID of the task which produced the build, if any (imported builds
+    /// don't have one).
+    pub task_id: Option<u64>,
+    /// URL of the task's page, if any.
+    pub task_url: Option<String>,
+    /// Source the build was made from, e.g.
+    /// `git+https://src.fedoraproject.org/rpms/bash.git#<commit>`.
+    pub source: Option<String>,
+}
+
+/// A package whose build couldn't be looked up. See [`KojiClient::enrich`].
+#[derive(Debug)]
+pub struct LookupError {
+    /// Key of the package.
+    pub package: String,
+    /// NVR of the build which was looked up.
+    pub nvr: String,
+    /// The underlying error, e.g. a fault returned by the hub.
+    pub error: Error,
+}
+
+impl std::fmt::Display for LookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: looking up build {}: {}",
+            self.package, self.nvr, self.error
+        )
+    }
+}
+
+/// A client for the XML-RPC API of a Koji hub.
+#[derive(Debug, Clone)]
+pub struct KojiClient {
+    hub_url: String,
+    web_url: String,
+    agent: ureq::Agent,
+    batch_size: usize,
+}
+
+/// Default timeout for connecting to the hub and for receiving a response.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Default number of builds looked up per request by [`KojiClient::enrich`].
+const DEFAULT_BATCH_SIZE: usize = 100;
+
+impl KojiClient {
+    /// Create a client for the hub at `hub_url` (e.g.
+    /// `https://koji.fedoraproject.org/kojihub`), whose web interface (used
+    /// for URLs) is at `web_url` (e.g. `https://koji.fedoraproject.org/koji`).
+    pub fn new(hub_url: impl Into<String>, web_url: impl Into<String>) -> Self {
+        Self {
+            hub_url: hub_url.into(),
+            web_url: web_url.into().trim_end_matches('/').to_string(),
+            agent: agent(DEFAULT_TIMEOUT),
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    /// Set the timeout for connecting to the hub, and separately for
+    /// receiving each response. Defaults to a minute.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.agent = agent(timeout);
+        self
+    }
+
+    /// Set how many builds [`KojiClient::enrich`] looks up per request to
+    /// the hub. Defaults to 100.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Create a client for the Fedora Koji instance.
+    pub fn fedora() -> Self {
+        Self::new(
+            "https://koji.fedoraproject.org/kojihub",
+            "https://koji.fedoraproject.org/koji",
+        )
+    }
+
+    /// Look up the build with the given NVR. Returns `None` if there is no
+    /// such build.
+    pub fn get_build(&self, nvr: &str) -> Result<Option<BuildInfo>> {
+        Ok(self
+            .impl_get_build(nvr)
+            .with_context(|| format!("looking up build {nvr}"))?)
+    }
+
+    fn impl_get_build(&self, nvr: &str) -> anyhow::Result<Option<BuildInfo>> {
+        let value = self.call("getBuild", &[Value::String(nvr.into())])?;
+        parse_build(&value, &self.web_url)
+    }
+
+    /// Look up the builds of `packages`, by the NVR of their source RPM.
+    /// Returns the builds by package key; packages without a source RPM or
+    /// whose build isn't known to the hub are left out.
+    ///
+    /// Builds are looked up in batches with a single `multiCall` request
+    /// each (see [`KojiClient::batch_size`]), and each build only once,
+    /// however many packages it produced. Lookups which fail, whether the
+    /// hub returned a fault for them or their batch couldn't be sent, don't
+    /// stop the others: they are returned as errors for each package of the
+    /// build.
+    pub fn enrich(
+        &self,
+        packages: &Packages,
+    ) -> Result<(HashMap<String, BuildInfo>, Vec<LookupError>)> {
+        let mut by_nvr: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (key, pkg) in packages {
+            if let Some(nvr) = pkg.sourcerpm.as_deref().and_then(source_nvr) {
+                by_nvr.entry(nvr).or_default().push(key);
+            }
+        }
+        let mut nvrs: Vec<&str> = by_nvr.keys().copied().collect();
+        nvrs.sort_unstable();
+
+        let mut enriched = HashMap::new();
+        let mut errors = Vec::new();
+        for batch in nvrs.chunks(self.batch_size) {
+            let results = match self.get_builds(batch) {
+                Ok(results) => results,
+                Err(e) => batch.iter().map(|_| Err(anyhow!("{e:#}"))).collect(),
+            };
+            for (nvr, result) in batch.iter().zip(results) {
+                for &key in &by_nvr[nvr] {
+                    match &result {
+                        Ok(Some(build)) => {
+                            enriched.insert(key.to_string(), build.clone());
+                        }
+                        Ok(None) => {}
+                        Err(e) => errors.push(LookupError {
+                            package: key.to_string(),
+                            nvr: nvr.to_string(),
+                            error: Error::Other(anyhow!("{e:#}")),
+                        }),
+                    }
+                }
+            }
+        }
+        errors.sort_by(|a, b| a.package.cmp(&b.package));
+        Ok((enriched, errors))
+    }
+
+    /// Look up several builds with a single `multiCall`, returning the result
+    /// of each lookup in order.
+    fn get_builds(&self, nvrs: &[&str]) -> anyhow::Result<Vec<anyhow::Result<Option<BuildInfo>>>> {
+        let calls = (nvrs.iter())
+            .map(|nvr| {
+                Value::Struct(HashMap::from([
+                    ("methodName".to_string(), Value::String("getBuild".into())),
+                    (
+                        "params".to_string(),
+                        Value::Array(vec![Value::String(nvr.to_string())]),
+                    ),
+                ]))
+            })
+            .collect();
+        let Value::Array(results) = self.call("multiCall", &[Value::Array(calls)])? else {
+            bail!("unexpected multiCall response");
+        };
+        if results.len() != nvrs.len() {
+            bail!(
+                "multiCall returned {} results for {} calls",
+                results.len(),
+                nvrs.len()
+            );
+        }
+        Ok((results.iter())
+            .map(|result| match result {
+                // Successful calls return their value wrapped in an array.
+                Value::Array(value) if value.len() == 1 => parse_build(&value[0], &self.web_url),
+                fault => Err(anyhow!("fault: {}", fault_string(fault))),
+            })
+            .collect())
+    }
+
+    /// Call `method` on the hub.
+    fn call(&self, method: &str, params: &[Value]) -> anyhow::Result<Value> {
+        let body = self
+            .agent
+            .post(&self.hub_url)
+            .header("Content-Type", "text/xml")
+            .send(method_call(method, params))?
+            .body_mut()
+            .read_to_string()?;
+        parse_response(&body)
+    }
+}
+
+/// Create an agent which times out connecting and receiving responses after
+/// `timeout` each.
+fn agent(timeout: Duration) -> ureq::Agent {
+    ureq::Agent::config_builder()
+        .timeout_connect(Some(timeout))
+        .timeout_recv_response(Some(timeout))
+        .timeout_recv_body(Some(timeout))
+        .build()
+        .into()
+}
+
+/// Get the NVR of a source RPM from its file name.
+fn source_nvr(sourcerpm: &str) -> Option<&str> {
+    sourcerpm
+        .strip_suffix(".src.rpm")
+        .or_else(|| sourcerpm.strip_suffix(".nosrc.rpm"))
+}
+
+/// An XML-RPC value.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    String(String),
+    Array(Vec<Value>),
+    Struct(HashMap<String, Value>),
+}
+
+/// Build an XML-RPC call of `method`.
+fn method_call(method: &str, params: &[Value]) -> String {
+    let mut xml =
+        format!("<?xml version=\"1.0\"?>\n<methodCall><methodName>{method}</methodName><params>");
+    for param in params {
+        xml.push_str("<param>");
+        write_value(&mut xml, param);
+        xml.push_str("</param>");
+    }
+    xml.push_str("</params></methodCall>");
+    xml
+}
+
+fn write_value(xml: &mut String, value: &Value) {
+    xml.push_str("<value>");
+    match value {
+        Value::Nil => xml.push_str("<nil/>"),
+        Value::Bool(b) => xml.push_str(&format!("<boolean>{}</boolean>", u8::from(*b))),
+        Value::Int(i) => xml.push_str(&format!("<int>{i}</int>")),
+        Value::Double(d) => xml.push_str(&format!("<double>{d}</double>")),
+        Value::String(s) => xml.push_str(&format!("<string>{}</string>", escape(s))),
+        Value::Array(values) => {
+            xml.push_str("<array><data>");
+            for value in values {
+                write_value(xml, value);
+            }
+            xml.push_str("</data></array>");
+        }
+        Value::Struct(members) => {
+            xml.push_str("<struct>");
+            for (name, value) in members {
+                xml.push_str(&format!("<member><name>{}</name>", escape(name)));
+                write_value(xml, value);
+                xml.push_str("</member>");
+            }
+            xml.push_str("</struct>");
+        }
+    }
+    xml.push_str("</value>");
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Get the message of a fault.
+fn fault_string(fault: &Value) -> String {
+    match fault {
+        Value::Struct(members) => match members.get("faultString") {
+            Some(Value::String(s)) => s.clone(),
+            _ => format!("{fault:?}"),
+        },
+        _ => format!("{fault:?}"),
+    }
+}
+
+/// Parse an XML-RPC response, returning its value or the fault.
+fn parse_response(xml: &str) -> anyhow::Result<Value> {
+    let doc = roxmltree::Document::parse(xml).context("parsing response")?;
+    let root = doc.root_element();
+    if !root.has_tag_name("methodResponse") {
+        bail!("unexpected <{}> response", root.tag_name().name());
+    }
+    if let Some(fault) = root.children().find(|n| n.has_tag_name("fault")) {
+        let fault = parse_value(child(fault, "value")?)?;
+        bail!("fault: {}", fault_string(&fault));
+    }
+    let params = child(root, "params")?;
+    parse_value(child(child(params, "param")?, "value")?)
+}
+
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> anyhow::Result<roxmltree::Node<'a, 'input>> {
+    node.children()
+        .find(|n| n.has_tag_name(name))
+        .ok_or_else(|| anyhow!("missing <{name}> in <{}>", node.tag_name().name()))
+}
+
+fn parse_value(node: roxmltree::Node) -> anyhow::Result<Value> {
+    let Some(typed) = node.children().find(|n| n.is_element()) else {
+        // Untyped values are strings.
+        return Ok(Value::String(node.text().unwrap_or_default().to_string()));
+    };
+    let text = typed.text().unwrap_or_default().trim();
+    Ok(match typed.tag_name().name() {
+        "nil" => Value::Nil,
+        "boolean" => Value::Bool(text == "1"),
+        "int" | "i4" | "i8" => Value::Int(
+            text.parse()
+                .with_context(|| format!("invalid int '{text}'"))?,
+        ),
+        "double" => Value::Double(
+            text.parse()
+                .with_context(|| format!("invalid double '{text}'"))?,
+        ),
+        "string" => Value::String(typed.text().unwrap_or_default().to_string()),
+        "array" => {
+            let data = child(typed, "data")?;
+            Value::Array(
+                data.children()
+                    .filter(|n| n.has_tag_name("value"))
+                    .map(parse_value)
+                    .collect::<anyhow::Result<_>>()?,
+            )
+        }
+        "struct" => {
+            let mut members = HashMap::new();
+            for member in typed.children().filter(|n| n.has_tag_name("member")) {
+                let name = child(member, "name")?.text().unwrap_or_default();
+                let value = parse_value(child(member, "value")?)?;
+                members.insert(name.to_string(), value);
+            }
+            Value::Struct(members)
+        }
+        other => bail!("unsupported value type <{other}>"),
+    })
+}
+
+/// Convert a `getBuild` result.
+fn parse_build(value: &Value, web_url: &str) -> anyhow::Result<Option<BuildInfo>> {
+    let members = match value {
+        Value::Nil => return Ok(None),
+        Value::Struct(members) => members,
+        _ => bail!("unexpected build info {value:?}"),
+    };
+    let int = |name: &str| match members.get(name) {
+        Some(&Value::Int(i)) => u64::try_from(i).ok(),
+        _ => None,
+    };
+    let string = |name: &str| match members.get(name) {
+        Some(Value::String(s)) if !s.is_empty() => Some(s.clone()),
+        _ => None,
+    };
+    let build_id = int("id").context("build info without id")?;
+    let task_id = int("task_id");
+    Ok(Some(BuildInfo {
+        build_id,
+        nvr: string("nvr").context("build info without nvr")?,
+        build_url: format!("{web_url}/buildinfo?buildID={build_id}"),
+        task_id,
+        task_url: task_id.map(|id| format!("{web_url}/taskinfo?taskID={id}")),
+        source: string("source"),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/fedora.qf");
+
+    fn response(value: &str) -> String {
+        format!(
+            "<?xml version='1.0'?>\n<methodResponse><params><param>\
+             <value>{value}</value></param></params></methodResponse>"
+        )
+    }
+
+    const BASH_BUILD: &str = "<struct>\
+        <member><name>id</name><value><int>2786934</int></value></member>\
+        <member><name>nvr</name><value><string>bash-5.3.0-2.fc43</string></value></member>\
+        <member><name>task_id</name><value><int>135489021</int></value></member>\
+        <member><name>source</name><value><string>git+https://src.fedoraproject.org/rpms/bash.git#abc</string></value></member>\
+        <member><name>extra</name><value><nil/></value></member>\
+        </struct>";
+
+    #[test]
+    fn test_parse_response() {
+        let value = parse_response(&response(BASH_BUILD)).unwrap();
+        let build = parse_build(&value, "https://koji/koji").unwrap().unwrap();
+        assert_eq!(build.build_id, 2786934);
+        assert_eq!(build.nvr, "bash-5.3.0-2.fc43");
+        assert_eq!(
+            build.build_url,
+            "https://koji/koji/buildinfo?buildID=2786934"
+        );
+        assert_eq!(
+            build.task_url.as_deref(),
+            Some("https://koji/koji/taskinfo?taskID=135489021")
+        );
+        assert!(build.source.unwrap().ends_with("bash.git#abc"));
+
+        let value = parse_response(&response("<nil/>")).unwrap();
+        assert_eq!(parse_build(&value, "").unwrap(), None);
+        let value = parse_response(&response(
+            "<array><data><value>a</value><value><i4>1</i4></value></data></array>",
+        ))
+        .unwrap();
+        assert_eq!(
+            value,
+            Value::Array(vec![Value::String("a".into()), Value::Int(1)])
+        );
+
+        let fault = "<?xml version='1.0'?>\n<methodResponse><fault><value><struct>\
+            <member><name>faultCode</name><value><int>1000</int></value></member>\
+            <member><name>faultString</name><value><string>invalid format</string></value></member>\
+            </struct></value></fault></methodResponse>";
+        let err = parse_response(fault).unwrap_err();
+        assert!(err.to_string().contains("invalid format"), "{err}");
+
+        let call = method_call("getBuild", &[Value::String("a<b".into())]);
+        assert!(
+            call.contains("<value><string>a&lt;b</string></value>"),
+            "{call}"
+        );
+        let call = method_call("multiCall", &[Value::Array(vec![Value::Int(1)])]);
+        let doc = roxmltree::Document::parse(&call).unwrap();
+        let param = doc.descendants().find(|n| n.has_tag_name("param")).unwrap();
+        assert_eq!(
+            parse_value(child(param, "value").unwrap()).unwrap(),
+            Value::Array(vec![Value::Int(1)])
+        );
+        assert_eq!(
+            source_nvr("bash-5.3.0-2.fc43.src.rpm"),
+            Some("bash-5.3.0-2.fc43")
+        );
+        assert_eq!(source_nvr("bash"), None);
+    }
+
+    #[test]
+    fn test_enrich() {
+        let packages = load_from_str(FIXTURE).unwrap();
+        let batch_size = 4;
+        let requests = source_rpms(FIXTURE).div_ceil(batch_size);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let hub = format!("http://{}/kojihub", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requested = Vec::new();
+            for stream in listener.incoming().take(requests) {
+                let mut stream = BufReader::new(stream.unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).unwrap();
+                    if let Some(len) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = len.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).unwrap();
+                let body = String::from_utf8(body).unwrap();
+                assert!(
+                    body.contains("<methodName>multiCall</methodName>"),
+                    "{body}"
+                );
+                let nvrs: Vec<String> = (body.split("<string>").skip(1))
+                    .map(|s| s.split("</string>").next().unwrap().to_string())
+                    .filter(|s| s != "getBuild")
+                    .collect();
+                let results: String = (nvrs.iter())
+                    .map(|nvr| match nvr.as_str() {
+                        "bash-5.3.0-2.fc43" => format!(
+                            "<value><array><data><value>{BASH_BUILD}</value></data></array></value>"
+                        ),
+                        "glibc-2.42-10.fc43" => "<value><struct><member><name>faultString</name>\
+                            <value><string>no access</string></value></member></struct></value>"
+                            .to_string(),
+                        _ => "<value><array><data><value><nil/></value></data></array></value>"
+                            .to_string(),
+                    })
+                    .collect();
+                requested.extend(nvrs);
+                let body = response(&format!("<array><data>{results}</data></array>"));
+                write!(
+                    stream.get_mut(),
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+            requested
+        });
+
+        let client = KojiClient::new(hub, "https://koji/koji/").batch_size(batch_size);
+        let (enriched, errors) = client.enrich(&packages).unwrap();
+        let requested = server.join().unwrap();
+        assert_eq!(requested.len(), source_rpms(FIXTURE));
+        assert_eq!(enriched.len(), 1);
+        assert_eq!(enriched["bash"].build_id, 2786934);
+        assert!(
+            enriched["bash"]
+                .build_url
+                .starts_with("https://koji/koji/buildinfo")
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].package, "glibc");
+        assert_eq!(errors[0].nvr, "glibc-2.42-10.fc43");
+        assert!(errors[0].to_string().contains("no access"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn test_enrich_unreachable() {
+        let packages = load_from_str(FIXTURE).unwrap();
+        // Nothing listens on the port once the listener is dropped.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let hub = format!("http://{}/kojihub", listener.local_addr().unwrap());
+        drop(listener);
+        let client = KojiClient::new(hub, "").timeout(Duration::from_secs(5));
+        let (enriched, errors) = client.enrich(&packages).unwrap();
+        assert!(enriched.is_empty());
+        assert_eq!(errors.len(), source_rpms(FIXTURE));
+    }
+
+    /// Number of distinct source RPMs in a fixture, i.e. of lookups.
+    fn source_rpms(fixture: &str) -> usize {
+        let packages = load_from_str(fixture).unwrap();
+        let sources: std::collections::HashSet<_> = packages
+            .values()
+            .filter_map(|pkg| pkg.sourcerpm.as_deref())
+            .collect();
+        sources.len()
+    }
+}
@@ -5,26 +5,192 @@
 //! names to `Package` structs.
 //!
 //! Uses `--queryformat` instead of `--json` for compatibility with older RPM.
+//!
+//! With the `log` feature, diagnostics (the rpm command lines run, warnings
+//! printed by rpm and entries skipped while parsing) are emitted through the
+//! [`log`](https://docs.rs/log) facade.
+//!
+//! Everything which runs rpm is behind the `exec` feature, enabled by default.
+//! Without it, the crate never spawns processes: it only parses queryformat
+//! output obtained otherwise, e.g. with [`load_from_reader`] or
+//! [`load_from_fd`].
+
+/// Emit a debug message through the `log` facade, if enabled.
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::debug!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+/// Emit a warning through the `log` facade, if enabled.
+macro_rules! warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::warn!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
 
+mod alternatives;
+mod arch;
+#[cfg(feature = "arena")]
+mod arena;
+#[cfg(feature = "async")]
+mod async_exec;
+#[cfg(feature = "exec")]
+mod cache;
+mod changelog;
+mod chunking;
+#[cfg(feature = "comps")]
+mod comps;
+mod conflicts;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod delta;
+mod deps;
+mod diff;
+mod error;
+#[cfg(feature = "exec")]
+mod exec;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod file_index;
+mod fips;
+#[cfg(feature = "exec")]
+mod fixture;
+#[cfg(feature = "exec")]
+mod flatpak;
+mod kernel;
+#[cfg(feature = "koji")]
+mod koji;
+mod manifest;
+mod memory;
+#[cfg(feature = "native-db")]
+mod native_db;
+mod observer;
+#[cfg(feature = "oci")]
+mod oci;
+#[cfg(feature = "ostree")]
+mod ostree;
 mod parse;
+#[cfg(feature = "path-index")]
+mod path_index;
+#[cfg(feature = "exec")]
+mod query;
+#[cfg(feature = "exec")]
+mod reload;
+#[cfg(feature = "rpm-rs")]
+mod rpm_rs;
+#[cfg(feature = "sbom")]
+mod sbom;
+#[cfg(feature = "selinux")]
+mod selinux;
+mod signing;
+mod solv;
+mod strip;
+#[cfg(feature = "exec")]
+mod sysext;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod unowned;
+#[cfg(feature = "verify")]
+mod verify;
+mod version;
+
+pub use alternatives::{ResolvedOwners, resolve_owners};
+#[cfg(feature = "arena")]
+pub use arena::PathArena;
+pub use changelog::{ChangelogEntry, ChangelogHeader};
+pub use chunking::{Chunk, Chunker};
+#[cfg(feature = "comps")]
+pub use comps::{Comps, Environment, Group, GroupCoverage, GroupPackage, GroupPackageKind};
+pub use conflicts::{ConflictKind, FileConflict, file_conflicts};
+#[cfg(feature = "dbus")]
+pub use dbus::{DBUS_NAME, DBUS_PATH, DbusService};
+pub use delta::PackagesDelta;
+pub use deps::{
+    DependencyClosure, InstallReason, InstallReasons, WeakDependencies, WeakDependency,
+    WeakDependencyKind, autoremove_candidates, dependency_closure, weak_dependencies,
+};
+pub use diff::{FileDiff, PackageDiff, diff};
+pub use error::{Error, Result};
+pub use file_index::FileIndex;
+pub use fips::{DigestPolicy, fips_enabled, weak_digests};
+#[cfg(feature = "exec")]
+pub use fixture::FixtureOptions;
+#[cfg(feature = "exec")]
+pub use flatpak::{FlatpakRuntime, find_flatpak_runtimes, load_flatpak_runtimes};
+pub use kernel::{Kernel, KernelInventory, kernel_inventory};
+#[cfg(feature = "koji")]
+pub use koji::{BuildInfo, KojiClient, LookupError};
+pub use manifest::{ManifestReport, PackageSpec, VersionMismatch, check_manifest};
+pub use memory::MemoryUsage;
+pub use observer::{LoadObserver, LoadStats, VerifyStats};
+#[cfg(feature = "oci")]
+pub use oci::{LayerChanges, layer_changes, load_from_oci_layout};
+#[cfg(feature = "ostree")]
+pub use ostree::{OstreeObject, ostree_checksums};
+pub use parse::QUERYFORMAT;
+#[cfg(feature = "path-index")]
+pub use path_index::PathIndex;
+#[cfg(feature = "exec")]
+pub use query::QueryBuilder;
+#[cfg(feature = "sbom")]
+pub use sbom::Sbom;
+#[cfg(feature = "selinux")]
+pub use selinux::{FileContexts, FileLabel, LabelMismatch, expected_labels, label_mismatches};
+#[cfg(feature = "exec")]
+pub use signing::load_public_keys;
+pub use signing::{
+    KeySignatures, PUBKEY_QUERYFORMAT, PublicKey, SigningReport, load_public_keys_from_str,
+    signing_report,
+};
+pub use solv::write_solv_testtags;
+pub use strip::{StripOptions, StripReport, StripSavings, strip_savings};
+#[cfg(feature = "exec")]
+pub use sysext::{Extension, MergedPackages, find_sysexts, load_with_sysexts};
+pub use unowned::{UnownedOptions, find_unowned};
+#[cfg(feature = "verify")]
+pub use verify::{VerifyFailure, VerifyOptions, VerifyProblem, VerifyReport, verify_files};
+pub use version::{Evr, Nevra, rpmvercmp};
 
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, anyhow};
 use camino::{Utf8Path, Utf8PathBuf};
+#[cfg(feature = "exec")]
 use cap_std_ext::cap_std::fs::Dir;
+#[cfg(feature = "exec")]
+use exec::*;
 use std::collections::{BTreeMap, HashMap};
 use std::io::Read;
-use std::os::fd::AsRawFd;
-use std::path::Path;
-use std::process::Command;
+#[cfg(feature = "exec")]
+use std::io::Write;
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+
+/// Where the rpmdb may be, relative to the rootfs, in order of preference.
+#[cfg(any(feature = "exec", feature = "native-db"))]
+pub(crate) const RPMDB_PATHS: &[&str] = &["usr/lib/sysimage/rpm", "var/lib/rpm", "usr/share/rpm"];
 
 /// A map of package names to their metadata.
 pub type Packages = HashMap<String, Package>;
 
+/// A map of package names to their metadata, hashed with FxHash rather than
+/// SipHash. It's much faster, which helps lookup-heavy workloads, but not
+/// HashDoS-resistant. See [`load_from_reader_fx`].
+#[cfg(feature = "fxhash")]
+pub type FxPackages = HashMap<String, Package, rustc_hash::FxBuildHasher>;
+
 /// A map of file paths to their metadata.
 pub type Files = BTreeMap<Utf8PathBuf, FileInfo>;
 
 /// Cryptographic hash algorithm used for file digests.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DigestAlgorithm {
     /// MD5 (legacy, insecure).
     Md5 = 1,
@@ -52,8 +218,179 @@ pub enum DigestAlgorithm {
     Sha3_512 = 14,
 }
 
+impl DigestAlgorithm {
+    /// Length in bytes of digests produced by this algorithm.
+    pub fn digest_len(self) -> usize {
+        match self {
+            Self::Md5 | Self::Md2 => 16,
+            Self::Sha1 | Self::RipeMd160 | Self::Haval5160 => 20,
+            Self::Tiger192 => 24,
+            Self::Sha224 => 28,
+            Self::Sha256 | Self::Sha3_256 => 32,
+            Self::Sha384 => 48,
+            Self::Sha512 | Self::Sha3_512 => 64,
+        }
+    }
+
+    /// Get the lowercase name of the algorithm, e.g. `sha256`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Md5 => "md5",
+            Self::Sha1 => "sha1",
+            Self::RipeMd160 => "ripemd160",
+            Self::Md2 => "md2",
+            Self::Tiger192 => "tiger192",
+            Self::Haval5160 => "haval-5-160",
+            Self::Sha256 => "sha256",
+            Self::Sha384 => "sha384",
+            Self::Sha512 => "sha512",
+            Self::Sha224 => "sha224",
+            Self::Sha3_256 => "sha3-256",
+            Self::Sha3_512 => "sha3-512",
+        }
+    }
+
+    /// Whether the algorithm is approved for FIPS 140, i.e. is from the
+    /// SHA-2 or SHA-3 families.
+    pub fn is_fips_approved(self) -> bool {
+        matches!(
+            self,
+            Self::Sha224
+                | Self::Sha256
+                | Self::Sha384
+                | Self::Sha512
+                | Self::Sha3_256
+                | Self::Sha3_512
+        )
+    }
+}
+
+/// A file digest, stored as raw bytes.
+///
+/// The bytes are kept inline (sized for the largest supported algorithm) to
+/// avoid a heap allocation per file. Use [`FileDigest::to_hex`] or the
+/// `Display` impl to get the usual hex representation.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileDigest {
+    len: u8,
+    bytes: [u8; FileDigest::MAX_LEN],
+}
+
+impl FileDigest {
+    /// Maximum digest length in bytes (SHA-512 and SHA3-512).
+    pub const MAX_LEN: usize = 64;
+
+    /// Create from raw digest bytes.
+    pub fn from_bytes(digest: &[u8]) -> Result<Self> {
+        if digest.is_empty() || digest.len() > Self::MAX_LEN {
+            return Err(anyhow!("invalid digest length {}", digest.len()).into());
+        }
+        let mut bytes = [0u8; Self::MAX_LEN];
+        bytes[..digest.len()].copy_from_slice(digest);
+        Ok(Self {
+            len: digest.len() as u8,
+            bytes,
+        })
+    }
+
+    /// Create from a hex-encoded digest.
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        if !hex.len().is_multiple_of(2) || hex.is_empty() || hex.len() > Self::MAX_LEN * 2 {
+            return Err(anyhow!("invalid hex digest length {}", hex.len()).into());
+        }
+        let mut bytes = [0u8; Self::MAX_LEN];
+        for (i, pair) in hex.as_bytes().chunks_exact(2).enumerate() {
+            let hi = hex_value(pair[0]);
+            let lo = hex_value(pair[1]);
+            match (hi, lo) {
+                (Some(hi), Some(lo)) => bytes[i] = (hi << 4) | lo,
+                _ => return Err(anyhow!("invalid hex digest '{hex}'").into()),
+            }
+        }
+        Ok(Self {
+            len: (hex.len() / 2) as u8,
+            bytes,
+        })
+    }
+
+    /// Get the raw digest bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    /// Get the hex-encoded digest.
+    pub fn to_hex(&self) -> String {
+        self.to_string()
+    }
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+impl std::fmt::Display for FileDigest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for b in self.as_bytes() {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for FileDigest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FileDigest({self})")
+    }
+}
+
+impl std::str::FromStr for FileDigest {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_hex(s)
+    }
+}
+
+/// Digests are serialized as hex strings.
+#[cfg(feature = "serde")]
+impl serde::Serialize for FileDigest {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FileDigest {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        Self::from_hex(&hex).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Fail to serialize a package whose file list is deferred, rather than
+/// silently leave its files out.
+#[cfg(feature = "serde")]
+fn serialize_unparsed_files<S: serde::Serializer>(
+    _: &Option<parse::RawFiles>,
+    _: S,
+) -> Result<S::Ok, S::Error> {
+    Err(serde::ser::Error::custom(
+        "the file list is deferred; call Package::parse_files first",
+    ))
+}
+
 /// File attribute flags from the RPM spec file.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct FileFlags(u32);
 
 impl FileFlags {
@@ -125,8 +462,164 @@ impl FileFlags {
     }
 }
 
+/// Install state of a file, as recorded by rpm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FileState {
+    /// The file was installed normally.
+    Normal = 0,
+    /// The file was replaced by a file from another package.
+    Replaced = 1,
+    /// The file wasn't installed, e.g. because it's a doc file and docs were
+    /// excluded, or because of `%_install_langs`.
+    NotInstalled = 2,
+    /// The file is on a network-shared path (`%_netsharedpath`) and wasn't
+    /// installed.
+    NetShared = 3,
+    /// The file wasn't installed because a file of the preferred color
+    /// (multilib architecture) from another package was.
+    WrongColor = 4,
+}
+
+/// Sense flags of a [`Dependency`] (`RPMSENSE_*`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct DependencyFlags(u32);
+
+impl DependencyFlags {
+    /// The version must be less than the one given.
+    pub const LESS: u32 = 1 << 1;
+    /// The version must be greater than the one given.
+    pub const GREATER: u32 = 1 << 2;
+    /// The version must be equal to the one given.
+    pub const EQUAL: u32 = 1 << 3;
+    /// The dependency is the condition of a `%triggerin` script.
+    pub const TRIGGERIN: u32 = 1 << 16;
+    /// The dependency is the condition of a `%triggerun` script.
+    pub const TRIGGERUN: u32 = 1 << 17;
+    /// The dependency is the condition of a `%triggerpostun` script.
+    pub const TRIGGERPOSTUN: u32 = 1 << 18;
+    /// The dependency is on a feature of rpm itself (`rpmlib(...)`).
+    pub const RPMLIB: u32 = 1 << 24;
+    /// The dependency is the condition of a `%triggerprein` script.
+    pub const TRIGGERPREIN: u32 = 1 << 25;
+
+    /// Create from raw flag value.
+    pub fn from_raw(value: u32) -> Self {
+        Self(value)
+    }
+
+    /// Get the raw flag value.
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
+
+    /// Get the comparison operator (e.g. `>=`), if the dependency is
+    /// versioned.
+    pub fn operator(&self) -> Option<&'static str> {
+        match self.0 & (Self::LESS | Self::GREATER | Self::EQUAL) {
+            0 => None,
+            Self::LESS => Some("<"),
+            Self::GREATER => Some(">"),
+            Self::EQUAL => Some("="),
+            x if x == Self::LESS | Self::EQUAL => Some("<="),
+            x if x == Self::GREATER | Self::EQUAL => Some(">="),
+            _ => Some("<>"),
+        }
+    }
+
+    /// Check if the rpmlib flag is set.
+    pub fn is_rpmlib(&self) -> bool {
+        self.0 & Self::RPMLIB != 0
+    }
+
+    /// Get the kind of trigger this is the condition of, if any.
+    pub fn trigger_kind(&self) -> Option<TriggerKind> {
+        if self.0 & Self::TRIGGERPREIN != 0 {
+            Some(TriggerKind::PreIn)
+        } else if self.0 & Self::TRIGGERIN != 0 {
+            Some(TriggerKind::In)
+        } else if self.0 & Self::TRIGGERUN != 0 {
+            Some(TriggerKind::Un)
+        } else if self.0 & Self::TRIGGERPOSTUN != 0 {
+            Some(TriggerKind::PostUn)
+        } else {
+            None
+        }
+    }
+}
+
+/// A dependency of a package, e.g. one of its Requires, Provides, Obsoletes
+/// or Conflicts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dependency {
+    /// Name of the capability, e.g. a package name, a virtual provide like
+    /// `libc.so.6()(64bit)`, or a file path.
+    pub name: String,
+    /// Sense flags, notably how the version is compared.
+    pub flags: DependencyFlags,
+    /// `[epoch:]version[-release]` the capability is constrained to, if any.
+    pub version: Option<String>,
+}
+
+impl std::fmt::Display for Dependency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)?;
+        if let (Some(op), Some(version)) = (self.flags.operator(), &self.version) {
+            write!(f, " {op} {version}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A script run by rpm when installing or erasing a package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Scriptlet {
+    /// Program running the script, e.g. `/bin/sh`, or `<lua>` for rpm's
+    /// embedded Lua interpreter.
+    pub interpreter: Option<String>,
+    /// The script, or `None` if the interpreter is run without one, as with
+    /// `%post -p /sbin/ldconfig`.
+    pub script: Option<String>,
+}
+
+/// When a trigger script runs, relative to the transaction element of the
+/// package matching its conditions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TriggerKind {
+    /// `%triggerprein`: before the package is installed.
+    PreIn,
+    /// `%triggerin`: after the package is installed.
+    In,
+    /// `%triggerun`: before the package is erased.
+    Un,
+    /// `%triggerpostun`: after the package is erased.
+    PostUn,
+}
+
+/// A script run by rpm when other packages are installed or erased.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Trigger {
+    /// When the script runs.
+    pub kind: TriggerKind,
+    /// Packages whose installation or erasure fires the trigger (any of
+    /// them).
+    pub conditions: Vec<Dependency>,
+    /// The script.
+    pub scriptlet: Scriptlet,
+}
+
 /// Metadata for a file contained in an RPM package.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FileInfo {
     /// File size in bytes.
     pub size: u64,
@@ -134,8 +627,8 @@ pub struct FileInfo {
     pub mode: u16,
     /// Unix modification timestamp.
     pub mtime: u64,
-    /// Hex-encoded file digest, if present (directories and symlinks have none).
-    pub digest: Option<String>,
+    /// File digest, if present (directories and symlinks have none).
+    pub digest: Option<FileDigest>,
     /// File attribute flags.
     pub flags: FileFlags,
     /// Owner username.
@@ -144,10 +637,64 @@ pub struct FileInfo {
     pub group: String,
     /// Symlink target, if this is a symbolic link.
     pub linkto: Option<Utf8PathBuf>,
+    /// Install state. Only files in state [`FileState::Normal`] were actually
+    /// laid down by rpm. This is `None` when parsing output from older
+    /// versions of this crate, and for packages read from rpm files (see
+    /// [`load_from_rpm_file`]).
+    pub state: Option<FileState>,
+    /// Languages the file is for (`%lang`), separated by `|`, or `None` if
+    /// it's for all of them. Always `None` when parsing output from older
+    /// versions of this crate.
+    pub lang: Option<String>,
+    /// Color of the file, i.e. the bitness of ELF files (1 for 32-bit, 2 for
+    /// 64-bit), or 0 for other files. This is `None` when parsing output from
+    /// older versions of this crate.
+    pub color: Option<u32>,
+    /// Capabilities set on the file (`%caps`), in the textual form of
+    /// `cap_to_text(3)`, e.g. `cap_net_bind_service=ep`, or `None` if it has
+    /// none. Always `None` when parsing output from older versions of this
+    /// crate.
+    pub caps: Option<String>,
+}
+
+impl FileInfo {
+    /// Mask of the file type bits of [`FileInfo::mode`].
+    pub const S_IFMT: u16 = 0o170000;
+    /// File type of directories.
+    pub const S_IFDIR: u16 = 0o040000;
+    /// File type of regular files.
+    pub const S_IFREG: u16 = 0o100000;
+    /// File type of symbolic links.
+    pub const S_IFLNK: u16 = 0o120000;
+
+    /// Get the file type bits of the mode, e.g. [`FileInfo::S_IFDIR`].
+    pub fn file_type(&self) -> u16 {
+        self.mode & Self::S_IFMT
+    }
+
+    /// Check if this is a regular file.
+    pub fn is_file(&self) -> bool {
+        self.file_type() == Self::S_IFREG
+    }
+
+    /// Check if this is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == Self::S_IFDIR
+    }
+
+    /// Check if this is a symbolic link.
+    pub fn is_symlink(&self) -> bool {
+        self.file_type() == Self::S_IFLNK
+    }
 }
 
 /// Metadata for an installed RPM package.
+///
+/// With the `serde` feature, packages can be serialized, e.g. to cache a
+/// snapshot on disk. Serializing a package whose file list is deferred (see
+/// [`ParseOptions::lazy_files`]) fails, so it must be parsed first.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Package {
     /// Package name.
     pub name: String,
@@ -155,10 +702,12 @@ pub struct Package {
     pub version: String,
     /// Package release.
     pub release: String,
-    /// Package epoch, if present.
+    /// Package epoch, if present. See [`EpochPolicy`] for how a missing epoch
+    /// is represented.
     pub epoch: Option<u32>,
     /// The architecture the package is for. `noarch` is a special case denoting
-    /// an architecture independent package.
+    /// an architecture independent package. See [`ParseOptions::missing_arch`]
+    /// for packages without one.
     pub arch: String,
     /// License of the package contents.
     pub license: String,
@@ -166,102 +715,993 @@ pub struct Package {
     pub size: u64,
     /// Unix timestamp of package build time.
     pub buildtime: u64,
-    /// Unix timestamp of package installation.
+    /// Unix timestamp of package installation, or 0 for packages read from
+    /// rpm files (see [`load_from_rpm_file`]).
     pub installtime: u64,
+    /// ID of the rpm transaction which installed the package. This is `None`
+    /// when parsing output from older versions of this crate, and for packages
+    /// read from rpm files.
+    pub installtid: Option<u64>,
     /// Package source rpm file name.
     pub sourcerpm: Option<String>,
     /// Digest algorithm used for file digests in this package.
     pub digest_algo: Option<DigestAlgorithm>,
-    /// Unix timestamps of changelog entries (most recent first).
-    pub changelog_times: Vec<u64>,
-    /// Files contained in this package.
+    /// ID of the OpenPGP key whose signature covers the package header, as
+    /// 16 lowercase hex digits, or `None` if unsigned. This is also `None`
+    /// when parsing output from older versions of this crate. See
+    /// [`signing_report`].
+    pub signing_key: Option<String>,
+    /// Changelog entries (most recent first).
+    pub changelog: Vec<ChangelogEntry>,
+    /// Capabilities the package requires. Empty when parsing output from
+    /// older versions of this crate.
+    pub requires: Vec<Dependency>,
+    /// Capabilities the package provides. Empty when parsing output from
+    /// older versions of this crate.
+    pub provides: Vec<Dependency>,
+    /// Capabilities the package recommends, i.e. weak Requires. Empty when
+    /// parsing output from older versions of this crate.
+    pub recommends: Vec<Dependency>,
+    /// Capabilities the package suggests, i.e. weaker Recommends. Empty when
+    /// parsing output from older versions of this crate.
+    pub suggests: Vec<Dependency>,
+    /// Packages this one replaces, which rpm removes when installing it.
+    /// Empty when parsing output from older versions of this crate.
+    pub obsoletes: Vec<Dependency>,
+    /// Capabilities which can't be installed along with the package. Empty
+    /// when parsing output from older versions of this crate.
+    pub conflicts: Vec<Dependency>,
+    /// Script run before the package is installed (`%pre`), if any. This is
+    /// `None` when parsing output from older versions of this crate.
+    pub pre: Option<Scriptlet>,
+    /// Script run after the package is installed (`%post`), if any.
+    pub post: Option<Scriptlet>,
+    /// Script run before the package is erased (`%preun`), if any.
+    pub preun: Option<Scriptlet>,
+    /// Script run after the package is erased (`%postun`), if any.
+    pub postun: Option<Scriptlet>,
+    /// Scripts run when other packages are installed or erased, in the order
+    /// rpm stores them. Empty when parsing output from older versions of this
+    /// crate.
+    pub triggers: Vec<Trigger>,
+    /// Files contained in this package, keyed by canonical path (see
+    /// [`Package::file`]). Empty until [`Package::parse_files`] is called if
+    /// the file list was deferred with [`ParseOptions::lazy_files`].
     pub files: Files,
+    /// Raw FILE lines whose parsing was deferred.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            skip_deserializing,
+            skip_serializing_if = "Option::is_none",
+            serialize_with = "serialize_unparsed_files"
+        )
+    )]
+    unparsed_files: Option<parse::RawFiles>,
+}
+
+impl Package {
+    /// Get the package's name, epoch, version, release and architecture in
+    /// the usual `name-[epoch:]version-release.arch` form.
+    pub fn nevra(&self) -> String {
+        match self.epoch {
+            Some(epoch) => format!(
+                "{}-{epoch}:{}-{}.{}",
+                self.name, self.version, self.release, self.arch
+            ),
+            None => format!(
+                "{}-{}-{}.{}",
+                self.name, self.version, self.release, self.arch
+            ),
+        }
+    }
+
+    /// Compare the epoch, version and release of this package with those of
+    /// `other` as rpm does, e.g. to tell which is newer. A missing epoch is
+    /// the same as 0.
+    pub fn cmp_evr(&self, other: &Package) -> std::cmp::Ordering {
+        version::cmp_evr(
+            (self.epoch, &self.version, &self.release),
+            (other.epoch, &other.version, &other.release),
+        )
+    }
+
+    /// Get the package's epoch, version and release, e.g. to compare them
+    /// with those of a candidate update. See also [`Nevra::from`].
+    pub fn evr(&self) -> Evr {
+        Evr::new(self.epoch, &self.version, &self.release)
+    }
+
+    /// Whether this package has a deferred file list that hasn't been parsed
+    /// yet.
+    pub fn has_unparsed_files(&self) -> bool {
+        self.unparsed_files.is_some()
+    }
+
+    /// Estimate the heap memory used by this package, broken down by kind of
+    /// data.
+    pub fn approx_memory_usage(&self) -> MemoryUsage {
+        memory::package_memory_usage(self)
+    }
+
+    /// Look up a file by path. File paths are stored canonically, without
+    /// repeated or trailing slashes; `path` is normalized the same way, so
+    /// e.g. `/usr/bin/` finds the `/usr/bin` directory.
+    pub fn file(&self, path: &Utf8Path) -> Option<&FileInfo> {
+        self.files
+            .get(Utf8Path::new(&*parse::normalize_path(path.as_str())))
+    }
+
+    /// Only keep files for which `pred` returns `true`. The memory of dropped
+    /// entries is released as they're removed.
+    ///
+    /// This only considers parsed files; a deferred file list (see
+    /// [`ParseOptions::lazy_files`]) must be parsed first with
+    /// [`Package::parse_files`].
+    pub fn retain_files<F: FnMut(&Utf8Path, &FileInfo) -> bool>(&mut self, mut pred: F) {
+        self.files.retain(|path, info| pred(path, info));
+    }
+
+    /// Get the files contained in this package, first parsing the deferred
+    /// file list if there is one.
+    pub fn parse_files(&mut self) -> Result<&Files> {
+        if let Some(raw) = self.unparsed_files.take() {
+            self.files = parse::parse_files(&raw, self.digest_algo)
+                .with_context(|| format!("parsing files of '{}'", self.nevra()))?;
+        }
+        Ok(&self.files)
+    }
+
+    /// Estimate how many times per year this package is updated, from the
+    /// span of its changelog. Returns `None` without at least two changelog
+    /// entries at different times.
+    ///
+    /// Distributions often trim old changelog entries, so this reflects the
+    /// recent history of the package.
+    pub fn update_frequency(&self) -> Option<f64> {
+        let newest = self.changelog.iter().map(|entry| entry.time).max()?;
+        let oldest = self.changelog.iter().map(|entry| entry.time).min()?;
+        if newest == oldest {
+            return None;
+        }
+        const YEAR: f64 = 365.25 * 24.0 * 3600.0;
+        let years = (newest - oldest) as f64 / YEAR;
+        Some((self.changelog.len() - 1) as f64 / years)
+    }
+}
+
+/// Extension methods for [`Packages`].
+pub trait PackagesExt {
+    /// Estimate the heap memory used by this snapshot, broken down by kind of
+    /// data.
+    fn approx_memory_usage(&self) -> MemoryUsage;
+
+    /// Only keep packages for which `pred` returns `true`, then release the
+    /// memory no longer needed by the map.
+    fn retain_packages<F: FnMut(&Package) -> bool>(&mut self, pred: F);
+
+    /// Rank packages by [`Package::update_frequency`], most frequently
+    /// updated first. Packages without enough changelog history are left out.
+    fn rank_by_churn(&self) -> Vec<(&Package, f64)>;
+
+    /// Attribute the bytes of the regular files under `prefix` (e.g.
+    /// `/usr/lib64`) to the packages owning them, largest first. Files which
+    /// weren't installed (see [`FileState`]) and ghost files don't count, and
+    /// packages owning nothing there are left out. A file shared by several
+    /// packages counts for each of them.
+    fn size_by_prefix(&self, prefix: &Utf8Path) -> Vec<(&Package, u64)>;
+
+    /// Iterate over the parsed files of every package, in no particular
+    /// order of packages and by path within each package.
+    fn files(&self) -> impl Iterator<Item = (&Package, &Utf8Path, &FileInfo)>;
+
+    /// Like [`PackagesExt::files`], but as a parallel iterator, spreading
+    /// packages across rayon's thread pool.
+    #[cfg(feature = "rayon")]
+    fn par_files(
+        &self,
+    ) -> impl rayon::iter::ParallelIterator<Item = (&Package, &Utf8Path, &FileInfo)>;
+
+    /// Guess the native architecture of the system, i.e. the most common
+    /// architecture of packages other than `noarch` (on multilib systems,
+    /// 32-bit packages are a minority). Returns `None` if there are only
+    /// `noarch` packages.
+    fn native_arch(&self) -> Option<&str>;
+
+    /// Get the packages for `arch`, including `noarch` ones, sorted by name.
+    fn filter_arch(&self, arch: &str) -> Vec<&Package>;
+
+    /// Find the packages installed for both a 64-bit architecture and one of
+    /// its 32-bit multilib architectures (e.g. `glibc.x86_64` and
+    /// `glibc.i686`), as `(64-bit, 32-bit)` pairs sorted by name. Packages
+    /// sharing a name must have been loaded with [`DuplicatePolicy::Merge`]
+    /// for both to be present.
+    fn multilib_pairs(&self) -> Vec<(&Package, &Package)>;
+}
+
+impl PackagesExt for Packages {
+    fn approx_memory_usage(&self) -> MemoryUsage {
+        memory::packages_memory_usage(self)
+    }
+
+    fn retain_packages<F: FnMut(&Package) -> bool>(&mut self, mut pred: F) {
+        self.retain(|_, pkg| pred(pkg));
+        self.shrink_to_fit();
+    }
+
+    fn rank_by_churn(&self) -> Vec<(&Package, f64)> {
+        let mut ranked: Vec<(&Package, f64)> = self
+            .values()
+            .filter_map(|pkg| Some((pkg, pkg.update_frequency()?)))
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.name.cmp(&b.0.name)));
+        ranked
+    }
+
+    fn size_by_prefix(&self, prefix: &Utf8Path) -> Vec<(&Package, u64)> {
+        let prefix = parse::normalize_path(prefix.as_str());
+        let mut ranked: Vec<(&Package, u64)> = self
+            .values()
+            .map(|pkg| {
+                let size = (pkg.files.iter())
+                    .filter(|(path, info)| {
+                        path.starts_with(&*prefix)
+                            && info.is_file()
+                            && !info.flags.is_ghost()
+                            && info.state.is_none_or(|s| s == FileState::Normal)
+                    })
+                    .map(|(_, info)| info.size)
+                    .sum();
+                (pkg, size)
+            })
+            .filter(|(_, size)| *size > 0)
+            .collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.name.cmp(&b.0.name)));
+        ranked
+    }
+
+    fn files(&self) -> impl Iterator<Item = (&Package, &Utf8Path, &FileInfo)> {
+        self.values()
+            .flat_map(|pkg| (pkg.files.iter()).map(move |(path, info)| (pkg, path.as_path(), info)))
+    }
+
+    #[cfg(feature = "rayon")]
+    fn par_files(
+        &self,
+    ) -> impl rayon::iter::ParallelIterator<Item = (&Package, &Utf8Path, &FileInfo)> {
+        use rayon::iter::{IntoParallelIterator, ParallelIterator};
+        self.into_par_iter().flat_map_iter(|(_, pkg)| {
+            (pkg.files.iter()).map(move |(path, info)| (pkg, path.as_path(), info))
+        })
+    }
+
+    fn native_arch(&self) -> Option<&str> {
+        arch::native_arch(self)
+    }
+
+    fn filter_arch(&self, arch: &str) -> Vec<&Package> {
+        arch::filter_arch(self, arch)
+    }
+
+    fn multilib_pairs(&self) -> Vec<(&Package, &Package)> {
+        arch::multilib_pairs(self)
+    }
+}
+
+/// An error skipped over while loading leniently.
+#[derive(Debug)]
+pub struct PackageError {
+    /// Name of the package the error relates to, if known.
+    pub package: Option<String>,
+    /// Line (or record) number, 1-based, in the queryformat output.
+    pub line: usize,
+    /// The underlying error.
+    pub error: Error,
+    /// Whether this is only a warning: the data in question was kept as is
+    /// rather than dropped.
+    pub warning: bool,
+}
+
+impl std::fmt::Display for PackageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.package {
+            Some(name) => write!(f, "{name}: {}", self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+/// How to represent a package without an epoch.
+///
+/// rpm itself treats a missing epoch as 0 when comparing versions, but keeps
+/// the distinction in the header: `%{EPOCH}` expands to `(none)` rather than
+/// `0`. Mixing the two representations, e.g. when comparing snapshots loaded
+/// differently, makes identical versions look different.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EpochPolicy {
+    /// Keep the distinction: [`Package::epoch`] is `None` if the package has
+    /// no epoch, and `Some(0)` only if it explicitly has epoch 0.
+    #[default]
+    Distinguish,
+    /// Promote a missing epoch to 0, as rpm does when comparing versions:
+    /// [`Package::epoch`] is never `None`.
+    PromoteToZero,
+}
+
+/// What to do when several packages share a name, e.g. multiple installed
+/// kernels, or multilib packages installed for several architectures.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Keep only the last package with a given name.
+    #[default]
+    Overwrite,
+    /// Fail with [`Error::DuplicatePackage`].
+    Error,
+    /// Keep all packages. Those sharing a name are keyed by their NEVRA (see
+    /// [`Package::nevra`]) instead of their name, so that none of them is
+    /// picked arbitrarily.
+    Merge,
+}
+
+/// Bounds on plausible timestamps. See [`ParseOptions::check_timestamps`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampBounds {
+    /// Accept timestamps of 0, e.g. for file mtimes clamped to the epoch by
+    /// reproducible builds.
+    pub allow_zero: bool,
+    /// How far past the time of parsing a timestamp may be, to allow for
+    /// clock skew between hosts.
+    pub max_future: std::time::Duration,
+}
+
+impl Default for TimestampBounds {
+    /// Reject timestamps of 0 and those more than a year in the future.
+    fn default() -> Self {
+        Self {
+            allow_zero: false,
+            max_future: std::time::Duration::from_secs(365 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// A predicate on a package header.
+type PackageFilter = dyn Fn(&Package) -> bool + Send + Sync;
+
+/// Options controlling how queryformat output is parsed.
+#[derive(Default)]
+pub struct ParseOptions {
+    filters: Vec<Box<PackageFilter>>,
+    lazy_files: bool,
+    epoch_policy: EpochPolicy,
+    duplicates: DuplicatePolicy,
+    strict_validation: bool,
+    timestamp_bounds: Option<TimestampBounds>,
+    missing_arch: Option<String>,
+    max_line_length: Option<usize>,
+    max_files_per_package: Option<usize>,
+    max_packages: Option<usize>,
+    max_changelog_entries: Option<usize>,
+    max_errors: Option<usize>,
+    skip_header_checks: bool,
+}
+
+impl std::fmt::Debug for ParseOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParseOptions")
+            .field("filters", &self.filters.len())
+            .field("lazy_files", &self.lazy_files)
+            .field("epoch_policy", &self.epoch_policy)
+            .field("duplicates", &self.duplicates)
+            .field("strict_validation", &self.strict_validation)
+            .field("timestamp_bounds", &self.timestamp_bounds)
+            .field("missing_arch", &self.missing_arch)
+            .field("max_line_length", &self.max_line_length)
+            .field("max_files_per_package", &self.max_files_per_package)
+            .field("max_packages", &self.max_packages)
+            .field("max_changelog_entries", &self.max_changelog_entries)
+            .field("max_errors", &self.max_errors)
+            .field("skip_header_checks", &self.skip_header_checks)
+            .finish()
+    }
+}
+
+impl ParseOptions {
+    /// Create options with the default behaviour.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only keep packages for which `filter` returns `true`.
+    ///
+    /// The filter is called with the package header before its file list and
+    /// changelog are parsed, so `files`, `changelog` and dependencies are
+    /// always empty at that point. The file lines of non-matching packages are
+    /// skipped without being parsed. Multiple filters must all match.
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&Package) -> bool + Send + Sync + 'static,
+    {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Only keep packages with one of the given names.
+    pub fn names<I, S>(self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let names: std::collections::HashSet<String> = names.into_iter().map(Into::into).collect();
+        self.filter(move |pkg| names.contains(&pkg.name))
+    }
+
+    /// Only keep packages with one of the given architectures.
+    pub fn arches<I, S>(self, arches: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let arches: std::collections::HashSet<String> =
+            arches.into_iter().map(Into::into).collect();
+        self.filter(move |pkg| arches.contains(&pkg.arch))
+    }
+
+    /// Defer parsing of file lists until [`Package::parse_files`] is called.
+    ///
+    /// The raw file lines are kept attached to each package instead. This
+    /// saves most of the parsing work when only a few packages' files are
+    /// actually needed.
+    pub fn lazy_files(mut self, lazy: bool) -> Self {
+        self.lazy_files = lazy;
+        self
+    }
+
+    /// Set how packages without an epoch are represented. This is applied
+    /// before filters are called.
+    pub fn epoch_policy(mut self, policy: EpochPolicy) -> Self {
+        self.epoch_policy = policy;
+        self
+    }
+
+    /// Set what to do when several packages share a name.
+    pub fn duplicates(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// Check parsed data for internal consistency, to catch corrupted rpmdbs
+    /// early:
+    ///
+    /// - file digests must have the length of the package's digest algorithm
+    ///   (MD5 if it doesn't declare one)
+    /// - file modes must have a valid file type
+    ///
+    /// Inconsistencies are reported as errors naming the package and the
+    /// index of the offending file.
+    pub fn strict_validation(mut self, strict: bool) -> Self {
+        self.strict_validation = strict;
+        self
+    }
+
+    /// Check that package build and install times and file mtimes are
+    /// plausible, i.e. within `bounds`. Garbage timestamps, e.g. from a
+    /// corrupted rpmdb, would otherwise only blow up later when converted to
+    /// dates.
+    ///
+    /// Packages with implausible timestamps are kept as they are, and the
+    /// violations are logged as warnings. When loading leniently, they're
+    /// also reported as warnings (see [`PackageError::warning`]). Only the
+    /// first implausible mtime of each package is reported, and mtimes in
+    /// file lists deferred with [`ParseOptions::lazy_files`] aren't checked.
+    pub fn check_timestamps(mut self, bounds: TimestampBounds) -> Self {
+        self.timestamp_bounds = Some(bounds);
+        self
+    }
+
+    /// Set the architecture given to packages without one.
+    ///
+    /// Besides gpg-pubkey entries, which are always skipped, imported or
+    /// foreign headers occasionally lack an arch. This fails the load, but
+    /// when loading leniently such packages are kept with `placeholder` as
+    /// their arch (`(none)` by default, as `rpm -q` prints it) and a warning
+    /// is reported.
+    pub fn missing_arch(mut self, placeholder: impl Into<String>) -> Self {
+        self.missing_arch = Some(placeholder.into());
+        self
+    }
+
+    /// Fail on lines (or records) longer than `max` bytes.
+    ///
+    /// This and the other limits bound the memory used when parsing untrusted
+    /// queryformat output; there are no limits by default. When loading
+    /// leniently, overlong lines are skipped and reported.
+    pub fn max_line_length(mut self, max: usize) -> Self {
+        self.max_line_length = Some(max);
+        self
+    }
+
+    /// Fail on packages with more than `max` files. When loading leniently,
+    /// such packages are dropped and reported.
+    pub fn max_files_per_package(mut self, max: usize) -> Self {
+        self.max_files_per_package = Some(max);
+        self
+    }
+
+    /// Fail if the output has more than `max` packages, including those
+    /// skipped by filters. This is fatal even when loading leniently.
+    pub fn max_packages(mut self, max: usize) -> Self {
+        self.max_packages = Some(max);
+        self
+    }
+
+    /// Keep at most `max` changelog entries per package, the most recent
+    /// ones as rpm lists them first. Further entries are dropped without
+    /// being parsed, and their number is reported as a warning.
+    pub fn max_changelog_entries(mut self, max: usize) -> Self {
+        self.max_changelog_entries = Some(max);
+        self
+    }
+
+    /// When loading leniently, record at most `max` errors and warnings.
+    /// Further ones are only counted, and reported by a final entry giving
+    /// their number.
+    pub fn max_errors(mut self, max: usize) -> Self {
+        self.max_errors = Some(max);
+        self
+    }
+
+    /// Skip rpm's checks of header digests and signatures when loading by
+    /// running rpm, i.e. pass `--nodigest --nosignature`. On huge rpmdbs the
+    /// checks add noticeable latency, and they're of little use to read-only
+    /// inventories. This has no effect when parsing output obtained
+    /// otherwise.
+    pub fn skip_header_checks(mut self, skip: bool) -> Self {
+        self.skip_header_checks = skip;
+        self
+    }
+
+    /// Check whether a package header passes all filters.
+    pub(crate) fn matches(&self, pkg: &Package) -> bool {
+        self.filters.iter().all(|f| f(pkg))
+    }
+
+    /// Add the `rpm` arguments these options call for to a query command.
+    #[cfg(feature = "exec")]
+    pub(crate) fn add_rpm_args(&self, cmd: &mut std::process::Command) {
+        if self.skip_header_checks {
+            cmd.args(["--nodigest", "--nosignature"]);
+        }
+    }
+}
+
+/// Get the `--queryformat` string of format version 4.
+///
+/// Unlike [`QUERYFORMAT`], this is stable: future releases of this crate keep
+/// returning the same string and keep loading its output. Use it to run
+/// `rpm -qa --queryformat` where this crate can't, e.g. in a build sandbox,
+/// then load the output with [`load_from_reader`]. Note that rpm versions
+/// older than 4.6 don't know about all of its tags.
+///
+/// Output of this format lacks newer fields, e.g. [`FileInfo::lang`].
+pub fn queryformat_v4() -> &'static str {
+    parse::QUERYFORMAT_V4
+}
+
+/// Load packages from a reader containing queryformat output.
+///
+/// The output doesn't have to come from this crate: rpm can be run externally
+/// with [`queryformat_v4`].
+pub fn load_from_reader<R: Read>(reader: R) -> Result<Packages> {
+    load_from_reader_with_options(reader, &ParseOptions::default())
+}
+
+/// Load packages from a reader containing queryformat output, using the
+/// given parse options.
+///
+/// This is the parser behind all the loading functions. It doesn't run rpm or
+/// touch the filesystem, so it's also a deterministic target for fuzzers and
+/// property tests. Both the current format and the tab-separated one of older
+/// versions of this crate are accepted, told apart by the start of the output;
+/// [`parse_queryformat`] only accepts the former.
+pub fn load_from_reader_with_options<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<Packages> {
+    parse::load_from_reader_impl(reader, options)
+}
+
+/// Parse output of [`QUERYFORMAT`] or [`queryformat_v4`] from a reader.
+///
+/// Unlike [`load_from_reader`], this only accepts the current
+/// format: output in the tab-separated format of older versions of this crate
+/// is rejected rather than detected. This makes it an explicit entry point for
+/// fuzzers, property tests and tools which only ever produce the current
+/// format. Empty output parses to no packages.
+pub fn parse_queryformat<R: Read>(reader: R) -> Result<Packages> {
+    parse::parse_queryformat_impl(reader, &ParseOptions::default())
+}
+
+/// Load packages one at a time from a reader containing queryformat output.
+///
+/// Unlike [`load_from_reader`], packages are yielded as soon as they're
+/// parsed rather than collected into a map, so that memory stays bounded by
+/// the largest package however many there are. Packages aren't keyed, so
+/// duplicates are all yielded in order and [`ParseOptions::duplicates`] is
+/// ignored. The first error ends the iteration.
+pub fn load_iter_from_reader<'a, R: Read + 'a>(
+    reader: R,
+) -> impl Iterator<Item = Result<Package>> + 'a {
+    static OPTIONS: std::sync::LazyLock<ParseOptions> = std::sync::LazyLock::new(Default::default);
+    load_iter_from_reader_with_options(reader, &OPTIONS)
+}
+
+/// Load packages one at a time from a reader containing queryformat output,
+/// using the given parse options. See [`load_iter_from_reader`].
+pub fn load_iter_from_reader_with_options<'a, R: Read + 'a>(
+    reader: R,
+    options: &'a ParseOptions,
+) -> impl Iterator<Item = Result<Package>> + 'a {
+    parse::load_iter_from_reader_impl(reader, options)
+}
+
+/// Load packages from a reader containing queryformat output, skipping over
+/// malformed entries instead of failing.
+///
+/// A package whose header can't be parsed is dropped along with its files and
+/// changelog; a malformed file or changelog entry is dropped from its package.
+/// Every such error is returned alongside the packages which did parse, as
+/// are warnings about suspicious but usable data. I/O errors are still fatal.
+pub fn load_from_reader_lenient<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<(Packages, Vec<PackageError>)> {
+    parse::load_from_reader_lenient_impl(reader, options)
+}
+
+/// Load packages from a reader containing queryformat output into an
+/// [`FxPackages`] map, using the given parse options. See
+/// [`load_from_reader_with_options`].
+#[cfg(feature = "fxhash")]
+pub fn load_from_reader_fx<R: Read>(reader: R, options: &ParseOptions) -> Result<FxPackages> {
+    parse::load_from_reader_hashed(reader, options)
+}
+
+/// Load packages from a string containing queryformat output.
+pub fn load_from_str(s: &str) -> Result<Packages> {
+    parse::load_from_str_impl(s)
+}
+
+/// Load packages from a file descriptor, e.g. a pipe or socket passed by a
+/// privileged helper which ran rpm, reading queryformat output until EOF.
+pub fn load_from_fd(fd: OwnedFd, options: &ParseOptions) -> Result<Packages> {
+    load_from_reader_with_options(std::fs::File::from(fd), options)
+}
+
+/// Load packages from queryformat output served on the Unix socket at `path`,
+/// read until the other end closes the connection.
+pub fn load_from_unix_socket(path: &Utf8Path, options: &ParseOptions) -> Result<Packages> {
+    let stream = UnixStream::connect(path).with_context(|| format!("connecting to {path}"))?;
+    load_from_fd(stream.into(), options)
+}
+
+/// Load all installed RPM packages from a rootfs path by running `rpm -qa`.
+#[cfg(feature = "exec")]
+pub fn load_from_rootfs(rootfs: &Utf8Path) -> Result<Packages> {
+    load_from_rootfs_with_options(rootfs, &ParseOptions::default())
+}
+
+/// Load installed RPM packages from a rootfs path by running `rpm -qa`, using
+/// the given parse options.
+///
+/// With the `native-db` feature, the rpmdb is read directly with
+/// [`load_from_rpmdb_with_options`] if rpm isn't installed.
+#[cfg(feature = "exec")]
+pub fn load_from_rootfs_with_options(
+    rootfs: &Utf8Path,
+    options: &ParseOptions,
+) -> Result<Packages> {
+    match QueryBuilder::new().root(rootfs).load_with_options(options) {
+        #[cfg(feature = "native-db")]
+        Err(e) if matches!(e.inner(), Error::RpmNotFound { .. }) => {
+            load_from_rpmdb_with_options(rootfs, options)
+        }
+        r => r,
+    }
+}
+
+/// Load installed RPM packages from a rootfs path into an [`FxPackages`]
+/// map. See [`load_from_rootfs_with_options`].
+#[cfg(all(feature = "exec", feature = "fxhash"))]
+pub fn load_from_rootfs_fx(rootfs: &Utf8Path, options: &ParseOptions) -> Result<FxPackages> {
+    match QueryBuilder::new().root(rootfs).load_fx(options) {
+        #[cfg(feature = "native-db")]
+        Err(e) if matches!(e.inner(), Error::RpmNotFound { .. }) => {
+            Ok(load_from_rpmdb_with_options(rootfs, options)?
+                .into_iter()
+                .collect())
+        }
+        r => r,
+    }
+}
+
+/// Load all installed RPM packages from a rootfs path by running `rpm -qa`
+/// asynchronously, so as not to block the runtime while rpm enumerates
+/// packages. This must be called within a tokio runtime.
+#[cfg(feature = "async")]
+pub async fn load_from_rootfs_async(rootfs: &Utf8Path) -> Result<Packages> {
+    load_from_rootfs_async_with_options(rootfs, &ParseOptions::default()).await
+}
+
+/// Load installed RPM packages from a rootfs path by running `rpm -qa`
+/// asynchronously, using the given parse options. See
+/// [`load_from_rootfs_async`].
+#[cfg(feature = "async")]
+pub async fn load_from_rootfs_async_with_options(
+    rootfs: &Utf8Path,
+    options: &ParseOptions,
+) -> Result<Packages> {
+    async_exec::load_from_rootfs_async_impl(rootfs, options).await
+}
+
+/// Load all installed RPM packages from a rootfs path by reading its rpmdb
+/// directly, without running rpm, e.g. in containers which don't ship it.
+///
+/// Only the sqlite rpmdb backend is supported, not the older Berkeley DB and
+/// ndb ones. Package headers aren't verified against their digests and
+/// signatures as rpm would.
+#[cfg(feature = "native-db")]
+pub fn load_from_rpmdb(rootfs: &Utf8Path) -> Result<Packages> {
+    load_from_rpmdb_with_options(rootfs, &ParseOptions::default())
+}
+
+/// Load installed RPM packages from a rootfs path by reading its rpmdb
+/// directly, using the given parse options. See [`load_from_rpmdb`].
+#[cfg(feature = "native-db")]
+pub fn load_from_rpmdb_with_options(rootfs: &Utf8Path, options: &ParseOptions) -> Result<Packages> {
+    native_db::load_from_rpmdb_impl(rootfs.as_std_path(), options)
+}
+
+/// Load the package in an rpm file, e.g. to compare what it would install
+/// with what's installed. With the `exec` feature, this runs `rpm -qp`;
+/// otherwise, or with the `native-db` feature if rpm isn't installed, the
+/// file's header is read directly.
+///
+/// Fields only known once a package is installed are absent, e.g.
+/// [`Package::installtime`] is 0 and [`FileInfo::state`] is `None`.
+#[cfg(any(feature = "exec", feature = "native-db"))]
+pub fn load_from_rpm_file(path: &Utf8Path) -> Result<Package> {
+    load_from_rpm_file_with_options(path, &ParseOptions::default())
+}
+
+/// Load the package in an rpm file, using the given parse options. See
+/// [`load_from_rpm_file`].
+#[cfg(any(feature = "exec", feature = "native-db"))]
+pub fn load_from_rpm_file_with_options(path: &Utf8Path, options: &ParseOptions) -> Result<Package> {
+    #[cfg(feature = "exec")]
+    let packages = match QueryBuilder::new().query_file(path, options) {
+        #[cfg(feature = "native-db")]
+        Err(e) if matches!(e.inner(), Error::RpmNotFound { .. }) => {
+            native_db::load_from_rpm_file_impl(path.as_std_path(), options)
+        }
+        r => r,
+    }?;
+    #[cfg(not(feature = "exec"))]
+    let packages = native_db::load_from_rpm_file_impl(path.as_std_path(), options)?;
+    single_package(packages, path.as_str())
+}
+
+/// Load the package in an rpm file read from `reader`, decoding its header
+/// directly; the payload isn't read. See [`load_from_rpm_file`].
+#[cfg(feature = "native-db")]
+pub fn load_from_rpm_reader<R: Read>(reader: R, options: &ParseOptions) -> Result<Package> {
+    let packages = native_db::load_from_rpm_reader_impl(reader, options)?;
+    single_package(packages, "rpm file")
+}
+
+/// Get the one package loaded from an rpm file.
+#[cfg(any(feature = "exec", feature = "native-db"))]
+fn single_package(packages: Packages, what: &str) -> Result<Package> {
+    let mut packages = packages.into_values();
+    match (packages.next(), packages.next()) {
+        (Some(pkg), None) => Ok(pkg),
+        _ => Err(anyhow!("{what}: expected exactly one package").into()),
+    }
+}
+
+/// Load installed RPM packages from a rootfs path by running `rpm -qa`,
+/// skipping over malformed entries instead of failing. See
+/// [`load_from_reader_lenient`].
+#[cfg(feature = "exec")]
+pub fn load_from_rootfs_lenient(
+    rootfs: &Utf8Path,
+    options: &ParseOptions,
+) -> Result<(Packages, Vec<PackageError>)> {
+    QueryBuilder::new().root(rootfs).load_lenient(options)
+}
+
+/// Write the raw `rpm -qa` output for a rootfs path to `writer`, e.g. to save
+/// a snapshot to be loaded later with [`load_from_reader`].
+#[cfg(feature = "exec")]
+pub fn dump_from_rootfs<W: Write>(rootfs: &Utf8Path, writer: W) -> Result<()> {
+    QueryBuilder::new().root(rootfs).dump(writer)
+}
+
+/// Expand an rpm macro expression (e.g. `%{_dbpath}` or `%{?dist}`) with
+/// `rpm --eval`, run as for loading packages from a rootfs path. Note that
+/// rpm reads the macro files of the host rather than those of the rootfs;
+/// only `%{_dbpath}` reflects the rootfs.
+#[cfg(feature = "exec")]
+pub fn eval_macro(rootfs: &Utf8Path, expr: &str) -> Result<String> {
+    QueryBuilder::new().root(rootfs).eval(expr)
+}
+
+/// Capture the `rpm -qa` output for a rootfs path to `writer` in the format
+/// of the crate's test fixtures, keeping only the packages and scrubbing the
+/// strings selected by `options`. The result loads with [`load_from_reader`].
+#[cfg(feature = "exec")]
+pub fn capture_fixture<W: Write>(
+    rootfs: &Utf8Path,
+    options: &FixtureOptions,
+    writer: W,
+) -> Result<()> {
+    Ok(fixture::capture_fixture_impl(rootfs, options, writer)
+        .with_context(|| format!("capturing fixture of {rootfs}"))?)
+}
+
+/// Load all installed RPM packages from a rootfs path, reusing the previously
+/// loaded snapshot if the rpmdb hasn't changed since.
+///
+/// The snapshot is shared process-wide, so multiple components calling this
+/// for the same rootfs get the same `Arc`. Changes are detected by comparing
+/// the size and mtime of the rpmdb files.
+#[cfg(feature = "exec")]
+pub fn load_from_rootfs_cached(rootfs: &Utf8Path) -> Result<Arc<Packages>> {
+    cache::load_from_rootfs_cached_impl(rootfs)
+}
+
+/// Load all installed RPM packages, reusing the previously loaded snapshot if
+/// the rpmdb hasn't changed since. See [`load_from_rootfs_cached`].
+#[cfg(feature = "exec")]
+pub fn load_cached() -> Result<Arc<Packages>> {
+    load_from_rootfs_cached(Utf8Path::new("/"))
 }
 
-/// Load packages from a reader containing queryformat output.
-pub fn load_from_reader<R: Read>(reader: R) -> Result<Packages> {
-    parse::load_from_reader_impl(reader)
+/// Drop all snapshots cached by [`load_cached`] and
+/// [`load_from_rootfs_cached`].
+#[cfg(feature = "exec")]
+pub fn clear_cache() {
+    cache::clear_cache_impl()
 }
 
-/// Load packages from a string containing queryformat output.
-pub fn load_from_str(s: &str) -> Result<Packages> {
-    parse::load_from_str_impl(s)
+/// Register `observer` to be notified of loads and cache lookups
+/// process-wide, replacing any previous one. `None` unregisters it.
+pub fn set_load_observer(observer: Option<Arc<dyn LoadObserver>>) {
+    observer::set_load_observer_impl(observer)
 }
 
-/// Load all installed RPM packages from a rootfs path by running `rpm -qa`.
-pub fn load_from_rootfs(rootfs: &Utf8Path) -> Result<Packages> {
-    run_rpm(rootfs.as_str())
+/// Bring a snapshot previously loaded from a rootfs path up to date.
+///
+/// Rather than re-reading everything, this lists the installed packages and
+/// their install transaction IDs, drops packages which are no longer installed
+/// and only re-queries those installed by a transaction newer than any in the
+/// snapshot. Returns whether the snapshot changed.
+///
+/// If any package in the snapshot lacks an install transaction ID, the whole
+/// snapshot is reloaded.
+#[cfg(feature = "exec")]
+pub fn reload_from_rootfs(rootfs: &Utf8Path, packages: &mut Packages) -> Result<bool> {
+    reload::reload_impl(rootfs, packages)
+}
+
+/// Bring a snapshot previously loaded with [`load`] up to date. See
+/// [`reload_from_rootfs`].
+#[cfg(feature = "exec")]
+pub fn reload(packages: &mut Packages) -> Result<bool> {
+    reload_from_rootfs(Utf8Path::new("/"), packages)
+}
+
+/// Load all installed RPM packages from multiple rootfs paths.
+///
+/// See [`load_many_with_options`].
+#[cfg(feature = "exec")]
+pub fn load_many<P: AsRef<Utf8Path> + Sync>(roots: &[P]) -> HashMap<Utf8PathBuf, Result<Packages>> {
+    load_many_with_options(roots, &ParseOptions::default())
+}
+
+/// Load installed RPM packages from multiple rootfs paths concurrently, using
+/// the given parse options.
+///
+/// At most [`std::thread::available_parallelism`] `rpm` processes run at a
+/// time. Each root maps to its own result so that one broken rpmdb doesn't
+/// prevent loading the others.
+#[cfg(feature = "exec")]
+pub fn load_many_with_options<P: AsRef<Utf8Path> + Sync>(
+    roots: &[P],
+    options: &ParseOptions,
+) -> HashMap<Utf8PathBuf, Result<Packages>> {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let jobs = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(roots.len());
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(HashMap::with_capacity(roots.len()));
+    std::thread::scope(|s| {
+        for _ in 0..jobs {
+            s.spawn(|| {
+                while let Some(root) = roots.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let root = root.as_ref();
+                    let packages = load_from_rootfs_with_options(root, options);
+                    results.lock().unwrap().insert(root.to_path_buf(), packages);
+                }
+            });
+        }
+    });
+    results.into_inner().unwrap()
 }
 
 /// Load all installed RPM packages from a rootfs directory by running `rpm -qa`.
+#[cfg(feature = "exec")]
 pub fn load_from_rootfs_dir(rootfs: &Dir) -> Result<Packages> {
+    load_from_rootfs_dir_with_options(rootfs, &ParseOptions::default())
+}
+
+/// Load installed RPM packages from a rootfs directory by running `rpm -qa`,
+/// using the given parse options.
+///
+/// The rootfs is only accessed through `rootfs`, so this works in sandboxed
+/// programs without ambient filesystem authority (as long as they may run
+/// `rpm`).
+#[cfg(feature = "exec")]
+pub fn load_from_rootfs_dir_with_options(rootfs: &Dir, options: &ParseOptions) -> Result<Packages> {
     use rustix::io::dup;
+    use std::os::fd::AsRawFd;
+    let dbpath = find_dbpath_dir(rootfs)?;
     // Dup the fd as a way to clear O_CLOEXEC so rpm can access it.
     // See also CapStdExtCommandExt::take_fn_n() though here we don't leak.
     let duped = dup(rootfs).context("failed to dup rootfs fd")?;
     let rootfs_path = format!("/proc/self/fd/{}", duped.as_raw_fd());
-    run_rpm(&rootfs_path)
-}
-
-/// Note the host `rpm` resolves `%_dbpath` from its own macro context, not the
-/// target rootfs's. We probe the rootfs to find where the rpmdb actually is and
-/// pass `--dbpath` explicitly to avoid mismatches (e.g. Fedora host reading a
-/// RHEL 9 rootfs).
-const RPMDB_PATHS: &[&str] = &["usr/lib/sysimage/rpm", "var/lib/rpm", "usr/share/rpm"];
-
-fn find_dbpath(rootfs: &Path) -> Result<Option<&'static str>> {
-    for dbpath in RPMDB_PATHS {
-        if std::fs::exists(rootfs.join(dbpath)).context("failed to probe rpmdb path")? {
-            return Ok(Some(dbpath));
-        }
-    }
-    Ok(None)
-}
-
-fn run_rpm(rootfs_path: &str) -> Result<Packages> {
-    let mut cmd = Command::new("rpm");
-    cmd.arg("--root").arg(rootfs_path);
-    if let Some(dbpath) = find_dbpath(Path::new(rootfs_path))? {
-        cmd.arg("--dbpath").arg(format!("/{dbpath}"));
-    }
-    cmd.args(["-qa", "--queryformat", parse::QUERYFORMAT]);
-    cmd.stdout(std::process::Stdio::piped());
-    let mut child = cmd.spawn().context("failed to run rpm")?;
-    let stdout = child
-        .stdout
-        .take()
-        .context("failed to capture rpm stdout")?;
-
-    let packages = load_from_reader(stdout);
-
-    let status = child.wait().context("failed to wait for rpm")?;
-    if !status.success() {
-        match status.code() {
-            Some(code) => bail!("rpm command failed (exit code {})", code),
-            None => {
-                use std::os::unix::process::ExitStatusExt;
-                bail!(
-                    "rpm command killed by signal {}",
-                    status.signal().unwrap_or(0)
-                )
-            }
-        }
-    }
-
-    packages
+    (QueryBuilder::new().root(rootfs_path))
+        .found_dbpath(dbpath)
+        .load_with_options(options)
 }
 
 /// Load all installed RPM packages by running `rpm -qa`.
+#[cfg(feature = "exec")]
 pub fn load() -> Result<Packages> {
     load_from_rootfs(Utf8Path::new("/"))
 }
 
+/// Load the installed RPM package named `name`, if any, by running `rpm -qa`
+/// for it alone. See [`QueryBuilder::load_package`].
+#[cfg(feature = "exec")]
+pub fn load_package(name: &str) -> Result<Option<Package>> {
+    QueryBuilder::new().load_package(name)
+}
+
+/// Load the installed RPM packages whose names match any of the glob
+/// `patterns` (e.g. `kernel*`) by running `rpm -qa` with them. See
+/// [`QueryBuilder::load_matching`].
+#[cfg(feature = "exec")]
+pub fn load_packages_matching(patterns: &[&str]) -> Result<Packages> {
+    QueryBuilder::new().load_matching(patterns)
+}
+
+/// Load all installed RPM packages by running `rpm -qa`, skipping over
+/// malformed entries instead of failing. See [`load_from_reader_lenient`].
+#[cfg(feature = "exec")]
+pub fn load_lenient() -> Result<(Packages, Vec<PackageError>)> {
+    load_from_rootfs_lenient(Utf8Path::new("/"), &ParseOptions::default())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     const FIXTURE: &str = include_str!("../tests/fixtures/fedora.qf");
 
+    #[cfg(any(feature = "exec", feature = "native-db"))]
     fn setup_test_rootfs_at(rpmdb_relpath: &str) -> tempfile::TempDir {
         let tmpdir = tempfile::tempdir().expect("failed to create tempdir");
         let rpmdb_dir = tmpdir.path().join(rpmdb_relpath);
@@ -274,10 +1714,12 @@ mod tests {
         tmpdir
     }
 
+    #[cfg(feature = "exec")]
     fn setup_test_rootfs() -> tempfile::TempDir {
         setup_test_rootfs_at("usr/lib/sysimage/rpm")
     }
 
+    #[cfg(any(feature = "exec", feature = "native-db"))]
     fn assert_has_test_packages(packages: &Packages) {
         assert!(packages.contains_key("filesystem"));
         assert!(packages.contains_key("setup"));
@@ -285,6 +1727,75 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "native-db")]
+    fn test_load_from_rpmdb() {
+        let tmpdir = setup_test_rootfs_at("var/lib/rpm");
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let packages = load_from_rpmdb(rootfs).unwrap();
+        assert_has_test_packages(&packages);
+        assert!(!packages.contains_key("gpg-pubkey"));
+
+        // Reading the rpmdb directly gives the same as going through rpm.
+        #[cfg(feature = "exec")]
+        {
+            let expected = load_from_rootfs(rootfs).unwrap();
+            assert_eq!(packages.len(), expected.len());
+            for (name, pkg) in &expected {
+                let native = &packages[name];
+                assert_eq!(native.nevra(), pkg.nevra());
+                assert_eq!(native.size, pkg.size);
+                assert_eq!(native.installtid, pkg.installtid);
+                assert_eq!(native.signing_key, pkg.signing_key);
+                assert_eq!(native.changelog, pkg.changelog);
+                assert_eq!(native.requires.len(), pkg.requires.len());
+                assert_eq!(native.provides.len(), pkg.provides.len());
+                assert_eq!(
+                    (&native.pre, &native.post, &native.preun, &native.postun),
+                    (&pkg.pre, &pkg.post, &pkg.preun, &pkg.postun)
+                );
+                assert_eq!(native.triggers, pkg.triggers);
+                assert!(native.files.keys().eq(pkg.files.keys()));
+                for (path, info) in &pkg.files {
+                    let file = &native.files[path];
+                    assert_eq!(
+                        (file.size, file.mode, file.digest),
+                        (info.size, info.mode, info.digest)
+                    );
+                }
+            }
+        }
+
+        let empty = tempfile::tempdir().unwrap();
+        let err = load_from_rpmdb(Utf8Path::from_path(empty.path()).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("no rpmdb found"), "{err}");
+
+        let dbpath = empty.path().join("usr/lib/sysimage/rpm");
+        std::fs::create_dir_all(&dbpath).unwrap();
+        std::fs::write(dbpath.join("rpmdb.sqlite"), vec![0x42; 4096]).unwrap();
+        let err = load_from_rpmdb(Utf8Path::from_path(empty.path()).unwrap()).unwrap_err();
+        assert!(matches!(err.inner(), Error::DbCorrupt { .. }), "{err}");
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_signing_report() {
+        let tmpdir = setup_test_rootfs();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let packages = load_from_rootfs(rootfs).unwrap();
+        let keys = load_public_keys(rootfs).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert!(keys[0].summary.starts_with("Fedora (44)"));
+        let report = signing_report(&packages, &keys);
+        assert!(report.is_trusted());
+        assert_eq!(report.keys[0].packages.len(), packages.len());
+        assert_eq!(
+            packages["setup"].signing_key.as_deref(),
+            Some("dbfcf71c6d9f90a6")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
     fn test_load_from_rootfs() {
         let tmpdir = setup_test_rootfs();
         let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
@@ -293,6 +1804,133 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "exec")]
+    fn test_file_states_from_rootfs() {
+        let tmpdir = setup_test_rootfs();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let packages = load_from_rootfs(rootfs).expect("failed to load packages");
+        let setup = &packages["setup"];
+        assert!(setup.files.values().all(|f| f.state.is_some()));
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_weak_dependencies_from_rootfs() {
+        let tmpdir = setup_test_rootfs();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let packages = load_from_rootfs(rootfs).expect("failed to load packages");
+        let weak = weak_dependencies(&packages);
+        assert!(weak.satisfied.iter().any(|dep| {
+            dep.package.name == "fedora-release"
+                && dep.dependency.name == "fedora-release-identity-basic"
+        }));
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_dependency_closure_from_rootfs() {
+        let tmpdir = setup_test_rootfs();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let packages = load_from_rootfs(rootfs).expect("failed to load packages");
+        let setup = &packages["setup"];
+        assert!(setup.provides.iter().any(|dep| dep.name == "setup"));
+        let closure = dependency_closure(&packages, &["fedora-release"]);
+        assert!(
+            closure
+                .required
+                .iter()
+                .any(|pkg| pkg.name == "fedora-release")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_eval_macro() {
+        for dbpath in ["usr/lib/sysimage/rpm", "var/lib/rpm"] {
+            let tmpdir = setup_test_rootfs_at(dbpath);
+            let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+            let expanded = eval_macro(rootfs, "%{_dbpath}").expect("failed to eval");
+            assert_eq!(expanded, format!("/{dbpath}"));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_dump_from_rootfs() {
+        let tmpdir = setup_test_rootfs();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let mut dump = Vec::new();
+        dump_from_rootfs(rootfs, &mut dump).expect("failed to dump rpmdb");
+        let packages = load_from_reader(dump.as_slice()).expect("failed to load dump");
+        assert_has_test_packages(&packages);
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_queryformat_v4() {
+        // Output produced outside of the crate loads as usual.
+        let tmpdir = setup_test_rootfs();
+        let output = std::process::Command::new("rpm")
+            .arg("--root")
+            .arg(tmpdir.path())
+            .args(["-qa", "--queryformat", queryformat_v4()])
+            .output()
+            .expect("failed to run rpm");
+        assert!(output.status.success());
+        let packages = load_from_reader(output.stdout.as_slice()).expect("failed to load output");
+        assert_has_test_packages(&packages);
+        assert!(packages["setup"].installtid.is_some());
+    }
+
+    #[test]
+    fn test_load_from_fd() {
+        use std::io::Write;
+
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+        let writer = std::thread::spawn(move || {
+            tx.write_all(include_bytes!("../tests/fixtures/fedora.qf"))
+                .unwrap()
+        });
+        let packages = load_from_fd(rx.into(), &ParseOptions::default()).unwrap();
+        writer.join().unwrap();
+        assert_eq!(packages.len(), 10);
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = Utf8Path::from_path(tmpdir.path()).unwrap().join("rpm.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .write_all(include_bytes!("../tests/fixtures/multiline.qf"))
+                .unwrap();
+        });
+        let options = ParseOptions::new().names(["plain"]);
+        let packages = load_from_unix_socket(&path, &options).unwrap();
+        server.join().unwrap();
+        assert_eq!(packages.keys().collect::<Vec<_>>(), ["plain"]);
+        assert!(load_from_unix_socket(&path, &options).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_capture_fixture() {
+        let tmpdir = setup_test_rootfs();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let options = FixtureOptions::new()
+            .packages(["setup"])
+            .scrub("fc44", "fcXX");
+        let mut fixture = Vec::new();
+        capture_fixture(rootfs, &options, &mut fixture).expect("failed to capture fixture");
+        let packages = load_from_reader(fixture.as_slice()).expect("failed to load fixture");
+        assert_eq!(packages.len(), 1);
+        assert_eq!(packages["setup"].release, "27.fcXX");
+
+        let options = FixtureOptions::new().scrub("fc44", "\t");
+        assert!(capture_fixture(rootfs, &options, std::io::sink()).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
     fn test_load_from_rootfs_dir() {
         let tmpdir = setup_test_rootfs();
         let rootfs_dir =
@@ -300,9 +1938,16 @@ mod tests {
                 .expect("failed to open rootfs dir");
         let packages = load_from_rootfs_dir(&rootfs_dir).expect("failed to load packages");
         assert_has_test_packages(&packages);
+
+        let options = ParseOptions::new().names(["setup"]);
+        let packages = load_from_rootfs_dir_with_options(&rootfs_dir, &options)
+            .expect("failed to load packages");
+        assert_eq!(packages.len(), 1);
+        assert!(packages.contains_key("setup"));
     }
 
     #[test]
+    #[cfg(feature = "exec")]
     fn test_load_from_rootfs_legacy_dbpath() {
         let tmpdir = setup_test_rootfs_at("var/lib/rpm");
         let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
@@ -310,6 +1955,361 @@ mod tests {
         assert_has_test_packages(&packages);
     }
 
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_query_builder() {
+        // An rpmdb at a path rpm wouldn't find by itself.
+        let tmpdir = setup_test_rootfs_at("layer/rpmdb");
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let packages = QueryBuilder::new()
+            .root(rootfs)
+            .dbpath("/layer/rpmdb")
+            .define("_db_backend", "sqlite")
+            .env("LC_ALL", "C")
+            .load()
+            .expect("failed to load packages");
+        assert_has_test_packages(&packages);
+        assert!(QueryBuilder::new().root(rootfs).load().is_err());
+
+        let query = QueryBuilder::new().root(rootfs).dbpath("/layer/rpmdb");
+        let packages = query.load_matching(&["fedora-release*", "setup"]).unwrap();
+        let mut names: Vec<_> = packages.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "fedora-release",
+                "fedora-release-common",
+                "fedora-release-identity-basic",
+                "setup"
+            ]
+        );
+        assert!(query.load_matching(&[]).unwrap().is_empty());
+        assert!(query.load_matching(&["nonexistent*"]).unwrap().is_empty());
+        let setup = query.load_package("setup").unwrap().unwrap();
+        assert_eq!(setup.name, "setup");
+        assert!(!setup.files.is_empty());
+        assert!(query.load_package("setu?").unwrap().is_none());
+
+        let err = (QueryBuilder::new().rpm("/nonexistent/rpm").root(rootfs))
+            .dbpath("/layer/rpmdb")
+            .load()
+            .unwrap_err();
+        assert!(matches!(err.inner(), Error::RpmNotFound { .. }), "{err}");
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_parse_error_stops_rpm() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmpdir = setup_test_rootfs();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        // An rpm repeating its output until it's not read anymore.
+        let script = tmpdir.path().join("rpm");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\n\
+             case \"$*\" in *--querytags*) exec rpm \"$@\" ;; esac\n\
+             out=$(rpm \"$@\")\n\
+             while :; do printf '%s' \"$out\"; done\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let options = ParseOptions::new().max_packages(5);
+        let err = (QueryBuilder::new().rpm(&script).root(rootfs))
+            .load_with_options(&options)
+            .unwrap_err();
+        assert!(matches!(err, Error::Parse { .. }), "{err}");
+        assert!(err.to_string().contains("more than 5 packages"), "{err}");
+    }
+
+    /// Check a package loaded from `tests/fixtures/foo-1.0-1.fc43.x86_64.rpm`,
+    /// whichever way it was loaded.
+    #[cfg(any(feature = "exec", feature = "native-db"))]
+    pub(crate) fn check_rpm_fixture(foo: &Package) {
+        assert_eq!(foo.nevra(), "foo-2:1.0-1.fc43.x86_64");
+        assert_eq!(foo.evr().to_string(), "2:1.0-1.fc43");
+        assert_eq!(foo.license, "MIT");
+        assert_eq!(foo.sourcerpm.as_deref(), Some("foo-1.0-1.fc43.src.rpm"));
+        assert_eq!(
+            (foo.size, foo.buildtime, foo.installtime),
+            (33, 1700000100, 0)
+        );
+        assert_eq!(foo.digest_algo, Some(DigestAlgorithm::Sha256));
+        let requires: Vec<String> = foo.requires.iter().map(ToString::to_string).collect();
+        assert!(requires.contains(&"/bin/sh".to_string()), "{requires:?}");
+
+        let paths: Vec<&str> = foo.files.keys().map(|p| p.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "/usr/bin/foo",
+                "/usr/bin/foo-link",
+                "/usr/share/doc/foo",
+                "/usr/share/doc/foo/README",
+            ]
+        );
+        let bin = &foo.files[Utf8Path::new("/usr/bin/foo")];
+        assert!(bin.is_file());
+        assert_eq!((bin.mode, bin.size, bin.state), (0o100755, 19, None));
+        assert_eq!(
+            bin.digest.as_ref().map(FileDigest::to_hex).as_deref(),
+            Some("18eb0ba043d6fc5b06b6f785b4a411fa0d6d695c4a08d2497e8b07c4043048f7")
+        );
+        let link = &foo.files[Utf8Path::new("/usr/bin/foo-link")];
+        assert!(link.is_symlink());
+        assert_eq!(link.linkto.as_deref(), Some(Utf8Path::new("foo")));
+        assert!(foo.files[Utf8Path::new("/usr/share/doc/foo")].is_dir());
+        assert!(
+            foo.files[Utf8Path::new("/usr/share/doc/foo/README")]
+                .flags
+                .is_doc()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_load_from_rpm_file() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = Utf8Path::from_path(tmpdir.path()).unwrap().join("foo.rpm");
+        std::fs::write(&path, "not an rpm").unwrap();
+        assert!(load_from_rpm_file(&path).is_err());
+
+        // With rpm -qp.
+        let fixture = Utf8Path::new("tests/fixtures/foo-1.0-1.fc43.x86_64.rpm");
+        check_rpm_fixture(&load_from_rpm_file(fixture).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn test_load_from_rootfs_async() {
+        let tmpdir = setup_test_rootfs();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let packages = runtime
+            .block_on(load_from_rootfs_async(rootfs))
+            .expect("failed to load packages");
+        assert_has_test_packages(&packages);
+        let expected = load_from_rootfs(rootfs).unwrap();
+        assert_eq!(packages.len(), expected.len());
+        for (name, pkg) in &expected {
+            assert_eq!(packages[name].nevra(), pkg.nevra());
+            assert_eq!(packages[name].files.len(), pkg.files.len());
+        }
+
+        let empty = tempfile::tempdir().unwrap();
+        let empty = Utf8Path::from_path(empty.path()).unwrap();
+        assert!(runtime.block_on(load_from_rootfs_async(empty)).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_load_many() {
+        let tmpdir1 = setup_test_rootfs();
+        let tmpdir2 = setup_test_rootfs_at("var/lib/rpm");
+        let root1 = Utf8Path::from_path(tmpdir1.path()).expect("non-utf8 path");
+        let root2 = Utf8Path::from_path(tmpdir2.path()).expect("non-utf8 path");
+        let results = load_many(&[root1, root2]);
+        assert_eq!(results.len(), 2);
+        for root in [root1, root2] {
+            let packages = results[root].as_ref().expect("failed to load packages");
+            assert_has_test_packages(packages);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_rpm_not_found() {
+        let cmd = std::process::Command::new("/nonexistent/rpm");
+        match run_rpm_query(cmd, &ParseOptions::default()).unwrap_err() {
+            Error::RpmNotFound { path } => {
+                assert_eq!(path, std::path::Path::new("/nonexistent/rpm"))
+            }
+            e => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_db_corrupt() {
+        let tmpdir = setup_test_rootfs();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let db = tmpdir.path().join("usr/lib/sysimage/rpm/rpmdb.sqlite");
+        std::fs::write(db, "garbage that is definitely not a sqlite database")
+            .expect("failed to write rpmdb");
+        match load_from_rootfs(rootfs).unwrap_err() {
+            Error::DbCorrupt {
+                stderr,
+                remediation,
+            } => {
+                assert!(stderr.contains("file is not a database"));
+                assert!(remediation.contains("rpm --rebuilddb"));
+            }
+            e => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_rpm_failed() {
+        let tmpdir = tempfile::tempdir().expect("failed to create tempdir");
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        match load_from_rootfs(rootfs).unwrap_err() {
+            Error::RpmFailed { code, stderr, .. } => {
+                assert_ne!(code, Some(0));
+                assert!(!stderr.is_empty());
+            }
+            e => panic!("unexpected error: {e}"),
+        }
+        // A rootfs which looks like an option is still taken as a path.
+        let err = load_from_rootfs(Utf8Path::new("--version")).unwrap_err();
+        assert!(matches!(err, Error::RpmFailed { .. }), "{err}");
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_load_from_rootfs_lenient() {
+        let tmpdir = setup_test_rootfs();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let (packages, errors) = load_from_rootfs_lenient(rootfs, &ParseOptions::default())
+            .expect("failed to load packages");
+        assert_has_test_packages(&packages);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_skip_header_checks() {
+        let tmpdir = setup_test_rootfs();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let options = ParseOptions::new().skip_header_checks(true);
+        let mut cmd = std::process::Command::new("rpm");
+        options.add_rpm_args(&mut cmd);
+        assert_eq!(
+            cmd.get_args().collect::<Vec<_>>(),
+            ["--nodigest", "--nosignature"]
+        );
+        let packages =
+            load_from_rootfs_with_options(rootfs, &options).expect("failed to load packages");
+        assert_has_test_packages(&packages);
+        let (packages, _) =
+            load_from_rootfs_lenient(rootfs, &options).expect("failed to load packages");
+        assert_has_test_packages(&packages);
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_load_from_rootfs_cached() {
+        let tmpdir = setup_test_rootfs();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let first = load_from_rootfs_cached(rootfs).expect("failed to load packages");
+        assert_has_test_packages(&first);
+        let second = load_from_rootfs_cached(rootfs).expect("failed to load packages");
+        assert!(Arc::ptr_eq(&first, &second));
+
+        // Simulate a transaction touching the rpmdb.
+        let db = tmpdir.path().join("usr/lib/sysimage/rpm/rpmdb.sqlite");
+        std::fs::File::options()
+            .write(true)
+            .open(db)
+            .expect("failed to open rpmdb")
+            .set_modified(std::time::SystemTime::UNIX_EPOCH)
+            .expect("failed to set mtime");
+        let third = load_from_rootfs_cached(rootfs).expect("failed to load packages");
+        assert!(!Arc::ptr_eq(&first, &third));
+        assert_has_test_packages(&third);
+
+        clear_cache();
+        let fourth = load_from_rootfs_cached(rootfs).expect("failed to load packages");
+        assert!(!Arc::ptr_eq(&third, &fourth));
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_load_observer() {
+        #[derive(Default)]
+        struct Recorder {
+            loads: std::sync::Mutex<Vec<LoadStats>>,
+            lookups: std::sync::Mutex<Vec<(Utf8PathBuf, bool)>>,
+            verifies: std::sync::Mutex<Vec<VerifyStats>>,
+        }
+        impl LoadObserver for Recorder {
+            fn on_load(&self, stats: &LoadStats) {
+                self.loads.lock().unwrap().push(stats.clone());
+            }
+            fn on_cache_lookup(&self, rootfs: &Utf8Path, hit: bool) {
+                self.lookups.lock().unwrap().push((rootfs.into(), hit));
+            }
+            fn on_verify(&self, stats: &VerifyStats) {
+                self.verifies.lock().unwrap().push(stats.clone());
+            }
+        }
+
+        let tmpdir = setup_test_rootfs();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let recorder = Arc::new(Recorder::default());
+        set_load_observer(Some(recorder.clone()));
+        let packages = load_from_rootfs_cached(rootfs).expect("failed to load packages");
+        load_from_rootfs_cached(rootfs).expect("failed to load packages");
+        #[cfg(feature = "verify")]
+        let report = verify_files(&packages, rootfs, &VerifyOptions::new()).unwrap();
+        set_load_observer(None);
+
+        // Other tests may load concurrently, so only look for ours.
+        let lookups: Vec<bool> = (recorder.lookups.lock().unwrap().iter())
+            .filter(|(path, _)| path == rootfs)
+            .map(|(_, hit)| *hit)
+            .collect();
+        assert_eq!(lookups, [false, true]);
+        let files: usize = packages.values().map(|pkg| pkg.files.len()).sum();
+        assert!(recorder.loads.lock().unwrap().iter().any(|stats| {
+            stats.packages == packages.len()
+                && stats.files == files
+                && stats.bytes > 0
+                && stats.errors == 0
+        }));
+        #[cfg(feature = "verify")]
+        assert!(recorder.verifies.lock().unwrap().iter().any(|stats| {
+            stats.files == report.files
+                && stats.bytes == report.bytes
+                && stats.failures == report.failures.len()
+        }));
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn test_reload_from_rootfs() {
+        let tmpdir = setup_test_rootfs();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+        let mut packages = load_from_rootfs(rootfs).expect("failed to load packages");
+        assert!(packages.values().all(|pkg| pkg.installtid.is_some()));
+        let orig_len = packages.len();
+        assert!(!reload_from_rootfs(rootfs, &mut packages).expect("failed to reload"));
+
+        // A package which is no longer installed gets dropped.
+        let mut bogus = packages["setup"].clone();
+        bogus.name = "bogus".into();
+        packages.insert(bogus.name.clone(), bogus);
+        assert!(reload_from_rootfs(rootfs, &mut packages).expect("failed to reload"));
+        assert!(!packages.contains_key("bogus"));
+        assert_eq!(packages.len(), orig_len);
+
+        // Packages from newer transactions get re-queried.
+        for pkg in packages.values_mut() {
+            pkg.installtid = Some(1);
+        }
+        let filesystem = packages.get_mut("filesystem").unwrap();
+        filesystem.installtid = Some(0);
+        filesystem.files.clear();
+        assert!(reload_from_rootfs(rootfs, &mut packages).expect("failed to reload"));
+        assert!(!packages["filesystem"].files.is_empty());
+        assert!(packages["filesystem"].installtid > Some(1));
+        assert_eq!(packages.len(), orig_len);
+    }
+
     #[test]
     fn test_load_from_str() {
         let packages = load_from_str(FIXTURE).expect("failed to load packages");
@@ -334,11 +2334,163 @@ mod tests {
         assert_eq!(packages["perl-POSIX"].epoch, Some(0));
     }
 
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde() {
+        let packages = load_from_str(FIXTURE).unwrap();
+        let json = serde_json::to_string(&packages).unwrap();
+        let loaded: Packages = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.len(), packages.len());
+        for (name, pkg) in &packages {
+            let other = &loaded[name];
+            assert_eq!(other.nevra(), pkg.nevra());
+            assert_eq!(other.digest_algo, pkg.digest_algo);
+            assert_eq!(other.requires, pkg.requires);
+            assert_eq!(other.changelog, pkg.changelog);
+            assert_eq!(other.files.len(), pkg.files.len());
+            for (path, info) in &pkg.files {
+                let file = &other.files[path];
+                assert_eq!(
+                    (file.digest, file.flags, file.mode, file.state),
+                    (info.digest, info.flags, info.mode, info.state)
+                );
+            }
+        }
+
+        let digest = packages["bash"]
+            .files
+            .values()
+            .find_map(|f| f.digest)
+            .unwrap();
+        let json = serde_json::to_string(&digest).unwrap();
+        assert_eq!(json, format!("\"{digest}\""));
+        assert!(serde_json::from_str::<FileDigest>("\"xyz\"").is_err());
+
+        // Deferred file lists aren't silently left out.
+        let opts = ParseOptions::new().lazy_files(true);
+        let mut lazy = load_from_reader_with_options(FIXTURE.as_bytes(), &opts).unwrap();
+        let err = serde_json::to_string(&lazy).unwrap_err();
+        assert!(err.to_string().contains("parse_files"), "{err}");
+        for pkg in lazy.values_mut() {
+            pkg.parse_files().unwrap();
+        }
+        let loaded: Packages =
+            serde_json::from_str(&serde_json::to_string(&lazy).unwrap()).unwrap();
+        for (name, pkg) in &packages {
+            assert_eq!(loaded[name].files.len(), pkg.files.len());
+            assert!(!loaded[name].has_unparsed_files());
+        }
+    }
+
     #[test]
     fn test_load_from_reader() {
         let packages = load_from_reader(FIXTURE.as_bytes()).expect("failed to load packages");
         assert!(!packages.is_empty(), "expected at least one package");
-        assert!(packages.get("rpm").is_some());
+        assert!(packages.contains_key("rpm"));
+    }
+
+    #[test]
+    #[cfg(feature = "fxhash")]
+    fn test_load_fx() {
+        let packages = load_from_reader(FIXTURE.as_bytes()).unwrap();
+        let fx = load_from_reader_fx(FIXTURE.as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(fx.len(), packages.len());
+        assert!(
+            packages
+                .iter()
+                .all(|(name, pkg)| fx[name].nevra() == pkg.nevra())
+        );
+        #[cfg(feature = "exec")]
+        {
+            let tmpdir = setup_test_rootfs();
+            let rootfs = Utf8Path::from_path(tmpdir.path()).expect("non-utf8 path");
+            let fx = load_from_rootfs_fx(rootfs, &ParseOptions::default()).unwrap();
+            assert_has_test_packages(&fx.into_iter().collect());
+        }
+    }
+
+    #[test]
+    fn test_load_with_filter() {
+        let opts = ParseOptions::new().names(["bash", "glibc"]);
+        let packages =
+            load_from_reader_with_options(FIXTURE.as_bytes(), &opts).expect("failed to load");
+        assert_eq!(packages.len(), 2);
+        assert!(!packages["bash"].files.is_empty());
+        assert!(!packages["bash"].changelog.is_empty());
+
+        let opts = ParseOptions::new().arches(["noarch"]);
+        let packages =
+            load_from_reader_with_options(FIXTURE.as_bytes(), &opts).expect("failed to load");
+        assert!(!packages.is_empty());
+        assert!(packages.values().all(|pkg| pkg.arch == "noarch"));
+    }
+
+    #[test]
+    fn test_strict_validation() {
+        let opts = ParseOptions::new().strict_validation(true);
+        let packages =
+            load_from_reader_with_options(FIXTURE.as_bytes(), &opts).expect("failed to load");
+        assert_eq!(packages.len(), load_from_str(FIXTURE).unwrap().len());
+    }
+
+    #[test]
+    fn test_lazy_files() {
+        let eager = load_from_str(FIXTURE).expect("failed to load packages");
+        let opts = ParseOptions::new().lazy_files(true);
+        let mut lazy =
+            load_from_reader_with_options(FIXTURE.as_bytes(), &opts).expect("failed to load");
+        assert_eq!(eager.len(), lazy.len());
+
+        let bash = lazy.get_mut("bash").expect("bash package not found");
+        assert!(bash.has_unparsed_files());
+        assert!(bash.files.is_empty());
+        assert!(!bash.changelog.is_empty());
+
+        let files = bash.parse_files().expect("failed to parse files");
+        assert_eq!(files.len(), eager["bash"].files.len());
+        assert!(files.contains_key(Utf8Path::new("/usr/bin/bash")));
+        assert!(!bash.has_unparsed_files());
+    }
+
+    #[test]
+    fn test_approx_memory_usage() {
+        let packages = load_from_str(FIXTURE).expect("failed to load packages");
+        let usage = packages.approx_memory_usage();
+        assert!(usage.packages > 0);
+        assert!(usage.files > 0);
+        assert!(usage.paths > 0);
+        assert!(usage.digests > 0);
+        assert_eq!(
+            usage.total(),
+            usage.packages + usage.files + usage.paths + usage.digests
+        );
+        let bash = packages["bash"].approx_memory_usage();
+        assert!(bash.total() < usage.total());
+
+        // With lazy parsing, no memory is attributed to files until parsed.
+        let opts = ParseOptions::new().lazy_files(true);
+        let lazy =
+            load_from_reader_with_options(FIXTURE.as_bytes(), &opts).expect("failed to load");
+        let lazy_usage = lazy.approx_memory_usage();
+        assert_eq!(lazy_usage.paths, 0);
+        assert_eq!(lazy_usage.digests, 0);
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut packages = load_from_str(FIXTURE).expect("failed to load packages");
+        let before = packages.approx_memory_usage();
+        packages.retain_packages(|pkg| pkg.name.starts_with("b") || pkg.name == "glibc");
+        assert_eq!(packages.len(), 2);
+        let after = packages.approx_memory_usage();
+        assert!(after.packages < before.packages);
+
+        let glibc = packages.get_mut("glibc").unwrap();
+        let nfiles = glibc.files.len();
+        glibc.retain_files(|path, _| path.starts_with("/usr/lib64"));
+        assert!(glibc.files.len() < nfiles);
+        assert!(glibc.files.keys().all(|p| p.starts_with("/usr/lib64")));
+        assert!(packages.approx_memory_usage().paths < after.paths);
     }
 
     #[test]
@@ -425,11 +2577,8 @@ mod tests {
             .get(Utf8Path::new("/usr/lib/rpm/macros.d"))
             .expect("/usr/lib/rpm/macros.d not found in rpm");
         // Directory mode: 0o40755 = 16877
-        assert_eq!(
-            macros_d.mode & 0o170000,
-            0o040000,
-            "macros.d should be a directory"
-        );
+        assert!(macros_d.is_dir(), "macros.d should be a directory");
+        assert_eq!(macros_d.file_type(), FileInfo::S_IFDIR);
 
         // Verify fedora-release-common owns macros.dist file
         assert!(
@@ -441,18 +2590,16 @@ mod tests {
 
         // Verify the file is NOT in rpm's file list
         assert!(
-            rpm.files
-                .get(Utf8Path::new("/usr/lib/rpm/macros.d/macros.dist"))
-                .is_none(),
+            !rpm.files
+                .contains_key(Utf8Path::new("/usr/lib/rpm/macros.d/macros.dist")),
             "macros.dist should not be owned by rpm"
         );
 
         // Verify the directory is NOT in fedora-release-common's file list
         assert!(
-            fedora_release
+            !fedora_release
                 .files
-                .get(Utf8Path::new("/usr/lib/rpm/macros.d"))
-                .is_none(),
+                .contains_key(Utf8Path::new("/usr/lib/rpm/macros.d")),
             "macros.d directory should not be owned by fedora-release-common"
         );
     }
@@ -480,26 +2627,101 @@ mod tests {
         assert_eq!(file.user, "root");
         assert_eq!(file.group, "root");
         assert_eq!(
-            file.digest.as_deref(),
+            file.digest.map(|d| d.to_hex()).as_deref(),
             Some("d0ba061c715c73b91d2be66ab40adfab510ed4e69cf5d40970733e211de38ce6")
         );
+        assert_eq!(file.digest.unwrap().as_bytes().len(), 32);
     }
 
     #[test]
-    fn test_changelog_times() {
+    fn test_changelog() {
         let packages = load_from_str(FIXTURE).expect("failed to load packages");
 
         // bash package should have multiple changelog entries
         let bash = packages.get("bash").expect("bash package not found");
         assert!(
-            !bash.changelog_times.is_empty(),
+            !bash.changelog.is_empty(),
             "bash should have changelog entries"
         );
 
         // Verify changelog times are reasonable Unix timestamps (after 2020)
         let min_valid_time = 1577836800u64; // 2020-01-01
-        for &time in &bash.changelog_times {
+        for entry in &bash.changelog {
+            let time = entry.time;
             assert!(time > min_valid_time, "changelog time {} is too old", time);
         }
     }
+
+    #[test]
+    fn test_size_by_prefix() {
+        let packages = load_from_str(FIXTURE).expect("failed to load packages");
+        let ranked = packages.size_by_prefix(Utf8Path::new("/usr/bin/"));
+        assert_eq!(ranked[0].0.name, "coreutils");
+        let size: u64 = (packages["coreutils"].files.iter())
+            .filter(|(path, info)| path.starts_with("/usr/bin") && info.is_file())
+            .map(|(_, info)| info.size)
+            .sum();
+        assert_eq!(ranked[0].1, size);
+        assert!(ranked.windows(2).all(|w| w[0].1 >= w[1].1));
+        // Prefixes match whole path components.
+        assert!(packages.size_by_prefix(Utf8Path::new("/usr/bi")).is_empty());
+        let total: u64 = (packages.size_by_prefix(Utf8Path::new("/")).iter())
+            .map(|(_, size)| size)
+            .sum();
+        assert!(total >= ranked.iter().map(|(_, size)| size).sum());
+    }
+
+    #[test]
+    fn test_files() {
+        let packages = load_from_str(FIXTURE).expect("failed to load packages");
+        let count: usize = packages.values().map(|pkg| pkg.files.len()).sum();
+        assert_eq!(packages.files().count(), count);
+        let (pkg, _, info) = (packages.files())
+            .find(|(_, path, _)| *path == "/usr/bin/bash")
+            .unwrap();
+        assert_eq!(pkg.name, "bash");
+        assert!(info.is_file() && !info.is_dir() && !info.is_symlink());
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::iter::ParallelIterator;
+            assert_eq!(packages.par_files().count(), count);
+            let size: u64 = packages.files().map(|(_, _, info)| info.size).sum();
+            assert_eq!(
+                packages
+                    .par_files()
+                    .map(|(_, _, info)| info.size)
+                    .sum::<u64>(),
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn test_update_frequency() {
+        let mut packages = load_from_str(FIXTURE).expect("failed to load packages");
+        let pkg = packages.get_mut("bash").unwrap();
+        const DAY: u64 = 86400;
+        pkg.changelog = [1000 + 365 * DAY, 1000 + 200 * DAY, 1000]
+            .map(|time| ChangelogEntry {
+                time,
+                author: String::new(),
+                text: String::new(),
+            })
+            .into();
+        let frequency = pkg.update_frequency().unwrap();
+        assert!((frequency - 2.0 * 365.25 / 365.0).abs() < 1e-9);
+        pkg.changelog.truncate(1);
+        assert_eq!(pkg.update_frequency(), None);
+
+        let ranked = packages.rank_by_churn();
+        assert!(!ranked.iter().any(|(pkg, _)| pkg.name == "bash"));
+        assert!(ranked.windows(2).all(|w| w[0].1 >= w[1].1));
+        assert_eq!(
+            ranked.len(),
+            packages
+                .values()
+                .filter(|pkg| pkg.update_frequency().is_some())
+                .count()
+        );
+    }
 }
@@ -0,0 +1,182 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::*;
+
+/// A package expected to be installed, e.g. from a `[[packages]]` entry of an
+/// image builder blueprint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageSpec {
+    /// Package name.
+    pub name: String,
+    /// Expected version, if any. This is a glob (`*` and `?` are supported)
+    /// matched against the installed package's `version`,
+    /// `version-release` and, if it has an epoch, `epoch:version-release`.
+    pub version: Option<String>,
+}
+
+impl PackageSpec {
+    /// Expect a package with any version.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            version: None,
+        }
+    }
+
+    /// Expect a package with a version matching `version`.
+    pub fn with_version(name: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            version: Some(version.into()),
+        }
+    }
+
+    /// Whether `pkg` has the expected version.
+    pub fn matches_version(&self, pkg: &Package) -> bool {
+        let Some(pattern) = self.version.as_deref() else {
+            return true;
+        };
+        let vr = format!("{}-{}", pkg.version, pkg.release);
+        glob_match(pattern, &pkg.version)
+            || glob_match(pattern, &vr)
+            || pkg
+                .epoch
+                .is_some_and(|epoch| glob_match(pattern, &format!("{epoch}:{vr}")))
+    }
+}
+
+/// An expected package installed with another version. See
+/// [`check_manifest`].
+#[derive(Debug, Clone)]
+pub struct VersionMismatch<'a> {
+    /// What was expected.
+    pub spec: &'a PackageSpec,
+    /// The installed packages with that name.
+    pub installed: Vec<&'a Package>,
+}
+
+/// How installed packages differ from an expected list. See
+/// [`check_manifest`].
+#[derive(Debug, Clone, Default)]
+pub struct ManifestReport<'a> {
+    /// Expected packages which aren't installed.
+    pub missing: Vec<&'a PackageSpec>,
+    /// Installed packages which weren't listed, sorted by name. This usually
+    /// includes the dependencies of the listed packages.
+    pub extra: Vec<&'a Package>,
+    /// Expected packages installed with another version.
+    pub mismatched: Vec<VersionMismatch<'a>>,
+}
+
+impl ManifestReport<'_> {
+    /// Whether every expected package is installed with the expected version.
+    /// Extra packages are ignored.
+    pub fn is_satisfied(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+/// Compare the expected packages `specs`, e.g. from an image definition, with
+/// what's actually installed. Missing and mismatched packages are reported in
+/// the order of `specs`.
+pub fn check_manifest<'a>(specs: &'a [PackageSpec], packages: &'a Packages) -> ManifestReport<'a> {
+    // Keys aren't necessarily names (see DuplicatePolicy::Merge).
+    let mut by_name: HashMap<&str, Vec<&Package>> = HashMap::new();
+    for pkg in packages.values() {
+        by_name.entry(&pkg.name).or_default().push(pkg);
+    }
+
+    let mut report = ManifestReport::default();
+    for spec in specs {
+        match by_name.get(spec.name.as_str()) {
+            None => report.missing.push(spec),
+            Some(installed) if !installed.iter().any(|pkg| spec.matches_version(pkg)) => {
+                report.mismatched.push(VersionMismatch {
+                    spec,
+                    installed: installed.clone(),
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    let listed: HashSet<&str> = specs.iter().map(|spec| spec.name.as_str()).collect();
+    report.extra = packages
+        .values()
+        .filter(|pkg| !listed.contains(pkg.name.as_str()))
+        .collect();
+    report.extra.sort_by(|a, b| a.name.cmp(&b.name));
+    report
+}
+
+/// Match `s` against a glob `pattern` supporting `*` and `?`.
+fn glob_match(pattern: &str, s: &str) -> bool {
+    let (p, s): (Vec<char>, Vec<char>) = (pattern.chars().collect(), s.chars().collect());
+    let (mut pi, mut si) = (0, 0);
+    // Position of the last `*` and what it matched up to, to backtrack to.
+    let mut star = None;
+    while si < s.len() {
+        match p.get(pi) {
+            Some('*') => {
+                star = Some((pi, si));
+                pi += 1;
+            }
+            Some(&c) if c == '?' || c == s[si] => {
+                pi += 1;
+                si += 1;
+            }
+            _ => match star {
+                Some((spi, ssi)) => {
+                    pi = spi + 1;
+                    si = ssi + 1;
+                    star = Some((spi, ssi + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/fedora.qf");
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("5.3.0", "5.3.0"));
+        assert!(glob_match("5.*", "5.3.0"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("5.?.0-*.fc43", "5.3.0-2.fc43"));
+        assert!(glob_match("*.fc*", "5.3.0-2.fc43"));
+        assert!(!glob_match("5.3", "5.3.0"));
+        assert!(!glob_match("6.*", "5.3.0"));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test]
+    fn test_check_manifest() {
+        let packages = load_from_str(FIXTURE).unwrap();
+        let specs = [
+            PackageSpec::new("bash"),
+            PackageSpec::with_version("coreutils", "9.*"),
+            PackageSpec::with_version("glibc", "1.0"),
+            PackageSpec::new("vim-enhanced"),
+            PackageSpec::with_version("rpm", "*-*.fc43"),
+        ];
+        let report = check_manifest(&specs, &packages);
+        assert!(!report.is_satisfied());
+        assert_eq!(report.missing, [&specs[3]]);
+        assert_eq!(report.mismatched.len(), 1);
+        assert_eq!(report.mismatched[0].spec.name, "glibc");
+        assert_eq!(report.mismatched[0].installed[0].name, "glibc");
+        let extra: Vec<&str> = report.extra.iter().map(|pkg| pkg.name.as_str()).collect();
+        assert_eq!(extra.len(), packages.len() - 4);
+        assert!(extra.is_sorted());
+        assert!(!extra.contains(&"bash"));
+
+        let report = check_manifest(&specs[..2], &packages);
+        assert!(report.is_satisfied());
+    }
+}
@@ -0,0 +1,121 @@
+use std::mem::size_of;
+
+use crate::*;
+
+/// Estimated heap memory used by package data, in bytes.
+///
+/// This counts the allocations owned by the data structures (including unused
+/// capacity), but not allocator overhead or the internal nodes of the file
+/// maps, so it's a lower bound.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Package map entries and package metadata, excluding files.
+    pub packages: usize,
+    /// File metadata, excluding paths and digests.
+    pub files: usize,
+    /// File paths.
+    pub paths: usize,
+    /// File digests.
+    pub digests: usize,
+}
+
+impl MemoryUsage {
+    /// Get the total estimated bytes.
+    pub fn total(&self) -> usize {
+        self.packages + self.files + self.paths + self.digests
+    }
+}
+
+impl std::ops::AddAssign for MemoryUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.packages += other.packages;
+        self.files += other.files;
+        self.paths += other.paths;
+        self.digests += other.digests;
+    }
+}
+
+pub(crate) fn package_memory_usage(pkg: &Package) -> MemoryUsage {
+    let mut usage = MemoryUsage {
+        packages: pkg.name.capacity()
+            + pkg.version.capacity()
+            + pkg.release.capacity()
+            + pkg.arch.capacity()
+            + pkg.license.capacity()
+            + pkg.sourcerpm.as_ref().map_or(0, String::capacity)
+            + pkg.signing_key.as_ref().map_or(0, String::capacity)
+            + pkg.changelog.capacity() * size_of::<ChangelogEntry>()
+            + (pkg.changelog.iter())
+                .map(|entry| entry.author.capacity() + entry.text.capacity())
+                .sum::<usize>()
+            + [
+                &pkg.requires,
+                &pkg.provides,
+                &pkg.recommends,
+                &pkg.suggests,
+                &pkg.obsoletes,
+                &pkg.conflicts,
+            ]
+            .into_iter()
+            .map(|deps| {
+                deps.capacity() * size_of::<Dependency>()
+                    + (deps.iter())
+                        .map(|dep| {
+                            dep.name.capacity() + dep.version.as_ref().map_or(0, String::capacity)
+                        })
+                        .sum::<usize>()
+            })
+            .sum::<usize>()
+            + (pkg.pre.iter())
+                .chain(&pkg.post)
+                .chain(&pkg.preun)
+                .chain(&pkg.postun)
+                .chain(pkg.triggers.iter().map(|trigger| &trigger.scriptlet))
+                .map(|scriptlet| {
+                    scriptlet.interpreter.as_ref().map_or(0, String::capacity)
+                        + scriptlet.script.as_ref().map_or(0, String::capacity)
+                })
+                .sum::<usize>()
+            + pkg.triggers.capacity() * size_of::<Trigger>()
+            + (pkg.triggers.iter())
+                .map(|trigger| {
+                    trigger.conditions.capacity() * size_of::<Dependency>()
+                        + (trigger.conditions.iter())
+                            .map(|dep| {
+                                dep.name.capacity()
+                                    + dep.version.as_ref().map_or(0, String::capacity)
+                            })
+                            .sum::<usize>()
+                })
+                .sum::<usize>()
+            + pkg
+                .unparsed_files
+                .as_ref()
+                .map_or(0, |raw| raw.data.capacity()),
+        ..Default::default()
+    };
+    let entry_size = size_of::<(Utf8PathBuf, FileInfo)>() - size_of::<Option<FileDigest>>();
+    for (path, info) in &pkg.files {
+        usage.paths += path.capacity();
+        usage.digests += size_of::<Option<FileDigest>>();
+        usage.files += entry_size
+            + info.user.capacity()
+            + info.group.capacity()
+            + info.linkto.as_ref().map_or(0, |p| p.capacity());
+    }
+    usage
+}
+
+pub(crate) fn packages_memory_usage(packages: &Packages) -> MemoryUsage {
+    let mut usage = MemoryUsage {
+        // Approximation of the hash table: one entry plus a control byte per
+        // bucket.
+        packages: packages.capacity() * (size_of::<(String, Package)>() + 1),
+        ..Default::default()
+    };
+    for (name, pkg) in packages {
+        usage.packages += name.capacity();
+        usage += package_memory_usage(pkg);
+    }
+    usage
+}
@@ -0,0 +1,700 @@
+use anyhow::{Context, bail};
+use rusqlite::{Connection, ErrorCode, OpenFlags, OptionalExtension};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::*;
+
+/// Numbers of the header tags in [`QUERYFORMAT`], as in rpm's `rpmtag.h`.
+const TAGS: &[(&str, u32)] = &[
+    ("DSAHEADER", 267),
+    ("RSAHEADER", 268),
+    ("NAME", 1000),
+    ("VERSION", 1001),
+    ("RELEASE", 1002),
+    ("EPOCH", 1003),
+    ("BUILDTIME", 1006),
+    ("INSTALLTIME", 1008),
+    ("SIZE", 1009),
+    ("LICENSE", 1014),
+    ("ARCH", 1022),
+    ("PREIN", 1023),
+    ("POSTIN", 1024),
+    ("PREUN", 1025),
+    ("POSTUN", 1026),
+    ("FILESIZES", 1028),
+    ("FILESTATES", 1029),
+    ("FILEMODES", 1030),
+    ("FILEMTIMES", 1034),
+    ("FILEDIGESTS", 1035),
+    ("FILELINKTOS", 1036),
+    ("FILEFLAGS", 1037),
+    ("FILEUSERNAME", 1039),
+    ("FILEGROUPNAME", 1040),
+    ("SOURCERPM", 1044),
+    ("PROVIDENAME", 1047),
+    ("REQUIREFLAGS", 1048),
+    ("REQUIRENAME", 1049),
+    ("REQUIREVERSION", 1050),
+    ("CONFLICTFLAGS", 1053),
+    ("CONFLICTNAME", 1054),
+    ("CONFLICTVERSION", 1055),
+    ("TRIGGERSCRIPTS", 1065),
+    ("TRIGGERNAME", 1066),
+    ("TRIGGERVERSION", 1067),
+    ("TRIGGERFLAGS", 1068),
+    ("TRIGGERINDEX", 1069),
+    ("CHANGELOGTIME", 1080),
+    ("CHANGELOGNAME", 1081),
+    ("CHANGELOGTEXT", 1082),
+    ("PREINPROG", 1085),
+    ("POSTINPROG", 1086),
+    ("PREUNPROG", 1087),
+    ("POSTUNPROG", 1088),
+    ("OBSOLETENAME", 1090),
+    ("TRIGGERSCRIPTPROG", 1092),
+    ("FILELANGS", 1097),
+    ("PROVIDEFLAGS", 1112),
+    ("PROVIDEVERSION", 1113),
+    ("OBSOLETEFLAGS", 1114),
+    ("OBSOLETEVERSION", 1115),
+    ("DIRINDEXES", 1116),
+    ("BASENAMES", 1117),
+    ("DIRNAMES", 1118),
+    ("INSTALLTID", 1128),
+    ("FILECOLORS", 1140),
+    ("LONGFILESIZES", 5008),
+    ("LONGSIZE", 5009),
+    ("FILECAPS", 5010),
+    ("FILEDIGESTALGO", 5011),
+    ("RECOMMENDNAME", 5046),
+    ("RECOMMENDVERSION", 5047),
+    ("RECOMMENDFLAGS", 5048),
+    ("SUGGESTNAME", 5049),
+    ("SUGGESTVERSION", 5050),
+    ("SUGGESTFLAGS", 5051),
+];
+
+/// Tags rpm falls back to when a package lacks the 64-bit variant.
+const FALLBACK_TAGS: &[(&str, &str)] = &[("LONGSIZE", "SIZE"), ("LONGFILESIZES", "FILESIZES")];
+
+/// Load installed packages from the sqlite rpmdb of a rootfs, decoding the
+/// package headers ourselves instead of running rpm.
+///
+/// The headers are rendered into [`QUERYFORMAT`] output as rpm would, so
+/// everything downstream is shared with loading through rpm.
+pub(crate) fn load_from_rpmdb_impl(rootfs: &Path, options: &ParseOptions) -> Result<Packages> {
+    let path = find_rpmdb(rootfs)?;
+    let reader =
+        RpmdbReader::open(&path).with_context(|| format!("opening rpmdb {}", path.display()))?;
+    load_from_reader_with_options(reader, options)
+}
+
+/// Load the package in an rpm file, decoding its header ourselves instead of
+/// running `rpm -qp`.
+pub(crate) fn load_from_rpm_file_impl(path: &Path, options: &ParseOptions) -> Result<Packages> {
+    let out = (std::fs::File::open(path).map_err(anyhow::Error::from))
+        .and_then(|file| render_rpm_file(std::io::BufReader::new(file)))
+        .with_context(|| format!("reading {}", path.display()))?;
+    load_from_reader_with_options(out.as_bytes(), options)
+}
+
+/// Load the package in an rpm file read from `reader`. See
+/// [`load_from_rpm_file_impl`].
+pub(crate) fn load_from_rpm_reader_impl(
+    reader: impl Read,
+    options: &ParseOptions,
+) -> Result<Packages> {
+    let out = render_rpm_file(reader).context("reading rpm file")?;
+    load_from_reader_with_options(out.as_bytes(), options)
+}
+
+/// Render the header of an rpm file into queryformat output. The file starts
+/// with a lead, then the signature header, padded to 8 bytes, and the main
+/// header; the payload after it isn't read.
+fn render_rpm_file(mut file: impl Read) -> anyhow::Result<String> {
+    let mut lead = [0u8; 96];
+    file.read_exact(&mut lead).context("reading lead")?;
+    if lead[..4] != RPM_LEAD_MAGIC {
+        bail!("not an rpm package");
+    }
+    let sig_blob = read_header_blob(&mut file).context("reading signature header")?;
+    let pad = (8 - sig_blob.len() % 8) % 8;
+    file.read_exact(&mut [0u8; 8][..pad])
+        .context("reading signature header")?;
+    let blob = read_header_blob(&mut file).context("reading header")?;
+
+    let sig = Header::parse(&sig_blob).context("invalid signature header")?;
+    let mut header = Header::parse(&blob).context("invalid header")?;
+    // As rpm does, make the header signatures (DSAHEADER and RSAHEADER)
+    // available as tags of the header. The other tags of the signature header
+    // have their own numbering.
+    let mut sig_entries = sig.entries;
+    for tag in [267, 268] {
+        if let Some(value) = sig_entries.remove(&tag) {
+            header.entries.entry(tag).or_insert(value);
+        }
+    }
+    let mut out = String::new();
+    render(&compile(QUERYFORMAT)?, &header, None, &mut out);
+    Ok(out)
+}
+
+/// Magic numbers at the start of an rpm file and of the headers in it.
+const RPM_LEAD_MAGIC: [u8; 4] = [0xed, 0xab, 0xee, 0xdb];
+const HEADER_MAGIC: [u8; 8] = [0x8e, 0xad, 0xe8, 0x01, 0, 0, 0, 0];
+
+/// Read a header as stored in an rpm file, returning it without its magic as
+/// in the rpmdb.
+fn read_header_blob(reader: &mut impl Read) -> anyhow::Result<Vec<u8>> {
+    let mut intro = [0u8; 16];
+    reader.read_exact(&mut intro)?;
+    if intro[..8] != HEADER_MAGIC {
+        bail!("bad header magic");
+    }
+    let il = u32::from_be_bytes(intro[8..12].try_into().unwrap()) as u64;
+    let dl = u32::from_be_bytes(intro[12..16].try_into().unwrap()) as u64;
+    let len = 16 * il + dl;
+    // rpm refuses headers larger than this, too.
+    if len > 256 << 20 {
+        bail!("header is too large");
+    }
+    let mut blob = intro[8..].to_vec();
+    reader.take(len).read_to_end(&mut blob)?;
+    if blob.len() as u64 != 8 + len {
+        bail!("header is truncated");
+    }
+    Ok(blob)
+}
+
+/// Find the sqlite rpmdb of a rootfs.
+fn find_rpmdb(rootfs: &Path) -> anyhow::Result<PathBuf> {
+    for dbpath in RPMDB_PATHS {
+        let dir = rootfs.join(dbpath);
+        let path = dir.join("rpmdb.sqlite");
+        if std::fs::exists(&path).context("failed to probe rpmdb path")? {
+            return Ok(path);
+        }
+        if std::fs::exists(&dir).context("failed to probe rpmdb path")? {
+            bail!(
+                "{}: only sqlite rpmdbs can be read without rpm",
+                dir.display()
+            );
+        }
+    }
+    bail!("no rpmdb found in {}", rootfs.display())
+}
+
+/// Renders the package headers of an rpmdb into queryformat output, one at a
+/// time as it's read.
+struct RpmdbReader {
+    conn: Connection,
+    /// Header numbers of the packages left to render.
+    hnums: std::vec::IntoIter<i64>,
+    format: Vec<Item<'static>>,
+    /// Output of the current package, and how much of it was read.
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl RpmdbReader {
+    fn open(path: &Path) -> anyhow::Result<Self> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(sqlite_error)?;
+        let hnums = list_hnums(&conn).map_err(sqlite_error)?;
+        Ok(Self {
+            conn,
+            hnums: hnums.into_iter(),
+            format: compile(QUERYFORMAT)?,
+            buf: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    /// Render the next package into the buffer. Returns false once there are
+    /// no more packages.
+    fn render_next(&mut self) -> anyhow::Result<bool> {
+        self.buf.clear();
+        self.pos = 0;
+        let Some(hnum) = self.hnums.next() else {
+            return Ok(false);
+        };
+        let blob: Option<Vec<u8>> = (self.conn)
+            .prepare_cached("SELECT blob FROM Packages WHERE hnum = ?1")
+            .and_then(|mut stmt| stmt.query_row([hnum], |row| row.get(0)).optional())
+            .map_err(sqlite_error)?;
+        // The package may have been removed since we listed them.
+        let Some(blob) = blob else {
+            return Ok(true);
+        };
+        let header = Header::parse(&blob)
+            .map_err(|e| corrupt_error(format!("invalid header {hnum}: {e:#}")))?;
+        let mut out = String::new();
+        render(&self.format, &header, None, &mut out);
+        self.buf = out.into_bytes();
+        Ok(true)
+    }
+}
+
+impl Read for RpmdbReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.buf.len() {
+            // Errors are passed through to the parser as they are.
+            if !(self.render_next()).map_err(|e| std::io::Error::other(Error::from(e)))? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// List the header numbers of the packages in an rpmdb.
+fn list_hnums(conn: &Connection) -> rusqlite::Result<Vec<i64>> {
+    conn.prepare("SELECT hnum FROM Packages ORDER BY hnum")?
+        .query_map([], |row| row.get(0))?
+        .collect()
+}
+
+/// Report an rpmdb which can't be read because it's corrupted.
+fn corrupt_error(message: String) -> anyhow::Error {
+    Error::DbCorrupt {
+        stderr: message,
+        remediation: error::DB_CORRUPT_REMEDIATION,
+    }
+    .into()
+}
+
+/// Convert a sqlite error, telling a corrupted database apart.
+fn sqlite_error(e: rusqlite::Error) -> anyhow::Error {
+    match e.sqlite_error_code() {
+        Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase) => corrupt_error(e.to_string()),
+        _ => e.into(),
+    }
+}
+
+/// The data of a header entry.
+enum Value<'a> {
+    Ints(Vec<u64>),
+    Strings(Vec<Cow<'a, str>>),
+    Bin(&'a [u8]),
+}
+
+impl Value<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Value::Ints(v) => v.len(),
+            Value::Strings(v) => v.len(),
+            Value::Bin(_) => 1,
+        }
+    }
+}
+
+/// A package header, as stored in the rpmdb: a count of index entries and the
+/// length of the data, the index entries (tag, type, offset and count), then
+/// the data, all big-endian.
+struct Header<'a> {
+    entries: HashMap<u32, Value<'a>>,
+    /// Paths of the files, as for rpm's FILENAMES extension tag.
+    filenames: Option<Value<'a>>,
+}
+
+impl<'a> Header<'a> {
+    fn parse(blob: &'a [u8]) -> anyhow::Result<Self> {
+        let u32_at = |b: &[u8], off: usize| -> anyhow::Result<u32> {
+            let bytes = b.get(off..off + 4).context("header is truncated")?;
+            Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+        };
+        let il = u32_at(blob, 0)? as usize;
+        let dl = u32_at(blob, 4)? as usize;
+        let data_start = il
+            .checked_mul(16)
+            .and_then(|n| n.checked_add(8))
+            .context("header is too large")?;
+        let data = (blob.get(data_start..))
+            .and_then(|data| data.get(..dl))
+            .context("header is truncated")?;
+        let mut entries = HashMap::new();
+        for i in 0..il {
+            let entry = &blob[8 + 16 * i..8 + 16 * (i + 1)];
+            let tag = u32_at(entry, 0)?;
+            // Only decode what we render, skipping e.g. region tags.
+            if !TAGS.iter().any(|(_, t)| *t == tag) {
+                continue;
+            }
+            let kind = u32_at(entry, 4)?;
+            let (offset, count) = (u32_at(entry, 8)? as usize, u32_at(entry, 12)? as usize);
+            let value = parse_value(data, kind, offset, count)
+                .with_context(|| format!("invalid entry for tag {tag}"))?;
+            if let Some(value) = value {
+                entries.insert(tag, value);
+            }
+        }
+        let mut header = Self {
+            entries,
+            filenames: None,
+        };
+        header.filenames = header.join_filenames();
+        Ok(header)
+    }
+
+    /// Get the value of a tag by name, including the FILENAMES extension tag
+    /// and falling back to older tags as rpm does.
+    fn get(&self, name: &str) -> Option<&Value<'a>> {
+        if name == "FILENAMES" {
+            return self.filenames.as_ref();
+        }
+        let tag = |name: &str| TAGS.iter().find(|(n, _)| *n == name).map(|(_, tag)| *tag);
+        if let Some(value) = tag(name).and_then(|tag| self.entries.get(&tag)) {
+            return Some(value);
+        }
+        let (_, old) = FALLBACK_TAGS.iter().find(|(new, _)| *new == name)?;
+        self.entries.get(&tag(old)?)
+    }
+
+    /// Join the directory and base names of files into paths.
+    fn join_filenames(&self) -> Option<Value<'a>> {
+        let (Some(Value::Strings(dirs)), Some(Value::Ints(indexes)), Some(Value::Strings(bases))) = (
+            self.get("DIRNAMES"),
+            self.get("DIRINDEXES"),
+            self.get("BASENAMES"),
+        ) else {
+            return None;
+        };
+        let names = (indexes.iter().zip(bases))
+            .map(|(i, base)| {
+                let dir = dirs.get(*i as usize).map_or("", |dir| dir);
+                Cow::Owned(format!("{dir}{base}"))
+            })
+            .collect();
+        Some(Value::Strings(names))
+    }
+}
+
+/// Decode the data of a header entry of type `kind`. Types we don't use
+/// (e.g. region tags) are skipped.
+fn parse_value(
+    data: &[u8],
+    kind: u32,
+    offset: usize,
+    count: usize,
+) -> anyhow::Result<Option<Value<'_>>> {
+    let data = data.get(offset..).context("offset out of bounds")?;
+    let ints = |size: usize| -> anyhow::Result<Value> {
+        let len = count.checked_mul(size).context("count out of bounds")?;
+        let bytes = data.get(..len).context("count out of bounds")?;
+        Ok(Value::Ints(
+            (bytes.chunks_exact(size))
+                .map(|b| b.iter().fold(0u64, |n, b| (n << 8) | u64::from(*b)))
+                .collect(),
+        ))
+    };
+    let value = match kind {
+        // CHAR, INT8, INT16, INT32, INT64
+        1 | 2 => ints(1)?,
+        3 => ints(2)?,
+        4 => ints(4)?,
+        5 => ints(8)?,
+        // STRING, STRING_ARRAY, I18NSTRING
+        6 | 8 | 9 => {
+            let mut strings = Vec::with_capacity(count.min(data.len()));
+            let mut rest = data;
+            for _ in 0..count {
+                let end = (rest.iter().position(|b| *b == 0)).context("unterminated string")?;
+                strings.push(String::from_utf8_lossy(&rest[..end]));
+                rest = &rest[end + 1..];
+            }
+            Value::Strings(strings)
+        }
+        // BIN
+        7 => Value::Bin(data.get(..count).context("count out of bounds")?),
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
+/// A piece of a queryformat.
+enum Item<'q> {
+    Literal(&'q str),
+    Tag {
+        name: &'q str,
+        pgpsig: bool,
+    },
+    /// A `[...]` array, repeated for each element of its tags.
+    Array(Vec<Item<'q>>),
+}
+
+/// Compile a queryformat into items. Only what [`QUERYFORMAT`] uses is
+/// supported: tags with an optional `:pgpsig` formatter and non-nested
+/// arrays.
+fn compile(format: &str) -> anyhow::Result<Vec<Item<'_>>> {
+    let mut items = Vec::new();
+    let mut rest = format;
+    while !rest.is_empty() {
+        if let Some(tail) = rest.strip_prefix("%{") {
+            let end = tail.find('}').context("unterminated tag")?;
+            let (name, fmt) = tail[..end].split_once(':').unwrap_or((&tail[..end], ""));
+            let pgpsig = match fmt {
+                "" => false,
+                "pgpsig" => true,
+                _ => bail!("unsupported formatter '{fmt}'"),
+            };
+            if name != "FILENAMES" && !TAGS.iter().any(|(n, _)| *n == name) {
+                bail!("unknown tag '{name}'");
+            }
+            items.push(Item::Tag { name, pgpsig });
+            rest = &tail[end + 1..];
+        } else if let Some(tail) = rest.strip_prefix('[') {
+            let end = tail.find(']').context("unterminated array")?;
+            items.push(Item::Array(compile(&tail[..end])?));
+            rest = &tail[end + 1..];
+        } else {
+            let end = rest
+                .find(['%', '['])
+                .filter(|i| *i > 0)
+                .unwrap_or(rest.len());
+            items.push(Item::Literal(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    Ok(items)
+}
+
+/// Render a compiled queryformat for a header as rpm would. Within an array,
+/// `index` is the element to render.
+fn render(items: &[Item], header: &Header, index: Option<usize>, out: &mut String) {
+    use std::fmt::Write;
+    for item in items {
+        match item {
+            Item::Literal(s) => out.push_str(s),
+            Item::Tag { name, pgpsig } => {
+                let value = header.get(name);
+                let i = index.unwrap_or(0);
+                match value {
+                    Some(Value::Ints(v)) if i < v.len() => write!(out, "{}", v[i]).unwrap(),
+                    Some(Value::Strings(v)) if i < v.len() => out.push_str(&v[i]),
+                    Some(Value::Bin(b)) if *pgpsig => out.push_str(&format_pgpsig(b)),
+                    Some(Value::Bin(b)) => b.iter().for_each(|b| write!(out, "{b:02x}").unwrap()),
+                    _ => out.push_str("(none)"),
+                }
+            }
+            Item::Array(items) => {
+                let len = (items.iter())
+                    .filter_map(|item| match item {
+                        Item::Tag { name, .. } => header.get(name).map(|v| v.len()),
+                        _ => None,
+                    })
+                    .max()
+                    .unwrap_or(0);
+                for i in 0..len {
+                    render(items, header, Some(i), out);
+                }
+            }
+        }
+    }
+}
+
+/// Describe an OpenPGP signature packet like rpm's `:pgpsig` formatter, as far
+/// as its algorithms and the ID of the key which made it.
+fn format_pgpsig(packet: &[u8]) -> String {
+    parse_pgpsig(packet).unwrap_or_else(|| "(not an OpenPGP signature)".into())
+}
+
+fn parse_pgpsig(packet: &[u8]) -> Option<String> {
+    let ctb = *packet.first()?;
+    let (tag, body) = if ctb & 0x40 != 0 {
+        let len_bytes = match *packet.get(1)? {
+            0..192 => 1,
+            192..224 => 2,
+            _ => 5,
+        };
+        (ctb & 0x3f, packet.get(1 + len_bytes..)?)
+    } else {
+        let len_bytes = [1, 2, 4, 0][usize::from(ctb & 3)];
+        ((ctb >> 2) & 0xf, packet.get(1 + len_bytes..)?)
+    };
+    if tag != 2 {
+        return None;
+    }
+    let (pubkey_algo, hash_algo, key_id) = match *body.first()? {
+        3 => (*body.get(15)?, *body.get(16)?, body.get(7..15)?.to_vec()),
+        4 | 6 => {
+            let (pubkey_algo, hash_algo) = (*body.get(2)?, *body.get(3)?);
+            let len_size = if body[0] == 6 { 4 } else { 2 };
+            let mut rest = body.get(4..)?;
+            let mut key_id = None;
+            // Hashed, then unhashed subpackets.
+            for _ in 0..2 {
+                let len = (rest.get(..len_size)?)
+                    .iter()
+                    .fold(0usize, |n, b| (n << 8) | usize::from(*b));
+                let mut subpackets = rest.get(len_size..len_size + len)?;
+                rest = &rest[len_size + len..];
+                while let Some(&first) = subpackets.first() {
+                    let (len, skip) = match first {
+                        0..192 => (usize::from(first), 1),
+                        192..255 => {
+                            let second = usize::from(*subpackets.get(1)?);
+                            (((usize::from(first) - 192) << 8) + second + 192, 2)
+                        }
+                        255 => (
+                            u32::from_be_bytes(subpackets.get(1..5)?.try_into().ok()?) as usize,
+                            5,
+                        ),
+                    };
+                    let subpacket = subpackets.get(skip..skip + len)?;
+                    subpackets = &subpackets[skip + len..];
+                    match (
+                        subpacket.first().map(|t| t & 0x7f),
+                        &subpacket[1.min(len)..],
+                    ) {
+                        // Issuer key ID.
+                        (Some(16), id) => key_id = Some(id.to_vec()),
+                        // Issuer fingerprint, whose end is the key ID.
+                        (Some(33), fpr) if key_id.is_none() && fpr.len() > 8 => {
+                            key_id = Some(fpr[fpr.len() - 8..].to_vec())
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            (pubkey_algo, hash_algo, key_id?)
+        }
+        _ => return None,
+    };
+    let pubkey_algo = match pubkey_algo {
+        1 => "RSA",
+        17 => "DSA",
+        19 => "ECDSA",
+        22 => "EdDSA",
+        _ => "(unknown)",
+    };
+    let hash_algo = match hash_algo {
+        2 => "SHA1",
+        8 => "SHA256",
+        9 => "SHA384",
+        10 => "SHA512",
+        11 => "SHA224",
+        _ => "(unknown)",
+    };
+    let key_id: String = key_id.iter().map(|b| format!("{b:02x}")).collect();
+    Some(format!("{pubkey_algo}/{hash_algo}, Key ID {key_id}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile() {
+        assert!(compile(QUERYFORMAT).is_ok());
+        assert!(compile("%{NOPE}").is_err());
+        assert!(compile("%{NAME:hex}").is_err());
+        assert!(compile("[%{NAME}").is_err());
+    }
+
+    /// Build a header blob from (tag, type, data, count) entries.
+    fn header_blob(entries: &[(u32, u32, &[u8], u32)]) -> Vec<u8> {
+        let mut index = Vec::new();
+        let mut data: Vec<u8> = Vec::new();
+        for (tag, kind, value, count) in entries {
+            for n in [*tag, *kind, data.len() as u32, *count] {
+                index.extend(n.to_be_bytes());
+            }
+            data.extend(*value);
+        }
+        let mut blob = Vec::new();
+        blob.extend((entries.len() as u32).to_be_bytes());
+        blob.extend((data.len() as u32).to_be_bytes());
+        blob.extend(index);
+        blob.extend(data);
+        blob
+    }
+
+    #[test]
+    fn test_header() {
+        // NAME (STRING), EPOCH (INT32) and DIRNAMES/DIRINDEXES/BASENAMES.
+        let blob = header_blob(&[
+            (1000, 6, b"foo\0", 1),
+            (1003, 4, &[0, 0, 0, 2], 1),
+            (1118, 8, b"/usr/bin/\0/etc/\0", 2),
+            (1116, 4, &[0, 0, 0, 1, 0, 0, 0, 0], 2),
+            (1117, 8, b"foo.conf\0foo\0", 2),
+        ]);
+
+        let header = Header::parse(&blob).unwrap();
+        let format = compile("%{NAME}:%{EPOCH}:%{ARCH}[ %{FILENAMES}]").unwrap();
+        let mut out = String::new();
+        render(&format, &header, None, &mut out);
+        assert_eq!(out, "foo:2:(none) /etc/foo.conf /usr/bin/foo");
+
+        assert!(Header::parse(&blob[..blob.len() - 1]).is_err());
+        assert!(Header::parse(&[0xff; 8]).is_err());
+    }
+
+    #[test]
+    fn test_rpm_fixture() {
+        let path = Path::new("tests/fixtures/foo-1.0-1.fc43.x86_64.rpm");
+        let packages = load_from_rpm_file_impl(path, &ParseOptions::default()).unwrap();
+        crate::tests::check_rpm_fixture(&packages["foo"]);
+    }
+
+    #[test]
+    fn test_rpm_file() {
+        let mut file = vec![0xed, 0xab, 0xee, 0xdb];
+        file.resize(96, 0);
+        // The signature header's SIZE shares its number with NAME, and its
+        // data needs padding.
+        file.extend(HEADER_MAGIC);
+        file.extend(header_blob(&[(1000, 4, &[0, 0, 0, 42], 1)]));
+        file.extend([0; 4]);
+        file.extend(HEADER_MAGIC);
+        file.extend(header_blob(&[
+            (1000, 6, b"foo\0", 1),
+            (1001, 6, b"1.0\0", 1),
+            (1002, 6, b"1.fc43\0", 1),
+            (1006, 4, &[0, 0, 0, 100], 1),
+            (1009, 4, &[0, 0, 0, 5], 1),
+            (1014, 6, b"MIT\0", 1),
+            (1022, 6, b"x86_64\0", 1),
+            (1028, 4, &[0, 0, 0, 5], 1),
+            (1030, 3, &[0x81, 0xed], 1),
+            (1034, 4, &[0, 0, 0, 100], 1),
+            (1035, 8, b"\0", 1),
+            (1036, 8, b"\0", 1),
+            (1037, 4, &[0, 0, 0, 0], 1),
+            (1039, 8, b"root\0", 1),
+            (1040, 8, b"root\0", 1),
+            (1118, 8, b"/usr/bin/\0", 1),
+            (1116, 4, &[0, 0, 0, 0], 1),
+            (1117, 8, b"foo\0", 1),
+        ]));
+        file.extend(b"payload");
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("foo.rpm");
+        std::fs::write(&path, &file).unwrap();
+
+        let packages = load_from_rpm_file_impl(&path, &ParseOptions::default()).unwrap();
+        let foo = &packages["foo"];
+        assert_eq!(foo.nevra(), "foo-1.0-1.fc43.x86_64");
+        assert_eq!((foo.size, foo.buildtime, foo.installtime), (5, 100, 0));
+        let bin = &foo.files[Utf8Path::new("/usr/bin/foo")];
+        assert_eq!((bin.mode, bin.state, bin.color), (0o100755, None, None));
+        assert_eq!(bin.lang, None);
+
+        let foo = load_from_rpm_reader(&file[..], &ParseOptions::default()).unwrap();
+        assert_eq!(foo.nevra(), "foo-1.0-1.fc43.x86_64");
+
+        std::fs::write(&path, &file[..200]).unwrap();
+        assert!(load_from_rpm_file_impl(&path, &ParseOptions::default()).is_err());
+        std::fs::write(&path, b"not an rpm").unwrap();
+        assert!(load_from_rpm_file_impl(&path, &ParseOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use camino::Utf8Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Statistics about a completed load, reported to [`LoadObserver::on_load`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadStats {
+    /// Time spent loading, including waiting for rpm when loading from a
+    /// rootfs since its output is parsed as it's produced.
+    pub duration: Duration,
+    /// Number of packages loaded.
+    pub packages: usize,
+    /// Number of files parsed. Files left unparsed with
+    /// [`ParseOptions::lazy_files`](crate::ParseOptions::lazy_files) aren't
+    /// counted.
+    pub files: usize,
+    /// Bytes of queryformat output read.
+    pub bytes: u64,
+    /// Number of errors and warnings skipped over when loading leniently.
+    pub errors: usize,
+}
+
+/// Statistics about a completed verification, reported to
+/// [`LoadObserver::on_verify`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyStats {
+    /// Time spent verifying.
+    pub duration: Duration,
+    /// Number of files checked.
+    pub files: u64,
+    /// Number of bytes hashed.
+    pub bytes: u64,
+    /// Number of files which failed verification.
+    pub failures: usize,
+}
+
+/// Hooks reporting what the crate does, e.g. to export metrics from a
+/// long-running service. All methods do nothing by default.
+///
+/// An observer is registered process-wide with
+/// [`set_load_observer`](crate::set_load_observer). It's called
+/// synchronously from the loading thread, so it should return quickly.
+pub trait LoadObserver: Send + Sync {
+    /// Called when queryformat output has been parsed, whether it came from
+    /// rpm or a reader. Failed loads aren't reported.
+    fn on_load(&self, stats: &LoadStats) {
+        let _ = stats;
+    }
+
+    /// Called when [`load_from_rootfs_cached`](crate::load_from_rootfs_cached)
+    /// looks up the snapshot of `rootfs`, with whether it was up to date.
+    fn on_cache_lookup(&self, rootfs: &Utf8Path, hit: bool) {
+        let _ = (rootfs, hit);
+    }
+
+    /// Called when [`verify_files`](crate::verify_files) completes. Failed
+    /// verifications aren't reported.
+    fn on_verify(&self, stats: &VerifyStats) {
+        let _ = stats;
+    }
+}
+
+static OBSERVER: RwLock<Option<Arc<dyn LoadObserver>>> = RwLock::new(None);
+
+pub(crate) fn set_load_observer_impl(observer: Option<Arc<dyn LoadObserver>>) {
+    *OBSERVER.write().unwrap() = observer;
+}
+
+/// Call `f` with the registered observer, if any.
+pub(crate) fn notify(f: impl FnOnce(&dyn LoadObserver)) {
+    let observer = OBSERVER.read().unwrap().clone();
+    if let Some(observer) = observer {
+        f(&*observer);
+    }
+}
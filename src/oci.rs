@@ -0,0 +1,616 @@
+use anyhow::{Context, bail};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use cap_std_ext::cap_std::ambient_authority;
+use cap_std_ext::cap_std::fs::Dir;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use crate::*;
+
+/// Opaque whiteout marker: hides the contents of its directory in lower layers.
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+/// Prefix of whiteout files, which hide the named file in lower layers.
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// How the packages of an image changed with one of its layers. See
+/// [`layer_changes`].
+#[derive(Debug, Clone, Default)]
+pub struct LayerChanges {
+    /// Digest of the layer, e.g. `sha256:...`.
+    pub digest: String,
+    /// Packages added by the layer, sorted by name.
+    pub added: Vec<Package>,
+    /// Packages removed by the layer, sorted by name.
+    pub removed: Vec<Package>,
+    /// Packages whose NEVRA changed, as (before, after) pairs, sorted by name.
+    pub changed: Vec<(Package, Package)>,
+}
+
+impl LayerChanges {
+    /// Whether the layer didn't change any package.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Tell which packages each layer of an image added, removed or changed.
+///
+/// `layout` is a directory in the OCI image layout format, holding a single
+/// image (e.g. as created by `skopeo copy docker://... oci:DIR`). Only the
+/// rpmdb is extracted from the layers, applied on top of each other, and
+/// loaded after each layer which touches it. Layers must be uncompressed or
+/// gzip-compressed tarballs.
+///
+/// Layers with paths which could point outside of the image, e.g. with `..`
+/// components, are rejected, as are rpmdbs reached through symlinks leading
+/// outside of it.
+///
+/// One entry is returned per layer, in order.
+pub fn layer_changes(layout: &Utf8Path) -> Result<Vec<LayerChanges>> {
+    let mut packages = Packages::default();
+    let mut changes = Vec::new();
+    replay_layers(layout, |digest, state| {
+        let mut layer = LayerChanges {
+            digest: digest.to_string(),
+            ..Default::default()
+        };
+        if let Some(state) = state {
+            let updated = load_state(state, digest)?;
+            diff_packages(&packages, &updated, &mut layer);
+            packages = updated;
+        }
+        changes.push(layer);
+        Ok(())
+    })?;
+    Ok(changes)
+}
+
+/// Load the installed packages of an image, as they are once all its layers
+/// are applied. See [`layer_changes`] for the supported images.
+pub fn load_from_oci_layout(layout: &Utf8Path) -> Result<Packages> {
+    let mut last = None;
+    let (_tmpdir, state) = replay_layers(layout, |digest, state| {
+        if state.is_some() {
+            last = Some(digest.to_string());
+        }
+        Ok(())
+    })?;
+    let Some(digest) = last else {
+        return Ok(Packages::default());
+    };
+    Ok(load_state(&state, &digest)?)
+}
+
+/// Apply the rpmdb changes of each layer of an image in turn, calling
+/// `on_layer` after each with the layer digest and, if the layer changed the
+/// rpmdb, the directory where the rpmdb state is accumulated. Returns that
+/// directory once all layers are applied.
+fn replay_layers(
+    layout: &Utf8Path,
+    mut on_layer: impl FnMut(&str, Option<&Dir>) -> anyhow::Result<()>,
+) -> anyhow::Result<(tempfile::TempDir, Dir)> {
+    let layers = image_layers(layout)?;
+    let (tmpdir, state) = tempdir()?;
+    for digest in layers {
+        let touched = apply_layer(&blob_path(layout, &digest)?, &state)
+            .with_context(|| format!("applying layer {digest}"))?;
+        on_layer(&digest, touched.then_some(&state))?;
+    }
+    Ok((tmpdir, state))
+}
+
+/// Create a temporary directory, opened for the layers to be applied in.
+fn tempdir() -> anyhow::Result<(tempfile::TempDir, Dir)> {
+    let tmpdir = tempfile::tempdir().context("creating tempdir")?;
+    let dir =
+        Dir::open_ambient_dir(tmpdir.path(), ambient_authority()).context("opening tempdir")?;
+    Ok((tmpdir, dir))
+}
+
+/// Load the packages of the rpmdb state after the layer `digest`.
+fn load_state(state: &Dir, digest: &str) -> anyhow::Result<Packages> {
+    let Some(dbpath) = find_dbpath_dir(state)? else {
+        return Ok(Packages::default());
+    };
+    check_confined(state, dbpath)
+        .with_context(|| format!("rpmdb of layer {digest} leads outside of the image"))?;
+    load_from_rootfs_dir(state)
+        .map_err(anyhow::Error::from)
+        .with_context(|| format!("loading rpmdb of layer {digest}"))
+}
+
+/// Make sure that the rpmdb at `dbpath` and its files are within `state`.
+/// rpm opens them by path, so it would follow symlinks out of the image,
+/// whereas resolving them through `state` fails if they lead outside of it.
+fn check_confined(state: &Dir, dbpath: &str) -> std::io::Result<()> {
+    let dir = state.open_dir(dbpath)?;
+    for entry in dir.entries()? {
+        dir.metadata(entry?.file_name())?;
+    }
+    Ok(())
+}
+
+/// Get the digests of the layers of the image in an OCI layout, in order.
+fn image_layers(layout: &Utf8Path) -> anyhow::Result<Vec<String>> {
+    let mut manifest = read_json(&layout.join("index.json"))?;
+    // Follow image indexes down to the image manifest.
+    while let Some(manifests) = manifest.get("manifests").and_then(|m| m.as_array()) {
+        let [descriptor] = manifests.as_slice() else {
+            bail!(
+                "expected a single image, found {} manifests",
+                manifests.len()
+            );
+        };
+        let digest = descriptor_digest(descriptor)?;
+        manifest = read_json(&blob_path(layout, digest)?)?;
+    }
+    let layers = manifest
+        .get("layers")
+        .and_then(|layers| layers.as_array())
+        .context("image manifest without layers")?;
+    layers
+        .iter()
+        .map(|layer| descriptor_digest(layer).map(str::to_string))
+        .collect()
+}
+
+fn read_json(path: &Utf8Path) -> anyhow::Result<serde_json::Value> {
+    let file = File::open(path).with_context(|| format!("opening {path}"))?;
+    serde_json::from_reader(BufReader::new(file)).with_context(|| format!("parsing {path}"))
+}
+
+fn descriptor_digest(descriptor: &serde_json::Value) -> anyhow::Result<&str> {
+    descriptor
+        .get("digest")
+        .and_then(|digest| digest.as_str())
+        .context("descriptor without digest")
+}
+
+/// Get the path of a blob, making sure its digest can't escape the layout.
+fn blob_path(layout: &Utf8Path, digest: &str) -> anyhow::Result<Utf8PathBuf> {
+    let (algo, hex) = digest
+        .split_once(':')
+        .with_context(|| format!("invalid digest '{digest}'"))?;
+    let valid = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric());
+    if !valid(algo) || !valid(hex) {
+        bail!("invalid digest '{digest}'");
+    }
+    Ok(layout.join("blobs").join(algo).join(hex))
+}
+
+/// Get the path of a layer entry relative to the root of the image, or `None`
+/// for the root itself. Paths with components other than plain names (past
+/// a leading `/` or `./`), e.g. `..`, are rejected: they could point outside
+/// of the image.
+fn layer_path(path: &Utf8Path) -> anyhow::Result<Option<Utf8PathBuf>> {
+    let mut normalized = Utf8PathBuf::new();
+    for (i, component) in path.components().enumerate() {
+        match component {
+            Utf8Component::Normal(name) => normalized.push(name),
+            Utf8Component::RootDir | Utf8Component::CurDir if i == 0 => {}
+            _ => bail!("invalid path '{path}' in layer"),
+        }
+    }
+    Ok((!normalized.as_str().is_empty()).then_some(normalized))
+}
+
+/// Whether `path` is an rpmdb directory, inside one, or one of their parents.
+fn affects_rpmdb(path: &Utf8Path) -> bool {
+    RPMDB_PATHS.iter().any(|dbpath| {
+        let dbpath = Utf8Path::new(dbpath);
+        path.starts_with(dbpath) || dbpath.starts_with(path)
+    })
+}
+
+/// Apply the rpmdb changes of a layer tarball onto `state`. Returns whether
+/// there were any.
+///
+/// All changes are made through directory handles, so that symlinks from
+/// the layers can't make them land outside of the state directory.
+fn apply_layer(blob: &Utf8Path, state: &Dir) -> anyhow::Result<bool> {
+    let file = File::open(blob).with_context(|| format!("opening {blob}"))?;
+    let mut reader = BufReader::new(file);
+    let gzipped = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    let reader: Box<dyn Read> = if gzipped {
+        Box::new(flate2::read::GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+
+    // Whiteouts only apply to lower layers, so the layer's own files are
+    // staged and only moved into place once they're processed.
+    let (_tmpdir, staging) = tempdir()?;
+    let mut whiteouts = Vec::new();
+    let mut touched = false;
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        // Non-UTF-8 paths can't be in the rpmdb.
+        let Some(path) = Utf8Path::from_path(&path) else {
+            continue;
+        };
+        let Some(path) = layer_path(path)? else {
+            continue;
+        };
+        let parent = path.parent().unwrap_or(Utf8Path::new(""));
+        let name = path.file_name().unwrap_or_default();
+        if name == OPAQUE_WHITEOUT {
+            if affects_rpmdb(parent) {
+                whiteouts.push((parent.to_path_buf(), true));
+            }
+        } else if let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) {
+            if matches!(hidden, "" | "." | "..") {
+                bail!("invalid whiteout '{path}' in layer");
+            }
+            let hidden = parent.join(hidden);
+            if affects_rpmdb(&hidden) {
+                whiteouts.push((hidden, false));
+            }
+        } else if affects_rpmdb(&path) {
+            touched |= stage_entry(&staging, &path, &mut entry)
+                .with_context(|| format!("extracting {path}"))?;
+        }
+    }
+
+    touched |= !whiteouts.is_empty();
+    for (path, opaque) in whiteouts {
+        // Paths leading outside of the state through symlinks can't be
+        // resolved, so are never removed.
+        let Ok(meta) = state.symlink_metadata(&path) else {
+            continue;
+        };
+        if !opaque {
+            remove_all(state, path.as_std_path())?;
+        } else if meta.is_dir() {
+            let dir = state.open_dir(&path)?;
+            for child in dir.entries()? {
+                remove_all(&dir, child?.file_name().as_ref())?;
+            }
+        }
+    }
+    merge_into(&staging, state)?;
+    Ok(touched)
+}
+
+/// Write a regular file, directory or symlink of a layer into `staging`.
+/// Returns whether it was anything but a directory; other kinds of entries
+/// are skipped.
+fn stage_entry(
+    staging: &Dir,
+    path: &Utf8Path,
+    entry: &mut tar::Entry<impl Read>,
+) -> anyhow::Result<bool> {
+    use tar::EntryType;
+    let kind = entry.header().entry_type();
+    if !matches!(
+        kind,
+        EntryType::Regular | EntryType::Directory | EntryType::Symlink
+    ) {
+        return Ok(false);
+    }
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_str().is_empty()) {
+        staging.create_dir_all(parent)?;
+    }
+    // Later entries replace earlier ones, but mustn't write through them.
+    if staging
+        .symlink_metadata(path)
+        .is_ok_and(|meta| !meta.is_dir())
+    {
+        staging.remove_file(path)?;
+    }
+    match kind {
+        EntryType::Directory => staging.create_dir_all(path)?,
+        EntryType::Symlink => {
+            let target = entry.link_name()?.context("symlink without target")?;
+            staging.symlink(image_relative(path, &target), path)?;
+        }
+        _ => {
+            let mut file = staging.create(path)?;
+            std::io::copy(entry, &mut file)?;
+        }
+    }
+    Ok(kind != EntryType::Directory)
+}
+
+/// Make the target of the symlink at `path` relative, so that it resolves
+/// within the image as absolute ones do in a container.
+fn image_relative(path: &Utf8Path, target: &Path) -> PathBuf {
+    match target.strip_prefix("/") {
+        Ok(target) => {
+            let depth = path.components().count() - 1;
+            let mut relative: PathBuf = std::iter::repeat_n("..", depth).collect();
+            relative.push(target);
+            relative
+        }
+        Err(_) => target.to_path_buf(),
+    }
+}
+
+fn remove_all(dir: &Dir, path: &Path) -> std::io::Result<()> {
+    if dir.symlink_metadata(path)?.is_dir() {
+        dir.remove_dir_all(path)
+    } else {
+        dir.remove_file(path)
+    }
+}
+
+/// Move the contents of `src` into `dst`, replacing what's there.
+fn merge_into(src: &Dir, dst: &Dir) -> std::io::Result<()> {
+    for entry in src.entries()? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let is_dir = entry.file_type()?.is_dir();
+        match dst.symlink_metadata(&name) {
+            Ok(meta) if is_dir && meta.is_dir() => {
+                merge_into(&src.open_dir(&name)?, &dst.open_dir(&name)?)?;
+                continue;
+            }
+            Ok(meta) if meta.is_dir() => dst.remove_dir_all(&name)?,
+            Ok(_) => dst.remove_file(&name)?,
+            Err(_) => {}
+        }
+        src.rename(&name, dst, &name)?;
+    }
+    Ok(())
+}
+
+/// Record how `after` differs from `before` into `changes`.
+fn diff_packages(before: &Packages, after: &Packages, changes: &mut LayerChanges) {
+    let before_by_key: HashMap<&str, &Package> =
+        before.iter().map(|(k, p)| (k.as_str(), p)).collect();
+    for (key, pkg) in after {
+        match before_by_key.get(key.as_str()) {
+            None => changes.added.push(pkg.clone()),
+            Some(old) if old.nevra() != pkg.nevra() => {
+                changes.changed.push(((*old).clone(), pkg.clone()))
+            }
+            Some(_) => {}
+        }
+    }
+    changes.removed = before
+        .iter()
+        .filter(|(key, _)| !after.contains_key(*key))
+        .map(|(_, pkg)| pkg.clone())
+        .collect();
+    changes.added.sort_by(|a, b| a.name.cmp(&b.name));
+    changes.removed.sort_by(|a, b| a.name.cmp(&b.name));
+    changes.changed.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Write a blob into the layout and return its descriptor.
+    fn add_blob(layout: &Utf8Path, name: &str, data: &[u8]) -> serde_json::Value {
+        // Not a real digest, but the layout format doesn't care.
+        let digest = format!("sha256:{name}");
+        std::fs::write(blob_path(layout, &digest).unwrap(), data).unwrap();
+        serde_json::json!({ "digest": digest, "size": data.len() })
+    }
+
+    fn layer(entries: &[(&str, Option<&[u8]>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            match data {
+                Some(data) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_size(data.len() as u64);
+                    header.set_mode(0o644);
+                    header.set_cksum();
+                    builder.append_data(&mut header, path, *data).unwrap();
+                }
+                None => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_size(0);
+                    header.set_mode(0o755);
+                    header.set_cksum();
+                    builder.append_data(&mut header, path, &[][..]).unwrap();
+                }
+            }
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// Build a layer with paths as they are, rather than as sanitized by the
+    /// tar crate: empty files, or symlinks to the given targets.
+    fn raw_layer(entries: &[(&str, Option<&str>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, target) in entries {
+            let mut header = tar::Header::new_old();
+            assert!(path.len() < 100, "{path} too long");
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            match target {
+                Some(target) => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_link_name_literal(target).unwrap();
+                }
+                None => header.set_entry_type(tar::EntryType::Regular),
+            }
+            header.set_size(0);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, &[][..]).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// Point the index of the layout to an image with the given layers.
+    fn set_layers(layout: &Utf8Path, layers: &[serde_json::Value]) {
+        let manifest = serde_json::json!({ "schemaVersion": 2, "layers": layers });
+        let name = format!("manifest{}", layers.len());
+        let manifest = add_blob(layout, &name, manifest.to_string().as_bytes());
+        let index = serde_json::json!({ "schemaVersion": 2, "manifests": [manifest] });
+        std::fs::write(layout.join("index.json"), index.to_string()).unwrap();
+    }
+
+    #[test]
+    fn test_layer_escape() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let layout = Utf8Path::from_path(tmpdir.path()).unwrap();
+        std::fs::create_dir_all(layout.join("blobs/sha256")).unwrap();
+        let outside = layout.join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        std::fs::write(outside.join("victim"), "").unwrap();
+        let rpmdb = std::fs::read("tests/fixtures/rpmdb.sqlite").unwrap();
+        std::fs::write(outside.join("rpmdb.sqlite"), &rpmdb).unwrap();
+        let base = add_blob(
+            layout,
+            "base",
+            &layer(&[
+                ("usr/lib/sysimage/rpm/", None),
+                ("usr/lib/sysimage/rpm/rpmdb.sqlite", Some(&rpmdb)),
+            ]),
+        );
+
+        // A whiteout reaching out of the image with `..`.
+        let traversal = format!(
+            "usr/lib/sysimage/rpm/{}{}/.wh.victim",
+            "../".repeat(12),
+            outside.as_str().trim_start_matches('/')
+        );
+        let traversal = add_blob(layout, "traversal", &raw_layer(&[(&traversal, None)]));
+        set_layers(layout, &[base.clone(), traversal]);
+        let err = layer_changes(layout).unwrap_err();
+        assert!(format!("{err:#}").contains("invalid path"), "{err:#}");
+        assert!(outside.join("victim").exists());
+
+        // An rpmdb symlinked to outside of the image isn't read. Absolute
+        // symlinks resolve within the image.
+        let escape = format!("{}{outside}", "../".repeat(12));
+        let link = add_blob(
+            layout,
+            "link",
+            &raw_layer(&[("usr/lib/sysimage/rpm", Some(outside.as_str()))]),
+        );
+        set_layers(layout, &[link]);
+        assert!(load_from_oci_layout(layout).unwrap().is_empty());
+        let link = add_blob(
+            layout,
+            "link2",
+            &raw_layer(&[("usr/lib/sysimage/rpm", Some(&escape))]),
+        );
+        set_layers(layout, &[link]);
+        assert!(layer_changes(layout).is_err());
+
+        // Nor is anything removed or written through a symlinked directory.
+        let link = add_blob(
+            layout,
+            "link3",
+            &raw_layer(&[("var/lib/rpm", Some(&escape))]),
+        );
+        let write = add_blob(
+            layout,
+            "write",
+            &raw_layer(&[("var/lib/rpm/.wh.victim", None), ("var/lib/rpm/new", None)]),
+        );
+        set_layers(layout, &[base, link, write]);
+        let changes = layer_changes(layout).unwrap();
+        assert!(changes[0].added.iter().any(|pkg| pkg.name == "setup"));
+        assert!(outside.join("victim").exists());
+        assert!(!outside.join("new").exists());
+    }
+
+    #[test]
+    fn test_layer_changes() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let layout = Utf8Path::from_path(tmpdir.path()).unwrap();
+        std::fs::create_dir_all(layout.join("blobs/sha256")).unwrap();
+        let rpmdb = std::fs::read("tests/fixtures/rpmdb.sqlite").unwrap();
+
+        let base = layer(&[
+            ("usr/", None),
+            ("usr/lib/sysimage/rpm/", None),
+            ("usr/lib/sysimage/rpm/rpmdb.sqlite", Some(&rpmdb)),
+            ("usr/bin/foo", Some(b"foo")),
+        ]);
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gz.write_all(&base).unwrap();
+        let layers = [
+            add_blob(layout, "l1", &gz.finish().unwrap()),
+            add_blob(layout, "l2", &layer(&[("etc/motd", Some(b"hi"))])),
+            add_blob(
+                layout,
+                "l3",
+                &layer(&[("./usr/lib/sysimage/.wh.rpm", Some(b""))]),
+            ),
+        ];
+        let manifest = serde_json::json!({ "schemaVersion": 2, "layers": layers });
+        let manifest = add_blob(layout, "manifest", manifest.to_string().as_bytes());
+        let index = serde_json::json!({ "schemaVersion": 2, "manifests": [manifest] });
+        std::fs::write(layout.join("index.json"), index.to_string()).unwrap();
+
+        let changes = layer_changes(layout).unwrap();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].digest, "sha256:l1");
+        assert!(changes[0].added.iter().any(|pkg| pkg.name == "setup"));
+        assert!(changes[0].removed.is_empty());
+        assert!(changes[1].is_empty());
+        assert_eq!(changes[2].removed.len(), changes[0].added.len());
+        assert!(changes[2].added.is_empty());
+        // Everything was removed by the last layer.
+        assert!(load_from_oci_layout(layout).unwrap().is_empty());
+
+        let manifest = serde_json::json!({ "schemaVersion": 2, "layers": &layers[..2] });
+        let manifest = add_blob(layout, "manifest2", manifest.to_string().as_bytes());
+        let index = serde_json::json!({ "schemaVersion": 2, "manifests": [manifest] });
+        std::fs::write(layout.join("index.json"), index.to_string()).unwrap();
+        let packages = load_from_oci_layout(layout).unwrap();
+        assert_eq!(packages.len(), changes[0].added.len());
+    }
+
+    #[test]
+    fn test_diff_packages() {
+        let before = load_from_str(include_str!("../tests/fixtures/fedora.qf")).unwrap();
+        let mut after = before.clone();
+        after.remove("rpm");
+        after.get_mut("bash").unwrap().release = "3.fc43".into();
+        let mut foo = before["setup"].clone();
+        foo.name = "foo".into();
+        after.insert("foo".into(), foo);
+
+        let mut changes = LayerChanges::default();
+        diff_packages(&before, &after, &mut changes);
+        assert_eq!(changes.added[0].name, "foo");
+        assert_eq!(changes.removed[0].name, "rpm");
+        assert_eq!(changes.changed.len(), 1);
+        assert_eq!(changes.changed[0].0.release, "2.fc43");
+        assert_eq!(changes.changed[0].1.release, "3.fc43");
+    }
+
+    #[test]
+    fn test_blob_path() {
+        let layout = Utf8Path::new("/layout");
+        assert_eq!(
+            blob_path(layout, "sha256:abc").unwrap(),
+            "/layout/blobs/sha256/abc"
+        );
+        assert!(blob_path(layout, "sha256:../../etc/passwd").is_err());
+        assert!(blob_path(layout, "abc").is_err());
+        assert!(affects_rpmdb("usr/lib/sysimage/rpm/rpmdb.sqlite".into()));
+        assert!(affects_rpmdb("usr/lib".into()));
+        assert!(affects_rpmdb("var/lib/rpm".into()));
+        assert!(!affects_rpmdb("usr/lib/sysimage/rpm-state".into()));
+        assert!(!affects_rpmdb("usr/bin".into()));
+
+        let path = |s: &str| layer_path(s.into()).unwrap();
+        assert_eq!(path("./usr/lib/"), Some("usr/lib".into()));
+        assert_eq!(path("/usr//lib"), Some("usr/lib".into()));
+        assert_eq!(path("./"), None);
+        assert_eq!(
+            image_relative("var/lib/rpm".into(), Path::new("/usr/lib/sysimage/rpm")),
+            Path::new("../../usr/lib/sysimage/rpm")
+        );
+        assert_eq!(
+            image_relative("var/lib/rpm".into(), Path::new("../sysimage/rpm")),
+            Path::new("../sysimage/rpm")
+        );
+        assert!(layer_path("usr/../../etc".into()).is_err());
+        assert!(layer_path("usr/./../etc".into()).is_err());
+    }
+}
@@ -0,0 +1,289 @@
+use anyhow::Context;
+use camino::{Utf8Path, Utf8PathBuf};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+
+use crate::*;
+
+/// A packaged file in a checked-out ostree commit, with the checksum of its
+/// ostree content object.
+#[derive(Debug, Clone)]
+pub struct OstreeObject<'a> {
+    /// The package owning the file.
+    pub package: &'a Package,
+    /// Path of the file, as packaged.
+    pub path: &'a Utf8Path,
+    /// Path of the file relative to the checkout, e.g. `usr/etc/os-release`
+    /// for `/etc/os-release` in commits composed by rpm-ostree.
+    pub checkout_path: Utf8PathBuf,
+    /// Checksum of the content object, as hex.
+    pub checksum: String,
+}
+
+/// Correlate the packaged files of a checked-out ostree commit (e.g. a
+/// deployment under `/ostree/deploy`) with the checksums of their content
+/// objects, sorted by path.
+///
+/// Checksums are computed from the checkout like ostree does when
+/// committing: from the owner, mode, symlink target, extended attributes
+/// and contents of each file. They only match those of the commit if the
+/// checkout preserved all of these, i.e. it's a checkout in bare mode rather
+/// than a user mode one (`ostree checkout -U`).
+///
+/// Only regular files and symlinks have content objects. Directories,
+/// ghost files and files which aren't in the checkout (e.g. under `/var`,
+/// which isn't part of commits) are skipped. If the commit has a `/usr/etc`,
+/// as composed by rpm-ostree, files under `/etc` are looked up there.
+pub fn ostree_checksums<'a>(
+    packages: &'a Packages,
+    checkout: &Utf8Path,
+) -> Result<Vec<OstreeObject<'a>>> {
+    Ok(ostree_checksums_impl(packages, checkout)
+        .with_context(|| format!("computing ostree checksums of {checkout}"))?)
+}
+
+fn ostree_checksums_impl<'a>(
+    packages: &'a Packages,
+    checkout: &Utf8Path,
+) -> anyhow::Result<Vec<OstreeObject<'a>>> {
+    let usr_etc = checkout.join("usr/etc").is_dir();
+    let mut objects = Vec::new();
+    for (package, path, info) in packages.files() {
+        if !(info.is_file() || info.is_symlink())
+            || info.flags.is_ghost()
+            || !info.state.is_none_or(|s| s == FileState::Normal)
+        {
+            continue;
+        }
+        let relative = path.strip_prefix("/").unwrap_or(path);
+        let checkout_path = match relative.strip_prefix("etc") {
+            Ok(rest) if usr_etc => Utf8Path::new("usr/etc").join(rest),
+            _ => relative.to_path_buf(),
+        };
+        let Some(checksum) = content_checksum(&checkout.join(&checkout_path))
+            .with_context(|| format!("checksumming {checkout_path}"))?
+        else {
+            continue;
+        };
+        objects.push(OstreeObject {
+            package,
+            path,
+            checkout_path,
+            checksum,
+        });
+    }
+    objects.sort_by(|a, b| a.path.cmp(b.path));
+    Ok(objects)
+}
+
+/// Compute the checksum of the content object of a file, or `None` if it
+/// doesn't exist or isn't a regular file or symlink.
+fn content_checksum(path: &Utf8Path) -> anyhow::Result<Option<String>> {
+    let meta = match path.symlink_metadata() {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let file_type = meta.file_type();
+    let target = if file_type.is_symlink() {
+        path.read_link_utf8()?.into_string()
+    } else if file_type.is_file() {
+        String::new()
+    } else {
+        return Ok(None);
+    };
+    let xattrs = read_xattrs(path)?;
+    let mut hasher = Sha256::new();
+    hasher.update(file_header(
+        meta.uid(),
+        meta.gid(),
+        meta.mode(),
+        &target,
+        &xattrs,
+    ));
+    if file_type.is_file() {
+        let mut file = std::fs::File::open(path)?;
+        let mut buf = vec![0; 64 << 10];
+        loop {
+            match file.read(&mut buf)? {
+                0 => break,
+                n => hasher.update(&buf[..n]),
+            }
+        }
+    }
+    let digest = hasher.finalize();
+    Ok(Some(digest.iter().map(|b| format!("{b:02x}")).collect()))
+}
+
+/// Read the extended attributes of a file without following symlinks, as
+/// `(name, value)` pairs sorted by name like ostree canonicalizes them.
+fn read_xattrs(path: &Utf8Path) -> rustix::io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut names = vec![0; 1024];
+    let len = loop {
+        match rustix::fs::llistxattr(path.as_std_path(), &mut names[..]) {
+            Ok(len) => break len,
+            Err(rustix::io::Errno::NOTSUP) => return Ok(Vec::new()),
+            Err(rustix::io::Errno::RANGE) => names.resize(names.len() * 2, 0),
+            Err(e) => return Err(e),
+        }
+    };
+    let mut xattrs = Vec::new();
+    for name in names[..len].split(|&c| c == 0).filter(|n| !n.is_empty()) {
+        let name = std::str::from_utf8(name).map_err(|_| rustix::io::Errno::INVAL)?;
+        let mut value = vec![0; 256];
+        loop {
+            match rustix::fs::lgetxattr(path.as_std_path(), name, &mut value[..]) {
+                Ok(len) => {
+                    value.truncate(len);
+                    break;
+                }
+                Err(rustix::io::Errno::RANGE) => value.resize(value.len() * 2, 0),
+                Err(e) => return Err(e),
+            }
+        }
+        xattrs.push((name.as_bytes().to_vec(), value));
+    }
+    xattrs.sort();
+    Ok(xattrs)
+}
+
+/// Serialize the header of a content object, which is checksummed before
+/// the contents: the GVariant `(uuuusa(ayay))` of the owner, group, mode,
+/// device (always 0), symlink target and extended attributes, with
+/// big-endian integers, prefixed by its big-endian length and 4 bytes of
+/// padding.
+fn file_header(
+    uid: u32,
+    gid: u32,
+    mode: u32,
+    target: &str,
+    xattrs: &[(Vec<u8>, Vec<u8>)],
+) -> Vec<u8> {
+    let mut body = Vec::new();
+    for n in [uid, gid, mode, 0] {
+        body.extend_from_slice(&n.to_be_bytes());
+    }
+    body.extend_from_slice(target.as_bytes());
+    body.push(0);
+    let target_end = body.len();
+    // Names are bytestrings, which include the trailing nul.
+    let mut entries = Vec::new();
+    let mut ends = Vec::new();
+    for (name, value) in xattrs {
+        let mut entry = name.clone();
+        entry.push(0);
+        let name_end = entry.len();
+        entry.extend_from_slice(value);
+        entries.extend(frame(entry, &[name_end]));
+        ends.push(entries.len());
+    }
+    body.extend(frame(entries, &ends));
+    let variant = frame(body, &[target_end]);
+
+    let mut header = Vec::with_capacity(8 + variant.len());
+    header.extend_from_slice(&(variant.len() as u32).to_be_bytes());
+    header.extend_from_slice(&[0; 4]);
+    header.extend(variant);
+    header
+}
+
+/// Append the framing offsets of the variable-size members of a GVariant
+/// tuple or array to its body. These are little-endian, of the smallest
+/// size which can address the whole container.
+fn frame(mut body: Vec<u8>, offsets: &[usize]) -> Vec<u8> {
+    if offsets.is_empty() {
+        return body;
+    }
+    let size = [1, 2, 4, 8]
+        .into_iter()
+        .find(|&size| size == 8 || body.len() + size * offsets.len() < 1 << (8 * size))
+        .unwrap();
+    // Tuples store them in reverse order, but they only ever have one here.
+    for offset in offsets {
+        body.extend_from_slice(&offset.to_le_bytes()[..size]);
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FileInfoBuilder, PackageBuilder, packages};
+
+    #[test]
+    fn test_file_header() {
+        assert_eq!(
+            file_header(0, 0, 0o120777, "sh", &[]),
+            [
+                &[0, 0, 0, 20, 0, 0, 0, 0][..],
+                &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0o241, 0o377, 0, 0, 0, 0],
+                b"sh\0",
+                &[19],
+            ]
+            .concat()
+        );
+        let xattrs = [(b"user.a".to_vec(), b"xy".to_vec())];
+        assert_eq!(
+            file_header(1000, 1000, 0o100644, "", &xattrs),
+            [
+                &[0, 0, 0, 29, 0, 0, 0, 0][..],
+                &[0, 0, 3, 232, 0, 0, 3, 232, 0, 0, 0o201, 0o244, 0, 0, 0, 0],
+                b"\0",
+                // The (ayay) entry and its offset, then the array's.
+                b"user.a\0xy",
+                &[7, 10],
+                &[17],
+            ]
+            .concat()
+        );
+        let long = "x".repeat(300);
+        let header = file_header(0, 0, 0o120777, &long, &[]);
+        assert_eq!(header[header.len() - 2..], (16 + 301u16).to_le_bytes());
+    }
+
+    #[test]
+    fn test_ostree_checksums() {
+        let tmp = tempfile::tempdir().unwrap();
+        let checkout = Utf8Path::from_path(tmp.path()).unwrap();
+        std::fs::create_dir_all(checkout.join("usr/bin")).unwrap();
+        std::fs::create_dir_all(checkout.join("usr/etc")).unwrap();
+        std::fs::write(checkout.join("usr/bin/foo"), "foo").unwrap();
+        std::fs::write(checkout.join("usr/etc/foo.conf"), "foo").unwrap();
+        std::os::unix::fs::symlink("foo", checkout.join("usr/bin/bar")).unwrap();
+
+        let packages = packages([PackageBuilder::new("foo")
+            .dir("/usr/bin")
+            .file("/usr/bin/foo", FileInfoBuilder::new())
+            .symlink("/usr/bin/bar", "foo")
+            .file("/etc/foo.conf", FileInfoBuilder::new())
+            .file(
+                "/usr/bin/ghost",
+                FileInfoBuilder::new().flags(FileFlags::GHOST),
+            )
+            .file("/var/lib/foo", FileInfoBuilder::new())
+            .build()]);
+        let objects = ostree_checksums(&packages, checkout).unwrap();
+        let paths: Vec<_> = (objects.iter())
+            .map(|o| (o.path.as_str(), o.checkout_path.as_str()))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("/etc/foo.conf", "usr/etc/foo.conf"),
+                ("/usr/bin/bar", "usr/bin/bar"),
+                ("/usr/bin/foo", "usr/bin/foo"),
+            ]
+        );
+        assert!(objects.iter().all(|o| o.checksum.len() == 64));
+        // Same owner, mode and contents, so the same object.
+        assert_eq!(objects[0].checksum, objects[2].checksum);
+        assert_ne!(objects[1].checksum, objects[2].checksum);
+        let meta = std::fs::symlink_metadata(checkout.join("usr/bin/bar")).unwrap();
+        let header = file_header(meta.uid(), meta.gid(), meta.mode(), "foo", &[]);
+        let expected: String = (Sha256::digest(header).iter())
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        assert_eq!(objects[1].checksum, expected);
+    }
+}
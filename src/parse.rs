@@ -1,56 +1,876 @@
 use anyhow::{Context, Result, bail};
 use camino::{Utf8Path, Utf8PathBuf};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
 use std::io::{BufRead, Read};
+use std::time::Instant;
 
+use crate::observer::notify;
 use crate::*;
 
 /// The `--queryformat` string used to query RPM. This is the format that
-/// `load_from_str` and `load_from_reader` expect.
+/// [`load_from_str`] and [`load_from_reader`] expect, so output produced
+/// externally with it (e.g. by running rpm in a build sandbox) can be loaded.
+/// It may change between releases of this crate; see [`queryformat_v4`] for
+/// a stable one.
 ///
-/// The `\t` and `\n` here are literal backslash escapes for rpm to interpret
-/// (raw strings pass them through without Rust processing them).
-pub(crate) const QUERYFORMAT: &str = concat!(
-    // Per-package header line:
-    r"@@PKG@@\t%{NAME}\t%{VERSION}\t%{RELEASE}\t%{EPOCH}\t%{ARCH}",
-    r"\t%{LICENSE}\t%{SIZE}\t%{BUILDTIME}\t%{INSTALLTIME}",
-    r"\t%{SOURCERPM}\t%{FILEDIGESTALGO}\n",
-    // Per-file lines (iterated with []):
-    r"[@@FILE@@\t%{FILENAMES}\t%{FILESIZES}\t%{FILEMODES}\t%{FILEMTIMES}",
-    r"\t%{FILEDIGESTS}\t%{FILEFLAGS}",
-    r"\t%{FILEUSERNAME}\t%{FILEGROUPNAME}\t%{FILELINKTOS}\n]",
-    // Per-changelog lines (iterated with []):
-    r"[@@CL@@\t%{CHANGELOGTIME}\n]",
+/// Fields are separated by the ASCII unit separator and records terminated by
+/// the record separator rather than by tabs and newlines, which values such
+/// as licenses and file names may contain.
+///
+/// Every package starts with a record stating the [`FORMAT_VERSION`]; rpm
+/// applies the queryformat to each package in turn, so there's no way to
+/// emit it only once.
+///
+/// Sizes are queried with the 64-bit LONGSIZE and LONGFILESIZES tags. rpm
+/// only stores these for packages and files too large for the 32-bit SIZE and
+/// FILESIZES tags, but computes them from the latter otherwise, so they're
+/// always complete and never wrap.
+///
+/// Header signatures are queried from both RSAHEADER and DSAHEADER, since
+/// rpm stores EdDSA and ECDSA ones in the latter; at most one is set.
+pub const QUERYFORMAT: &str = concat!(
+    // Format version record:
+    "@@FMT@@\x1f13\x1e",
+    // Per-package header record:
+    "@@PKG@@\x1f%{NAME}\x1f%{VERSION}\x1f%{RELEASE}\x1f%{EPOCH}\x1f%{ARCH}",
+    "\x1f%{LICENSE}\x1f%{LONGSIZE}\x1f%{BUILDTIME}\x1f%{INSTALLTIME}",
+    "\x1f%{SOURCERPM}\x1f%{FILEDIGESTALGO}\x1f%{INSTALLTID}",
+    "\x1f%{RSAHEADER:pgpsig}\x1f%{DSAHEADER:pgpsig}\x1e",
+    // Per-file records (iterated with []):
+    "[@@FILE@@\x1f%{FILENAMES}\x1f%{LONGFILESIZES}\x1f%{FILEMODES}\x1f%{FILEMTIMES}",
+    "\x1f%{FILEDIGESTS}\x1f%{FILEFLAGS}",
+    "\x1f%{FILEUSERNAME}\x1f%{FILEGROUPNAME}\x1f%{FILELINKTOS}\x1f%{FILESTATES}",
+    "\x1f%{FILELANGS}\x1f%{FILECOLORS}\x1f%{FILECAPS}\x1e]",
+    // Per-changelog records (iterated with []), the text last since it spans
+    // lines:
+    "[@@CL@@\x1f%{CHANGELOGTIME}\x1f%{CHANGELOGNAME}\x1f%{CHANGELOGTEXT}\x1e]",
+    // Per-dependency records (iterated with []):
+    "[@@REQ@@\x1f%{REQUIRENAME}\x1f%{REQUIREFLAGS}\x1f%{REQUIREVERSION}\x1e]",
+    "[@@PROV@@\x1f%{PROVIDENAME}\x1f%{PROVIDEFLAGS}\x1f%{PROVIDEVERSION}\x1e]",
+    "[@@REC@@\x1f%{RECOMMENDNAME}\x1f%{RECOMMENDFLAGS}\x1f%{RECOMMENDVERSION}\x1e]",
+    "[@@SUG@@\x1f%{SUGGESTNAME}\x1f%{SUGGESTFLAGS}\x1f%{SUGGESTVERSION}\x1e]",
+    "[@@OBS@@\x1f%{OBSOLETENAME}\x1f%{OBSOLETEFLAGS}\x1f%{OBSOLETEVERSION}\x1e]",
+    "[@@CONF@@\x1f%{CONFLICTNAME}\x1f%{CONFLICTFLAGS}\x1f%{CONFLICTVERSION}\x1e]",
+    // Scriptlet records, always present but with `(none)` fields if the
+    // package has no such scriptlet:
+    "@@PRE@@\x1f%{PREINPROG}\x1f%{PREIN}\x1e",
+    "@@POST@@\x1f%{POSTINPROG}\x1f%{POSTIN}\x1e",
+    "@@PREUN@@\x1f%{PREUNPROG}\x1f%{PREUN}\x1e",
+    "@@POSTUN@@\x1f%{POSTUNPROG}\x1f%{POSTUN}\x1e",
+    // Per-trigger condition records, then per-trigger script records, which
+    // the conditions refer to by index (iterated with []):
+    "[@@TRIG@@\x1f%{TRIGGERINDEX}\x1f%{TRIGGERNAME}\x1f%{TRIGGERFLAGS}\x1f%{TRIGGERVERSION}\x1e]",
+    "[@@TRIGSCRIPT@@\x1f%{TRIGGERSCRIPTPROG}\x1f%{TRIGGERSCRIPTS}\x1e]",
+    // End of package record, to detect truncated output:
+    "@@END@@\x1e",
+);
+
+/// [`QUERYFORMAT`] as of format version 4, returned by [`queryformat_v4`].
+pub(crate) const QUERYFORMAT_V4: &str = concat!(
+    "@@FMT@@\x1f4\x1e",
+    "@@PKG@@\x1f%{NAME}\x1f%{VERSION}\x1f%{RELEASE}\x1f%{EPOCH}\x1f%{ARCH}",
+    "\x1f%{LICENSE}\x1f%{LONGSIZE}\x1f%{BUILDTIME}\x1f%{INSTALLTIME}",
+    "\x1f%{SOURCERPM}\x1f%{FILEDIGESTALGO}\x1f%{INSTALLTID}\x1e",
+    "[@@FILE@@\x1f%{FILENAMES}\x1f%{LONGFILESIZES}\x1f%{FILEMODES}\x1f%{FILEMTIMES}",
+    "\x1f%{FILEDIGESTS}\x1f%{FILEFLAGS}",
+    "\x1f%{FILEUSERNAME}\x1f%{FILEGROUPNAME}\x1f%{FILELINKTOS}\x1f%{FILESTATES}\x1e]",
+    "[@@CL@@\x1f%{CHANGELOGTIME}\x1e]",
+    "@@END@@\x1e",
 );
 
-/// Expected number of tab-separated fields after stripping the @@PKG@@ prefix.
-const PKG_FIELDS: usize = 11;
-/// Expected number of tab-separated fields after stripping the @@FILE@@ prefix.
-const FILE_FIELDS: usize = 9;
+/// Default arch of packages without one when parsing leniently, as `rpm -q`
+/// would print it.
+pub(crate) const MISSING_ARCH: &str = "(none)";
+
+/// How fields and records are delimited in queryformat output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Delimiters {
+    /// Tab-separated fields, one record per line. This is what older versions
+    /// of this crate used; it's ambiguous if values contain tabs or newlines.
+    Lines,
+    /// Fields separated by the ASCII unit separator (0x1f), records
+    /// terminated by the record separator (0x1e).
+    Separators,
+}
+
+impl Delimiters {
+    pub(crate) fn field(self) -> char {
+        match self {
+            Delimiters::Lines => '\t',
+            Delimiters::Separators => '\x1f',
+        }
+    }
+
+    pub(crate) fn record(self) -> u8 {
+        match self {
+            Delimiters::Lines => b'\n',
+            Delimiters::Separators => 0x1e,
+        }
+    }
+
+    /// Detect the delimiters from the start of the output, which is a FMT or
+    /// PKG record tag followed by the field separator.
+    pub(crate) fn detect(start: &[u8]) -> Self {
+        if start.starts_with(b"@@FMT@@\x1f") || start.starts_with(b"@@PKG@@\x1f") {
+            Delimiters::Separators
+        } else {
+            Delimiters::Lines
+        }
+    }
+}
+
+/// Tags in [`QUERYFORMAT`] backing optional fields. Older rpm versions which
+/// don't know about them get a reduced queryformat with the fields left empty.
+#[cfg(feature = "exec")]
+const OPTIONAL_TAGS: &[&str] = &[
+    "EPOCH",
+    "SOURCERPM",
+    "FILEDIGESTALGO",
+    "INSTALLTID",
+    "FILEDIGESTS",
+    "FILELINKTOS",
+    "FILESTATES",
+    "FILECAPS",
+    "RSAHEADER",
+    "DSAHEADER",
+];
+
+/// Tags in [`QUERYFORMAT`] backing optional records. Older rpm versions which
+/// don't know about them (weak dependencies are from rpm 4.12) get a reduced
+/// queryformat without the records.
+#[cfg(feature = "exec")]
+const OPTIONAL_RECORD_TAGS: &[&str] = &["RECOMMENDNAME", "SUGGESTNAME"];
+
+/// Older names to fall back to for tags in [`QUERYFORMAT`] which rpm versions
+/// from before they were introduced don't know about.
+#[cfg(feature = "exec")]
+const FALLBACK_TAGS: &[(&str, &str)] = &[
+    // rpm < 4.6 has no 64-bit size tags, and so no packages that need them.
+    ("LONGSIZE", "SIZE"),
+    ("LONGFILESIZES", "FILESIZES"),
+    ("FILEDIGESTS", "FILEMD5S"),
+];
+
+/// Build a variant of [`QUERYFORMAT`] for an rpm which only supports the tags
+/// for which `supported` returns `true`.
+///
+/// Unsupported optional tags are replaced with a literal marking the field as
+/// absent: `(none)` in PKG lines, and an empty string in FILE lines. Records
+/// of unsupported optional record tags are left out (see
+/// [`OPTIONAL_RECORD_TAGS`]). Tags with an older equivalent are replaced with
+/// it (see [`FALLBACK_TAGS`]).
+#[cfg(feature = "exec")]
+pub(crate) fn queryformat_for_tags(supported: impl Fn(&str) -> bool) -> Result<String> {
+    // Leave out the `[...]` arrays of unsupported optional records first.
+    let mut qf = String::with_capacity(QUERYFORMAT.len());
+    let mut rest = QUERYFORMAT;
+    while let Some(start) = rest.find('[') {
+        let end = start
+            + rest[start..]
+                .find(']')
+                .expect("unterminated array in QUERYFORMAT");
+        let array = &rest[start..=end];
+        let unsupported = (OPTIONAL_RECORD_TAGS.iter())
+            .any(|tag| array.contains(&format!("%{{{tag}}}")) && !supported(tag));
+        qf.push_str(&rest[..start]);
+        if !unsupported {
+            qf.push_str(array);
+        }
+        rest = &rest[end + 1..];
+    }
+    qf.push_str(rest);
+
+    let mut out = String::with_capacity(qf.len());
+    let mut rest = qf.as_str();
+    // Whether we're inside a `[...]` array, i.e. in FILE or CL lines.
+    let mut in_array = false;
+    while let Some(start) = rest.find("%{") {
+        let (lit, tail) = rest.split_at(start);
+        if let Some(i) = lit.rfind(['[', ']']) {
+            in_array = lit.as_bytes()[i] == b'[';
+        }
+        out.push_str(lit);
+        let end = tail.find('}').expect("unterminated tag in QUERYFORMAT");
+        // Strip any formatter, e.g. `:pgpsig`.
+        let tag = tail[2..end].split(':').next().unwrap_or_default();
+        rest = &tail[end + 1..];
+        let fallback = FALLBACK_TAGS
+            .iter()
+            .find(|(new, old)| *new == tag && supported(old))
+            .map(|(_, old)| old);
+        if supported(tag) {
+            out.push_str(&tail[..=end]);
+        } else if let Some(old) = fallback {
+            out.push_str(&format!("%{{{old}}}"));
+        } else if OPTIONAL_TAGS.contains(&tag) {
+            out.push_str(if in_array { "" } else { "(none)" });
+        } else {
+            return Err(Error::UnsupportedRpmVersion(format!(
+                "rpm does not support required tag {tag}"
+            ))
+            .into());
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Version of the format produced by [`QUERYFORMAT`], stated in its @@FMT@@
+/// records. Bump this whenever records change shape.
+pub(crate) const FORMAT_VERSION: u32 = 13;
+
+/// First format version in which CL records have the author and text of
+/// changelog entries.
+const FORMAT_VERSION_CHANGELOG: u32 = 13;
+
+/// First format version in which each package ends with an @@END@@ record.
+const FORMAT_VERSION_END: u32 = 4;
+
+/// Version assumed for line-delimited output without a @@FMT@@ record, as
+/// produced by older versions of this crate.
+const FORMAT_VERSION_LEGACY: u32 = 1;
+
+/// Expected number of fields after stripping the @@PKG@@ prefix, for each
+/// format version.
+fn pkg_fields(version: u32) -> &'static [usize] {
+    match version {
+        // INSTALLTID was added partway through.
+        1 => &[12, 11],
+        // The header signatures were added in version 8.
+        2..=7 => &[12],
+        _ => &[14],
+    }
+}
+
+/// Expected number of fields after stripping the @@FILE@@ prefix, for each
+/// format version.
+fn file_fields(version: u32) -> usize {
+    match version {
+        // FILESTATES was added in version 3, FILELANGS in version 5,
+        // FILECOLORS in version 7 and FILECAPS in version 11.
+        1 | 2 => 9,
+        3 | 4 => 10,
+        5 | 6 => 11,
+        7..=10 => 12,
+        _ => 13,
+    }
+}
+
+/// Names of the FILE fields, in order, for diagnostics.
+const FILE_FIELD_NAMES: [&str; 13] = [
+    "path", "size", "mode", "mtime", "digest", "flags", "user", "group", "linkto", "state", "lang",
+    "color", "caps",
+];
+
+/// FILE lines whose parsing was deferred (with the @@FILE@@ prefix already
+/// stripped), each terminated by the record delimiter.
+#[derive(Debug, Clone)]
+pub(crate) struct RawFiles {
+    pub(crate) delimiters: Delimiters,
+    pub(crate) version: u32,
+    /// Whether to validate files once parsed (see
+    /// [`ParseOptions::strict_validation`]).
+    pub(crate) validate: bool,
+    pub(crate) data: String,
+}
 
 /// Stream-parse queryformat output from a reader.
-pub(crate) fn load_from_reader_impl<R: Read>(reader: R) -> Result<Packages> {
-    let mut packages = Packages::new();
-    let mut current_pkg: Option<Package> = None;
-    // Whether the current package is gpg-pubkey (skip its FILE/CL lines).
-    let mut skip = false;
-
-    for (line_no, line) in std::io::BufReader::new(reader).lines().enumerate() {
-        let line = line.context("reading line")?;
+pub(crate) fn load_from_reader_impl<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> crate::Result<Packages> {
+    load_from_reader_hashed(reader, options)
+}
+
+/// Stream-parse output of [`QUERYFORMAT`] or [`QUERYFORMAT_V4`], rejecting
+/// the tab-separated format instead of detecting it.
+pub(crate) fn parse_queryformat_impl<R: Read>(
+    mut reader: R,
+    options: &ParseOptions,
+) -> crate::Result<Packages> {
+    let (start, len) = read_start(&mut reader)?;
+    if len > 0 && Delimiters::detect(&start[..len]) != Delimiters::Separators {
+        return Err(Error::Parse {
+            line: 1,
+            message: "not queryformat output: missing @@FMT@@ or @@PKG@@ record".into(),
+        });
+    }
+    let start = std::io::Cursor::new(start).take(len as u64);
+    load_from_reader_impl(start.chain(reader), options)
+}
+
+/// Read the first bytes of the output, enough to tell how it's delimited.
+/// Returns fewer only if the output is shorter.
+fn read_start<R: Read>(reader: &mut R) -> std::io::Result<([u8; 8], usize)> {
+    let mut start = [0u8; 8];
+    let mut len = 0;
+    while len < start.len() {
+        match reader.read(&mut start[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((start, len))
+}
+
+/// Like [`load_from_reader_impl`], into a map using the hasher `S`.
+pub(crate) fn load_from_reader_hashed<R: Read, S: BuildHasher + Default>(
+    reader: R,
+    options: &ParseOptions,
+) -> crate::Result<HashMap<String, Package, S>> {
+    parse_reader(reader, options, None)
+}
+
+/// Stream-parse queryformat output from a reader, skipping over malformed
+/// packages, files and lines and recording the errors instead of failing.
+pub(crate) fn load_from_reader_lenient_impl<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> crate::Result<(Packages, Vec<PackageError>)> {
+    let mut errors = Vec::new();
+    let packages = parse_reader(reader, options, Some(&mut errors))?;
+    Ok((packages, errors))
+}
+
+/// Parse queryformat output. If `errors` is provided, errors specific to a
+/// line are recorded there and parsing continues; otherwise, they're fatal.
+fn parse_reader<R: Read, S: BuildHasher + Default>(
+    reader: R,
+    options: &ParseOptions,
+    errors: Option<&mut Vec<PackageError>>,
+) -> crate::Result<HashMap<String, Package, S>> {
+    let started = Instant::now();
+    let mut reader = CountingReader {
+        inner: reader,
+        bytes: 0,
+    };
+    let (packages, nerrors) = parse_counted(&mut reader, options, errors)?;
+    notify(|observer| {
+        observer.on_load(&LoadStats {
+            duration: started.elapsed(),
+            packages: packages.len(),
+            files: packages.values().map(|pkg| pkg.files.len()).sum(),
+            bytes: reader.bytes,
+            errors: nerrors,
+        })
+    });
+    Ok(packages)
+}
+
+/// A reader counting the bytes read through it.
+struct CountingReader<R> {
+    inner: R,
+    bytes: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes += n as u64;
+        Ok(n)
+    }
+}
+
+/// Returns the packages and the number of errors and warnings, including
+/// those not recorded over [`ParseOptions::max_errors`].
+fn parse_counted<R: Read, S: BuildHasher + Default>(
+    reader: R,
+    options: &ParseOptions,
+    mut errors: Option<&mut Vec<PackageError>>,
+) -> crate::Result<(HashMap<String, Package, S>, usize)> {
+    let mut records = RecordReader::new(reader, options)?;
+    let mut parser = Parser::new(options, records.delimiters, errors.is_some());
+    let max_errors = options.max_errors.unwrap_or(usize::MAX);
+    let mut dropped = 0;
+    let mut last_line = 0;
+    for line_no in 1.. {
+        parser.line = line_no;
+        let Some(r) = records.parse_next(&mut parser)? else {
+            break;
+        };
+        last_line = line_no;
+        if let Some(errors) = errors.as_deref_mut() {
+            errors.append(&mut parser.warnings);
+        }
+        if let Err(error) = r {
+            report(errors.as_deref_mut(), &parser, line_no, error)?;
+        }
+        if let Some(errors) = errors.as_deref_mut() {
+            dropped += cap_errors(errors, max_errors);
+        }
+    }
+
+    if parser.is_incomplete() {
+        let error = parser.truncate();
+        report(errors.as_deref_mut(), &parser, last_line, error)?;
+    }
+    parser.finish_current();
+    let Some(errors) = errors else {
+        return Ok((parser.finish()?, 0));
+    };
+    errors.append(&mut parser.warnings);
+    dropped += cap_errors(errors, max_errors);
+    let nerrors = errors.len() + dropped;
+    if dropped > 0 {
+        errors.push(PackageError {
+            package: None,
+            line: last_line,
+            error: Error::Parse {
+                line: last_line,
+                message: format!("{dropped} more errors and warnings not recorded"),
+            },
+            warning: false,
+        });
+    }
+    Ok((parser.finish()?, nerrors))
+}
+
+/// Drop the errors over `max`, returning how many were.
+fn cap_errors(errors: &mut Vec<PackageError>, max: usize) -> usize {
+    let dropped = errors.len().saturating_sub(max);
+    errors.truncate(max);
+    dropped
+}
+
+/// Reads queryformat output one record at a time.
+struct RecordReader<R> {
+    reader: std::io::BufReader<std::io::Chain<std::io::Take<std::io::Cursor<[u8; 8]>>, R>>,
+    delimiters: Delimiters,
+    /// Record buffer, reused so that memory stays bounded by the longest
+    /// record plus the parsed structures, however large the input.
+    buf: Vec<u8>,
+    /// Read at most one byte past the line limit to tell if a line exceeds
+    /// it.
+    max_read: u64,
+}
+
+impl<R: Read> RecordReader<R> {
+    fn new(mut reader: R, options: &ParseOptions) -> crate::Result<Self> {
+        // Peek at the start of the output to tell how it's delimited.
+        let (start, len) = read_start(&mut reader)?;
+        let delimiters = Delimiters::detect(&start[..len]);
+        let start = std::io::Cursor::new(start).take(len as u64);
+        Ok(Self {
+            reader: std::io::BufReader::new(start.chain(reader)),
+            delimiters,
+            buf: Vec::new(),
+            max_read: options
+                .max_line_length
+                .map_or(u64::MAX, |max| max as u64 + 1),
+        })
+    }
+
+    /// Read the next record and feed it to `parser`, returning the outcome
+    /// of parsing it, or `None` at the end of the output. Only I/O errors are
+    /// returned directly.
+    fn parse_next(
+        &mut self,
+        parser: &mut Parser<impl BuildHasher>,
+    ) -> crate::Result<Option<std::result::Result<(), Error>>> {
+        let (delimiters, max_read) = (self.delimiters, self.max_read);
+        self.buf.clear();
+        let read = (&mut self.reader)
+            .take(max_read)
+            .read_until(delimiters.record(), &mut self.buf);
+        match read {
+            Ok(0) => return Ok(None),
+            Ok(_) => {}
+            Err(e) => return Err(e.into()),
+        }
+        if is_overlong(&self.buf, delimiters, max_read) {
+            skip_record(&mut self.reader, delimiters.record())?;
+            return Ok(Some(Err(overlong_error(parser.line, max_read))));
+        }
+        Ok(Some(parse_record(parser, &self.buf)))
+    }
+}
+
+/// Whether `buf` was cut at the line length limit before the end of its
+/// record.
+fn is_overlong(buf: &[u8], delimiters: Delimiters, max_read: u64) -> bool {
+    buf.len() as u64 == max_read && buf.last() != Some(&delimiters.record())
+}
+
+fn overlong_error(line: usize, max_read: u64) -> Error {
+    parse_error(line, anyhow::anyhow!("line exceeds {} bytes", max_read - 1))
+}
+
+/// Parse a record read into `buf`, with its terminator unless it ends the
+/// output.
+fn parse_record(
+    parser: &mut Parser<impl BuildHasher>,
+    buf: &[u8],
+) -> std::result::Result<(), Error> {
+    let delimiters = parser.delimiters;
+    match buf.strip_suffix(&[delimiters.record()]) {
+        // Records are always terminated, so a partial one means the output
+        // was cut short.
+        None if delimiters == Delimiters::Separators => Err(parser.truncate()),
+        line => {
+            let mut line = line.unwrap_or(buf);
+            if delimiters == Delimiters::Lines {
+                line = line.strip_suffix(b"\r").unwrap_or(line);
+            }
+            std::str::from_utf8(line)
+                .context("invalid UTF-8")
+                .and_then(|line| parser.parse_line(line))
+                .map_err(|e| parse_error(parser.line, e))
+        }
+    }
+}
+
+/// Stream-parse queryformat output from an async reader. Errors are fatal, as
+/// with [`load_from_reader_impl`].
+#[cfg(feature = "async")]
+pub(crate) async fn load_from_async_reader_impl<R: tokio::io::AsyncRead + Unpin>(
+    mut reader: R,
+    options: &ParseOptions,
+) -> crate::Result<Packages> {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+    let started = Instant::now();
+    // Peek at the start of the output to tell how it's delimited.
+    let mut start = [0u8; 8];
+    let mut len = 0;
+    while len < start.len() {
+        match reader.read(&mut start[len..]).await {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let delimiters = Delimiters::detect(&start[..len]);
+    let mut parser = Parser::new(options, delimiters, false);
+    let mut reader = tokio::io::BufReader::new(AsyncReadExt::chain(&start[..len], reader));
+    let max_read = options
+        .max_line_length
+        .map_or(u64::MAX, |max| max as u64 + 1);
+    let mut buf = Vec::new();
+    let mut bytes = 0;
+    for line_no in 1.. {
+        buf.clear();
+        parser.line = line_no;
+        let n = (&mut reader)
+            .take(max_read)
+            .read_until(delimiters.record(), &mut buf)
+            .await?;
+        if n == 0 {
+            break;
+        }
+        bytes += n as u64;
+        if is_overlong(&buf, delimiters, max_read) {
+            return Err(overlong_error(line_no, max_read));
+        }
+        parse_record(&mut parser, &buf)?;
+    }
+    if parser.is_incomplete() {
+        return Err(parser.truncate());
+    }
+    let packages = parser.finish()?;
+    notify(|observer| {
+        observer.on_load(&LoadStats {
+            duration: started.elapsed(),
+            packages: packages.len(),
+            files: packages.values().map(|pkg| pkg.files.len()).sum(),
+            bytes,
+            errors: 0,
+        })
+    });
+    Ok(packages)
+}
+
+/// Parse queryformat output into packages one at a time, as they're
+/// completed. Errors are fatal: the first one is returned after the packages
+/// completed before it, and ends the iteration.
+pub(crate) fn load_iter_from_reader_impl<'a, R: Read + 'a>(
+    reader: R,
+    options: &'a ParseOptions,
+) -> impl Iterator<Item = crate::Result<Package>> + 'a {
+    let (state, error) = match RecordReader::new(reader, options) {
+        Ok(records) => {
+            let mut parser = Parser::new(options, records.delimiters, false);
+            // Hand packages over one at a time rather than collect them.
+            parser.packages.queue = Some(Vec::new());
+            (Some((records, parser)), None)
+        }
+        Err(e) => (None, Some(e)),
+    };
+    PackageIter { state, error }
+}
+
+struct PackageIter<'a, R> {
+    /// The reader and parser, until the end of the output or an error.
+    state: Option<(RecordReader<R>, Parser<'a>)>,
+    /// An error to return once the packages completed before it are.
+    error: Option<Error>,
+}
+
+impl<R: Read> Iterator for PackageIter<'_, R> {
+    type Item = crate::Result<Package>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((records, parser)) = &mut self.state {
+            // Each record completes at most one package.
+            if let Some(pkg) = parser.packages.pop() {
+                return Some(Ok(pkg));
+            }
+            parser.line += 1;
+            match records.parse_next(parser) {
+                Ok(Some(Ok(()))) => continue,
+                Ok(Some(Err(e))) | Err(e) => self.error = Some(e),
+                Ok(None) if parser.is_incomplete() => self.error = Some(parser.truncate()),
+                Ok(None) => parser.finish_current(),
+            }
+            // Stop, after the package completed by the last record if any.
+            let pending = parser.packages.pop();
+            self.state = None;
+            if pending.is_some() {
+                return pending.map(Ok);
+            }
+        }
+        self.error.take().map(Err)
+    }
+}
+
+fn parse_error(line: usize, e: anyhow::Error) -> Error {
+    Error::Parse {
+        line,
+        message: format!("{e:#}"),
+    }
+}
+
+/// Record an error if parsing leniently, or else return it.
+fn report(
+    errors: Option<&mut Vec<PackageError>>,
+    parser: &Parser<impl BuildHasher>,
+    line: usize,
+    error: Error,
+) -> crate::Result<()> {
+    match errors {
+        // Exceeding the package limit is fatal even when lenient.
+        Some(errors) if !parser.too_many_packages => {
+            let error = PackageError {
+                package: parser.pkg_name.clone(),
+                line,
+                error,
+                warning: false,
+            };
+            warn!("skipping malformed entry on line {line}: {error}");
+            errors.push(error);
+            Ok(())
+        }
+        _ => Err(error),
+    }
+}
+
+/// Discard input up to and including the next `delim`.
+fn skip_record(reader: &mut impl BufRead, delim: u8) -> std::io::Result<()> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        if let Some(i) = buf.iter().position(|&b| b == delim) {
+            reader.consume(i + 1);
+            return Ok(());
+        }
+        let n = buf.len();
+        reader.consume(n);
+    }
+}
+
+/// Line-by-line queryformat parser state.
+struct Parser<'a, S = std::hash::RandomState> {
+    options: &'a ParseOptions,
+    delimiters: Delimiters,
+    packages: PackagesBuilder<S>,
+    current_pkg: Option<Package>,
+    /// File entries of the current package, reused across packages.
+    current_files: Vec<(Utf8PathBuf, FileInfo)>,
+    /// Format version, once known from the first record.
+    version: Option<u32>,
+    /// Name from the last PKG line, for error reporting.
+    pkg_name: Option<String>,
+    /// Whether the current package is gpg-pubkey, filtered out or malformed
+    /// (skip its FILE/CL lines).
+    skip: bool,
+    /// Number of PKG lines seen so far.
+    npackages: usize,
+    /// Number of FILE lines seen for the current package.
+    nfiles: usize,
+    /// Trigger conditions of the current package not yet matched with their
+    /// script, with the index of the script.
+    trigger_conditions: Vec<(usize, Dependency)>,
+    /// Whether [`ParseOptions::max_packages`] was exceeded.
+    too_many_packages: bool,
+    /// Whether the current package was terminated by an @@END@@ record.
+    ended: bool,
+    /// Name of the last package known to be complete.
+    last_complete: Option<String>,
+    /// Whether the output was found to be truncated.
+    truncated: bool,
+    /// Whether errors are recorded rather than fatal, in which case
+    /// implausible values are only warned about.
+    lenient: bool,
+    /// Warnings not yet handed over to the caller.
+    warnings: Vec<PackageError>,
+    /// Number of the line being parsed.
+    line: usize,
+    /// Current time, for [`ParseOptions::check_timestamps`].
+    now: u64,
+    /// Whether an implausible mtime was already reported for the current
+    /// package.
+    mtime_warned: bool,
+    /// Number of changelog entries of the current package dropped over
+    /// [`ParseOptions::max_changelog_entries`], and the line of the first.
+    changelog_dropped: (usize, usize),
+}
+
+impl<'a, S: BuildHasher> Parser<'a, S> {
+    fn new(options: &'a ParseOptions, delimiters: Delimiters, lenient: bool) -> Self
+    where
+        S: Default,
+    {
+        Self {
+            options,
+            delimiters,
+            packages: PackagesBuilder::new(options.duplicates),
+            current_pkg: None,
+            current_files: Vec::new(),
+            version: None,
+            pkg_name: None,
+            skip: false,
+            npackages: 0,
+            nfiles: 0,
+            trigger_conditions: Vec::new(),
+            too_many_packages: false,
+            ended: false,
+            last_complete: None,
+            truncated: false,
+            lenient,
+            warnings: Vec::new(),
+            line: 0,
+            now: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            mtime_warned: false,
+            changelog_dropped: (0, 0),
+        }
+    }
+
+    /// Report an implausible but usable value, which is kept: log it, and
+    /// record a warning if parsing leniently.
+    fn warn(&mut self, message: String) {
+        self.warn_at(self.line, message);
+    }
+
+    /// Like [`Self::warn`], for a value on an earlier line of the current
+    /// package.
+    fn warn_at(&mut self, line: usize, message: String) {
+        warn!("line {line}: {message}");
+        if !self.lenient {
+            return;
+        }
+        self.warnings.push(PackageError {
+            package: self.pkg_name.clone(),
+            line,
+            error: Error::Parse { line, message },
+            warning: true,
+        });
+    }
+
+    /// Finalize the current package, if any.
+    fn finish_current(&mut self) {
+        if let Some(pkg) = self.current_pkg.take() {
+            let (dropped, line) = self.changelog_dropped;
+            if dropped > 0 {
+                self.warn_at(line, format!("dropped {dropped} changelog entries"));
+            }
+            finish_package(&mut self.packages, pkg, &mut self.current_files);
+        }
+        self.changelog_dropped = (0, 0);
+    }
+
+    /// Strip the given record tag and the following field separator.
+    fn strip_tag<'l>(&self, line: &'l str, tag: &str) -> Option<&'l str> {
+        line.strip_prefix(tag)?
+            .strip_prefix(self.delimiters.field())
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<()> {
         if line.is_empty() {
-            continue;
+            return Ok(());
         }
+        let sep = self.delimiters.field();
 
-        if let Some(rest) = line.strip_prefix("@@PKG@@\t") {
+        if line == "@@END@@" {
+            if self.npackages == 0 || self.ended {
+                bail!("END line without PKG");
+            }
+            self.finish_current();
+            self.ended = true;
+            self.skip = false;
+            self.last_complete.clone_from(&self.pkg_name);
+        } else if let Some(rest) = self.strip_tag(line, "@@FMT@@") {
+            let version: u32 =
+                parse_uint(rest).with_context(|| format!("invalid format version '{rest}'"))?;
+            match self.version {
+                None if version == 0 || version > FORMAT_VERSION => bail!(
+                    "unsupported format version {version} (expected at most {FORMAT_VERSION}); \
+                     was the output produced by a newer version of this crate?"
+                ),
+                None => self.version = Some(version),
+                Some(v) if v != version => {
+                    bail!("format version changed from {v} to {version}")
+                }
+                Some(_) => {}
+            }
+        } else if let Some(rest) = self.strip_tag(line, "@@PKG@@") {
             // Finalize previous package.
-            if let Some(pkg) = current_pkg.take() {
-                packages.insert(pkg.name.clone(), pkg);
+            self.finish_current();
+            // The previous package was finalized above regardless; this is
+            // reported once this package's header is parsed.
+            let mut missing_end = None;
+            if self.version.is_some_and(|v| v >= FORMAT_VERSION_END) {
+                if self.npackages > 0 && !self.ended {
+                    missing_end = self.pkg_name.take();
+                }
+            } else if self.pkg_name.is_some() {
+                self.last_complete = self.pkg_name.take();
+            }
+            self.ended = false;
+            // Skip this package's lines unless its header parses fine.
+            self.skip = true;
+            self.nfiles = 0;
+            self.trigger_conditions.clear();
+            self.mtime_warned = false;
+            if let Some(max) = self.options.max_packages
+                && self.npackages >= max
+            {
+                self.too_many_packages = true;
+                bail!("more than {max} packages");
             }
+            self.npackages += 1;
 
-            let fields: Vec<&str> = rest.split('\t').collect();
-            if fields.len() != PKG_FIELDS {
+            let fields: Vec<&str> = rest.split(sep).collect();
+            self.pkg_name = Some(fields[0].to_string());
+            let version = match (self.version, self.delimiters) {
+                (Some(v), _) => v,
+                (None, Delimiters::Lines) => *self.version.insert(FORMAT_VERSION_LEGACY),
+                (None, Delimiters::Separators) => {
+                    bail!("missing @@FMT@@ record before first package")
+                }
+            };
+            let expected = pkg_fields(version);
+            if !expected.contains(&fields.len()) {
                 bail!(
-                    "line {}: expected {PKG_FIELDS} fields in PKG line, got {}",
-                    line_no + 1,
+                    "expected {} fields in PKG line for format version {version}, got {}",
+                    expected[0],
                     fields.len()
                 );
             }
@@ -58,72 +878,332 @@ pub(crate) fn load_from_reader_impl<R: Read>(reader: R) -> Result<Packages> {
             let name = fields[0];
             // Skip gpg-pubkey entries (they lack Arch and aren't real packages).
             if name == "gpg-pubkey" {
-                skip = true;
-                continue;
-            }
-
-            skip = false;
-            let pkg = parse_pkg_header(&fields)
-                .with_context(|| format!("parsing package header at line {}", line_no + 1))?;
-            current_pkg = Some(pkg);
-        } else if skip {
-            // Consume FILE/CL lines for skipped packages.
-            continue;
-        } else if let Some(rest) = line.strip_prefix("@@FILE@@\t") {
-            let pkg = current_pkg
+                debug!("skipping gpg-pubkey entry on line {}", self.line);
+                return match missing_end {
+                    Some(prev) => Err(anyhow::anyhow!("missing END line for '{prev}'")),
+                    None => Ok(()),
+                };
+            }
+
+            // Imported or foreign headers occasionally lack an arch too.
+            let missing_arch = self
+                .lenient
+                .then(|| self.options.missing_arch.as_deref().unwrap_or(MISSING_ARCH));
+            let mut pkg =
+                parse_pkg_header(&fields, missing_arch).context("parsing package header")?;
+            if self.options.epoch_policy == EpochPolicy::PromoteToZero {
+                pkg.epoch.get_or_insert(0);
+            }
+            self.skip = !self.options.matches(&pkg);
+            if !self.skip {
+                if parse_optional(fields[4]).is_none() {
+                    self.warn(format!("missing arch, using '{}'", pkg.arch));
+                }
+                if let Some(bounds) = &self.options.timestamp_bounds {
+                    for (what, time) in [
+                        ("buildtime", pkg.buildtime),
+                        ("installtime", pkg.installtime),
+                    ] {
+                        if let Some(problem) = check_timestamp(bounds, self.now, time) {
+                            self.warn(format!("implausible {what} {time}: {problem}"));
+                        }
+                    }
+                }
+                self.current_pkg = Some(pkg);
+            }
+            if let Some(prev) = missing_end {
+                bail!("missing END line for '{prev}'");
+            }
+        } else if let Some(rest) = self.strip_tag(line, "@@FILE@@") {
+            if self.skip {
+                return Ok(());
+            }
+            let pkg = self
+                .current_pkg
                 .as_mut()
-                .ok_or_else(|| anyhow::anyhow!("line {}: FILE line before any PKG", line_no + 1))?;
-            let fields: Vec<&str> = rest.split('\t').collect();
-            if fields.len() != FILE_FIELDS {
-                bail!(
-                    "line {}: expected {} fields in FILE line for '{}', got {}",
-                    line_no + 1,
-                    FILE_FIELDS,
-                    pkg.name,
-                    fields.len()
-                );
+                .ok_or_else(|| anyhow::anyhow!("FILE line before any PKG"))?;
+            if let Some(max) = self.options.max_files_per_package
+                && self.nfiles >= max
+            {
+                // Drop the package rather than keep a truncated file list.
+                let name = std::mem::take(&mut pkg.name);
+                self.current_pkg = None;
+                self.current_files.clear();
+                self.skip = true;
+                bail!("package '{name}' has more than {max} files");
+            }
+            self.nfiles += 1;
+            // Always known by now, since we have a package.
+            let version = self.version.unwrap_or(FORMAT_VERSION_LEGACY);
+            if self.options.lazy_files {
+                let raw = pkg.unparsed_files.get_or_insert_with(|| RawFiles {
+                    delimiters: self.delimiters,
+                    version,
+                    validate: self.options.strict_validation,
+                    data: String::new(),
+                });
+                raw.data.push_str(rest);
+                raw.data.push(self.delimiters.record() as char);
+                return Ok(());
+            }
+            let index = self.nfiles - 1;
+            let context = || format!("file #{index} of '{}'", pkg.nevra());
+            let (path, info) = parse_file_line(rest, sep, version).with_context(context)?;
+            if self.options.strict_validation {
+                validate_file(pkg.digest_algo, &path, &info).with_context(context)?;
+            }
+            if let Some(bounds) = &self.options.timestamp_bounds
+                && !self.mtime_warned
+                && let Some(problem) = check_timestamp(bounds, self.now, info.mtime)
+            {
+                self.mtime_warned = true;
+                self.warn(format!(
+                    "file '{path}': implausible mtime {}: {problem}",
+                    info.mtime
+                ));
+            }
+            self.current_files.push((path, info));
+        } else if let Some(rest) = self.strip_tag(line, "@@CL@@") {
+            if self.skip {
+                return Ok(());
+            }
+            let pkg = self
+                .current_pkg
+                .as_mut()
+                .ok_or_else(|| anyhow::anyhow!("CL line before any PKG"))?;
+            if let Some(max) = self.options.max_changelog_entries
+                && pkg.changelog.len() >= max
+            {
+                let (dropped, line) = &mut self.changelog_dropped;
+                if *dropped == 0 {
+                    *line = self.line;
+                }
+                *dropped += 1;
+                return Ok(());
+            }
+            let version = self.version.unwrap_or(FORMAT_VERSION_LEGACY);
+            let (time, author, text) = split_changelog_entry(rest, sep, version)
+                .with_context(|| format!("invalid CL line for '{}'", pkg.name))?;
+            let time: u64 = parse_uint(time)
+                .with_context(|| format!("invalid changelog time for '{}'", pkg.name))?;
+            pkg.changelog.push(ChangelogEntry {
+                time,
+                author: author.to_string(),
+                text: text.to_string(),
+            });
+        } else if let Some((rest, tag)) = ["REQ", "PROV", "REC", "SUG", "OBS", "CONF"]
+            .into_iter()
+            .find_map(|tag| Some((self.strip_tag(line, &format!("@@{tag}@@"))?, tag)))
+        {
+            if self.skip {
+                return Ok(());
+            }
+            let pkg = self
+                .current_pkg
+                .as_mut()
+                .ok_or_else(|| anyhow::anyhow!("{tag} line before any PKG"))?;
+            let dep = parse_dependency(rest, sep)
+                .with_context(|| format!("invalid {tag} line for '{}'", pkg.name))?;
+            match tag {
+                "REQ" => pkg.requires.push(dep),
+                "PROV" => pkg.provides.push(dep),
+                "REC" => pkg.recommends.push(dep),
+                "SUG" => pkg.suggests.push(dep),
+                "OBS" => pkg.obsoletes.push(dep),
+                _ => pkg.conflicts.push(dep),
+            }
+        } else if let Some((rest, tag)) = ["PRE", "POST", "PREUN", "POSTUN"]
+            .into_iter()
+            .find_map(|tag| Some((self.strip_tag(line, &format!("@@{tag}@@"))?, tag)))
+        {
+            if self.skip {
+                return Ok(());
+            }
+            let pkg = self
+                .current_pkg
+                .as_mut()
+                .ok_or_else(|| anyhow::anyhow!("{tag} line before any PKG"))?;
+            let scriptlet = parse_scriptlet(rest, sep)
+                .with_context(|| format!("invalid {tag} line for '{}'", pkg.name))?;
+            match tag {
+                "PRE" => pkg.pre = scriptlet,
+                "POST" => pkg.post = scriptlet,
+                "PREUN" => pkg.preun = scriptlet,
+                _ => pkg.postun = scriptlet,
+            }
+        } else if let Some(rest) = self.strip_tag(line, "@@TRIG@@") {
+            if self.skip {
+                return Ok(());
+            }
+            let pkg = self
+                .current_pkg
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("TRIG line before any PKG"))?;
+            let context = || format!("invalid TRIG line for '{}'", pkg.name);
+            let (index, rest) = rest.split_once(sep).with_context(context)?;
+            let index = (parse_uint::<usize>(index))
+                .with_context(|| format!("invalid trigger index '{index}'"))
+                .with_context(context)?;
+            let dep = parse_dependency(rest, sep).with_context(context)?;
+            self.trigger_conditions.push((index, dep));
+        } else if let Some(rest) = self.strip_tag(line, "@@TRIGSCRIPT@@") {
+            if self.skip {
+                return Ok(());
             }
-            let (path, info) = parse_file_line(&fields)
-                .with_context(|| format!("line {}: file in '{}'", line_no + 1, pkg.name))?;
-            pkg.files.insert(path, info);
-        } else if let Some(rest) = line.strip_prefix("@@CL@@\t") {
-            let pkg = current_pkg
+            let pkg = self
+                .current_pkg
                 .as_mut()
-                .ok_or_else(|| anyhow::anyhow!("line {}: CL line before any PKG", line_no + 1))?;
-            let time: u64 = rest.parse().with_context(|| {
-                format!(
-                    "line {}: invalid changelog time for '{}'",
-                    line_no + 1,
-                    pkg.name
-                )
-            })?;
-            pkg.changelog_times.push(time);
+                .ok_or_else(|| anyhow::anyhow!("TRIGSCRIPT line before any PKG"))?;
+            let index = pkg.triggers.len();
+            let context = || format!("invalid trigger #{index} of '{}'", pkg.name);
+            let scriptlet = parse_scriptlet(rest, sep).with_context(context)?;
+            let conditions: Vec<Dependency> = (self.trigger_conditions.iter())
+                .filter(|(i, _)| *i == index)
+                .map(|(_, dep)| dep.clone())
+                .collect();
+            let kind = (conditions.first())
+                .and_then(|dep| dep.flags.trigger_kind())
+                .context("no trigger conditions")
+                .with_context(context)?;
+            pkg.triggers.push(Trigger {
+                kind,
+                conditions,
+                scriptlet: scriptlet.unwrap_or(Scriptlet {
+                    interpreter: None,
+                    script: None,
+                }),
+            });
         } else {
-            bail!(
-                "line {}: unexpected line format: {}",
-                line_no + 1,
-                &line[..line.len().min(80)]
-            );
+            let snippet: String = line.chars().take(80).collect();
+            bail!("unexpected line format: {snippet}");
         }
+        Ok(())
     }
 
-    // Finalize last package.
-    if let Some(pkg) = current_pkg.take() {
-        packages.insert(pkg.name.clone(), pkg);
+    /// Whether the output ended in the middle of a package. This can only be
+    /// told with format versions which terminate packages.
+    fn is_incomplete(&self) -> bool {
+        !self.truncated
+            && self.npackages > 0
+            && !self.ended
+            && self.version.is_some_and(|v| v >= FORMAT_VERSION_END)
     }
 
-    Ok(packages)
+    /// Mark the output as truncated, dropping the incomplete package.
+    fn truncate(&mut self) -> Error {
+        self.truncated = true;
+        self.current_pkg = None;
+        self.current_files.clear();
+        self.skip = true;
+        Error::Truncated {
+            last_complete: self.last_complete.clone(),
+        }
+    }
+
+    fn finish(mut self) -> crate::Result<HashMap<String, Package, S>> {
+        // Finalize last package.
+        self.finish_current();
+        self.packages.finish()
+    }
+}
+
+/// Collects parsed packages into a map as they're completed.
+struct PackagesBuilder<S> {
+    packages: HashMap<String, Package, S>,
+    duplicates: DuplicatePolicy,
+    /// Names shared by several packages, which are keyed by NEVRA instead
+    /// with [`DuplicatePolicy::Merge`].
+    merged: HashSet<String>,
+    /// The first name shared by several packages, with
+    /// [`DuplicatePolicy::Error`].
+    duplicate: Option<String>,
+    /// Completed packages not yet handed over, when streaming them rather
+    /// than collecting them.
+    queue: Option<Vec<Package>>,
+}
+
+impl<S: BuildHasher> PackagesBuilder<S> {
+    fn new(duplicates: DuplicatePolicy) -> Self
+    where
+        S: Default,
+    {
+        Self {
+            packages: HashMap::default(),
+            duplicates,
+            merged: HashSet::new(),
+            duplicate: None,
+            queue: None,
+        }
+    }
+
+    fn push(&mut self, pkg: Package) {
+        if let Some(queue) = &mut self.queue {
+            queue.push(pkg);
+            return;
+        }
+        if self.merged.contains(&pkg.name) {
+            self.packages.insert(pkg.nevra(), pkg);
+            return;
+        }
+        // The map owns its keys, so the name is copied once here.
+        match self.packages.entry(pkg.name.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert(pkg);
+            }
+            Entry::Occupied(mut entry) => match self.duplicates {
+                DuplicatePolicy::Overwrite => {
+                    entry.insert(pkg);
+                }
+                DuplicatePolicy::Error => {
+                    self.duplicate.get_or_insert(pkg.name);
+                }
+                DuplicatePolicy::Merge => {
+                    let (name, prev) = entry.remove_entry();
+                    self.packages.insert(prev.nevra(), prev);
+                    self.packages.insert(pkg.nevra(), pkg);
+                    self.merged.insert(name);
+                }
+            },
+        }
+    }
+
+    /// Take the next streamed package, if any.
+    fn pop(&mut self) -> Option<Package> {
+        self.queue.as_mut()?.pop()
+    }
+
+    fn finish(self) -> crate::Result<HashMap<String, Package, S>> {
+        match self.duplicate {
+            Some(name) => Err(Error::DuplicatePackage(name)),
+            None => Ok(self.packages),
+        }
+    }
+}
+
+/// Add a fully parsed package to the builder, together with its file entries.
+///
+/// rpm emits file lists already sorted by path, so collecting them in one go
+/// lets the `BTreeMap` bulk-build, which is much faster than inserting paths
+/// one at a time (and than a `HashMap` would be).
+fn finish_package<S: BuildHasher>(
+    packages: &mut PackagesBuilder<S>,
+    mut pkg: Package,
+    files: &mut Vec<(Utf8PathBuf, FileInfo)>,
+) {
+    pkg.files = files.drain(..).collect();
+    packages.push(pkg);
 }
 
 /// Parse queryformat output from a string.
-pub(crate) fn load_from_str_impl(input: &str) -> Result<Packages> {
-    load_from_reader_impl(input.as_bytes())
+pub(crate) fn load_from_str_impl(input: &str) -> crate::Result<Packages> {
+    load_from_reader_impl(input.as_bytes(), &ParseOptions::default())
 }
 
 /// Parse the package header fields from a @@PKG@@ line into a partially-built
-/// Package (files and changelog_times are filled in later).
-fn parse_pkg_header(fields: &[&str]) -> Result<Package> {
-    assert_eq!(fields.len(), PKG_FIELDS); // checked by caller
+/// Package (files and changelog are filled in later). A missing arch is
+/// replaced with `missing_arch` if given, or else is an error.
+fn parse_pkg_header(fields: &[&str], missing_arch: Option<&str>) -> Result<Package> {
+    assert!(fields.len() >= 11); // checked by caller
     let name = fields[0];
     let epoch = match parse_optional(fields[3]) {
         None => None,
@@ -133,6 +1213,7 @@ fn parse_pkg_header(fields: &[&str]) -> Result<Package> {
         ),
     };
     let arch = parse_optional(fields[4])
+        .or(missing_arch)
         .ok_or_else(|| anyhow::anyhow!("{name}: missing arch"))?
         .to_string();
     let size = fields[6]
@@ -141,9 +1222,13 @@ fn parse_pkg_header(fields: &[&str]) -> Result<Package> {
     let buildtime = fields[7]
         .parse::<u64>()
         .with_context(|| format!("{name}: invalid buildtime"))?;
-    let installtime = fields[8]
-        .parse::<u64>()
-        .with_context(|| format!("{name}: invalid installtime"))?;
+    // Packages read from rpm files rather than the rpmdb have no install time.
+    let installtime = match parse_optional(fields[8]) {
+        None => 0,
+        Some(s) => s
+            .parse::<u64>()
+            .with_context(|| format!("{name}: invalid installtime"))?,
+    };
     let sourcerpm = parse_optional(fields[9]).map(|s| s.to_string());
 
     let digest_algo = match parse_optional(fields[10]) {
@@ -159,6 +1244,20 @@ fn parse_pkg_header(fields: &[&str]) -> Result<Package> {
         }
     };
 
+    let installtid = match fields.get(11).copied().and_then(parse_optional) {
+        None => None,
+        Some(s) => Some(
+            s.parse::<u64>()
+                .with_context(|| format!("{name}: invalid installtid '{s}'"))?,
+        ),
+    };
+
+    // Both signatures are absent from older format versions.
+    let signing_key = (fields.get(12..).unwrap_or_default().iter())
+        .find_map(|sig| parse_optional(sig))
+        .map(|sig| parse_key_id(sig).with_context(|| format!("{name}: invalid signature")))
+        .transpose()?;
+
     Ok(Package {
         name: name.to_string(),
         version: fields[1].to_string(),
@@ -169,13 +1268,127 @@ fn parse_pkg_header(fields: &[&str]) -> Result<Package> {
         size,
         buildtime,
         installtime,
+        installtid,
         sourcerpm,
         digest_algo,
-        changelog_times: Vec::new(),
+        signing_key,
+        changelog: Vec::new(),
+        requires: Vec::new(),
+        provides: Vec::new(),
+        recommends: Vec::new(),
+        suggests: Vec::new(),
+        obsoletes: Vec::new(),
+        conflicts: Vec::new(),
+        pre: None,
+        post: None,
+        preun: None,
+        postun: None,
+        triggers: Vec::new(),
         files: Files::new(),
+        unparsed_files: None,
     })
 }
 
+/// Get the key ID from a signature formatted by rpm's `pgpsig` formatter,
+/// e.g. `RSA/SHA256, Tue 12 Aug 2025 05:03:09 PM UTC, Key ID
+/// dbfcf71c6d9f90a6`.
+fn parse_key_id(sig: &str) -> Result<String> {
+    let Some((_, key_id)) = sig.rsplit_once("Key ID ") else {
+        bail!("no key ID in '{sig}'");
+    };
+    if key_id.is_empty() || !key_id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!("invalid key ID '{key_id}'");
+    }
+    Ok(key_id.to_ascii_lowercase())
+}
+
+/// Parse a dependency record (with the prefix stripped) whose fields are
+/// separated by `sep`.
+fn parse_dependency(line: &str, sep: char) -> Result<Dependency> {
+    let fields: Vec<&str> = line.split(sep).collect();
+    let [name, flags, version] = fields[..] else {
+        bail!("expected 3 fields, got {}", fields.len());
+    };
+    let flags = parse_uint::<u32>(flags).with_context(|| format!("invalid flags '{flags}'"))?;
+    Ok(Dependency {
+        name: name.to_string(),
+        flags: DependencyFlags::from_raw(flags),
+        version: (!version.is_empty()).then(|| version.to_string()),
+    })
+}
+
+/// Split a CL line into the time, author and text of the changelog entry.
+/// The text comes last and is taken as is, since it may contain any
+/// character.
+fn split_changelog_entry(line: &str, sep: char, version: u32) -> Result<(&str, &str, &str)> {
+    if version < FORMAT_VERSION_CHANGELOG {
+        return Ok((line, "", ""));
+    }
+    let mut fields = line.splitn(3, sep);
+    let (Some(time), Some(author), Some(text)) = (fields.next(), fields.next(), fields.next())
+    else {
+        bail!("expected 3 fields, got {}", line.split(sep).count());
+    };
+    Ok((time, author, text))
+}
+
+/// Parse the interpreter and script of a scriptlet, the rest of the record
+/// being the script since it may contain any character. Returns `None` if
+/// neither is set.
+fn parse_scriptlet(line: &str, sep: char) -> Result<Option<Scriptlet>> {
+    let (interpreter, script) = (line.split_once(sep)).context("expected 2 fields, got 1")?;
+    let (interpreter, script) = (parse_optional(interpreter), parse_optional(script));
+    if interpreter.is_none() && script.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(Scriptlet {
+        interpreter: interpreter.map(String::from),
+        script: script.map(String::from),
+    }))
+}
+
+/// Describe what's implausible about `time`, if anything.
+fn check_timestamp(bounds: &TimestampBounds, now: u64, time: u64) -> Option<&'static str> {
+    if time == 0 && !bounds.allow_zero {
+        Some("unset")
+    } else if time > now.saturating_add(bounds.max_future.as_secs()) {
+        Some("too far in the future")
+    } else {
+        None
+    }
+}
+
+/// Parse an unsigned decimal integer.
+///
+/// This is a faster replacement for `str::parse` on the hot per-file fields:
+/// it only accepts plain ASCII digits (no sign or whitespace), and skips
+/// overflow checks for inputs too short to overflow a `u64`.
+fn parse_uint<T: TryFrom<u64>>(s: &str) -> Option<T> {
+    let bytes = s.as_bytes();
+    if bytes.is_empty() {
+        return None;
+    }
+    let mut v: u64 = 0;
+    if bytes.len() < 20 {
+        for &b in bytes {
+            let d = b.wrapping_sub(b'0');
+            if d > 9 {
+                return None;
+            }
+            v = v * 10 + u64::from(d);
+        }
+    } else {
+        for &b in bytes {
+            let d = b.wrapping_sub(b'0');
+            if d > 9 {
+                return None;
+            }
+            v = v.checked_mul(10)?.checked_add(u64::from(d))?;
+        }
+    }
+    T::try_from(v).ok()
+}
+
 /// Map the RPM `(none)` sentinel to `None`.
 fn parse_optional(s: &str) -> Option<&str> {
     if s == "(none)" { None } else { Some(s) }
@@ -203,32 +1416,182 @@ impl TryFrom<u32> for DigestAlgorithm {
     }
 }
 
-/// Parse a @@FILE@@ line and return the path and file info.
-fn parse_file_line(fields: &[&str]) -> Result<(Utf8PathBuf, FileInfo)> {
-    assert_eq!(fields.len(), FILE_FIELDS); // checked by caller
-    let path = Utf8Path::new(fields[0]);
-    let size = fields[1]
-        .parse::<u64>()
-        .with_context(|| format!("invalid filesize for {path}"))?;
-    let mode = fields[2]
-        .parse::<u16>()
-        .with_context(|| format!("invalid filemode for {path}"))?;
-    let mtime = fields[3]
-        .parse::<u64>()
-        .with_context(|| format!("invalid filemtime for {path}"))?;
+impl TryFrom<u32> for FileState {
+    type Error = ();
+
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            x if x == Self::Normal as u32 => Ok(Self::Normal),
+            x if x == Self::Replaced as u32 => Ok(Self::Replaced),
+            x if x == Self::NotInstalled as u32 => Ok(Self::NotInstalled),
+            x if x == Self::NetShared as u32 => Ok(Self::NetShared),
+            x if x == Self::WrongColor as u32 => Ok(Self::WrongColor),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Canonicalize a file path: collapse repeated slashes and drop any trailing
+/// slash. rpm joins each dirname, which ends with a slash, with its basename,
+/// but other producers don't always agree on whether dirnames have one.
+pub(crate) fn normalize_path(path: &str) -> std::borrow::Cow<'_, str> {
+    let trailing = path.len() > 1 && path.ends_with('/');
+    if !trailing && !path.contains("//") {
+        return path.into();
+    }
+    let mut out = String::with_capacity(path.len());
+    if path.starts_with('/') {
+        out.push('/');
+    }
+    for (i, component) in path.split('/').filter(|c| !c.is_empty()).enumerate() {
+        if i > 0 {
+            out.push('/');
+        }
+        out.push_str(component);
+    }
+    out.into()
+}
+
+/// Parse a file mode. Some rpm versions print FILEMODES (an int16 tag) as a
+/// signed value, so e.g. a regular file with mode 0100644 comes out as -32348
+/// rather than 33188. Both forms are normalized to the unsigned mode.
+fn parse_file_mode(s: &str) -> Option<u16> {
+    match s.strip_prefix('-') {
+        Some(abs) => {
+            let abs: u32 = parse_uint(abs)?;
+            i16::try_from(-i64::from(abs)).ok().map(|v| v as u16)
+        }
+        None => parse_uint(s),
+    }
+}
+
+/// Parse deferred FILE lines.
+pub(crate) fn parse_files(raw: &RawFiles, digest_algo: Option<DigestAlgorithm>) -> Result<Files> {
+    let sep = raw.delimiters.field();
+    raw.data
+        .split_terminator(raw.delimiters.record() as char)
+        .enumerate()
+        .map(|(i, line)| {
+            let (path, info) =
+                parse_file_line(line, sep, raw.version).with_context(|| format!("file #{i}"))?;
+            if raw.validate {
+                validate_file(digest_algo, &path, &info).with_context(|| format!("file #{i}"))?;
+            }
+            Ok((path, info))
+        })
+        .collect()
+}
+
+/// Get the normalized paths of deferred FILE lines, without parsing the rest
+/// of them.
+#[cfg(feature = "arena")]
+pub(crate) fn raw_file_paths(raw: &RawFiles) -> impl Iterator<Item = std::borrow::Cow<'_, str>> {
+    let sep = raw.delimiters.field();
+    (raw.data.split_terminator(raw.delimiters.record() as char))
+        .map(move |line| normalize_path(line.split(sep).next().unwrap_or_default()))
+}
+
+/// Check a parsed file for consistency. See [`ParseOptions::strict_validation`].
+fn validate_file(
+    digest_algo: Option<DigestAlgorithm>,
+    path: &Utf8Path,
+    info: &FileInfo,
+) -> Result<()> {
+    // Packages from before FILEDIGESTALGO existed use MD5.
+    let algo = digest_algo.unwrap_or(DigestAlgorithm::Md5);
+    if let Some(digest) = &info.digest
+        && digest.as_bytes().len() != algo.digest_len()
+    {
+        bail!(
+            "{path}: digest is {} bytes long, expected {} for {algo:?}",
+            digest.as_bytes().len(),
+            algo.digest_len()
+        );
+    }
+    const FILE_TYPES: &[u16] = &[
+        0o010000, // FIFO
+        0o020000, // character device
+        FileInfo::S_IFDIR,
+        0o060000, // block device
+        FileInfo::S_IFREG,
+        FileInfo::S_IFLNK,
+        0o140000, // socket
+    ];
+    if !FILE_TYPES.contains(&info.file_type()) {
+        bail!("{path}: invalid file type in mode {:o}", info.mode);
+    }
+    Ok(())
+}
+
+/// Parse a @@FILE@@ line (with the prefix stripped) whose fields are separated
+/// by `sep` and return the path and file info.
+fn parse_file_line(line: &str, sep: char, version: u32) -> Result<(Utf8PathBuf, FileInfo)> {
+    let fields: Vec<&str> = line.split(sep).collect();
+    let expected = file_fields(version);
+    if fields.len() != expected {
+        let problem = match fields.get(expected..) {
+            Some(extra) => format!("{} unexpected trailing fields", extra.len()),
+            None => format!(
+                "missing {}",
+                FILE_FIELD_NAMES[fields.len()..expected].join(", ")
+            ),
+        };
+        bail!(
+            "expected {expected} fields in FILE line for format version {version}, got {} \
+             for '{}' ({problem})",
+            fields.len(),
+            fields[0]
+        );
+    }
+    let path = normalize_path(fields[0]);
+    let path = Utf8Path::new(&*path);
+    let size =
+        parse_uint::<u64>(fields[1]).with_context(|| format!("invalid filesize for {path}"))?;
+    let mode =
+        parse_file_mode(fields[2]).with_context(|| format!("invalid filemode for {path}"))?;
+    let mtime =
+        parse_uint::<u64>(fields[3]).with_context(|| format!("invalid filemtime for {path}"))?;
     let digest = if fields[4].is_empty() {
         None
     } else {
-        Some(fields[4].to_string())
+        Some(
+            FileDigest::from_hex(fields[4])
+                .with_context(|| format!("invalid digest for {path}"))?,
+        )
     };
-    let flags = fields[5]
-        .parse::<u32>()
-        .with_context(|| format!("invalid fileflags for {path}"))?;
+    let flags =
+        parse_uint::<u32>(fields[5]).with_context(|| format!("invalid fileflags for {path}"))?;
     let linkto = if fields[8].is_empty() {
         None
     } else {
         Some(Utf8PathBuf::from(fields[8]))
     };
+    let state = match fields.get(9) {
+        None | Some(&"") | Some(&"(none)") => None,
+        Some(s) => {
+            let v =
+                parse_uint::<u32>(s).with_context(|| format!("invalid filestate for {path}"))?;
+            Some(
+                FileState::try_from(v)
+                    .map_err(|_| anyhow::anyhow!("unknown file state {v} for {path}"))?,
+            )
+        }
+    };
+    let lang = match fields.get(10) {
+        None | Some(&"") | Some(&"(none)") => None,
+        Some(s) => Some(s.to_string()),
+    };
+    let color = match fields.get(11) {
+        None | Some(&"") | Some(&"(none)") => None,
+        Some(s) => {
+            Some(parse_uint::<u32>(s).with_context(|| format!("invalid filecolor for {path}"))?)
+        }
+    };
+    // Packages built without file capabilities lack FILECAPS altogether.
+    let caps = match fields.get(12) {
+        None | Some(&"") | Some(&"(none)") => None,
+        Some(s) => Some(s.to_string()),
+    };
 
     let info = FileInfo {
         size,
@@ -239,21 +1602,165 @@ fn parse_file_line(fields: &[&str]) -> Result<(Utf8PathBuf, FileInfo)> {
         user: fields[6].to_string(),
         group: fields[7].to_string(),
         linkto,
+        state,
+        lang,
+        color,
+        caps,
     };
 
     Ok((path.to_path_buf(), info))
 }
 
+/// Write `pkg` as [`QUERYFORMAT`] would have rpm output it, preceded by its
+/// @@FMT@@ record, so that it parses back to the same package.
+pub(crate) fn write_package(writer: &mut impl std::io::Write, pkg: &Package) -> Result<()> {
+    let optional = |v: Option<String>| v.unwrap_or_else(|| "(none)".into());
+    let mut fields = vec![
+        pkg.name.clone(),
+        pkg.version.clone(),
+        pkg.release.clone(),
+        optional(pkg.epoch.map(|e| e.to_string())),
+        pkg.arch.clone(),
+        pkg.license.clone(),
+        pkg.size.to_string(),
+        pkg.buildtime.to_string(),
+        pkg.installtime.to_string(),
+        optional(pkg.sourcerpm.clone()),
+        optional(pkg.digest_algo.map(|a| (a as u32).to_string())),
+        optional(pkg.installtid.map(|t| t.to_string())),
+        // Only the key ID of the signature is kept.
+        optional(pkg.signing_key.as_ref().map(|k| format!("Key ID {k}"))),
+        "(none)".into(),
+    ];
+    write!(writer, "@@FMT@@\x1f{FORMAT_VERSION}\x1e")?;
+    write_record(writer, "PKG", &fields)?;
+
+    let parsed;
+    let files = match &pkg.unparsed_files {
+        Some(raw) => {
+            parsed = parse_files(raw, pkg.digest_algo)?;
+            &parsed
+        }
+        None => &pkg.files,
+    };
+    for (path, info) in files {
+        fields.clear();
+        fields.extend([
+            path.to_string(),
+            info.size.to_string(),
+            info.mode.to_string(),
+            info.mtime.to_string(),
+            info.digest.map(|d| d.to_hex()).unwrap_or_default(),
+            info.flags.raw().to_string(),
+            info.user.clone(),
+            info.group.clone(),
+            info.linkto
+                .as_ref()
+                .map(|l| l.to_string())
+                .unwrap_or_default(),
+            info.state
+                .map(|s| (s as u32).to_string())
+                .unwrap_or_default(),
+            info.lang.clone().unwrap_or_default(),
+            info.color.map(|c| c.to_string()).unwrap_or_default(),
+            info.caps.clone().unwrap_or_default(),
+        ]);
+        write_record(writer, "FILE", &fields)?;
+    }
+    for entry in &pkg.changelog {
+        write_record(
+            writer,
+            "CL",
+            &[
+                entry.time.to_string(),
+                entry.author.clone(),
+                entry.text.clone(),
+            ],
+        )?;
+    }
+    for (tag, deps) in [
+        ("REQ", &pkg.requires),
+        ("PROV", &pkg.provides),
+        ("REC", &pkg.recommends),
+        ("SUG", &pkg.suggests),
+        ("OBS", &pkg.obsoletes),
+        ("CONF", &pkg.conflicts),
+    ] {
+        for dep in deps {
+            let version = dep.version.clone().unwrap_or_default();
+            write_record(
+                writer,
+                tag,
+                &[dep.name.clone(), dep.flags.raw().to_string(), version],
+            )?;
+        }
+    }
+    let scriptlet_fields = |scriptlet: Option<&Scriptlet>| {
+        let interpreter = scriptlet.and_then(|s| s.interpreter.clone());
+        let script = scriptlet.and_then(|s| s.script.clone());
+        [optional(interpreter), optional(script)]
+    };
+    for (tag, scriptlet) in [
+        ("PRE", &pkg.pre),
+        ("POST", &pkg.post),
+        ("PREUN", &pkg.preun),
+        ("POSTUN", &pkg.postun),
+    ] {
+        write_record(writer, tag, &scriptlet_fields(scriptlet.as_ref()))?;
+    }
+    for (index, trigger) in pkg.triggers.iter().enumerate() {
+        for dep in &trigger.conditions {
+            let version = dep.version.clone().unwrap_or_default();
+            write_record(
+                writer,
+                "TRIG",
+                &[
+                    index.to_string(),
+                    dep.name.clone(),
+                    dep.flags.raw().to_string(),
+                    version,
+                ],
+            )?;
+        }
+    }
+    for trigger in &pkg.triggers {
+        write_record(
+            writer,
+            "TRIGSCRIPT",
+            &scriptlet_fields(Some(&trigger.scriptlet)),
+        )?;
+    }
+    writer.write_all(b"@@END@@\x1e")?;
+    Ok(())
+}
+
+/// Write a record with the given tag and fields.
+pub(crate) fn write_record(
+    writer: &mut impl std::io::Write,
+    tag: &str,
+    fields: &[String],
+) -> std::io::Result<()> {
+    write!(writer, "@@{tag}@@")?;
+    for field in fields {
+        write!(writer, "\x1f{field}")?;
+    }
+    writer.write_all(b"\x1e")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     fn make_pkg_line(name: &str) -> String {
         format!(
-            "@@PKG@@\t{name}\t1.0\t1.fc42\t(none)\tx86_64\tMIT\t100\t1000\t2000\tfoo.src.rpm\t8\n"
+            "@@PKG@@\t{name}\t1.0\t1.fc42\t(none)\tx86_64\tMIT\t100\t1000\t2000\tfoo.src.rpm\t8\t2000\n"
         )
     }
 
+    fn changelog_times(pkg: &Package) -> Vec<u64> {
+        pkg.changelog.iter().map(|entry| entry.time).collect()
+    }
+
     fn make_file_line(path: &str) -> String {
         format!("@@FILE@@\t{path}\t100\t33188\t1000\taabbccdd\t0\troot\troot\t\n")
     }
@@ -280,7 +1787,18 @@ mod tests {
         assert_eq!(packages["test"].sourcerpm, None);
         assert_eq!(packages["test"].digest_algo, None);
         assert!(packages["test"].files.is_empty());
-        assert!(packages["test"].changelog_times.is_empty());
+        assert!(packages["test"].changelog.is_empty());
+    }
+
+    #[test]
+    fn test_installtid() {
+        let packages = load_from_str_impl(&make_pkg_line("test")).unwrap();
+        assert_eq!(packages["test"].installtid, Some(2000));
+
+        // Output from before INSTALLTID was queried.
+        let input = "@@PKG@@\ttest\t1.0\t1\t(none)\tx86_64\tMIT\t0\t0\t0\t(none)\t(none)\n";
+        let packages = load_from_str_impl(input).unwrap();
+        assert_eq!(packages["test"].installtid, None);
     }
 
     #[test]
@@ -291,7 +1809,7 @@ mod tests {
         input.push_str("@@CL@@\t1000\n");
         let packages = load_from_str_impl(&input).unwrap();
         assert!(packages["test"].files.is_empty());
-        assert_eq!(packages["test"].changelog_times, vec![3000, 2000, 1000]);
+        assert_eq!(changelog_times(&packages["test"]), vec![3000, 2000, 1000]);
     }
 
     #[test]
@@ -301,7 +1819,7 @@ mod tests {
         input.push_str(&make_file_line("/usr/bin/bar"));
         let packages = load_from_str_impl(&input).unwrap();
         assert_eq!(packages["test"].files.len(), 2);
-        assert!(packages["test"].changelog_times.is_empty());
+        assert!(packages["test"].changelog.is_empty());
     }
 
     #[test]
@@ -313,7 +1831,18 @@ mod tests {
         input.push_str("@@CL@@\t1000\n");
         let packages = load_from_str_impl(&input).unwrap();
         assert_eq!(packages["test"].files.len(), 2);
-        assert_eq!(packages["test"].changelog_times, vec![2000, 1000]);
+        assert_eq!(changelog_times(&packages["test"]), vec![2000, 1000]);
+    }
+
+    #[test]
+    fn test_unsorted_files() {
+        let mut input = make_pkg_line("test");
+        input.push_str(&make_file_line("/usr/bin/foo"));
+        input.push_str(&make_file_line("/usr/bin/bar"));
+        input.push_str(&make_file_line("/etc/foo"));
+        let packages = load_from_str_impl(&input).unwrap();
+        let paths: Vec<&str> = packages["test"].files.keys().map(|p| p.as_str()).collect();
+        assert_eq!(paths, ["/etc/foo", "/usr/bin/bar", "/usr/bin/foo"]);
     }
 
     #[test]
@@ -335,8 +1864,18 @@ mod tests {
 
         // Wrong number of fields in FILE line.
         let mut input = make_pkg_line("test");
+        input.push_str(&make_file_line("/usr/bin/test"));
         input.push_str("@@FILE@@\t/a\t0\n");
-        assert!(load_from_str_impl(&input).is_err());
+        let err = load_from_str_impl(&input).unwrap_err().to_string();
+        assert!(err.contains("file #1 of 'test-1.0-1.fc42.x86_64'"), "{err}");
+        assert!(
+            err.contains("got 2 for '/a' (missing mode, mtime, "),
+            "{err}"
+        );
+        let (packages, errors) =
+            load_from_reader_lenient_impl(input.as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(packages["test"].files.len(), 1);
+        assert_eq!(errors.len(), 1);
 
         // FILE line before any PKG line.
         assert!(load_from_str_impl("@@FILE@@\t/a\t0\t33188\t0\t\t0\troot\troot\t\n").is_err());
@@ -345,6 +1884,893 @@ mod tests {
         assert!(load_from_str_impl("garbage\n").is_err());
     }
 
+    #[test]
+    fn test_filter_skips_file_lines() {
+        let mut input = make_pkg_line("alpha");
+        input.push_str(&make_file_line("/usr/bin/alpha"));
+        input.push_str(&make_pkg_line("beta"));
+        // Malformed FILE line; never parsed since beta is filtered out.
+        input.push_str("@@FILE@@\t/usr/bin/beta\tnotanumber\t33188\t0\t\t0\troot\troot\t\n");
+        input.push_str("@@CL@@\tnotanumber\n");
+        let opts = ParseOptions::new().filter(|pkg| pkg.name != "beta");
+        let packages = load_from_reader_impl(input.as_bytes(), &opts).unwrap();
+        assert_eq!(packages.len(), 1);
+        assert_eq!(packages["alpha"].files.len(), 1);
+        assert!(load_from_str_impl(&input).is_err());
+    }
+
+    #[test]
+    fn test_lazy_files() {
+        let mut input = make_pkg_line("test");
+        input.push_str(&make_file_line("/usr/bin/foo"));
+        input.push_str(&make_file_line("/usr/bin/bar"));
+        input.push_str("@@CL@@\t1000\n");
+        let opts = ParseOptions::new().lazy_files(true);
+        let mut packages = load_from_reader_impl(input.as_bytes(), &opts).unwrap();
+        let pkg = packages.get_mut("test").unwrap();
+        assert!(pkg.files.is_empty());
+        assert_eq!(changelog_times(pkg), vec![1000]);
+        assert_eq!(pkg.parse_files().unwrap().len(), 2);
+
+        // Malformed file lines are only reported once parsed.
+        let mut input = make_pkg_line("test");
+        input.push_str("@@FILE@@\t/a\t0\n");
+        let mut packages = load_from_reader_impl(input.as_bytes(), &opts).unwrap();
+        assert!(packages.get_mut("test").unwrap().parse_files().is_err());
+    }
+
+    #[test]
+    fn test_file_digest() {
+        let digest = FileDigest::from_hex("AaBbCcDd").unwrap();
+        assert_eq!(digest.as_bytes(), &[0xaa, 0xbb, 0xcc, 0xdd]);
+        assert_eq!(digest.to_hex(), "aabbccdd");
+        assert_eq!(
+            digest,
+            FileDigest::from_bytes(&[0xaa, 0xbb, 0xcc, 0xdd]).unwrap()
+        );
+        assert!(FileDigest::from_hex(&"ab".repeat(64)).is_ok());
+
+        assert!(FileDigest::from_hex("abc").is_err());
+        assert!(FileDigest::from_hex("zz").is_err());
+        assert!(FileDigest::from_hex(&"ab".repeat(65)).is_err());
+        assert!(FileDigest::from_bytes(&[]).is_err());
+
+        let mut input = make_pkg_line("test");
+        input.push_str("@@FILE@@\t/a\t0\t33188\t0\tnothex\t0\troot\troot\t\n");
+        assert!(load_from_str_impl(&input).is_err());
+    }
+
+    #[test]
+    fn test_parse_uint() {
+        assert_eq!(parse_uint::<u64>("0"), Some(0));
+        assert_eq!(parse_uint::<u64>("0042"), Some(42));
+        assert_eq!(parse_uint::<u64>("18446744073709551615"), Some(u64::MAX));
+        assert_eq!(parse_uint::<u64>("18446744073709551616"), None);
+        assert_eq!(parse_uint::<u64>("99999999999999999999"), None);
+        assert_eq!(parse_uint::<u16>("65535"), Some(u16::MAX));
+        assert_eq!(parse_uint::<u16>("65536"), None);
+        assert_eq!(parse_uint::<u32>(""), None);
+        assert_eq!(parse_uint::<u32>("+1"), None);
+        assert_eq!(parse_uint::<u32>("-1"), None);
+        assert_eq!(parse_uint::<u32>(" 1"), None);
+        assert_eq!(parse_uint::<u32>("1a"), None);
+    }
+
+    #[test]
+    fn test_separators() {
+        let input = concat!(
+            "@@FMT@@\x1f2\x1e",
+            "@@PKG@@\x1ffoo\x1f1.0\x1f1.fc43\x1f(none)\x1fx86_64\x1fMIT and\n(GPL-2.0\tor BSD)",
+            "\x1f1234\x1f1700000000\x1f1700001000\x1ffoo.src.rpm\x1f8\x1f2000\x1e",
+            "@@FILE@@\x1f/usr/share/foo/a\tb\nc\x1f100\x1f33188\x1f1700000000\x1f\x1f0\x1froot\x1froot\x1f\x1e",
+            "@@FILE@@\x1f/usr/share/foo/d\x1f100\x1f33188\x1f1700000000\x1f\x1f0\x1froot\x1froot\x1f\x1e",
+            "@@CL@@\x1f1700000000\x1e",
+            "@@FMT@@\x1f2\x1e",
+            "@@PKG@@\x1fbar\x1f1.0\x1f1.fc43\x1f(none)\x1fx86_64\x1fMIT",
+            "\x1f1234\x1f1700000000\x1f1700001000\x1fbar.src.rpm\x1f8\x1f2000\x1e",
+        );
+        let packages = load_from_str_impl(input).unwrap();
+        assert_eq!(packages.len(), 2);
+        let foo = &packages["foo"];
+        assert_eq!(foo.license, "MIT and\n(GPL-2.0\tor BSD)");
+        assert_eq!(foo.files.len(), 2);
+        assert!(
+            foo.files
+                .contains_key(Utf8Path::new("/usr/share/foo/a\tb\nc"))
+        );
+        assert_eq!(changelog_times(foo), vec![1700000000]);
+
+        let options = ParseOptions::new().lazy_files(true);
+        let mut packages = load_from_reader_impl(input.as_bytes(), &options).unwrap();
+        let files = packages.get_mut("foo").unwrap().parse_files().unwrap();
+        assert!(files.contains_key(Utf8Path::new("/usr/share/foo/a\tb\nc")));
+    }
+
+    #[test]
+    fn test_parse_queryformat() {
+        let fixture = include_str!("../tests/fixtures/multiline.qf");
+        let options = ParseOptions::default();
+        let packages = parse_queryformat_impl(fixture.as_bytes(), &options).unwrap();
+        let expected = load_from_str_impl(fixture).unwrap();
+        assert_eq!(packages.len(), expected.len());
+        for (name, pkg) in &expected {
+            assert_eq!(packages[name].nevra(), pkg.nevra());
+            assert_eq!(packages[name].license, pkg.license);
+        }
+        assert!(
+            parse_queryformat_impl(&b""[..], &options)
+                .unwrap()
+                .is_empty()
+        );
+
+        // The tab-separated format is rejected rather than detected.
+        let legacy = include_str!("../tests/fixtures/fedora.qf");
+        assert!(!load_from_str_impl(legacy).unwrap().is_empty());
+        let err = parse_queryformat_impl(legacy.as_bytes(), &options).unwrap_err();
+        assert!(matches!(err, Error::Parse { line: 1, .. }), "{err}");
+        assert!(parse_queryformat_impl(&b"@@PKG"[..], &options).is_err());
+    }
+
+    #[test]
+    fn test_multiline_fixture() {
+        let input = include_str!("../tests/fixtures/multiline.qf");
+        let packages = load_from_str_impl(input).unwrap();
+        assert_eq!(packages.len(), 3);
+        assert_eq!(
+            packages["oldlicense"].license,
+            "GPL-2.0-or-later and\nLGPL-2.1-or-later and\nBSD-3-Clause"
+        );
+        assert_eq!(packages["oldlicense"].files.len(), 2);
+        assert_eq!(packages["oldlicense"].changelog.len(), 2);
+        // Record tags inside values aren't mistaken for records.
+        let exotic = &packages["exotic"];
+        assert!(
+            exotic
+                .license
+                .ends_with("\n@@FILE@@\t/etc/shadow\t0\t33188")
+        );
+        assert_eq!(exotic.files.len(), 1);
+        assert!(exotic.file(Utf8Path::new("/etc/shadow")).is_none());
+        assert_eq!(packages["plain"].license, "MIT");
+    }
+
+    #[test]
+    fn test_truncated() {
+        let sep = |s: String| s.replace('\t', "\x1f").replace('\n', "\x1e");
+        let file = |path: &str| sep(make_file_line(path)).replace('\x1e', "\x1f0\x1e");
+        let mut input = String::from("@@FMT@@\x1f4\x1e");
+        input.push_str(&sep(make_pkg_line("alpha")));
+        input.push_str(&file("/usr/bin/alpha"));
+        input.push_str("@@END@@\x1e@@FMT@@\x1f4\x1e");
+        input.push_str(&sep(make_pkg_line("beta")));
+        input.push_str(&file("/usr/bin/beta1"));
+        let partial = file("/usr/bin/beta2");
+        let partial = &partial[..partial.len() / 2];
+
+        let complete = format!("{input}@@END@@\x1e");
+        assert_eq!(load_from_str_impl(&complete).unwrap().len(), 2);
+
+        for truncated in [input.clone(), format!("{input}{partial}")] {
+            match load_from_str_impl(&truncated).unwrap_err() {
+                Error::Truncated { last_complete } => {
+                    assert_eq!(last_complete.as_deref(), Some("alpha"))
+                }
+                e => panic!("unexpected error: {e}"),
+            }
+            let (packages, errors) =
+                load_from_reader_lenient_impl(truncated.as_bytes(), &ParseOptions::default())
+                    .unwrap();
+            assert_eq!(packages.len(), 1);
+            assert!(packages.contains_key("alpha"));
+            assert_eq!(errors.len(), 1);
+            assert!(matches!(errors[0].error, Error::Truncated { .. }));
+        }
+
+        // Truncated before the first package completed.
+        let input = format!("@@FMT@@\x1f4\x1e{}", sep(make_pkg_line("alpha")));
+        match load_from_str_impl(&input).unwrap_err() {
+            Error::Truncated { last_complete } => assert_eq!(last_complete, None),
+            e => panic!("unexpected error: {e}"),
+        }
+
+        // A missing END between packages is malformed rather than truncated.
+        let input = complete.replacen("@@END@@\x1e", "", 1);
+        let err = load_from_str_impl(&input).unwrap_err();
+        assert!(err.to_string().contains("missing END line for 'alpha'"));
+        let (packages, errors) =
+            load_from_reader_lenient_impl(input.as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_load_iter() {
+        let fixture = include_str!("../tests/fixtures/fedora.qf");
+        let packages = load_from_str_impl(fixture).unwrap();
+        let options = ParseOptions::default();
+        let mut names = Vec::new();
+        for pkg in load_iter_from_reader_impl(fixture.as_bytes(), &options) {
+            let pkg = pkg.unwrap();
+            assert_eq!(pkg.files.len(), packages[&pkg.name].files.len());
+            names.push(pkg.name);
+        }
+        let mut expected: Vec<String> = packages.into_keys().collect();
+        expected.sort();
+        assert_eq!(names, expected);
+
+        // Packages completed before an error are still yielded.
+        let sep = |s: String| s.replace('\t', "\x1f").replace('\n', "\x1e");
+        let mut input = String::from("@@FMT@@\x1f4\x1e");
+        input.push_str(&sep(make_pkg_line("alpha")));
+        input.push_str("@@END@@\x1e");
+        input.push_str(&sep(make_pkg_line("beta")));
+        let results: Vec<_> = load_iter_from_reader_impl(input.as_bytes(), &options).collect();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap().name, "alpha");
+        assert!(matches!(results[1], Err(Error::Truncated { .. })));
+        let input = format!("{input}@@END@@\x1e@@PKG@@\x1e");
+        let results: Vec<_> = load_iter_from_reader_impl(input.as_bytes(), &options).collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[2].is_err());
+    }
+
+    #[test]
+    fn test_strict_validation() {
+        let sha256 = "ab".repeat(32);
+        let mut input = make_pkg_line("foo");
+        let file = |path: &str, mode: &str, digest: &str| {
+            format!("@@FILE@@\t{path}\t100\t{mode}\t1000\t{digest}\t0\troot\troot\t\n")
+        };
+        input.push_str(&file("/usr/bin/foo", "33188", &sha256));
+        input.push_str(&file("/usr/lib/foo", "16877", ""));
+        let options = ParseOptions::new().strict_validation(true);
+        assert!(load_from_reader_impl(input.as_bytes(), &options).is_ok());
+
+        let mut bad_digest = input.clone();
+        bad_digest.push_str(&file("/usr/bin/bar", "33188", "aabbccdd"));
+        assert!(load_from_str_impl(&bad_digest).is_ok());
+        let err = load_from_reader_impl(bad_digest.as_bytes(), &options).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("file #2 of 'foo-1.0-1.fc42.x86_64'"), "{msg}");
+        assert!(msg.contains("/usr/bin/bar: digest is 4 bytes long, expected 32 for Sha256"));
+
+        let mut bad_mode = input.clone();
+        bad_mode.push_str(&file("/usr/bin/bar", "420", ""));
+        assert!(load_from_str_impl(&bad_mode).is_ok());
+        let err = load_from_reader_impl(bad_mode.as_bytes(), &options).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("/usr/bin/bar: invalid file type in mode 644")
+        );
+
+        let options = options.lazy_files(true);
+        let mut packages = load_from_reader_impl(bad_mode.as_bytes(), &options).unwrap();
+        let err = packages.get_mut("foo").unwrap().parse_files().unwrap_err();
+        assert!(format!("{err:#}").contains("file #2"));
+    }
+
+    #[test]
+    fn test_check_timestamps() {
+        let future = "4102444800"; // 2100-01-01
+        let mut input = make_pkg_line("foo").replace("\t1000\t2000\t", "\t0\t2000\t");
+        input.push_str(&make_file_line("/usr/bin/foo"));
+        input.push_str(
+            &make_file_line("/usr/bin/bar").replace("\t1000\t", &format!("\t{future}\t")),
+        );
+        input.push_str(
+            &make_file_line("/usr/bin/baz").replace("\t1000\t", &format!("\t{future}\t")),
+        );
+        assert!(load_from_str_impl(&input).is_ok());
+
+        // Packages with implausible timestamps are kept.
+        let options = ParseOptions::new().check_timestamps(TimestampBounds::default());
+        let packages = load_from_reader_impl(input.as_bytes(), &options).unwrap();
+        assert_eq!(packages["foo"].buildtime, 0);
+
+        let (packages, errors) = load_from_reader_lenient_impl(input.as_bytes(), &options).unwrap();
+        assert_eq!(packages["foo"].buildtime, 0);
+        assert_eq!(packages["foo"].files.len(), 3);
+        // Only the first implausible mtime is reported.
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|e| e.warning));
+        assert_eq!(errors[0].line, 1);
+        assert!(
+            errors[0]
+                .to_string()
+                .contains("implausible buildtime 0: unset")
+        );
+        assert_eq!(errors[1].line, 3);
+        assert_eq!(errors[1].package.as_deref(), Some("foo"));
+        assert!(
+            errors[1].to_string().contains(
+                "file '/usr/bin/bar': implausible mtime 4102444800: too far in the future"
+            )
+        );
+
+        let options = ParseOptions::new().check_timestamps(TimestampBounds {
+            allow_zero: true,
+            max_future: std::time::Duration::from_secs(200 * 365 * 24 * 60 * 60),
+        });
+        assert!(load_from_reader_impl(input.as_bytes(), &options).is_ok());
+    }
+
+    #[test]
+    fn test_large_sizes() {
+        // Sizes beyond 32 bits, as queried with LONGSIZE/LONGFILESIZES.
+        let mut input = make_pkg_line("foo").replace("\tMIT\t100\t", "\tMIT\t6442450944\t");
+        input
+            .push_str("@@FILE@@\t/usr/share/foo.img\t5368709120\t33188\t1000\t\t0\troot\troot\t\n");
+        let packages = load_from_str_impl(&input).unwrap();
+        let foo = &packages["foo"];
+        assert_eq!(foo.size, 6 << 30);
+        assert_eq!(foo.files[Utf8Path::new("/usr/share/foo.img")].size, 5 << 30);
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/usr/bin/bash"), "/usr/bin/bash");
+        assert_eq!(normalize_path("/usr/bin/"), "/usr/bin");
+        assert_eq!(normalize_path("/usr/bin//bash"), "/usr/bin/bash");
+        assert_eq!(normalize_path("//usr///bin//"), "/usr/bin");
+        assert_eq!(normalize_path("/"), "/");
+        assert_eq!(normalize_path("//"), "/");
+        assert_eq!(normalize_path("relative/dir/"), "relative/dir");
+
+        let mut input = make_pkg_line("foo");
+        input.push_str(&make_file_line("/usr/bin/"));
+        input.push_str(&make_file_line("/usr/bin//foo"));
+        let packages = load_from_str_impl(&input).unwrap();
+        let foo = &packages["foo"];
+        let paths: Vec<&str> = foo.files.keys().map(|p| p.as_str()).collect();
+        assert_eq!(paths, ["/usr/bin", "/usr/bin/foo"]);
+        assert!(foo.file(Utf8Path::new("/usr/bin/")).is_some());
+        assert!(foo.file(Utf8Path::new("/usr//bin/foo")).is_some());
+        assert!(foo.file(Utf8Path::new("/usr/bin/bar")).is_none());
+    }
+
+    #[test]
+    fn test_parse_file_mode() {
+        assert_eq!(parse_file_mode("33188"), Some(0o100644));
+        assert_eq!(parse_file_mode("-32348"), Some(0o100644));
+        assert_eq!(parse_file_mode("16877"), Some(0o40755));
+        assert_eq!(parse_file_mode("-32768"), Some(0o100000));
+        assert_eq!(parse_file_mode("-32769"), None);
+        assert_eq!(parse_file_mode("65536"), None);
+        assert_eq!(parse_file_mode("-"), None);
+        assert_eq!(parse_file_mode(""), None);
+
+        let mut input = make_pkg_line("foo");
+        input.push_str("@@FILE@@\t/usr/bin/foo\t100\t-32275\t1000\t\t0\troot\troot\t\n");
+        let packages = load_from_str_impl(&input).unwrap();
+        assert_eq!(
+            packages["foo"].files[Utf8Path::new("/usr/bin/foo")].mode,
+            0o100755
+        );
+    }
+
+    #[test]
+    fn test_file_states() {
+        let pkg = make_pkg_line("foo");
+        let file = |path: &str, state: &str| {
+            format!("@@FILE@@\t{path}\t100\t33188\t1000\t\t0\troot\troot\t\t{state}\n")
+        };
+        let input = format!(
+            "@@FMT@@\t3\n{pkg}{}{}{}",
+            file("/usr/bin/foo", "0"),
+            file("/usr/share/doc/foo/README", "2"),
+            file("/usr/lib/foo.so", "4")
+        );
+        let packages = load_from_str_impl(&input).unwrap();
+        let files = &packages["foo"].files;
+        let state = |path: &str| files[Utf8Path::new(path)].state;
+        assert_eq!(state("/usr/bin/foo"), Some(FileState::Normal));
+        assert_eq!(
+            state("/usr/share/doc/foo/README"),
+            Some(FileState::NotInstalled)
+        );
+        assert_eq!(state("/usr/lib/foo.so"), Some(FileState::WrongColor));
+
+        let input = format!("@@FMT@@\t3\n{pkg}{}", file("/usr/bin/foo", "9"));
+        assert!(load_from_str_impl(&input).is_err());
+        // Before version 3, FILE lines have no state.
+        let input = format!("@@FMT@@\t2\n{pkg}{}", file("/usr/bin/foo", "0"));
+        assert!(load_from_str_impl(&input).is_err());
+        let input = format!("@@FMT@@\t2\n{pkg}{}", make_file_line("/usr/bin/foo"));
+        let packages = load_from_str_impl(&input).unwrap();
+        assert_eq!(
+            packages["foo"].files[Utf8Path::new("/usr/bin/foo")].state,
+            None
+        );
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let mut input = String::from("@@FMT@@\t6\n");
+        input.push_str(&make_pkg_line("foo"));
+        input.push_str("@@REQ@@\tbar\t12\t1.0-1\n");
+        input.push_str("@@REQ@@\trpmlib(PayloadIsZstd)\t16777226\t5.4.18-1\n");
+        input.push_str("@@PROV@@\tfoo\t8\t1.0-1.fc42\n");
+        input.push_str("@@PROV@@\tlibfoo.so.1()(64bit)\t0\t\n");
+        input.push_str("@@REC@@\tfoo-docs\t0\t\n");
+        input.push_str("@@SUG@@\t(foo-gui if desktop)\t0\t\n");
+        input.push_str("@@OBS@@\tfoo-compat\t2\t1.0-1\n");
+        input.push_str("@@CONF@@\tbar\t4\t2.0\n");
+        input.push_str("@@END@@\n");
+        let packages = load_from_str_impl(&input).unwrap();
+        let foo = &packages["foo"];
+        let deps = |deps: &[Dependency]| deps.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            deps(&foo.requires),
+            ["bar >= 1.0-1", "rpmlib(PayloadIsZstd) <= 5.4.18-1"]
+        );
+        assert!(foo.requires[1].flags.is_rpmlib());
+        assert_eq!(
+            deps(&foo.provides),
+            ["foo = 1.0-1.fc42", "libfoo.so.1()(64bit)"]
+        );
+        assert_eq!(foo.provides[1].version, None);
+        assert_eq!(deps(&foo.recommends), ["foo-docs"]);
+        assert_eq!(deps(&foo.suggests), ["(foo-gui if desktop)"]);
+        assert_eq!(deps(&foo.obsoletes), ["foo-compat < 1.0-1"]);
+        assert_eq!(deps(&foo.conflicts), ["bar > 2.0"]);
+
+        let input = input.replace("\t12\t", "\tgreater\t");
+        assert!(load_from_str_impl(&input).is_err());
+        let input = "@@FMT@@\t6\n@@REQ@@\tbar\t0\t\n";
+        assert!(load_from_str_impl(input).is_err());
+    }
+
+    #[test]
+    fn test_changelog_entries() {
+        let pkg = make_pkg_line("foo")
+            .replace('\n', "\t(none)\t(none)\n")
+            .replace('\t', "\x1f")
+            .replace('\n', "\x1e");
+        let input = format!(
+            "@@FMT@@\x1f13\x1e{pkg}{}{}@@END@@\x1e",
+            "@@CL@@\x1f1700000000\x1fJane Doe <jane@example.com> - 1.0-2\x1f- Fix\tit\n- Again\x1f\x1e",
+            "@@CL@@\x1f1600000000\x1fJane Doe <jane@example.com> - 1.0-1\x1f- Initial\x1e",
+        );
+        let packages = load_from_str_impl(&input).unwrap();
+        let changelog = &packages["foo"].changelog;
+        assert_eq!(changelog_times(&packages["foo"]), [1700000000, 1600000000]);
+        // The text is taken as is, even with field separators.
+        assert_eq!(changelog[0].text, "- Fix\tit\n- Again\x1f");
+        assert_eq!(changelog[1].text, "- Initial");
+        let header = changelog[1].header();
+        assert_eq!(header.author, "Jane Doe");
+        assert_eq!(header.evr.as_deref(), Some("1.0-1"));
+
+        let mut out = Vec::new();
+        write_package(&mut out, &packages["foo"]).unwrap();
+        let written = load_from_str_impl(std::str::from_utf8(&out).unwrap()).unwrap();
+        assert_eq!(&written["foo"].changelog, changelog);
+
+        let input = input.replace("\x1f- Initial", "");
+        assert!(load_from_str_impl(&input).is_err());
+    }
+
+    #[test]
+    fn test_scriptlets() {
+        let pkg = make_pkg_line("foo").replace('\n', "\t(none)\t(none)\n");
+        let mut input = format!("@@FMT@@\t12\n{pkg}");
+        // Scripts may contain the field separator.
+        input.push_str("@@PRE@@\t/bin/sh\tgetent group foo >/dev/null ||\tgroupadd -r foo\n");
+        input.push_str("@@POST@@\t/sbin/ldconfig\t(none)\n");
+        input.push_str("@@PREUN@@\t(none)\t(none)\n");
+        input.push_str("@@POSTUN@@\t<lua>\tprint(1)\n");
+        input.push_str("@@TRIG@@\t1\tbar\t65536\t\n");
+        input.push_str("@@TRIG@@\t0\tbaz\t262156\t2.0\n");
+        input.push_str("@@TRIG@@\t1\tqux\t65536\t\n");
+        input.push_str("@@TRIGSCRIPT@@\t/bin/sh\techo postun\n");
+        input.push_str("@@TRIGSCRIPT@@\t/bin/sh\techo in\n");
+        input.push_str("@@END@@\n");
+        let packages = load_from_str_impl(&input).unwrap();
+        let foo = &packages["foo"];
+        let pre = foo.pre.as_ref().unwrap();
+        assert_eq!(pre.interpreter.as_deref(), Some("/bin/sh"));
+        assert_eq!(
+            pre.script.as_deref(),
+            Some("getent group foo >/dev/null ||\tgroupadd -r foo")
+        );
+        let post = foo.post.as_ref().unwrap();
+        assert_eq!(
+            (post.interpreter.as_deref(), post.script.as_deref()),
+            (Some("/sbin/ldconfig"), None)
+        );
+        assert_eq!(foo.preun, None);
+        assert_eq!(
+            foo.postun.as_ref().unwrap().interpreter.as_deref(),
+            Some("<lua>")
+        );
+
+        let triggers: Vec<_> = (foo.triggers.iter())
+            .map(|t| {
+                let conditions: Vec<_> = t.conditions.iter().map(|d| d.to_string()).collect();
+                (t.kind, conditions, t.scriptlet.script.as_deref().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            triggers,
+            [
+                (
+                    TriggerKind::PostUn,
+                    vec!["baz >= 2.0".to_string()],
+                    "echo postun"
+                ),
+                (TriggerKind::In, vec!["bar".into(), "qux".into()], "echo in"),
+            ]
+        );
+
+        // Scriptlets and triggers survive writing the package back out.
+        let mut out = Vec::new();
+        write_package(&mut out, foo).unwrap();
+        let written = load_from_str_impl(std::str::from_utf8(&out).unwrap()).unwrap();
+        let again = &written["foo"];
+        assert_eq!(
+            (&again.pre, &again.post, &again.preun, &again.postun),
+            (&foo.pre, &foo.post, &foo.preun, &foo.postun)
+        );
+        assert_eq!(again.triggers, foo.triggers);
+
+        // Triggers need conditions to tell when they run.
+        let input = input.replace("@@TRIG@@\t0\tbaz\t262156\t2.0\n", "");
+        assert!(load_from_str_impl(&input).is_err());
+    }
+
+    #[test]
+    fn test_signing_key() {
+        let pkg = |rsa: &str, dsa: &str| {
+            let line = make_pkg_line("foo").replace('\n', &format!("\t{rsa}\t{dsa}\n"));
+            load_from_str_impl(&format!("@@FMT@@\t8\n{line}@@END@@\n"))
+                .map(|packages| packages["foo"].signing_key.clone())
+        };
+        let sig = "RSA/SHA256, Tue 12 Aug 2025 05:03:09 PM UTC, Key ID DBFCF71C6D9F90A6";
+        assert_eq!(
+            pkg(sig, "(none)").unwrap().as_deref(),
+            Some("dbfcf71c6d9f90a6")
+        );
+        let sig = sig.replace("RSA", "EdDSA");
+        assert_eq!(
+            pkg("(none)", &sig).unwrap().as_deref(),
+            Some("dbfcf71c6d9f90a6")
+        );
+        assert_eq!(pkg("(none)", "(none)").unwrap(), None);
+        assert!(pkg("(not an OpenPGP signature)", "(none)").is_err());
+
+        // Older versions have no signature fields.
+        let input = format!("@@FMT@@\t8\n{}@@END@@\n", make_pkg_line("foo"));
+        assert!(load_from_str_impl(&input).is_err());
+        let input = format!("@@FMT@@\t7\n{}@@END@@\n", make_pkg_line("foo"));
+        assert_eq!(load_from_str_impl(&input).unwrap()["foo"].signing_key, None);
+    }
+
+    #[test]
+    fn test_file_langs() {
+        let pkg = make_pkg_line("foo");
+        let file = |path: &str, lang: &str| {
+            format!("@@FILE@@\t{path}\t100\t33188\t1000\t\t0\troot\troot\t\t0\t{lang}\n")
+        };
+        let input = format!(
+            "@@FMT@@\t5\n{pkg}{}{}@@END@@\n",
+            file("/usr/bin/foo", ""),
+            file("/usr/share/locale/de/foo.mo", "de|de_AT")
+        );
+        let packages = load_from_str_impl(&input).unwrap();
+        let files = &packages["foo"].files;
+        assert_eq!(files[Utf8Path::new("/usr/bin/foo")].lang, None);
+        assert_eq!(
+            files[Utf8Path::new("/usr/share/locale/de/foo.mo")]
+                .lang
+                .as_deref(),
+            Some("de|de_AT")
+        );
+        // Version 7 adds colors.
+        let input = format!(
+            "@@FMT@@\t7\n{pkg}{}@@END@@\n",
+            file("/usr/lib64/libfoo.so", "\t2")
+        );
+        let packages = load_from_str_impl(&input).unwrap();
+        let info = &packages["foo"].files[Utf8Path::new("/usr/lib64/libfoo.so")];
+        assert_eq!((info.lang.as_deref(), info.color), (None, Some(2)));
+        // Version 4 FILE lines have no languages.
+        let input = format!("@@FMT@@\t4\n{pkg}{}@@END@@\n", file("/usr/bin/foo", ""));
+        assert!(load_from_str_impl(&input).is_err());
+        // Version 11 adds capabilities.
+        let pkg = pkg.replace('\n', "\t(none)\t(none)\n");
+        let input = format!(
+            "@@FMT@@\t11\n{pkg}{}{}@@END@@\n",
+            file("/usr/bin/ping", "\t2\tcap_net_raw=p"),
+            file("/usr/bin/foo", "\t2\t(none)")
+        );
+        let packages = load_from_str_impl(&input).unwrap();
+        let files = &packages["foo"].files;
+        assert_eq!(
+            files[Utf8Path::new("/usr/bin/ping")].caps.as_deref(),
+            Some("cap_net_raw=p")
+        );
+        assert_eq!(files[Utf8Path::new("/usr/bin/foo")].caps, None);
+    }
+
+    #[test]
+    fn test_format_version() {
+        let pkg = make_pkg_line("foo")
+            .replace('\t', "\x1f")
+            .replace('\n', "\x1e");
+        let packages = load_from_str_impl(&format!("@@FMT@@\x1f2\x1e{pkg}")).unwrap();
+        assert!(packages.contains_key("foo"));
+
+        // Line-delimited output may state its version too.
+        let input = format!("@@FMT@@\t2\n{}", make_pkg_line("foo"));
+        assert!(load_from_str_impl(&input).unwrap().contains_key("foo"));
+
+        let next = FORMAT_VERSION + 1;
+        let err = load_from_str_impl(&format!("@@FMT@@\x1f{next}\x1e{pkg}")).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(&format!("unsupported format version {next}"))
+        );
+        assert!(QUERYFORMAT.starts_with(&format!("@@FMT@@\x1f{FORMAT_VERSION}\x1e")));
+        let err = load_from_str_impl(&pkg).unwrap_err();
+        assert!(err.to_string().contains("missing @@FMT@@"));
+        let input = format!("@@FMT@@\x1f2\x1e{pkg}@@FMT@@\x1f1\x1e");
+        assert!(load_from_str_impl(&input).is_err());
+
+        // The legacy 11-field PKG line is only accepted in format version 1.
+        let legacy = make_pkg_line("foo").replace("\t2000\n", "\n");
+        assert!(load_from_str_impl(&legacy).is_ok());
+        let err = load_from_str_impl(&format!("@@FMT@@\t2\n{legacy}")).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("expected 12 fields in PKG line for format version 2, got 11")
+        );
+    }
+
+    #[cfg(feature = "exec")]
+    #[test]
+    fn test_queryformat_for_tags() {
+        assert_eq!(queryformat_for_tags(|_| true).unwrap(), QUERYFORMAT);
+
+        let old = ["FILEDIGESTALGO", "INSTALLTID", "FILEDIGESTS", "FILELINKTOS"];
+        let qf = queryformat_for_tags(|tag| !old.contains(&tag)).unwrap();
+        assert!(qf.contains("%{SOURCERPM}\x1f(none)\x1f(none)\x1f%{RSAHEADER:pgpsig}"));
+        assert!(qf.contains("\x1f%{FILEMD5S}\x1f"));
+        assert!(qf.contains("%{FILEGROUPNAME}\x1f\x1f%{FILESTATES}\x1f"));
+        assert!(!qf.contains("INSTALLTID"));
+
+        // rpm < 4.12 lacks weak dependencies.
+        let qf = queryformat_for_tags(|tag| !tag.starts_with("SUGGEST")).unwrap();
+        assert!(qf.contains("%{RECOMMENDNAME}"));
+        assert!(!qf.contains("SUG"));
+        assert!(qf.ends_with("\x1e]@@END@@\x1e"));
+
+        let qf = queryformat_for_tags(|tag| tag != "FILEDIGESTS" && tag != "FILEMD5S").unwrap();
+        assert!(qf.contains("%{FILEMTIMES}\x1f\x1f%{FILEFLAGS}"));
+
+        assert!(queryformat_for_tags(|tag| tag != "FILENAMES").is_err());
+
+        // rpm < 4.6 lacks the 64-bit size tags.
+        let qf = queryformat_for_tags(|tag| !tag.starts_with("LONG")).unwrap();
+        assert!(qf.contains("%{LICENSE}\x1f%{SIZE}\x1f"));
+        assert!(qf.contains("%{FILENAMES}\x1f%{FILESIZES}\x1f"));
+        assert!(!qf.contains("LONG"));
+
+        // What such a reduced queryformat produces parses with the fields absent.
+        let mut input = String::from(
+            "@@PKG@@\tfoo\t1.0\t1.fc43\t(none)\tx86_64\tMIT\t1234\t1700000000\t1700001000\t(none)\t(none)\t(none)\n",
+        );
+        input.push_str("@@FILE@@\t/usr/bin/foo\t100\t33188\t1700000000\t\t0\troot\troot\t\n");
+        let packages = load_from_str_impl(&input).unwrap();
+        let pkg = &packages["foo"];
+        assert_eq!(pkg.sourcerpm, None);
+        assert_eq!(pkg.digest_algo, None);
+        assert_eq!(pkg.installtid, None);
+        let file = &pkg.files[Utf8Path::new("/usr/bin/foo")];
+        assert_eq!(file.digest, None);
+        assert_eq!(file.linkto, None);
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let mut input = make_pkg_line("alpha");
+        input.push_str("@@CL@@\tnotatime\n");
+        match load_from_str_impl(&input).unwrap_err() {
+            Error::Parse { line, message } => {
+                assert_eq!(line, 2);
+                assert!(message.contains("invalid changelog time for 'alpha'"));
+            }
+            e => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn test_epoch_policy() {
+        let mut input = make_pkg_line("alpha");
+        input.push_str(&make_pkg_line("beta").replace("\t(none)\t", "\t0\t"));
+        input.push_str(&make_pkg_line("gamma").replace("\t(none)\t", "\t2\t"));
+
+        let packages = load_from_str_impl(&input).unwrap();
+        assert_eq!(packages["alpha"].epoch, None);
+        assert_eq!(packages["beta"].epoch, Some(0));
+        assert_eq!(packages["gamma"].epoch, Some(2));
+
+        let options = ParseOptions::new().epoch_policy(EpochPolicy::PromoteToZero);
+        let packages = load_from_reader_impl(input.as_bytes(), &options).unwrap();
+        assert_eq!(packages["alpha"].epoch, Some(0));
+        assert_eq!(packages["beta"].epoch, Some(0));
+        assert_eq!(packages["gamma"].epoch, Some(2));
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let mut input = make_pkg_line("kernel");
+        input.push_str(&make_file_line("/boot/vmlinuz-1"));
+        input.push_str(&make_pkg_line("kernel").replace("\t1.0\t", "\t2.0\t"));
+        input.push_str(&make_file_line("/boot/vmlinuz-2"));
+        input.push_str(&make_pkg_line("bash"));
+
+        let packages = load_from_str_impl(&input).unwrap();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages["kernel"].version, "2.0");
+
+        let options = ParseOptions::new().duplicates(DuplicatePolicy::Error);
+        match load_from_reader_impl(input.as_bytes(), &options).unwrap_err() {
+            Error::DuplicatePackage(name) => assert_eq!(name, "kernel"),
+            e => panic!("unexpected error: {e}"),
+        }
+
+        let options = ParseOptions::new().duplicates(DuplicatePolicy::Merge);
+        let packages = load_from_reader_impl(input.as_bytes(), &options).unwrap();
+        assert_eq!(packages.len(), 3);
+        assert!(!packages.contains_key("kernel"));
+        let old = &packages["kernel-1.0-1.fc42.x86_64"];
+        assert!(old.files.contains_key(Utf8Path::new("/boot/vmlinuz-1")));
+        assert_eq!(packages["kernel-2.0-1.fc42.x86_64"].version, "2.0");
+        assert!(packages.contains_key("bash"));
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut input = make_pkg_line("alpha");
+        input.push_str(&make_file_line("/usr/bin/alpha"));
+        input.push_str(&make_file_line(&format!("/usr/share/{}", "x".repeat(200))));
+        input.push_str(&make_pkg_line("beta"));
+        input.push_str(&make_file_line("/usr/bin/beta1"));
+        input.push_str(&make_file_line("/usr/bin/beta2"));
+        input.push_str(&make_file_line("/usr/bin/beta3"));
+        input.push_str(&make_pkg_line("gamma"));
+
+        let options = ParseOptions::new().max_line_length(150);
+        assert!(load_from_reader_impl(input.as_bytes(), &options).is_err());
+        let (packages, errors) = load_from_reader_lenient_impl(input.as_bytes(), &options).unwrap();
+        assert_eq!(packages["alpha"].files.len(), 1);
+        assert_eq!(packages["beta"].files.len(), 3);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 3);
+
+        let options = ParseOptions::new().max_files_per_package(2);
+        assert!(load_from_reader_impl(input.as_bytes(), &options).is_err());
+        let (packages, errors) = load_from_reader_lenient_impl(input.as_bytes(), &options).unwrap();
+        assert_eq!(packages.len(), 2);
+        assert!(!packages.contains_key("beta"));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].package.as_deref(), Some("beta"));
+
+        let options = ParseOptions::new().max_packages(2);
+        assert!(load_from_reader_impl(input.as_bytes(), &options).is_err());
+        assert!(load_from_reader_lenient_impl(input.as_bytes(), &options).is_err());
+        let options = ParseOptions::new().max_packages(3);
+        assert_eq!(
+            load_from_reader_impl(input.as_bytes(), &options)
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_error_limits() {
+        let mut input = make_pkg_line("alpha");
+        for time in [3000, 2000, 1000] {
+            input.push_str(&format!("@@CL@@\t{time}\n"));
+        }
+        input.push_str(&make_pkg_line("beta"));
+        input.push_str("@@CL@@\t3000\n");
+
+        let options = ParseOptions::new().max_changelog_entries(1);
+        let packages = load_from_reader_impl(input.as_bytes(), &options).unwrap();
+        assert_eq!(changelog_times(&packages["alpha"]), [3000]);
+        assert_eq!(changelog_times(&packages["beta"]), [3000]);
+        let (_, errors) = load_from_reader_lenient_impl(input.as_bytes(), &options).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].warning);
+        assert_eq!(errors[0].package.as_deref(), Some("alpha"));
+        assert_eq!(errors[0].line, 3);
+        assert!(
+            errors[0]
+                .to_string()
+                .contains("dropped 2 changelog entries")
+        );
+
+        let mut input = String::new();
+        for name in ["alpha", "beta", "gamma", "delta"] {
+            input.push_str(&make_pkg_line(name));
+            input.push_str("@@FILE@@\t/broken\tnotanumber\n");
+        }
+        let options = ParseOptions::new().max_errors(2);
+        let (packages, errors) = load_from_reader_lenient_impl(input.as_bytes(), &options).unwrap();
+        assert_eq!(packages.len(), 4);
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[1].package.as_deref(), Some("beta"));
+        assert!(!errors[2].warning);
+        assert!(errors[2].to_string().contains("2 more errors"));
+    }
+
+    #[test]
+    fn test_lenient() {
+        let mut input = make_pkg_line("alpha");
+        input.push_str(&make_file_line("/usr/bin/alpha"));
+        input.push_str("@@FILE@@\t/usr/bin/broken\tnotanumber\t33188\t0\t\t0\troot\troot\t\n");
+        input.push_str("@@CL@@\t1000\n");
+        // Malformed header: its FILE/CL lines are skipped, not misattributed.
+        input.push_str("@@PKG@@\tbeta\t1.0\n");
+        input.push_str(&make_file_line("/usr/bin/beta"));
+        input.push_str("garbage\n");
+        input.push_str(&make_pkg_line("gamma"));
+        input.push_str(&make_file_line("/usr/bin/gamma"));
+        assert!(load_from_str_impl(&input).is_err());
+
+        let (packages, errors) =
+            load_from_reader_lenient_impl(input.as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages["alpha"].files.len(), 1);
+        assert_eq!(changelog_times(&packages["alpha"]), vec![1000]);
+        assert!(!packages.contains_key("beta"));
+        assert_eq!(packages["gamma"].files.len(), 1);
+
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].package.as_deref(), Some("alpha"));
+        assert_eq!(errors[0].line, 3);
+        assert_eq!(errors[1].package.as_deref(), Some("beta"));
+        assert_eq!(errors[1].line, 5);
+        assert_eq!(errors[2].line, 7);
+    }
+
+    #[test]
+    fn test_missing_arch() {
+        let mut input = make_pkg_line("foo").replace("\tx86_64\t", "\t(none)\t");
+        input.push_str(&make_file_line("/usr/bin/foo"));
+        input.push_str(&make_pkg_line("bar"));
+        let err = load_from_str_impl(&input).unwrap_err();
+        assert!(err.to_string().contains("foo: missing arch"), "{err}");
+
+        let (packages, errors) =
+            load_from_reader_lenient_impl(input.as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(packages["foo"].arch, "(none)");
+        assert_eq!(packages["foo"].files.len(), 1);
+        assert_eq!(packages["bar"].arch, "x86_64");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].warning);
+        assert_eq!(errors[0].package.as_deref(), Some("foo"));
+
+        let options = ParseOptions::new().missing_arch("unknown");
+        let (packages, _) = load_from_reader_lenient_impl(input.as_bytes(), &options).unwrap();
+        assert_eq!(packages["foo"].nevra(), "foo-1.0-1.fc42.unknown");
+    }
+
+    #[test]
+    fn test_lenient_invalid_utf8() {
+        let mut input = make_pkg_line("alpha").into_bytes();
+        input.extend_from_slice(b"@@FILE@@\t/usr/bin/\xff\t100\t33188\t1000\t\t0\troot\troot\t\n");
+        input.extend_from_slice(make_file_line("/usr/bin/alpha").as_bytes());
+        assert!(load_from_reader_impl(&input[..], &ParseOptions::default()).is_err());
+        let (packages, errors) =
+            load_from_reader_lenient_impl(&input[..], &ParseOptions::default()).unwrap();
+        assert_eq!(packages["alpha"].files.len(), 1);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+    }
+
     #[test]
     fn test_symlink_and_empty_digest() {
         let mut input = make_pkg_line("test");
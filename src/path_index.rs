@@ -0,0 +1,232 @@
+use anyhow::{Context, bail, ensure};
+use camino::Utf8Path;
+use std::collections::HashMap;
+
+use crate::*;
+
+/// Magic bytes at the start of a serialized [`PathIndex`].
+const MAGIC: &[u8; 8] = b"RPMQAIDX";
+/// Version of the serialization format.
+const VERSION: u32 = 1;
+
+/// A compact, read-only index from file paths to their owning packages.
+///
+/// Paths are stored in a finite state transducer, which shares common
+/// prefixes and suffixes, so the index is much smaller than a map of owned
+/// paths and cheap to build once and query many times. It can be serialized
+/// with [`PathIndex::to_bytes`] and loaded back without reparsing any rpm
+/// output.
+///
+/// [`FileIndex`] answers the same queries with [`FileIndex::owners_of`], but
+/// returns the packages themselves and can also look up directories.
+pub struct PathIndex {
+    /// Maps each path to an index into `owner_sets`.
+    paths: fst::Map<Vec<u8>>,
+    /// Distinct sets of owners, as indices into `names`.
+    owner_sets: Vec<Vec<u32>>,
+    /// Package names.
+    names: Vec<String>,
+}
+
+impl std::fmt::Debug for PathIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PathIndex")
+            .field("paths", &self.paths.len())
+            .field("owner_sets", &self.owner_sets.len())
+            .field("names", &self.names.len())
+            .finish()
+    }
+}
+
+impl PathIndex {
+    /// Build an index over all the parsed files of `packages`.
+    pub fn new(packages: &Packages) -> Result<Self> {
+        Ok(Self::build(packages)?)
+    }
+
+    fn build(packages: &Packages) -> anyhow::Result<Self> {
+        let mut names: Vec<&str> = packages.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        let mut entries: Vec<(&str, u32)> = Vec::new();
+        for (i, name) in names.iter().enumerate() {
+            let pkg = &packages[*name];
+            entries.extend(pkg.files.keys().map(|path| (path.as_str(), i as u32)));
+        }
+        // The FST needs its keys in lexicographic byte order.
+        entries.sort_unstable();
+
+        let mut builder = fst::MapBuilder::memory();
+        let mut owner_sets: Vec<Vec<u32>> = Vec::new();
+        let mut set_ids: HashMap<Vec<u32>, u64> = HashMap::new();
+        for group in entries.chunk_by(|a, b| a.0 == b.0) {
+            let owners: Vec<u32> = group.iter().map(|(_, i)| *i).collect();
+            let id = *set_ids.entry(owners).or_insert_with_key(|owners| {
+                owner_sets.push(owners.clone());
+                (owner_sets.len() - 1) as u64
+            });
+            builder
+                .insert(group[0].0, id)
+                .context("building path index")?;
+        }
+        let paths = fst::Map::new(builder.into_inner().context("building path index")?)
+            .context("building path index")?;
+
+        Ok(Self {
+            paths,
+            owner_sets,
+            names: names.into_iter().map(str::to_string).collect(),
+        })
+    }
+
+    /// Number of distinct paths in the index.
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// Whether the index contains no paths.
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Get the names of the packages owning `path`, in sorted order. Returns an
+    /// empty iterator if no package owns it. `path` is normalized as for
+    /// [`Package::file`].
+    pub fn owners_of(&self, path: &Utf8Path) -> impl Iterator<Item = &str> + '_ {
+        let set = self
+            .paths
+            .get(&*parse::normalize_path(path.as_str()))
+            .map_or(&[][..], |id| &self.owner_sets[id as usize][..]);
+        set.iter().map(|&i| self.names[i as usize].as_str())
+    }
+
+    /// Serialize the index.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        put_u32(&mut buf, VERSION);
+        put_u32(&mut buf, self.names.len() as u32);
+        for name in &self.names {
+            put_u32(&mut buf, name.len() as u32);
+            buf.extend_from_slice(name.as_bytes());
+        }
+        put_u32(&mut buf, self.owner_sets.len() as u32);
+        for set in &self.owner_sets {
+            put_u32(&mut buf, set.len() as u32);
+            for &i in set {
+                put_u32(&mut buf, i);
+            }
+        }
+        buf.extend_from_slice(self.paths.as_fst().as_bytes());
+        buf
+    }
+
+    /// Load an index serialized with [`PathIndex::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(Self::decode(bytes)?)
+    }
+
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut r = Reader(bytes);
+        if r.take(MAGIC.len())? != MAGIC {
+            bail!("not a path index");
+        }
+        let version = r.u32()?;
+        ensure!(
+            version == VERSION,
+            "unsupported path index version {version}"
+        );
+        let nnames = r.u32()? as usize;
+        let mut names = Vec::new();
+        for _ in 0..nnames {
+            let len = r.u32()? as usize;
+            let name = std::str::from_utf8(r.take(len)?).context("invalid package name")?;
+            names.push(name.to_string());
+        }
+        let nsets = r.u32()? as usize;
+        let mut owner_sets = Vec::new();
+        for _ in 0..nsets {
+            let len = r.u32()? as usize;
+            let mut set = Vec::new();
+            for _ in 0..len {
+                let i = r.u32()?;
+                ensure!((i as usize) < names.len(), "invalid package index {i}");
+                set.push(i);
+            }
+            owner_sets.push(set);
+        }
+        let paths = fst::Map::new(r.0.to_vec()).context("invalid path index")?;
+        // Traversing a corrupted FST can panic, so check its checksum first.
+        paths.as_fst().verify().context("invalid path index")?;
+        let mut stream = paths.stream();
+        while let Some((_, id)) = fst::Streamer::next(&mut stream) {
+            ensure!((id as usize) < owner_sets.len(), "invalid owner set {id}");
+        }
+        Ok(Self {
+            paths,
+            owner_sets,
+            names,
+        })
+    }
+}
+
+fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        ensure!(self.0.len() >= n, "truncated path index");
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/fedora.qf");
+
+    #[test]
+    fn test_path_index() {
+        let packages = load_from_str(FIXTURE).unwrap();
+        let index = PathIndex::new(&packages).unwrap();
+        let owners: Vec<&str> = index.owners_of(Utf8Path::new("/usr/bin/bash")).collect();
+        assert_eq!(owners, ["bash"]);
+        let owners: Vec<&str> = index.owners_of(Utf8Path::new("//usr/bin/bash/")).collect();
+        assert_eq!(owners, ["bash"]);
+        assert_eq!(index.owners_of(Utf8Path::new("/nonexistent")).count(), 0);
+        let nfiles: usize = packages.values().map(|pkg| pkg.files.len()).sum();
+        assert!(index.len() <= nfiles);
+
+        let loaded = PathIndex::from_bytes(&index.to_bytes()).unwrap();
+        assert_eq!(loaded.len(), index.len());
+        for (name, pkg) in &packages {
+            for path in pkg.files.keys() {
+                assert!(loaded.owners_of(path).any(|owner| owner == name));
+            }
+        }
+
+        assert!(PathIndex::from_bytes(b"garbage").is_err());
+        let bytes = index.to_bytes();
+        assert!(PathIndex::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn test_shared_paths() {
+        let mut packages = load_from_str(FIXTURE).unwrap();
+        let mut other = packages["bash"].clone();
+        other.name = "bash2".into();
+        packages.insert(other.name.clone(), other);
+        let index = PathIndex::new(&packages).unwrap();
+        let owners: Vec<&str> = index.owners_of(Utf8Path::new("/usr/bin/bash")).collect();
+        assert_eq!(owners, ["bash", "bash2"]);
+    }
+}
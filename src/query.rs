@@ -0,0 +1,293 @@
+use anyhow::Context;
+use camino::{Utf8Path, Utf8PathBuf};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::hash::BuildHasher;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::*;
+
+/// How to run rpm to query installed packages, for when the defaults of
+/// [`load_from_rootfs`] don't fit, e.g. to use a pinned rpm binary or an rpmdb
+/// at a non-standard path inside an unpacked OCI layer.
+///
+/// ```no_run
+/// # fn main() -> rpm_qa::Result<()> {
+/// let packages = rpm_qa::QueryBuilder::new()
+///     .rpm("/opt/rpm/bin/rpm")
+///     .root("/tmp/layer")
+///     .dbpath("/usr/share/rpm")
+///     .define("_db_backend", "sqlite")
+///     .load()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct QueryBuilder {
+    rpm: PathBuf,
+    root: Utf8PathBuf,
+    dbpath: Option<Utf8PathBuf>,
+    /// Whether to probe the rootfs for its rpmdb when no dbpath is set.
+    probe_dbpath: bool,
+    defines: Vec<(String, String)>,
+    envs: Vec<(OsString, OsString)>,
+}
+
+impl Default for QueryBuilder {
+    fn default() -> Self {
+        Self {
+            rpm: "rpm".into(),
+            root: "/".into(),
+            dbpath: None,
+            probe_dbpath: true,
+            defines: Vec::new(),
+            envs: Vec::new(),
+        }
+    }
+}
+
+impl QueryBuilder {
+    /// Query the host's packages by running `rpm` from `PATH`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run this rpm executable instead of `rpm` from `PATH`.
+    pub fn rpm(mut self, path: impl Into<PathBuf>) -> Self {
+        self.rpm = path.into();
+        self
+    }
+
+    /// Query the packages installed in this rootfs, as with `rpm --root`.
+    /// Defaults to `/`.
+    pub fn root(mut self, rootfs: impl Into<Utf8PathBuf>) -> Self {
+        self.root = rootfs.into();
+        self
+    }
+
+    /// Use the rpmdb at this path within the rootfs, as with `rpm --dbpath`.
+    /// By default, the rootfs is probed for where its rpmdb is.
+    pub fn dbpath(mut self, dbpath: impl Into<Utf8PathBuf>) -> Self {
+        self.dbpath = Some(dbpath.into());
+        self
+    }
+
+    /// Define an rpm macro, as with `rpm --define "name value"`.
+    pub fn define(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.defines.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set an environment variable for rpm.
+    pub fn env(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    /// Load all installed packages.
+    pub fn load(&self) -> Result<Packages> {
+        self.load_with_options(&ParseOptions::default())
+    }
+
+    /// Load installed packages, using the given parse options.
+    pub fn load_with_options(&self, options: &ParseOptions) -> Result<Packages> {
+        self.query(&[], options)
+    }
+
+    /// Load the installed packages whose names match any of `patterns`, glob
+    /// patterns as for `rpm -qa`, e.g. `kernel*`. Patterns matching nothing
+    /// are ignored.
+    pub fn load_matching(&self, patterns: &[&str]) -> Result<Packages> {
+        self.load_matching_with_options(patterns, &ParseOptions::default())
+    }
+
+    /// Load the installed packages whose names match any of `patterns`, using
+    /// the given parse options. See [`QueryBuilder::load_matching`].
+    pub fn load_matching_with_options(
+        &self,
+        patterns: &[&str],
+        options: &ParseOptions,
+    ) -> Result<Packages> {
+        if patterns.is_empty() {
+            return Ok(Packages::default());
+        }
+        self.query(patterns, options)
+    }
+
+    /// Load the installed package named `name`, if any. Only this package is
+    /// queried, which is much faster than loading all of them.
+    pub fn load_package(&self, name: &str) -> Result<Option<Package>> {
+        let mut packages = self.load_matching(&[name])?;
+        // The name is taken as a pattern by rpm, so it may match others.
+        Ok(packages.remove(name))
+    }
+
+    /// Load the installed packages into an [`FxPackages`] map, using the
+    /// given parse options.
+    #[cfg(feature = "fxhash")]
+    pub fn load_fx(&self, options: &ParseOptions) -> Result<FxPackages> {
+        self.query(&[], options)
+    }
+
+    /// Load installed packages, skipping over malformed entries instead of
+    /// failing. See [`load_from_reader_lenient`].
+    pub fn load_lenient(&self, options: &ParseOptions) -> Result<(Packages, Vec<PackageError>)> {
+        let cmd = self.query_command(options)?;
+        run_rpm_query_with(cmd, |stdout| load_from_reader_lenient(stdout, options))
+    }
+
+    /// Write the raw `rpm -qa` output to `writer`, e.g. to save a snapshot to
+    /// be loaded later with [`load_from_reader`].
+    pub fn dump<W: Write>(&self, mut writer: W) -> Result<()> {
+        let cmd = self.query_command(&ParseOptions::default())?;
+        run_rpm_query_with(cmd, |mut stdout| {
+            std::io::copy(&mut stdout, &mut writer).context("writing rpm output")?;
+            Ok(())
+        })
+    }
+
+    /// Expand an rpm macro expression (e.g. `%{_dbpath}` or `%{?dist}`) with
+    /// `rpm --eval`. See [`eval_macro`].
+    pub fn eval(&self, expr: &str) -> Result<String> {
+        let mut cmd = self.command()?;
+        cmd.arg(format!("--eval={expr}"));
+        let mut output = rpm_output(cmd)?;
+        // rpm terminates the expansion with a newline.
+        if output.ends_with('\n') {
+            output.pop();
+        }
+        Ok(output)
+    }
+
+    /// Query the package in an rpm file with `rpm -qp`. The rootfs and rpmdb
+    /// aren't used.
+    pub(crate) fn query_file(&self, path: &Utf8Path, options: &ParseOptions) -> Result<Packages> {
+        let mut cmd = self.bare_command();
+        options.add_rpm_args(&mut cmd);
+        cmd.args([
+            "-qp",
+            "--queryformat",
+            queryformat_for(self)?,
+            "--",
+            path.as_str(),
+        ]);
+        run_rpm_query(cmd, options)
+    }
+
+    /// Use the rpmdb already found at `dbpath` within the rootfs, if any,
+    /// rather than probing the rootfs path for it, e.g. when the rootfs is
+    /// only accessible through a directory fd.
+    pub(crate) fn found_dbpath(mut self, dbpath: Option<&str>) -> Self {
+        self.dbpath = dbpath.map(Into::into);
+        self.probe_dbpath = false;
+        self
+    }
+
+    /// Run `rpm -qa`, restricted to `patterns` if any.
+    fn query<S: BuildHasher + Default>(
+        &self,
+        patterns: &[&str],
+        options: &ParseOptions,
+    ) -> Result<HashMap<String, Package, S>> {
+        let mut cmd = self.query_command(options)?;
+        if !patterns.is_empty() {
+            cmd.arg("--").args(patterns);
+        }
+        run_rpm_query_with(cmd, |stdout| {
+            parse::load_from_reader_hashed(stdout, options)
+        })
+    }
+
+    /// Create an `rpm -qa` command printing packages in our queryformat,
+    /// with the arguments `options` call for.
+    pub(crate) fn query_command(&self, options: &ParseOptions) -> Result<Command> {
+        let mut cmd = self.command()?;
+        options.add_rpm_args(&mut cmd);
+        cmd.args(["-qa", "--queryformat", queryformat_for(self)?]);
+        Ok(cmd)
+    }
+
+    /// The rpm executable to run.
+    pub(crate) fn program(&self) -> &Path {
+        &self.rpm
+    }
+
+    /// Create an `rpm` command with the macros and environment, but no root.
+    pub(crate) fn bare_command(&self) -> Command {
+        let mut cmd = Command::new(&self.rpm);
+        cmd.envs(self.envs.iter().map(|(k, v)| (k, v)));
+        for (name, value) in &self.defines {
+            cmd.arg(format!("--define={name} {value}"));
+        }
+        cmd
+    }
+
+    /// Create an `rpm` command operating on the rootfs and rpmdb.
+    ///
+    /// Option values are attached with `=` so that they're never taken for
+    /// options themselves, e.g. a rootfs path starting with a dash. Callers
+    /// passing other arguments which may come from users (such as package
+    /// name patterns) must put them after a `--`.
+    pub(crate) fn command(&self) -> Result<Command> {
+        let dbpath = match &self.dbpath {
+            Some(dbpath) => Some(Utf8Path::new("/").join(dbpath)),
+            None if self.probe_dbpath => {
+                find_dbpath(self.root.as_std_path())?.map(|dbpath| format!("/{dbpath}").into())
+            }
+            None => None,
+        };
+        let mut cmd = self.bare_command();
+        cmd.arg(format!("--root={}", self.root));
+        if let Some(dbpath) = dbpath {
+            cmd.arg(format!("--dbpath={dbpath}"));
+        }
+        Ok(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        let query = QueryBuilder::new()
+            .rpm("/opt/rpm")
+            .root("/tmp/root")
+            .dbpath("usr/share/rpm")
+            .define("_foo", "bar baz")
+            .env("LC_ALL", "C");
+        let cmd = query.command().unwrap();
+        assert_eq!(cmd.get_program(), "/opt/rpm");
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "--define=_foo bar baz",
+                "--root=/tmp/root",
+                "--dbpath=/usr/share/rpm"
+            ]
+        );
+        let envs: Vec<_> = cmd.get_envs().collect();
+        assert_eq!(envs, [("LC_ALL".as_ref(), Some("C".as_ref()))]);
+
+        // Without a dbpath, a rootfs without rpmdb gets none.
+        let tmpdir = tempfile::tempdir().unwrap();
+        let root = Utf8Path::from_path(tmpdir.path()).unwrap();
+        let cmd = QueryBuilder::new().root(root).command().unwrap();
+        assert_eq!(cmd.get_program(), "rpm");
+        assert_eq!(cmd.get_args().count(), 1);
+
+        // Nor does one where it was already looked for.
+        std::fs::create_dir_all(root.join("var/lib/rpm")).unwrap();
+        let query = QueryBuilder::new().root(root);
+        assert_eq!(query.command().unwrap().get_args().count(), 2);
+        let cmd = query.clone().found_dbpath(None).command().unwrap();
+        assert_eq!(cmd.get_args().count(), 1);
+        let cmd = query.found_dbpath(Some("usr/share/rpm")).command().unwrap();
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args[1], "--dbpath=/usr/share/rpm");
+    }
+}
@@ -0,0 +1,57 @@
+use anyhow::Context;
+use std::collections::HashMap;
+
+use crate::*;
+
+/// Cheap query listing installed packages and their install transaction IDs.
+const TID_QUERYFORMAT: &str = r"%{NAME}\t%{INSTALLTID}\n";
+
+pub(crate) fn reload_impl(rootfs: &Utf8Path, packages: &mut Packages) -> Result<bool> {
+    let query = QueryBuilder::new().root(rootfs);
+    let Some(max_tid) = packages
+        .values()
+        .map(|pkg| pkg.installtid)
+        .collect::<Option<Vec<_>>>()
+        .map(|tids| tids.into_iter().max().unwrap_or(0))
+    else {
+        *packages = query.load()?;
+        return Ok(true);
+    };
+
+    let mut cmd = query.command()?;
+    cmd.args(["-qa", "--queryformat", TID_QUERYFORMAT]);
+    let output = rpm_output(cmd)?;
+    let mut installed: HashMap<&str, u64> = HashMap::new();
+    for line in output.lines() {
+        let (name, tid) = line
+            .split_once('\t')
+            .with_context(|| format!("unexpected line in rpm output: {line}"))?;
+        if name == "gpg-pubkey" {
+            continue;
+        }
+        let tid = tid
+            .parse::<u64>()
+            .with_context(|| format!("{name}: invalid installtid '{tid}'"))?;
+        let e = installed.entry(name).or_default();
+        *e = (*e).max(tid);
+    }
+
+    let orig_len = packages.len();
+    packages.retain(|name, _| installed.contains_key(name.as_str()));
+    let mut changed = packages.len() != orig_len;
+
+    let mut stale: Vec<&str> = installed
+        .iter()
+        .filter(|&(name, &tid)| tid > max_tid || !packages.contains_key(*name))
+        .map(|(name, _)| *name)
+        .collect();
+    if !stale.is_empty() {
+        stale.sort_unstable();
+        // Package names are passed as -qa patterns rather than to -q so that
+        // a package removed in the meantime is simply absent from the output.
+        packages.extend(query.load_matching(&stale)?);
+        changed = true;
+    }
+
+    Ok(changed)
+}
@@ -0,0 +1,209 @@
+use anyhow::Context;
+use camino::Utf8PathBuf;
+
+use crate::*;
+
+/// Convert the headers of a package of the `rpm` crate (rpm-rs), e.g. one
+/// built with it. The result is what `load_from_rpm_file` would return for the
+/// package's file, except that scriptlets and triggers are left out.
+impl TryFrom<&rpm::PackageMetadata> for Package {
+    type Error = Error;
+
+    fn try_from(metadata: &rpm::PackageMetadata) -> Result<Self> {
+        Ok(convert_metadata(metadata).context("converting rpm-rs package")?)
+    }
+}
+
+/// Convert a package of the `rpm` crate (rpm-rs). See
+/// [`TryFrom<&rpm::PackageMetadata>`](#impl-TryFrom<%26PackageMetadata>-for-Package).
+impl TryFrom<&rpm::Package> for Package {
+    type Error = Error;
+
+    fn try_from(pkg: &rpm::Package) -> Result<Self> {
+        Self::try_from(&pkg.metadata)
+    }
+}
+
+fn convert_metadata(metadata: &rpm::PackageMetadata) -> anyhow::Result<Package> {
+    let name = metadata.get_name()?.to_string();
+    let mut files = Files::new();
+    let mut digest_algo = None;
+    for entry in metadata.get_file_entries()? {
+        let path = Utf8PathBuf::try_from(entry.path.clone())?;
+        let info = FileInfo::try_from(&entry).with_context(|| format!("file {path}"))?;
+        if let Some(digest) = &entry.digest {
+            let algo = digest.algo as u32;
+            digest_algo = Some(
+                DigestAlgorithm::try_from(algo)
+                    .map_err(|_| anyhow::anyhow!("{name}: unknown digest algorithm {algo}"))?,
+            );
+        }
+        files.insert(path, info);
+    }
+    let changelog = (metadata.get_changelog_entries()?.into_iter())
+        .map(|entry| ChangelogEntry {
+            time: entry.timestamp,
+            author: entry.name,
+            text: entry.description,
+        })
+        .collect();
+    let deps = |deps: Vec<rpm::Dependency>| deps.iter().map(Dependency::from).collect();
+    Ok(Package {
+        name,
+        version: metadata.get_version()?.to_string(),
+        release: metadata.get_release()?.to_string(),
+        epoch: metadata.get_epoch().ok(),
+        arch: metadata.get_arch()?.to_string(),
+        license: metadata.get_license()?.to_string(),
+        size: metadata.get_installed_size()?,
+        buildtime: metadata.get_build_time()?,
+        installtime: 0,
+        installtid: None,
+        sourcerpm: metadata.get_source_rpm().ok().map(ToString::to_string),
+        digest_algo,
+        signing_key: None,
+        changelog,
+        requires: deps(metadata.get_requires()?),
+        provides: deps(metadata.get_provides()?),
+        recommends: deps(metadata.get_recommends()?),
+        suggests: deps(metadata.get_suggests()?),
+        obsoletes: deps(metadata.get_obsoletes()?),
+        conflicts: deps(metadata.get_conflicts()?),
+        pre: None,
+        post: None,
+        preun: None,
+        postun: None,
+        triggers: Vec::new(),
+        files,
+        unparsed_files: None,
+    })
+}
+
+/// Convert a file entry of the `rpm` crate (rpm-rs). Its path is the key of
+/// [`Package::files`] rather than part of [`FileInfo`].
+impl TryFrom<&rpm::FileEntry> for FileInfo {
+    type Error = Error;
+
+    fn try_from(entry: &rpm::FileEntry) -> Result<Self> {
+        let digest = match &entry.digest {
+            Some(digest) if !digest.digest.is_empty() => {
+                Some(FileDigest::from_hex(&digest.digest)?)
+            }
+            _ => None,
+        };
+        Ok(FileInfo {
+            size: entry.size as u64,
+            mode: entry.mode.raw_mode(),
+            mtime: u32::from(entry.modified_at).into(),
+            digest,
+            flags: FileFlags::from_raw(entry.flags.bits()),
+            user: entry.ownership.user.clone(),
+            group: entry.ownership.group.clone(),
+            linkto: (!entry.linkto.is_empty()).then(|| entry.linkto.as_str().into()),
+            state: None,
+            lang: None,
+            color: None,
+            caps: entry.caps.clone().filter(|caps| !caps.is_empty()),
+        })
+    }
+}
+
+/// Start building a package of the `rpm` crate (rpm-rs) with the metadata
+/// and dependencies of `pkg`. rpm-rs needs the contents of files to build a
+/// package, which [`Package`] doesn't have, so files must be added to the
+/// builder separately, e.g. with `with_file_contents`. The summary is the
+/// package name, and requires on features of rpm itself (`rpmlib(...)`) are
+/// left to rpm-rs, which adds its own.
+impl From<&Package> for rpm::PackageBuilder {
+    fn from(pkg: &Package) -> Self {
+        let mut builder =
+            rpm::PackageBuilder::new(&pkg.name, &pkg.version, &pkg.license, &pkg.arch, &pkg.name)
+                .release(&pkg.release);
+        if let Some(epoch) = pkg.epoch {
+            builder = builder.epoch(epoch);
+        }
+        for dep in pkg.requires.iter().filter(|dep| !dep.flags.is_rpmlib()) {
+            builder = builder.requires(dep.into());
+        }
+        for dep in &pkg.provides {
+            builder = builder.provides(dep.into());
+        }
+        for dep in &pkg.recommends {
+            builder = builder.recommends(dep.into());
+        }
+        for dep in &pkg.suggests {
+            builder = builder.suggests(dep.into());
+        }
+        for dep in &pkg.obsoletes {
+            builder = builder.obsoletes(dep.into());
+        }
+        for dep in &pkg.conflicts {
+            builder = builder.conflicts(dep.into());
+        }
+        builder
+    }
+}
+
+/// Convert a dependency of the `rpm` crate (rpm-rs).
+impl From<&rpm::Dependency> for Dependency {
+    fn from(dep: &rpm::Dependency) -> Self {
+        Self {
+            name: dep.name.clone(),
+            flags: DependencyFlags::from_raw(dep.flags.bits()),
+            version: (!dep.version.is_empty()).then(|| dep.version.clone()),
+        }
+    }
+}
+
+/// Convert a dependency to one of the `rpm` crate (rpm-rs).
+impl From<&Dependency> for rpm::Dependency {
+    fn from(dep: &Dependency) -> Self {
+        Self {
+            name: dep.name.clone(),
+            flags: rpm::DependencyFlags::from_bits_retain(dep.flags.raw()),
+            version: dep.version.clone().unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camino::Utf8Path;
+
+    #[test]
+    fn test_from_rpm_rs() {
+        let pkg = rpm::PackageBuilder::new("foo", "1.0", "MIT", "x86_64", "Foo")
+            .release("1.fc43")
+            .epoch(2)
+            .requires(rpm::Dependency::greater_eq("bash", "5.0"))
+            .with_file_contents(
+                "#!/bin/sh\n",
+                rpm::FileOptions::new("/usr/bin/foo").mode(0o100755),
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+        let converted = Package::try_from(&pkg).unwrap();
+        assert_eq!(converted.nevra(), "foo-2:1.0-1.fc43.x86_64");
+        assert_eq!(converted.license, "MIT");
+        assert_eq!((converted.size, converted.installtime), (10, 0));
+        let bin = &converted.files[Utf8Path::new("/usr/bin/foo")];
+        assert_eq!((bin.size, bin.mode, bin.state), (10, 0o100755, None));
+        assert_eq!(bin.user, "root");
+        assert!(bin.digest.is_some());
+        assert_eq!(converted.digest_algo, Some(DigestAlgorithm::Sha256));
+
+        let requires = pkg.metadata.get_requires().unwrap();
+        let bash = requires.iter().find(|dep| dep.name == "bash").unwrap();
+        assert_eq!(Dependency::from(bash).to_string(), "bash >= 5.0");
+        assert!(converted.requires.contains(&Dependency::from(bash)));
+
+        // And back, without the file contents.
+        let rebuilt = rpm::PackageBuilder::from(&converted).build().unwrap();
+        let rebuilt = Package::try_from(&rebuilt).unwrap();
+        assert_eq!(rebuilt.nevra(), converted.nevra());
+        assert!(rebuilt.requires.contains(&Dependency::from(bash)));
+        assert!(rebuilt.files.is_empty());
+    }
+}
@@ -0,0 +1,393 @@
+use anyhow::Context;
+use serde_json::{Value, json};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::*;
+
+/// Name of the tool recorded as the SBOM's creator.
+const TOOL: &str = env!("CARGO_PKG_NAME");
+const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A software bill of materials of installed packages, exported as SPDX or
+/// CycloneDX JSON.
+///
+/// Packages are identified by their [package URL] (`pkg:rpm/...`). Document
+/// identifiers are derived from the document's contents, so exporting the
+/// same packages with the same options is reproducible.
+///
+/// [package URL]: https://github.com/package-url/purl-spec
+#[derive(Debug, Clone)]
+pub struct Sbom<'a> {
+    packages: Vec<&'a Package>,
+    name: String,
+    vendor: Option<String>,
+    created: SystemTime,
+    digest_policy: DigestPolicy,
+}
+
+impl<'a> Sbom<'a> {
+    /// Create an SBOM of `packages`, named `name` (e.g. the image or host it
+    /// describes) and created now.
+    pub fn new(packages: &'a Packages, name: impl Into<String>) -> Self {
+        let mut packages: Vec<&Package> = packages.values().collect();
+        packages.sort_by_cached_key(|pkg| (pkg.name.clone(), pkg.nevra()));
+        Self {
+            packages,
+            name: name.into(),
+            vendor: None,
+            created: SystemTime::now(),
+            digest_policy: DigestPolicy::Allow,
+        }
+    }
+
+    /// Set the vendor of the packages (e.g. `fedora`), used as the namespace
+    /// of their package URLs.
+    pub fn vendor(mut self, vendor: impl Into<String>) -> Self {
+        self.vendor = Some(vendor.into());
+        self
+    }
+
+    /// Set the creation time of the SBOM, e.g. to make it reproducible.
+    pub fn created(mut self, created: SystemTime) -> Self {
+        self.created = created;
+        self
+    }
+
+    /// Set what to do about packages whose file digests aren't approved for
+    /// FIPS (see [`weak_digests`]). With [`DigestPolicy::Flag`], they're
+    /// annotated with their digest algorithm; with [`DigestPolicy::Reject`],
+    /// writing the SBOM fails.
+    pub fn digest_policy(mut self, policy: DigestPolicy) -> Self {
+        self.digest_policy = policy;
+        self
+    }
+
+    /// Write the SBOM as an SPDX 2.3 JSON document.
+    pub fn write_spdx<W: Write>(&self, writer: W) -> Result<()> {
+        self.check_digests()?;
+        Ok(write_json(writer, &self.spdx()).context("writing SPDX document")?)
+    }
+
+    /// Write the SBOM as a CycloneDX 1.5 JSON document.
+    pub fn write_cyclonedx<W: Write>(&self, writer: W) -> Result<()> {
+        self.check_digests()?;
+        Ok(write_json(writer, &self.cyclonedx()).context("writing CycloneDX document")?)
+    }
+
+    fn check_digests(&self) -> Result<()> {
+        if self.digest_policy != DigestPolicy::Reject {
+            return Ok(());
+        }
+        let weak: Vec<String> = (self.packages.iter())
+            .filter(|pkg| fips::is_weak(pkg))
+            .map(|pkg| pkg.nevra())
+            .collect();
+        if weak.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::WeakDigest(weak))
+        }
+    }
+
+    /// Get the digest algorithm of a package to flag, if any.
+    fn flagged_digest(&self, pkg: &Package) -> Option<&'static str> {
+        (self.digest_policy == DigestPolicy::Flag && fips::is_weak(pkg))
+            .then(|| fips::digest_algo(pkg).name())
+    }
+
+    fn spdx(&self) -> Value {
+        let id = |pkg: &Package| {
+            let nevra: String = pkg
+                .nevra()
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '.' {
+                        c
+                    } else {
+                        '-'
+                    }
+                })
+                .collect();
+            format!("SPDXRef-Package-{nevra}")
+        };
+        let packages: Vec<Value> = self
+            .packages
+            .iter()
+            .map(|pkg| {
+                let mut package = json!({
+                    "SPDXID": id(pkg),
+                    "name": pkg.name,
+                    "versionInfo": pkg.evr().to_string(),
+                    "supplier": "NOASSERTION",
+                    "downloadLocation": "NOASSERTION",
+                    "filesAnalyzed": false,
+                    "licenseConcluded": "NOASSERTION",
+                    "licenseDeclared": license(pkg).unwrap_or("NOASSERTION"),
+                    "copyrightText": "NOASSERTION",
+                    "externalRefs": [{
+                        "referenceCategory": "PACKAGE-MANAGER",
+                        "referenceType": "purl",
+                        "referenceLocator": self.purl(pkg),
+                    }],
+                });
+                if let Some(algo) = self.flagged_digest(pkg) {
+                    package["comment"] =
+                        format!("File digests use {algo}, which isn't approved for FIPS.").into();
+                }
+                package
+            })
+            .collect();
+        let relationships: Vec<Value> = self
+            .packages
+            .iter()
+            .map(|pkg| {
+                json!({
+                    "spdxElementId": "SPDXRef-DOCUMENT",
+                    "relationshipType": "DESCRIBES",
+                    "relatedSpdxElement": id(pkg),
+                })
+            })
+            .collect();
+        json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": self.name,
+            "documentNamespace": format!("https://spdx.org/spdxdocs/{}-{}", self.name, self.uuid()),
+            "creationInfo": {
+                "created": rfc3339(self.created),
+                "creators": [format!("Tool: {TOOL}-{TOOL_VERSION}")],
+            },
+            "packages": packages,
+            "relationships": relationships,
+        })
+    }
+
+    fn cyclonedx(&self) -> Value {
+        let components: Vec<Value> = self
+            .packages
+            .iter()
+            .map(|pkg| {
+                let purl = self.purl(pkg);
+                let mut component = json!({
+                    "type": "library",
+                    "bom-ref": purl,
+                    "name": pkg.name,
+                    "version": pkg.evr().to_string(),
+                    "purl": purl,
+                    "properties": [{ "name": "rpm:arch", "value": pkg.arch }],
+                });
+                if let Some(license) = license(pkg) {
+                    component["licenses"] = json!([{ "expression": license }]);
+                }
+                if let Some(algo) = self.flagged_digest(pkg) {
+                    component["properties"]
+                        .as_array_mut()
+                        .unwrap()
+                        .push(json!({ "name": "rpm:weak-digest", "value": algo }));
+                }
+                component
+            })
+            .collect();
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "serialNumber": format!("urn:uuid:{}", self.uuid()),
+            "version": 1,
+            "metadata": {
+                "timestamp": rfc3339(self.created),
+                "tools": {
+                    "components": [{ "type": "application", "name": TOOL, "version": TOOL_VERSION }],
+                },
+                "component": { "type": "operating-system", "name": self.name },
+            },
+            "components": components,
+        })
+    }
+
+    /// Get the package URL of a package.
+    fn purl(&self, pkg: &Package) -> String {
+        let mut purl = String::from("pkg:rpm/");
+        if let Some(vendor) = &self.vendor {
+            purl.push_str(&purl_encode(vendor));
+            purl.push('/');
+        }
+        purl.push_str(&format!(
+            "{}@{}-{}?arch={}",
+            purl_encode(&pkg.name),
+            purl_encode(&pkg.version),
+            purl_encode(&pkg.release),
+            purl_encode(&pkg.arch)
+        ));
+        if let Some(epoch) = pkg.epoch {
+            purl.push_str(&format!("&epoch={epoch}"));
+        }
+        purl
+    }
+
+    /// Derive a UUID-formatted identifier from the document's contents.
+    fn uuid(&self) -> String {
+        let hash = |seed: u8| {
+            let mut hasher = std::hash::DefaultHasher::new();
+            seed.hash(&mut hasher);
+            self.name.hash(&mut hasher);
+            self.vendor.hash(&mut hasher);
+            self.created.hash(&mut hasher);
+            for pkg in &self.packages {
+                pkg.nevra().hash(&mut hasher);
+            }
+            hasher.finish()
+        };
+        let (hi, lo) = (hash(0), hash(1));
+        // Formatted as a version 4 (random) UUID.
+        let hi = (hi & !0xf000) | 0x4000;
+        let lo = (lo & !(0xc << 60)) | (0x8 << 60);
+        format!(
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            hi >> 32,
+            (hi >> 16) & 0xffff,
+            hi & 0xffff,
+            lo >> 48,
+            lo & 0xffff_ffff_ffff
+        )
+    }
+}
+
+/// Get the license of a package, if it has a meaningful one. Note that only
+/// recent distributions use SPDX expressions in their packages.
+fn license(pkg: &Package) -> Option<&str> {
+    let license = pkg.license.trim();
+    (!license.is_empty() && license != "(none)").then_some(license)
+}
+
+/// Percent-encode a package URL component.
+fn purl_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    encoded
+}
+
+/// Format a time as an RFC 3339 UTC timestamp, with second precision.
+fn rfc3339(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Civil date from days since the epoch, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+fn write_json<W: Write>(mut writer: W, value: &Value) -> anyhow::Result<()> {
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/fedora.qf");
+
+    fn export(sbom: &Sbom, spdx: bool) -> Value {
+        let mut buf = Vec::new();
+        if spdx {
+            sbom.write_spdx(&mut buf).unwrap();
+        } else {
+            sbom.write_cyclonedx(&mut buf).unwrap();
+        }
+        serde_json::from_slice(&buf).unwrap()
+    }
+
+    #[test]
+    fn test_sbom() {
+        let packages = load_from_str(FIXTURE).unwrap();
+        let created = UNIX_EPOCH + Duration::from_secs(1772174884);
+        let sbom = Sbom::new(&packages, "host")
+            .vendor("fedora")
+            .created(created);
+
+        let spdx = export(&sbom, true);
+        assert_eq!(spdx["spdxVersion"], "SPDX-2.3");
+        assert_eq!(spdx["creationInfo"]["created"], "2026-02-27T06:48:04Z");
+        let bash = &spdx["packages"][0];
+        assert_eq!(bash["name"], "bash");
+        assert_eq!(bash["versionInfo"], "5.3.0-2.fc43");
+        assert_eq!(bash["licenseDeclared"], "GPL-3.0-or-later");
+        assert_eq!(
+            bash["externalRefs"][0]["referenceLocator"],
+            "pkg:rpm/fedora/bash@5.3.0-2.fc43?arch=x86_64"
+        );
+        assert_eq!(
+            spdx["relationships"].as_array().unwrap().len(),
+            packages.len()
+        );
+        assert_eq!(export(&sbom, true), spdx);
+
+        let cdx = export(&sbom, false);
+        assert_eq!(cdx["bomFormat"], "CycloneDX");
+        assert_eq!(cdx["components"].as_array().unwrap().len(), packages.len());
+        assert_eq!(
+            cdx["components"][0]["purl"],
+            bash["externalRefs"][0]["referenceLocator"]
+        );
+        let namespace = spdx["documentNamespace"].as_str().unwrap();
+        let serial = cdx["serialNumber"].as_str().unwrap();
+        assert!(namespace.ends_with(serial.strip_prefix("urn:uuid:").unwrap()));
+    }
+
+    #[test]
+    fn test_digest_policy() {
+        let mut packages = load_from_str(FIXTURE).unwrap();
+        packages.get_mut("bash").unwrap().digest_algo = Some(DigestAlgorithm::Sha1);
+        let sbom = Sbom::new(&packages, "host").digest_policy(DigestPolicy::Flag);
+        let spdx = export(&sbom, true);
+        assert_eq!(
+            spdx["packages"][0]["comment"],
+            "File digests use sha1, which isn't approved for FIPS."
+        );
+        assert!(spdx["packages"][1].get("comment").is_none());
+        let cdx = export(&sbom, false);
+        assert_eq!(
+            cdx["components"][0]["properties"][1],
+            json!({ "name": "rpm:weak-digest", "value": "sha1" })
+        );
+
+        let sbom = sbom.digest_policy(DigestPolicy::Reject);
+        let err = sbom.write_spdx(Vec::new()).unwrap_err();
+        assert!(matches!(err, Error::WeakDigest(nevras) if nevras.len() == 1));
+        assert!(sbom.write_cyclonedx(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_helpers() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_secs(951782400)),
+            "2000-02-29T00:00:00Z"
+        );
+        assert_eq!(purl_encode("gcc-c++"), "gcc-c%2B%2B");
+    }
+}
@@ -0,0 +1,303 @@
+use anyhow::{Context, bail};
+use camino::Utf8Path;
+use regex::Regex;
+
+use crate::*;
+
+/// Extended attribute holding the SELinux label of a file.
+const XATTR_NAME: &str = "security.selinux";
+
+/// A specification of SELinux file labels, as in the policy's
+/// `file_contexts` (e.g.
+/// `/etc/selinux/targeted/contexts/files/file_contexts`).
+#[derive(Debug, Clone, Default)]
+pub struct FileContexts {
+    specs: Vec<Spec>,
+}
+
+#[derive(Debug, Clone)]
+struct Spec {
+    /// The path regex, anchored at both ends.
+    regex: Regex,
+    /// File type bits the spec is restricted to, if any.
+    file_type: Option<u16>,
+    /// The label, or `None` for `<<none>>`, i.e. files not to be labeled.
+    context: Option<String>,
+    /// Whether the pattern has regex metacharacters. Specs without any take
+    /// precedence over those with some.
+    has_meta: bool,
+}
+
+impl FileContexts {
+    /// Load a `file_contexts` file, along with its `.local` counterpart if
+    /// there's one, as libselinux does.
+    pub fn load(path: &Utf8Path) -> Result<Self> {
+        Ok(Self::load_impl(path).with_context(|| format!("loading {path}"))?)
+    }
+
+    fn load_impl(path: &Utf8Path) -> anyhow::Result<Self> {
+        let mut contexts = Self::parse_impl(&std::fs::read_to_string(path)?)?;
+        let local = format!("{path}.local");
+        match std::fs::read_to_string(&local) {
+            Ok(s) => {
+                let local = Self::parse_impl(&s).with_context(|| format!("loading {local}"))?;
+                contexts.specs.extend(local.specs);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("reading {local}")),
+        }
+        Ok(contexts)
+    }
+
+    /// Parse the contents of a `file_contexts` file. Each line has a path
+    /// regex, an optional file type (e.g. `--` for regular files or `-d` for
+    /// directories) and a label or `<<none>>`.
+    pub fn parse(s: &str) -> Result<Self> {
+        Ok(Self::parse_impl(s)?)
+    }
+
+    fn parse_impl(s: &str) -> anyhow::Result<Self> {
+        let mut specs = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (pattern, file_type, context) = match fields[..] {
+                [pattern, context] => (pattern, None, context),
+                [pattern, file_type, context] => {
+                    (pattern, Some(parse_file_type(file_type)), context)
+                }
+                _ => bail!("line {}: expected 2 or 3 fields", i + 1),
+            };
+            let file_type = match file_type {
+                Some(Some(t)) => Some(t),
+                Some(None) => bail!("line {}: invalid file type", i + 1),
+                None => None,
+            };
+            let regex = Regex::new(&format!("^(?:{pattern})$"))
+                .with_context(|| format!("line {}: invalid regex", i + 1))?;
+            specs.push(Spec {
+                regex,
+                file_type,
+                context: (context != "<<none>>").then(|| context.to_string()),
+                has_meta: pattern.contains(['.', '^', '$', '?', '*', '+', '|', '[', '(', '{']),
+            });
+        }
+        Ok(Self { specs })
+    }
+
+    /// Get the label a file at `path` of the given `mode` should have, or
+    /// `None` if no spec matches or it's not to be labeled. As in
+    /// libselinux, specs without regex metacharacters take precedence, then
+    /// later specs over earlier ones.
+    pub fn lookup(&self, path: &Utf8Path, mode: u16) -> Option<&str> {
+        let matches = |spec: &&Spec| {
+            spec.file_type.is_none_or(|t| t == mode & FileInfo::S_IFMT)
+                && spec.regex.is_match(path.as_str())
+        };
+        let exact = self.specs.iter().rev().filter(|s| !s.has_meta);
+        let meta = self.specs.iter().rev().filter(|s| s.has_meta);
+        exact.chain(meta).find(matches)?.context.as_deref()
+    }
+}
+
+/// Parse the file type field of a spec into file type bits.
+fn parse_file_type(s: &str) -> Option<u16> {
+    Some(match s {
+        "--" => FileInfo::S_IFREG,
+        "-d" => FileInfo::S_IFDIR,
+        "-l" => FileInfo::S_IFLNK,
+        "-c" => 0o020000,
+        "-b" => 0o060000,
+        "-s" => 0o140000,
+        "-p" => 0o010000,
+        _ => return None,
+    })
+}
+
+/// A packaged file paired with its expected label. See [`expected_labels`].
+#[derive(Debug, Clone)]
+pub struct FileLabel<'a> {
+    /// The package owning the file.
+    pub package: &'a Package,
+    /// The file path.
+    pub path: &'a Utf8Path,
+    /// The expected label, or `None` if the file isn't to be labeled.
+    pub expected: Option<&'a str>,
+}
+
+/// A packaged file whose label on disk isn't the expected one. See
+/// [`label_mismatches`].
+#[derive(Debug, Clone)]
+pub struct LabelMismatch<'a> {
+    /// The package owning the file.
+    pub package: &'a Package,
+    /// The file path.
+    pub path: &'a Utf8Path,
+    /// The expected label.
+    pub expected: &'a str,
+    /// The label on disk, or `None` if the file is unlabeled.
+    pub actual: Option<String>,
+}
+
+/// Pair each file of `packages` with the label `contexts` gives it, sorted
+/// by path, then package name.
+pub fn expected_labels<'a>(
+    packages: &'a Packages,
+    contexts: &'a FileContexts,
+) -> Vec<FileLabel<'a>> {
+    let mut labels: Vec<FileLabel<'a>> = (packages.values())
+        .flat_map(|pkg| {
+            pkg.files.iter().map(move |(path, info)| FileLabel {
+                package: pkg,
+                path,
+                expected: contexts.lookup(path, info.mode),
+            })
+        })
+        .collect();
+    labels.sort_by(|a, b| (a.path, &a.package.name).cmp(&(b.path, &b.package.name)));
+    labels
+}
+
+/// Find the files of `packages` under `rootfs` whose label isn't the one
+/// `contexts` gives them, as `restorecon -n -F` would, sorted by path.
+///
+/// Files which aren't to be labeled, weren't installed (see [`FileState`])
+/// or aren't on disk (e.g. ghost files) are skipped. Labels are compared
+/// in full, including the SELinux user and MLS range.
+pub fn label_mismatches<'a>(
+    packages: &'a Packages,
+    contexts: &'a FileContexts,
+    rootfs: &Utf8Path,
+) -> Result<Vec<LabelMismatch<'a>>> {
+    Ok(label_mismatches_impl(packages, contexts, rootfs)
+        .with_context(|| format!("checking labels under {rootfs}"))?)
+}
+
+fn label_mismatches_impl<'a>(
+    packages: &'a Packages,
+    contexts: &'a FileContexts,
+    rootfs: &Utf8Path,
+) -> anyhow::Result<Vec<LabelMismatch<'a>>> {
+    let mut mismatches = Vec::new();
+    for label in expected_labels(packages, contexts) {
+        let Some(expected) = label.expected else {
+            continue;
+        };
+        let info = &label.package.files[label.path];
+        if !info.state.is_none_or(|s| s == FileState::Normal) {
+            continue;
+        }
+        let on_disk = rootfs.join(label.path.strip_prefix("/").unwrap_or(label.path));
+        let actual = match read_label(&on_disk) {
+            Ok(actual) => actual,
+            Err(rustix::io::Errno::NOENT | rustix::io::Errno::NOTDIR) => continue,
+            Err(e) => return Err(e).with_context(|| format!("reading label of {on_disk}")),
+        };
+        if actual.as_deref() != Some(expected) {
+            mismatches.push(LabelMismatch {
+                package: label.package,
+                path: label.path,
+                expected,
+                actual,
+            });
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Read the label of a file without following symlinks. Returns `None` if
+/// the file is unlabeled.
+fn read_label(path: &Utf8Path) -> rustix::io::Result<Option<String>> {
+    let mut buf = vec![0; 256];
+    loop {
+        match rustix::fs::lgetxattr(path.as_std_path(), XATTR_NAME, &mut buf[..]) {
+            Ok(len) => {
+                let label = &buf[..len];
+                let label = label.strip_suffix(b"\0").unwrap_or(label);
+                return Ok(Some(String::from_utf8_lossy(label).into_owned()));
+            }
+            // Filesystems without xattrs have no labels at all.
+            Err(rustix::io::Errno::NODATA | rustix::io::Errno::NOTSUP) => return Ok(None),
+            Err(rustix::io::Errno::RANGE) => buf.resize(buf.len() * 2, 0),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE_CONTEXTS: &str = "\
+# Comments are skipped.
+/.*\t\t\tsystem_u:object_r:default_t:s0
+/usr(/.*)?\t\tsystem_u:object_r:usr_t:s0
+/usr/bin(/.*)?\t\tsystem_u:object_r:bin_t:s0
+/usr/bin/passwd\t--\tsystem_u:object_r:passwd_exec_t:s0
+/usr/bin/.*\t-l\tsystem_u:object_r:bin_t:s0
+/proc(/.*)?\t\t<<none>>
+";
+
+    #[test]
+    fn test_lookup() {
+        let contexts = FileContexts::parse(FILE_CONTEXTS).unwrap();
+        let lookup = |path, mode| contexts.lookup(Utf8Path::new(path), mode);
+        assert_eq!(
+            lookup("/usr/bin/passwd", 0o100755),
+            Some("system_u:object_r:passwd_exec_t:s0")
+        );
+        // The file type must match.
+        assert_eq!(
+            lookup("/usr/bin/passwd", 0o040755),
+            Some("system_u:object_r:bin_t:s0")
+        );
+        assert_eq!(
+            lookup("/usr/share/foo", 0o100644),
+            Some("system_u:object_r:usr_t:s0")
+        );
+        assert_eq!(lookup("/usr", 0o040755), Some("system_u:object_r:usr_t:s0"));
+        assert_eq!(
+            lookup("/usrx", 0o040755),
+            Some("system_u:object_r:default_t:s0")
+        );
+        assert_eq!(lookup("/proc/1", 0o040755), None);
+
+        assert!(FileContexts::parse("/foo").is_err());
+        assert!(FileContexts::parse("/foo -x system_u:object_r:foo_t:s0").is_err());
+        assert!(FileContexts::parse("/foo( system_u:object_r:foo_t:s0").is_err());
+    }
+
+    #[test]
+    fn test_expected_labels() {
+        let contexts = FileContexts::parse(FILE_CONTEXTS).unwrap();
+        let packages = load_from_str(include_str!("../tests/fixtures/fedora.qf")).unwrap();
+        let labels = expected_labels(&packages, &contexts);
+        let count: usize = packages.values().map(|pkg| pkg.files.len()).sum();
+        assert_eq!(labels.len(), count);
+        assert!(labels.is_sorted_by_key(|l| l.path));
+        let bash = (labels.iter()).find(|l| l.path == "/usr/bin/bash").unwrap();
+        assert_eq!(bash.package.name, "bash");
+        assert_eq!(bash.expected, Some("system_u:object_r:bin_t:s0"));
+    }
+
+    #[test]
+    fn test_label_mismatches() {
+        // Files in a temporary directory don't have the labels of /usr.
+        let tmp = tempfile::tempdir().unwrap();
+        let rootfs = Utf8Path::from_path(tmp.path()).unwrap();
+        std::fs::create_dir_all(rootfs.join("usr/bin")).unwrap();
+        std::fs::write(rootfs.join("usr/bin/bash"), "").unwrap();
+        let contexts = FileContexts::parse(FILE_CONTEXTS).unwrap();
+        let packages = load_from_str(include_str!("../tests/fixtures/fedora.qf")).unwrap();
+        let mismatches = label_mismatches(&packages, &contexts, rootfs).unwrap();
+        let bash = (mismatches.iter())
+            .find(|m| m.path == "/usr/bin/bash")
+            .unwrap();
+        assert_eq!(bash.expected, "system_u:object_r:bin_t:s0");
+        // Files missing from disk are skipped.
+        assert!(mismatches.iter().all(|m| m.path.starts_with("/usr")));
+    }
+}
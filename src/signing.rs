@@ -0,0 +1,193 @@
+use anyhow::{Context, bail};
+#[cfg(feature = "exec")]
+use camino::Utf8Path;
+use std::collections::BTreeMap;
+
+use crate::*;
+
+/// The `--queryformat` string used to list the `gpg-pubkey` entries of the
+/// rpmdb, e.g. with `rpm -qa gpg-pubkey`. This is the format that
+/// [`load_public_keys_from_str`] expects.
+pub const PUBKEY_QUERYFORMAT: &str = "%{VERSION}\x1f%{RELEASE}\x1f%{SUMMARY}\x1e";
+
+/// A public key imported into the rpmdb, i.e. one of its `gpg-pubkey`
+/// entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    /// ID of the key as rpm names its entry, in lowercase hex: the last 8
+    /// digits of its key ID with older rpm versions, or its whole fingerprint
+    /// with newer ones.
+    pub key_id: String,
+    /// Unix timestamp of the key's creation.
+    pub created: u64,
+    /// Description of the key, naming its owner, e.g. `Fedora (43)
+    /// <fedora-43-primary@fedoraproject.org> public key`.
+    pub summary: String,
+}
+
+impl PublicKey {
+    /// Whether this is the key with ID `key_id` (e.g. a
+    /// [`Package::signing_key`]). Key IDs, short or long, are suffixes of
+    /// fingerprints, so they match if either ends with the other.
+    pub fn matches(&self, key_id: &str) -> bool {
+        let (a, b) = (
+            self.key_id.to_ascii_lowercase(),
+            key_id.to_ascii_lowercase(),
+        );
+        !a.is_empty() && !b.is_empty() && (a.ends_with(&b) || b.ends_with(&a))
+    }
+}
+
+/// Load the public keys imported into the rpmdb of a rootfs path by running
+/// `rpm -qa gpg-pubkey`, sorted by key ID.
+#[cfg(feature = "exec")]
+pub fn load_public_keys(rootfs: &Utf8Path) -> Result<Vec<PublicKey>> {
+    let mut cmd = QueryBuilder::new().root(rootfs).command()?;
+    cmd.args([
+        "-qa",
+        "--queryformat",
+        PUBKEY_QUERYFORMAT,
+        "--",
+        "gpg-pubkey",
+    ]);
+    let output = rpm_output(cmd)?;
+    Ok(parse_public_keys(&output).with_context(|| format!("listing public keys of {rootfs}"))?)
+}
+
+/// Parse the `gpg-pubkey` entries of an rpmdb, as listed with
+/// [`PUBKEY_QUERYFORMAT`], sorted by key ID.
+pub fn load_public_keys_from_str(s: &str) -> Result<Vec<PublicKey>> {
+    Ok(parse_public_keys(s).context("parsing public keys")?)
+}
+
+fn parse_public_keys(output: &str) -> anyhow::Result<Vec<PublicKey>> {
+    let mut keys = Vec::new();
+    for record in output.split_terminator('\x1e') {
+        let fields: Vec<&str> = record.split('\x1f').collect();
+        let [key_id, created, summary] = fields[..] else {
+            bail!(
+                "expected 3 fields in public key entry, got {}",
+                fields.len()
+            );
+        };
+        // The release is the creation time, in hex.
+        let created = u64::from_str_radix(created, 16)
+            .with_context(|| format!("{key_id}: invalid creation time '{created}'"))?;
+        keys.push(PublicKey {
+            key_id: key_id.to_ascii_lowercase(),
+            created,
+            summary: summary.to_string(),
+        });
+    }
+    keys.sort_by(|a, b| a.key_id.cmp(&b.key_id));
+    Ok(keys)
+}
+
+/// An imported public key and the packages signed with it.
+#[derive(Debug, Clone)]
+pub struct KeySignatures<'a> {
+    /// The key.
+    pub key: &'a PublicKey,
+    /// Packages signed with it, sorted by name.
+    pub packages: Vec<&'a Package>,
+}
+
+/// Which key signed which packages. See [`signing_report`].
+#[derive(Debug, Clone, Default)]
+pub struct SigningReport<'a> {
+    /// The imported public keys, in the order given, with the packages
+    /// signed with each. Keys which signed no package are included.
+    pub keys: Vec<KeySignatures<'a>>,
+    /// Packages signed with keys which aren't imported, keyed by key ID and
+    /// sorted by name.
+    pub unknown: BTreeMap<&'a str, Vec<&'a Package>>,
+    /// Packages which aren't signed, sorted by name.
+    pub unsigned: Vec<&'a Package>,
+}
+
+impl SigningReport<'_> {
+    /// Whether every package is signed with an imported key.
+    pub fn is_trusted(&self) -> bool {
+        self.unknown.is_empty() && self.unsigned.is_empty()
+    }
+}
+
+/// Match the signing key of each package (see [`Package::signing_key`])
+/// against imported public keys, e.g. as loaded by `load_public_keys` or
+/// [`load_public_keys_from_str`].
+///
+/// Note that signatures are only recorded by recent versions of this crate;
+/// packages parsed from older output all appear as unsigned.
+pub fn signing_report<'a>(packages: &'a Packages, keys: &'a [PublicKey]) -> SigningReport<'a> {
+    let mut report = SigningReport {
+        keys: (keys.iter())
+            .map(|key| KeySignatures {
+                key,
+                packages: Vec::new(),
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let mut sorted: Vec<&Package> = packages.values().collect();
+    sorted.sort_by_cached_key(|pkg| (pkg.name.clone(), pkg.nevra()));
+    for pkg in sorted {
+        let Some(key_id) = pkg.signing_key.as_deref() else {
+            report.unsigned.push(pkg);
+            continue;
+        };
+        match report.keys.iter_mut().find(|k| k.key.matches(key_id)) {
+            Some(signatures) => signatures.packages.push(pkg),
+            None => report.unknown.entry(key_id).or_default().push(pkg),
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{PackageBuilder, packages};
+
+    #[test]
+    fn test_signing_report() {
+        let keys = parse_public_keys(concat!(
+            "36f612dcf27f7d1a48a835e4dbfcf71c6d9f90a6\x1f6786af3b\x1f",
+            "Fedora (44) <fedora-44-primary@fedoraproject.org> public key\x1e",
+            "31645531\x1f6888bc98\x1fOld key\x1e",
+        ))
+        .unwrap();
+        assert_eq!(keys[0].key_id, "31645531");
+        assert_eq!(keys[1].created, 0x6786af3b);
+        assert!(parse_public_keys("31645531\x1fnope\x1fOld key\x1e").is_err());
+        assert!(parse_public_keys("31645531\x1e").is_err());
+
+        let packages = packages(
+            [
+                ("a", Some("dbfcf71c6d9f90a6")),
+                ("b", Some("f3bcd7e431645531")),
+                ("c", Some("0123456789abcdef")),
+                ("d", None),
+                ("e", Some("dbfcf71c6d9f90a6")),
+            ]
+            .map(|(name, key_id)| {
+                PackageBuilder::new(name)
+                    .signing_key(key_id.map(Into::into))
+                    .build()
+            }),
+        );
+
+        let report = signing_report(&packages, &keys);
+        let names = |pkgs: &[&Package]| pkgs.iter().map(|pkg| pkg.name.clone()).collect::<Vec<_>>();
+        assert_eq!(report.keys.len(), 2);
+        assert_eq!(names(&report.keys[0].packages), ["b"]);
+        assert_eq!(names(&report.keys[1].packages), ["a", "e"]);
+        assert_eq!(report.unknown.len(), 1);
+        assert_eq!(names(&report.unknown["0123456789abcdef"]), ["c"]);
+        assert_eq!(names(&report.unsigned), ["d"]);
+        assert!(!report.is_trusted());
+
+        let report = signing_report(&packages, &[]);
+        assert_eq!(report.unknown.len(), 3);
+        assert!(signing_report(&Packages::default(), &keys).is_trusted());
+    }
+}
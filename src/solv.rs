@@ -0,0 +1,143 @@
+use anyhow::Context;
+use std::collections::HashSet;
+use std::io::Write;
+
+use crate::*;
+
+/// Export packages as text in libsolv's testtags format. libsolv can read it
+/// into a pool, e.g. as the installed repo with `repo_add_testtags()` or in a
+/// `testsolv` testcase, to try out resolving updates against the installed
+/// set offline. This crate doesn't link to libsolv itself.
+///
+/// Packages are written sorted by NEVRA with their Requires, Provides,
+/// Obsoletes, Conflicts, Recommends and Suggests, and their build time.
+/// Since testtags carry no file lists, the files of a package which other
+/// packages require by path, including within rich dependencies, are written
+/// as provides, like libsolv adds them when reading the rpmdb. Requires on features of rpm itself (`rpmlib(...)`)
+/// are left out, as nothing in the pool provides them.
+pub fn write_solv_testtags<W: Write>(packages: &Packages, writer: W) -> Result<()> {
+    Ok(write_testtags(packages, writer).context("writing libsolv testtags")?)
+}
+
+fn write_testtags<W: Write>(packages: &Packages, mut w: W) -> anyhow::Result<()> {
+    let file_requires: HashSet<&str> = (packages.values())
+        .flat_map(|pkg| &pkg.requires)
+        .flat_map(|dep| deps::capabilities(&dep.name))
+        .filter(|name| name.starts_with('/'))
+        .collect();
+    let mut sorted: Vec<&Package> = packages.values().collect();
+    sorted.sort_by_cached_key(|pkg| pkg.nevra());
+
+    writeln!(w, "=Ver: 3.0")?;
+    for pkg in sorted {
+        let version = match pkg.epoch {
+            Some(epoch) => format!("{epoch}:{}", pkg.version),
+            None => pkg.version.clone(),
+        };
+        writeln!(
+            w,
+            "=Pkg: {} {version} {} {}",
+            pkg.name, pkg.release, pkg.arch
+        )?;
+        let requires = pkg.requires.iter().filter(|dep| !dep.flags.is_rpmlib());
+        write_deps(&mut w, "Req", requires.map(ToString::to_string))?;
+        let file_provides = (pkg.files.keys())
+            .filter(|path| file_requires.contains(path.as_str()))
+            .map(ToString::to_string);
+        let provides = pkg.provides.iter().map(ToString::to_string);
+        write_deps(&mut w, "Prv", provides.chain(file_provides))?;
+        for (tag, deps) in [
+            ("Obs", &pkg.obsoletes),
+            ("Con", &pkg.conflicts),
+            ("Rec", &pkg.recommends),
+            ("Sug", &pkg.suggests),
+        ] {
+            write_deps(&mut w, tag, deps.iter().map(ToString::to_string))?;
+        }
+        writeln!(w, "=Tim: {}", pkg.buildtime)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Write a block of dependencies, if there are any.
+fn write_deps<W: Write>(
+    w: &mut W,
+    tag: &str,
+    deps: impl Iterator<Item = String>,
+) -> std::io::Result<()> {
+    let mut deps = deps.peekable();
+    if deps.peek().is_none() {
+        return Ok(());
+    }
+    writeln!(w, "+{tag}:")?;
+    for dep in deps {
+        writeln!(w, "{dep}")?;
+    }
+    writeln!(w, "-{tag}:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FileInfoBuilder, PackageBuilder, packages};
+
+    fn dep(name: &str, flags: u32, version: Option<&str>) -> Dependency {
+        Dependency {
+            name: name.into(),
+            flags: DependencyFlags::from_raw(flags),
+            version: version.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn test_write_solv_testtags() {
+        let ge = DependencyFlags::GREATER | DependencyFlags::EQUAL;
+        let packages = packages([
+            PackageBuilder::new("bash")
+                .version("5.3.0")
+                .release("2.fc43")
+                .file("/usr/bin/bash", FileInfoBuilder::new())
+                .file("/usr/bin/sh", FileInfoBuilder::new())
+                .file("/usr/bin/bashbug", FileInfoBuilder::new())
+                .provides([dep("bash", DependencyFlags::EQUAL, Some("5.3.0-2.fc43"))])
+                .requires([
+                    dep("glibc", ge, Some("2.42")),
+                    dep("rpmlib(PayloadIsZstd)", DependencyFlags::RPMLIB, None),
+                ])
+                .build(),
+            PackageBuilder::new("glibc")
+                .epoch(1)
+                .version("2.42")
+                .requires([
+                    dep("/usr/bin/sh", 0, None),
+                    dep("(/usr/bin/bashbug if bash)", 0, None),
+                ])
+                .build(),
+        ]);
+        let mut out = Vec::new();
+        write_solv_testtags(&packages, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "=Ver: 3.0\n",
+                "=Pkg: bash 5.3.0 2.fc43 x86_64\n",
+                "+Req:\n",
+                "glibc >= 2.42\n",
+                "-Req:\n",
+                "+Prv:\n",
+                "bash = 5.3.0-2.fc43\n",
+                "/usr/bin/bashbug\n",
+                "/usr/bin/sh\n",
+                "-Prv:\n",
+                "=Tim: 1700000000\n",
+                "=Pkg: glibc 1:2.42 1 x86_64\n",
+                "+Req:\n",
+                "/usr/bin/sh\n",
+                "(/usr/bin/bashbug if bash)\n",
+                "-Req:\n",
+                "=Tim: 1700000000\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use crate::*;
+
+/// Which files to consider stripped by [`strip_savings`], mirroring rpm's
+/// `tsflags=nodocs` and `%_install_langs`.
+#[derive(Debug, Clone, Default)]
+pub struct StripOptions {
+    docs: bool,
+    licenses: bool,
+    install_langs: Option<Vec<String>>,
+}
+
+impl StripOptions {
+    /// Create options stripping nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Strip `%doc` files, as with `--nodocs`.
+    pub fn docs(mut self, docs: bool) -> Self {
+        self.docs = docs;
+        self
+    }
+
+    /// Strip `%license` files. rpm itself always installs them, even with
+    /// `--nodocs`.
+    pub fn licenses(mut self, licenses: bool) -> Self {
+        self.licenses = licenses;
+        self
+    }
+
+    /// Strip `%lang` files not matching any of `langs`, as with
+    /// `%_install_langs` set to them joined by `:`. As in rpm, `all` keeps
+    /// every language, and a file language matches languages it's a prefix
+    /// of (e.g. `de` matches `de_DE`).
+    pub fn install_langs<I, S>(mut self, langs: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.install_langs = Some(langs.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Whether a file for the `|`-separated languages `lang` is stripped.
+    fn strips_lang(&self, lang: &str) -> bool {
+        let Some(langs) = &self.install_langs else {
+            return false;
+        };
+        !lang
+            .split('|')
+            .any(|file_lang| (langs.iter()).any(|l| l == "all" || l.starts_with(file_lang)))
+    }
+}
+
+/// Bytes saved by stripping files, by reason. A file stripped for several
+/// reasons counts once, for the first of docs, licenses and languages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StripSavings {
+    /// `%doc` files.
+    pub docs: u64,
+    /// `%license` files.
+    pub licenses: u64,
+    /// `%lang` files for other languages.
+    pub langs: u64,
+}
+
+impl StripSavings {
+    /// Get the total bytes saved.
+    pub fn total(&self) -> u64 {
+        self.docs + self.licenses + self.langs
+    }
+}
+
+impl std::ops::AddAssign for StripSavings {
+    fn add_assign(&mut self, other: Self) {
+        self.docs += other.docs;
+        self.licenses += other.licenses;
+        self.langs += other.langs;
+    }
+}
+
+/// Bytes saved by stripping files from installed packages. See
+/// [`strip_savings`].
+#[derive(Debug, Clone, Default)]
+pub struct StripReport<'a> {
+    /// Savings of each package, largest first. Packages saving nothing are
+    /// left out.
+    pub packages: Vec<(&'a Package, StripSavings)>,
+    /// Savings over all packages.
+    pub total: StripSavings,
+}
+
+/// Estimate how many bytes stripping files as set in `options` would save,
+/// e.g. to decide on rpm's `tsflags` and `%_install_langs` for a minimal
+/// image. Only regular files count; files which weren't installed (see
+/// [`FileState`]) are already saved, and ghost files were never in the
+/// package.
+///
+/// Languages are only known for packages loaded from output of format
+/// version 5 or later (see [`FileInfo::lang`]).
+pub fn strip_savings<'a>(packages: &'a Packages, options: &StripOptions) -> StripReport<'a> {
+    let mut report = StripReport::default();
+    for pkg in packages.values() {
+        let mut savings = StripSavings::default();
+        for info in pkg.files.values() {
+            if !info.is_file()
+                || info.flags.is_ghost()
+                || !info.state.is_none_or(|s| s == FileState::Normal)
+            {
+                continue;
+            }
+            if options.docs && info.flags.is_doc() {
+                savings.docs += info.size;
+            } else if options.licenses && info.flags.is_license() {
+                savings.licenses += info.size;
+            } else if info.lang.as_deref().is_some_and(|l| options.strips_lang(l)) {
+                savings.langs += info.size;
+            }
+        }
+        if savings.total() > 0 {
+            report.packages.push((pkg, savings));
+            report.total += savings;
+        }
+    }
+    report
+        .packages
+        .sort_by(|a, b| (b.1.total().cmp(&a.1.total())).then_with(|| a.0.name.cmp(&b.0.name)));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FileInfoBuilder, PackageBuilder, packages};
+
+    fn file(size: u64, flags: u32) -> FileInfoBuilder {
+        FileInfoBuilder::new().size(size).flags(flags)
+    }
+
+    #[test]
+    fn test_strip_savings() {
+        let excluded = file(1000, FileFlags::DOC).state(Some(FileState::NotInstalled));
+        let packages = packages([
+            PackageBuilder::new("foo")
+                .file("/usr/bin/foo", file(5000, 0))
+                .file("/usr/share/doc/foo/README", file(100, FileFlags::DOC))
+                .file(
+                    "/usr/share/doc/foo/ghost",
+                    file(1000, FileFlags::DOC | FileFlags::GHOST),
+                )
+                .file("/usr/share/doc/foo/excluded", excluded)
+                .file(
+                    "/usr/share/licenses/foo/COPYING",
+                    file(10, FileFlags::LICENSE),
+                )
+                .file(
+                    "/usr/share/locale/de/LC_MESSAGES/foo.mo",
+                    file(20, 0).lang("de"),
+                )
+                .file(
+                    "/usr/share/locale/fr/LC_MESSAGES/foo.mo",
+                    file(30, 0).lang("fr"),
+                )
+                .file(
+                    "/usr/share/man/fr/man1/foo.1",
+                    file(40, FileFlags::DOC).lang("fr"),
+                )
+                .build(),
+            PackageBuilder::new("bar")
+                .file("/usr/share/locale/fr/bar.mo", file(50, 0).lang("fr|de"))
+                .build(),
+            PackageBuilder::new("baz")
+                .file("/usr/bin/baz", file(7, 0))
+                .build(),
+        ]);
+
+        let report = strip_savings(&packages, &StripOptions::new());
+        assert!(report.packages.is_empty());
+        assert_eq!(report.total.total(), 0);
+
+        let options = StripOptions::new().docs(true).install_langs(["de_DE"]);
+        let report = strip_savings(&packages, &options);
+        assert_eq!(report.packages.len(), 1);
+        assert_eq!(report.packages[0].0.name, "foo");
+        let expected = StripSavings {
+            docs: 140,
+            licenses: 0,
+            langs: 30,
+        };
+        assert_eq!(report.packages[0].1, expected);
+        assert_eq!(report.total, expected);
+
+        let options = StripOptions::new().licenses(true).install_langs(["en"]);
+        let report = strip_savings(&packages, &options);
+        let names: Vec<&str> = (report.packages.iter())
+            .map(|(pkg, _)| pkg.name.as_str())
+            .collect();
+        assert_eq!(names, ["foo", "bar"]);
+        assert_eq!(
+            report.total,
+            StripSavings {
+                docs: 0,
+                licenses: 10,
+                langs: 140,
+            }
+        );
+
+        let options = StripOptions::new().install_langs(["all"]);
+        assert!(strip_savings(&packages, &options).packages.is_empty());
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::Context;
+use camino::{Utf8Path, Utf8PathBuf};
+use std::collections::{HashMap, HashSet};
+
+use crate::*;
+
+/// Directories searched for system extensions, by decreasing precedence, as
+/// in systemd-sysext(8).
+const SYSEXT_DIRS: &[&str] = &[
+    "etc/extensions",
+    "run/extensions",
+    "var/lib/extensions",
+    "usr/lib/extensions",
+];
+
+/// A directory-based systemd system extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    /// Extension name.
+    pub name: String,
+    /// Path to the extension's root directory.
+    pub path: Utf8PathBuf,
+}
+
+/// Packages of the base OS merged with those of its system extensions. See
+/// [`load_with_sysexts`].
+#[derive(Debug, Clone, Default)]
+pub struct MergedPackages {
+    /// All packages, keyed as in the individual snapshots.
+    pub packages: Packages,
+    /// Name of the extension each package comes from, by package key.
+    /// Packages of the base OS aren't listed.
+    pub origins: HashMap<String, String>,
+}
+
+/// Find the directory-based system extensions of `rootfs`.
+///
+/// Like systemd-sysext, an extension is only recognized if it has an
+/// extension-release file matching its name, and an extension shadows those
+/// with the same name in lower precedence directories. Disk image extensions
+/// (`*.raw`) need to be mounted first and are skipped.
+pub fn find_sysexts(rootfs: &Utf8Path) -> Result<Vec<Extension>> {
+    let mut seen = HashSet::new();
+    let mut extensions = Vec::new();
+    for dir in SYSEXT_DIRS {
+        let dir = rootfs.join(dir);
+        let entries = match dir.read_dir_utf8() {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            r => r.with_context(|| format!("reading {dir}"))?,
+        };
+        let mut found = Vec::new();
+        for entry in entries {
+            let entry = entry.with_context(|| format!("reading {dir}"))?;
+            let name = entry.file_name();
+            let release = format!("usr/lib/extension-release.d/extension-release.{name}");
+            if entry.path().is_dir() && entry.path().join(release).is_file() {
+                found.push(Extension {
+                    name: name.to_string(),
+                    path: entry.into_path(),
+                });
+            }
+        }
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+        extensions.extend(
+            found
+                .into_iter()
+                .filter(|ext| seen.insert(ext.name.clone())),
+        );
+    }
+    Ok(extensions)
+}
+
+/// Load the installed packages of `rootfs` together with those of its system
+/// extensions (see [`find_sysexts`]) which have an rpmdb, as they appear once
+/// the extensions are merged.
+///
+/// Packages of an extension are added over those of the base OS, and tagged
+/// with the extension's name, unless the base OS has the same package
+/// already (e.g. because the extension's rpmdb was seeded with the base OS's).
+pub fn load_with_sysexts(rootfs: &Utf8Path) -> Result<MergedPackages> {
+    let base = load_from_rootfs(rootfs)?;
+    let mut extensions = Vec::new();
+    for ext in find_sysexts(rootfs)? {
+        if find_dbpath(ext.path.as_std_path())?.is_none() {
+            continue;
+        }
+        let packages = load_from_rootfs(&ext.path)
+            .map_err(anyhow::Error::from)
+            .with_context(|| format!("loading extension '{}'", ext.name))?;
+        extensions.push((ext.name, packages));
+    }
+    Ok(merge(base, extensions))
+}
+
+/// Merge extension snapshots over a base one. Extensions are given by
+/// decreasing precedence.
+fn merge(base: Packages, extensions: Vec<(String, Packages)>) -> MergedPackages {
+    let mut merged = MergedPackages {
+        packages: base,
+        origins: HashMap::new(),
+    };
+    // Apply the lowest precedence extension first so higher ones win.
+    for (name, packages) in extensions.into_iter().rev() {
+        for (key, pkg) in packages {
+            let from_base = !merged.origins.contains_key(&key);
+            if from_base
+                && merged
+                    .packages
+                    .get(&key)
+                    .is_some_and(|prev| prev.nevra() == pkg.nevra())
+            {
+                continue;
+            }
+            merged.origins.insert(key.clone(), name.clone());
+            merged.packages.insert(key, pkg);
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/fedora.qf");
+
+    fn add_extension(rootfs: &Utf8Path, dir: &str, name: &str, rpmdb: bool) -> Utf8PathBuf {
+        let path = rootfs.join(dir).join(name);
+        let release = path.join("usr/lib/extension-release.d");
+        std::fs::create_dir_all(&release).unwrap();
+        std::fs::write(
+            release.join(format!("extension-release.{name}")),
+            "ID=_any\n",
+        )
+        .unwrap();
+        if rpmdb {
+            let db = path.join("usr/lib/sysimage/rpm");
+            std::fs::create_dir_all(&db).unwrap();
+            std::fs::copy("tests/fixtures/rpmdb.sqlite", db.join("rpmdb.sqlite")).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn test_find_sysexts() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).unwrap();
+        assert!(find_sysexts(rootfs).unwrap().is_empty());
+
+        let etc_foo = add_extension(rootfs, "etc/extensions", "foo", false);
+        add_extension(rootfs, "var/lib/extensions", "foo", false);
+        let var_bar = add_extension(rootfs, "var/lib/extensions", "bar", false);
+        // Not an extension without its release file.
+        std::fs::create_dir_all(rootfs.join("run/extensions/baz")).unwrap();
+        std::fs::write(rootfs.join("var/lib/extensions/qux.raw"), "").unwrap();
+
+        let extensions = find_sysexts(rootfs).unwrap();
+        let found: Vec<(&str, &Utf8Path)> = extensions
+            .iter()
+            .map(|ext| (ext.name.as_str(), ext.path.as_path()))
+            .collect();
+        assert_eq!(
+            found,
+            [("foo", etc_foo.as_path()), ("bar", var_bar.as_path())]
+        );
+    }
+
+    #[test]
+    fn test_load_with_sysexts() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).unwrap();
+        let db = rootfs.join("usr/lib/sysimage/rpm");
+        std::fs::create_dir_all(&db).unwrap();
+        std::fs::copy("tests/fixtures/rpmdb.sqlite", db.join("rpmdb.sqlite")).unwrap();
+        add_extension(rootfs, "run/extensions", "tools", true);
+        add_extension(rootfs, "run/extensions", "empty", false);
+
+        let merged = load_with_sysexts(rootfs).unwrap();
+        assert_eq!(
+            merged.packages.len(),
+            load_from_rootfs(rootfs).unwrap().len()
+        );
+        // The extension's packages are all the same as the base's.
+        assert!(merged.origins.is_empty());
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = load_from_str(FIXTURE).unwrap();
+        let mut ext = Packages::default();
+        let mut bash = base["bash"].clone();
+        bash.release = "3.fc43".into();
+        ext.insert("bash".into(), bash);
+        ext.insert("rpm".into(), base["rpm"].clone());
+        let mut tool = base["setup"].clone();
+        tool.name = "tool".into();
+        ext.insert("tool".into(), tool.clone());
+        let mut other = Packages::default();
+        other.insert("tool".into(), tool);
+
+        let nbase = base.len();
+        let merged = merge(base, vec![("high".into(), ext), ("low".into(), other)]);
+        assert_eq!(merged.packages.len(), nbase + 1);
+        assert_eq!(merged.packages["bash"].release, "3.fc43");
+        assert_eq!(merged.origins["bash"], "high");
+        assert_eq!(merged.origins["tool"], "high");
+        assert!(!merged.origins.contains_key("rpm"));
+    }
+}
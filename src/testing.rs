@@ -0,0 +1,401 @@
+//! Builders for synthetic packages, for unit tests of code consuming
+//! [`Packages`].
+//!
+//! ```
+//! use rpm_qa::testing::{FileInfoBuilder, PackageBuilder, packages};
+//!
+//! let packages = packages([
+//!     PackageBuilder::new("bash")
+//!         .version("5.3.0")
+//!         .file("/usr/bin/bash", FileInfoBuilder::new().mode(0o755).size(1024))
+//!         .symlink("/usr/bin/sh", "bash")
+//!         .build(),
+//!     PackageBuilder::new("filesystem").dir("/usr/bin").build(),
+//! ]);
+//! assert_eq!(packages["bash"].nevra(), "bash-5.3.0-1.x86_64");
+//! ```
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::*;
+
+/// Time used for build, install and modification times unless set.
+const DEFAULT_TIME: u64 = 1_700_000_000;
+
+/// Collect packages into a [`Packages`] map keyed by name.
+pub fn packages(packages: impl IntoIterator<Item = Package>) -> Packages {
+    packages
+        .into_iter()
+        .map(|pkg| (pkg.name.clone(), pkg))
+        .collect()
+}
+
+/// Builder of a [`Package`] with realistic defaults.
+///
+/// Unless set, a package is version `1.0`, release `1`, `x86_64` and
+/// `MIT`-licensed, built from `name-version-release.src.rpm` and unsigned, and
+/// its size is the total size of its files.
+#[derive(Debug, Clone)]
+pub struct PackageBuilder {
+    package: Package,
+    size: Option<u64>,
+    sourcerpm: Option<Option<String>>,
+}
+
+impl PackageBuilder {
+    /// Start building a package named `name`.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            package: Package {
+                name: name.into(),
+                version: "1.0".into(),
+                release: "1".into(),
+                epoch: None,
+                arch: "x86_64".into(),
+                license: "MIT".into(),
+                size: 0,
+                buildtime: DEFAULT_TIME,
+                installtime: DEFAULT_TIME,
+                installtid: Some(DEFAULT_TIME),
+                sourcerpm: None,
+                digest_algo: Some(DigestAlgorithm::Sha256),
+                signing_key: None,
+                changelog: Vec::new(),
+                requires: Vec::new(),
+                provides: Vec::new(),
+                recommends: Vec::new(),
+                suggests: Vec::new(),
+                obsoletes: Vec::new(),
+                conflicts: Vec::new(),
+                pre: None,
+                post: None,
+                preun: None,
+                postun: None,
+                triggers: Vec::new(),
+                files: Files::new(),
+                unparsed_files: None,
+            },
+            size: None,
+            sourcerpm: None,
+        }
+    }
+
+    /// Set the version.
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.package.version = version.into();
+        self
+    }
+
+    /// Set the release.
+    pub fn release(mut self, release: impl Into<String>) -> Self {
+        self.package.release = release.into();
+        self
+    }
+
+    /// Set the epoch.
+    pub fn epoch(mut self, epoch: u32) -> Self {
+        self.package.epoch = Some(epoch);
+        self
+    }
+
+    /// Set the architecture.
+    pub fn arch(mut self, arch: impl Into<String>) -> Self {
+        self.package.arch = arch.into();
+        self
+    }
+
+    /// Set the license.
+    pub fn license(mut self, license: impl Into<String>) -> Self {
+        self.package.license = license.into();
+        self
+    }
+
+    /// Set the installed size, instead of the total size of the files.
+    pub fn size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Set the build time.
+    pub fn buildtime(mut self, time: u64) -> Self {
+        self.package.buildtime = time;
+        self
+    }
+
+    /// Set the install time and install transaction ID.
+    pub fn installtime(mut self, time: u64) -> Self {
+        self.package.installtime = time;
+        self.package.installtid = Some(time);
+        self
+    }
+
+    /// Set the install transaction ID, e.g. to group packages installed
+    /// together.
+    pub fn installtid(mut self, tid: Option<u64>) -> Self {
+        self.package.installtid = tid;
+        self
+    }
+
+    /// Set the source rpm file name.
+    pub fn sourcerpm(mut self, sourcerpm: Option<String>) -> Self {
+        self.sourcerpm = Some(sourcerpm);
+        self
+    }
+
+    /// Set the file digest algorithm.
+    pub fn digest_algo(mut self, algo: Option<DigestAlgorithm>) -> Self {
+        self.package.digest_algo = algo;
+        self
+    }
+
+    /// Set the ID of the key the package is signed with.
+    pub fn signing_key(mut self, key_id: Option<String>) -> Self {
+        self.package.signing_key = key_id;
+        self
+    }
+
+    /// Set the changelog entries, most recent first.
+    pub fn changelog(mut self, entries: impl IntoIterator<Item = ChangelogEntry>) -> Self {
+        self.package.changelog = entries.into_iter().collect();
+        self
+    }
+
+    /// Set the changelog to entries at the given times, most recent first,
+    /// without authors or texts.
+    pub fn changelog_times(self, times: impl IntoIterator<Item = u64>) -> Self {
+        self.changelog(times.into_iter().map(|time| ChangelogEntry {
+            time,
+            author: String::new(),
+            text: String::new(),
+        }))
+    }
+
+    /// Set the capabilities the package requires.
+    pub fn requires(mut self, deps: impl IntoIterator<Item = Dependency>) -> Self {
+        self.package.requires = deps.into_iter().collect();
+        self
+    }
+
+    /// Set the capabilities the package provides.
+    pub fn provides(mut self, deps: impl IntoIterator<Item = Dependency>) -> Self {
+        self.package.provides = deps.into_iter().collect();
+        self
+    }
+
+    /// Add a file. Paths are normalized as when parsing.
+    pub fn file(mut self, path: impl AsRef<Utf8Path>, file: FileInfoBuilder) -> Self {
+        let path = parse::normalize_path(path.as_ref().as_str()).into_owned();
+        self.package.files.insert(path.into(), file.build());
+        self
+    }
+
+    /// Add a directory with the default attributes.
+    pub fn dir(self, path: impl AsRef<Utf8Path>) -> Self {
+        self.file(path, FileInfoBuilder::dir())
+    }
+
+    /// Add a symlink to `target` with the default attributes.
+    pub fn symlink(self, path: impl AsRef<Utf8Path>, target: impl Into<Utf8PathBuf>) -> Self {
+        self.file(path, FileInfoBuilder::symlink(target))
+    }
+
+    /// Build the package.
+    pub fn build(self) -> Package {
+        let mut package = self.package;
+        package.size = self
+            .size
+            .unwrap_or_else(|| package.files.values().map(|file| file.size).sum());
+        package.sourcerpm = self.sourcerpm.unwrap_or_else(|| {
+            Some(format!(
+                "{}-{}-{}.src.rpm",
+                package.name, package.version, package.release
+            ))
+        });
+        package
+    }
+}
+
+/// Builder of a [`FileInfo`] with realistic defaults.
+///
+/// Unless set, a file is owned by `root:root`, installed normally and has no
+/// digest. [`FileInfoBuilder::new`] starts from an empty regular file with
+/// mode `0644`.
+#[derive(Debug, Clone)]
+pub struct FileInfoBuilder {
+    file: FileInfo,
+}
+
+impl Default for FileInfoBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileInfoBuilder {
+    /// Start building a regular file.
+    pub fn new() -> Self {
+        Self::with_mode(FileInfo::S_IFREG | 0o644)
+    }
+
+    /// Start building a directory, with mode `0755`.
+    pub fn dir() -> Self {
+        Self::with_mode(FileInfo::S_IFDIR | 0o755)
+    }
+
+    /// Start building a symlink to `target`.
+    pub fn symlink(target: impl Into<Utf8PathBuf>) -> Self {
+        let target = target.into();
+        let mut builder = Self::with_mode(FileInfo::S_IFLNK | 0o777);
+        builder.file.size = target.as_str().len() as u64;
+        builder.file.linkto = Some(target);
+        builder
+    }
+
+    fn with_mode(mode: u16) -> Self {
+        Self {
+            file: FileInfo {
+                size: 0,
+                mode,
+                mtime: DEFAULT_TIME,
+                digest: None,
+                flags: FileFlags::default(),
+                user: "root".into(),
+                group: "root".into(),
+                linkto: None,
+                state: Some(FileState::Normal),
+                lang: None,
+                color: Some(0),
+                caps: None,
+            },
+        }
+    }
+
+    /// Set the size.
+    pub fn size(mut self, size: u64) -> Self {
+        self.file.size = size;
+        self
+    }
+
+    /// Set the permission bits, keeping the file type.
+    pub fn mode(mut self, mode: u16) -> Self {
+        self.file.mode = self.file.file_type() | (mode & !FileInfo::S_IFMT);
+        self
+    }
+
+    /// Set the modification time.
+    pub fn mtime(mut self, mtime: u64) -> Self {
+        self.file.mtime = mtime;
+        self
+    }
+
+    /// Set the digest.
+    pub fn digest(mut self, digest: FileDigest) -> Self {
+        self.file.digest = Some(digest);
+        self
+    }
+
+    /// Set the attribute flags, e.g. [`FileFlags::CONFIG`].
+    pub fn flags(mut self, flags: u32) -> Self {
+        self.file.flags = FileFlags::from_raw(flags);
+        self
+    }
+
+    /// Set the owner.
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.file.user = user.into();
+        self
+    }
+
+    /// Set the group.
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        self.file.group = group.into();
+        self
+    }
+
+    /// Set the install state.
+    pub fn state(mut self, state: Option<FileState>) -> Self {
+        self.file.state = state;
+        self
+    }
+
+    /// Set the languages the file is for, as with `%lang`.
+    pub fn lang(mut self, lang: impl Into<String>) -> Self {
+        self.file.lang = Some(lang.into());
+        self
+    }
+
+    /// Set the color, i.e. the bitness of ELF files (1 for 32-bit, 2 for
+    /// 64-bit), or 0 for other files.
+    pub fn color(mut self, color: u32) -> Self {
+        self.file.color = Some(color);
+        self
+    }
+
+    /// Set the capabilities of the file, as with `%caps`.
+    pub fn caps(mut self, caps: impl Into<String>) -> Self {
+        self.file.caps = Some(caps.into());
+        self
+    }
+
+    /// Build the file.
+    pub fn build(self) -> FileInfo {
+        self.file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builders() {
+        let bash = PackageBuilder::new("bash")
+            .version("5.3.0")
+            .release("2.fc43")
+            .file(
+                "/usr/bin//bash",
+                FileInfoBuilder::new()
+                    .mode(0o755)
+                    .size(1024)
+                    .digest(FileDigest::from_hex("abcd").unwrap()),
+            )
+            .symlink("/usr/bin/sh", "bash")
+            .file(
+                "/etc/bashrc",
+                FileInfoBuilder::new().flags(FileFlags::CONFIG),
+            )
+            .build();
+        assert_eq!(bash.nevra(), "bash-5.3.0-2.fc43.x86_64");
+        assert_eq!(bash.sourcerpm.as_deref(), Some("bash-5.3.0-2.fc43.src.rpm"));
+        assert_eq!(bash.size, 1024 + 4);
+        let file = bash.file(Utf8Path::new("/usr/bin/bash")).unwrap();
+        assert_eq!(file.mode, 0o100755);
+        assert_eq!(file.digest.unwrap().to_hex(), "abcd");
+        let sh = bash.file(Utf8Path::new("/usr/bin/sh")).unwrap();
+        assert_eq!(sh.mode, 0o120777);
+        assert_eq!(sh.linkto.as_deref(), Some(Utf8Path::new("bash")));
+        assert!(
+            bash.file(Utf8Path::new("/etc/bashrc"))
+                .unwrap()
+                .flags
+                .is_config()
+        );
+
+        let packages = packages([
+            bash,
+            PackageBuilder::new("filesystem")
+                .arch("noarch")
+                .epoch(1)
+                .size(7)
+                .dir("/usr/bin")
+                .build(),
+        ]);
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages["filesystem"].nevra(), "filesystem-1:1.0-1.noarch");
+        assert_eq!(packages["filesystem"].size, 7);
+        assert_eq!(
+            packages["filesystem"].files[Utf8Path::new("/usr/bin")].mode,
+            0o40755
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::Context;
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::*;
+
+/// Suffixes rpm gives to config files it leaves beside a package's own: the
+/// new version of a modified `%config(noreplace)` file, or the modified file
+/// set aside when replacing or erasing a `%config` one.
+const CONFIG_BACKUP_SUFFIXES: &[&str] = &[".rpmnew", ".rpmsave", ".rpmorig"];
+
+/// Options for [`find_unowned`].
+#[derive(Debug, Clone)]
+pub struct UnownedOptions {
+    excludes: Vec<Utf8PathBuf>,
+    config_backups: bool,
+}
+
+impl Default for UnownedOptions {
+    fn default() -> Self {
+        Self {
+            excludes: ["/dev", "/proc", "/run", "/sys", "/tmp"]
+                .into_iter()
+                .map(Utf8PathBuf::from)
+                .collect(),
+            config_backups: true,
+        }
+    }
+}
+
+impl UnownedOptions {
+    /// Create the default options: skip the API filesystems and `/tmp`, and
+    /// count config files rpm left beside owned ones as owned.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Skip `prefix` and everything under it, e.g. `/var` to only check
+    /// static content. Prefixes match whole path components.
+    pub fn exclude(mut self, prefix: impl AsRef<Utf8Path>) -> Self {
+        let prefix = parse::normalize_path(prefix.as_ref().as_str());
+        self.excludes.push(Utf8PathBuf::from(&*prefix));
+        self
+    }
+
+    /// Don't skip anything, including the default exclusions.
+    pub fn clear_excludes(mut self) -> Self {
+        self.excludes.clear();
+        self
+    }
+
+    /// Set whether `.rpmnew`, `.rpmsave` and `.rpmorig` files beside config
+    /// files of packages count as owned. rpm creates these when a config
+    /// file was modified locally, e.g. for `%config(noreplace)` files in
+    /// `/etc`.
+    pub fn config_backups(mut self, owned: bool) -> Self {
+        self.config_backups = owned;
+        self
+    }
+}
+
+/// Scan `rootfs` for paths which no package owns, e.g. to check that an
+/// image only has rpm content.
+///
+/// Returns paths as seen from inside the rootfs, sorted. An unowned directory
+/// with nothing owned under it is reported by itself rather than along with
+/// everything under it; one which only exists as the parent of owned paths
+/// isn't reported. Symlinks aren't followed. Deferred file lists (see
+/// [`ParseOptions::lazy_files`]) must be parsed first.
+pub fn find_unowned(
+    rootfs: &Utf8Path,
+    packages: &Packages,
+    options: &UnownedOptions,
+) -> Result<Vec<Utf8PathBuf>> {
+    let scan = Scan {
+        rootfs,
+        index: FileIndex::new(packages),
+        options,
+    };
+    let mut unowned = Vec::new();
+    scan.walk(Utf8Path::new("/"), &mut unowned)
+        .with_context(|| format!("scanning {rootfs}"))?;
+    Ok(unowned)
+}
+
+struct Scan<'a> {
+    rootfs: &'a Utf8Path,
+    index: FileIndex<'a>,
+    options: &'a UnownedOptions,
+}
+
+impl Scan<'_> {
+    /// Collect the unowned paths under `dir`, in order.
+    fn walk(&self, dir: &Utf8Path, unowned: &mut Vec<Utf8PathBuf>) -> anyhow::Result<()> {
+        let on_disk = self.rootfs.join(dir.strip_prefix("/").unwrap_or(dir));
+        let mut entries = Vec::new();
+        for entry in on_disk
+            .read_dir_utf8()
+            .with_context(|| format!("reading {on_disk}"))?
+        {
+            let entry = entry.with_context(|| format!("reading {on_disk}"))?;
+            let is_dir = entry.file_type()?.is_dir();
+            entries.push((dir.join(entry.file_name()), is_dir));
+        }
+        entries.sort();
+        for (path, is_dir) in entries {
+            if self.options.excludes.iter().any(|ex| path.starts_with(ex)) {
+                continue;
+            }
+            if self.is_owned(&path) {
+                if is_dir {
+                    self.walk(&path, unowned)?;
+                }
+            } else if is_dir && self.index.files_under(&path).next().is_some() {
+                self.walk(&path, unowned)?;
+            } else {
+                unowned.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_owned(&self, path: &Utf8Path) -> bool {
+        if !self.index.owners_of(path).is_empty() {
+            return true;
+        }
+        self.options.config_backups
+            && CONFIG_BACKUP_SUFFIXES.iter().any(|suffix| {
+                path.as_str().strip_suffix(suffix).is_some_and(|config| {
+                    let config = Utf8Path::new(config);
+                    (self.index.owners_of(config).iter())
+                        .any(|pkg| pkg.files[config].flags.is_config())
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_unowned() {
+        let packages = load_from_str(include_str!("../tests/fixtures/fedora.qf")).unwrap();
+        let tmpdir = tempfile::tempdir().unwrap();
+        let rootfs = Utf8Path::from_path(tmpdir.path()).unwrap();
+        for path in [
+            "etc/skel/.bashrc",
+            "etc/skel/.bashrc.rpmnew",
+            "etc/skel/notes.txt",
+            "usr/bin/bash",
+            "usr/bin/stray",
+            "opt/app/bin/app",
+            "tmp/scratch",
+            "var/cache/foo",
+        ] {
+            let path = rootfs.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        let unowned = find_unowned(rootfs, &packages, &UnownedOptions::new()).unwrap();
+        assert_eq!(
+            unowned,
+            [
+                "/etc/skel/notes.txt",
+                "/opt",
+                "/usr/bin/stray",
+                "/var/cache/foo"
+            ]
+        );
+
+        let options = UnownedOptions::new().exclude("/var/").config_backups(false);
+        let unowned = find_unowned(rootfs, &packages, &options).unwrap();
+        assert_eq!(
+            unowned,
+            [
+                "/etc/skel/.bashrc.rpmnew",
+                "/etc/skel/notes.txt",
+                "/opt",
+                "/usr/bin/stray"
+            ]
+        );
+
+        let options = UnownedOptions::new().clear_excludes();
+        let unowned = find_unowned(rootfs, &packages, &options).unwrap();
+        assert!(unowned.contains(&"/tmp".into()));
+    }
+}
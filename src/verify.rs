@@ -0,0 +1,405 @@
+use anyhow::Context;
+use camino::Utf8Path;
+use digest::Digest;
+use std::fs::File;
+use std::io::Read;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use crate::observer::notify;
+use crate::*;
+
+/// Options for [`verify_files`].
+#[derive(Debug, Clone)]
+pub struct VerifyOptions {
+    threads: usize,
+    buffer_size: usize,
+    digest_policy: DigestPolicy,
+    drop_cache: bool,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        Self {
+            threads: std::thread::available_parallelism().map_or(4, |n| n.get()),
+            buffer_size: 1 << 20,
+            digest_policy: DigestPolicy::Allow,
+            drop_cache: false,
+        }
+    }
+}
+
+impl VerifyOptions {
+    /// Create the default options: a worker per CPU, each with a 1 MiB
+    /// buffer, accepting all digest algorithms.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the number of worker threads hashing files. Fewer threads than
+    /// CPUs may be enough to saturate a slow disk.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Set the size of the buffer each worker reads files with. Larger
+    /// buffers mean fewer reads.
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size.max(4096);
+        self
+    }
+
+    /// Set what to do about packages whose digests aren't approved for
+    /// FIPS. Their files are still verified when flagged, and nothing is
+    /// when they're rejected.
+    pub fn digest_policy(mut self, policy: DigestPolicy) -> Self {
+        self.digest_policy = policy;
+        self
+    }
+
+    /// Drop files from the page cache once hashed, so that verifying a large
+    /// install doesn't evict everything else. This also drops pages which
+    /// were cached before, e.g. of binaries in use, so it's off by default.
+    pub fn drop_cache(mut self, drop: bool) -> Self {
+        self.drop_cache = drop;
+        self
+    }
+}
+
+/// How a file on disk differs from the rpmdb. See [`verify_files`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyProblem {
+    /// The file doesn't exist.
+    Missing,
+    /// The file isn't a regular file.
+    FileType,
+    /// The file's size differs.
+    Size {
+        /// Size in the rpmdb.
+        expected: u64,
+        /// Size on disk.
+        actual: u64,
+    },
+    /// The file's contents differ.
+    Digest,
+    /// The file's digest algorithm isn't supported, so its contents weren't
+    /// checked.
+    UnsupportedDigest(DigestAlgorithm),
+    /// The file couldn't be read.
+    Unreadable(String),
+}
+
+/// A file which failed verification.
+#[derive(Debug, Clone)]
+pub struct VerifyFailure<'a> {
+    /// The package owning the file.
+    pub package: &'a Package,
+    /// The file path.
+    pub path: &'a Utf8Path,
+    /// What's wrong with the file.
+    pub problem: VerifyProblem,
+}
+
+/// The result of [`verify_files`].
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport<'a> {
+    /// The files which failed verification, sorted by path, then package
+    /// name.
+    pub failures: Vec<VerifyFailure<'a>>,
+    /// Packages whose digests aren't approved for FIPS, if flagged by the
+    /// [`DigestPolicy`].
+    pub weak_digests: Vec<&'a Package>,
+    /// Number of files checked.
+    pub files: u64,
+    /// Number of bytes hashed.
+    pub bytes: u64,
+}
+
+/// A file to verify.
+struct Job<'a> {
+    package: &'a Package,
+    path: &'a Utf8Path,
+    info: &'a FileInfo,
+    digest: &'a FileDigest,
+}
+
+/// Check the contents of the regular files of `packages` under `rootfs`
+/// against the sizes and digests in the rpmdb, like the size and digest
+/// checks of `rpm -V`.
+///
+/// Files are hashed by a pool of worker threads, each reusing one buffer,
+/// largest files first so that workers finish together. Files are read
+/// with sequential access hints, and optionally dropped from the page cache
+/// once hashed (see [`VerifyOptions::drop_cache`]). Ghost files, files which weren't installed (see [`FileState`]) and files
+/// without a digest are skipped.
+pub fn verify_files<'a>(
+    packages: &'a Packages,
+    rootfs: &Utf8Path,
+    options: &VerifyOptions,
+) -> Result<VerifyReport<'a>> {
+    let started = Instant::now();
+    let weak_digests = options.digest_policy.check(packages)?;
+    let mut jobs: Vec<Job<'a>> = Vec::new();
+    for pkg in packages.values() {
+        for (path, info) in &pkg.files {
+            if !info.is_file()
+                || info.flags.is_ghost()
+                || !info.state.is_none_or(|s| s == FileState::Normal)
+            {
+                continue;
+            }
+            if let Some(digest) = &info.digest {
+                jobs.push(Job {
+                    package: pkg,
+                    path,
+                    info,
+                    digest,
+                });
+            }
+        }
+    }
+    jobs.sort_by_key(|job| std::cmp::Reverse(job.info.size));
+
+    let next = AtomicUsize::new(0);
+    let bytes = AtomicU64::new(0);
+    let failures = Mutex::new(Vec::new());
+    let threads = options.threads.min(jobs.len()).max(1);
+    std::thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                let mut buf = vec![0; options.buffer_size];
+                let mut local = Vec::new();
+                while let Some(job) = jobs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let on_disk = rootfs.join(job.path.strip_prefix("/").unwrap_or(job.path));
+                    let algo = fips::digest_algo(job.package);
+                    let hashed = verify_file(
+                        &on_disk,
+                        job.info.size,
+                        job.digest,
+                        algo,
+                        &mut buf,
+                        options.drop_cache,
+                    );
+                    match hashed {
+                        Ok((hashed, problem)) => {
+                            bytes.fetch_add(hashed, Ordering::Relaxed);
+                            if let Some(problem) = problem {
+                                local.push(VerifyFailure {
+                                    package: job.package,
+                                    path: job.path,
+                                    problem,
+                                });
+                            }
+                        }
+                        Err(e) => local.push(VerifyFailure {
+                            package: job.package,
+                            path: job.path,
+                            problem: VerifyProblem::Unreadable(format!("{e:#}")),
+                        }),
+                    }
+                }
+                failures.lock().unwrap().append(&mut local);
+            });
+        }
+    });
+
+    let mut failures = failures.into_inner().unwrap();
+    failures.sort_by(|a, b| (a.path, &a.package.name).cmp(&(b.path, &b.package.name)));
+    let report = VerifyReport {
+        failures,
+        weak_digests,
+        files: jobs.len() as u64,
+        bytes: bytes.into_inner(),
+    };
+    notify(|observer| {
+        observer.on_verify(&VerifyStats {
+            duration: started.elapsed(),
+            files: report.files,
+            bytes: report.bytes,
+            failures: report.failures.len(),
+        })
+    });
+    Ok(report)
+}
+
+/// Verify a single file, returning the number of bytes hashed and what's
+/// wrong with it, if anything.
+fn verify_file(
+    path: &Utf8Path,
+    size: u64,
+    digest: &FileDigest,
+    algo: DigestAlgorithm,
+    buf: &mut [u8],
+    drop_cache: bool,
+) -> anyhow::Result<(u64, Option<VerifyProblem>)> {
+    let meta = match path.symlink_metadata() {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok((0, Some(VerifyProblem::Missing)));
+        }
+        Err(e) => return Err(e).with_context(|| format!("reading {path}")),
+    };
+    if !meta.is_file() {
+        return Ok((0, Some(VerifyProblem::FileType)));
+    }
+    if meta.len() != size {
+        let problem = VerifyProblem::Size {
+            expected: size,
+            actual: meta.len(),
+        };
+        return Ok((0, Some(problem)));
+    }
+    let file = File::open(path).with_context(|| format!("opening {path}"))?;
+    let actual = match algo {
+        DigestAlgorithm::Md5 => hash::<md5::Md5>(&file, buf, drop_cache),
+        DigestAlgorithm::Sha1 => hash::<sha1::Sha1>(&file, buf, drop_cache),
+        DigestAlgorithm::Sha224 => hash::<sha2::Sha224>(&file, buf, drop_cache),
+        DigestAlgorithm::Sha256 => hash::<sha2::Sha256>(&file, buf, drop_cache),
+        DigestAlgorithm::Sha384 => hash::<sha2::Sha384>(&file, buf, drop_cache),
+        DigestAlgorithm::Sha512 => hash::<sha2::Sha512>(&file, buf, drop_cache),
+        _ => return Ok((0, Some(VerifyProblem::UnsupportedDigest(algo)))),
+    }
+    .with_context(|| format!("reading {path}"))?;
+    let problem = (actual != digest.as_bytes()).then_some(VerifyProblem::Digest);
+    Ok((size, problem))
+}
+
+/// Hash a whole file with `buf`, hinting the kernel to read ahead and, if
+/// `drop_cache`, to drop the file from the page cache afterwards.
+fn hash<D: Digest>(mut file: &File, buf: &mut [u8], drop_cache: bool) -> std::io::Result<Vec<u8>> {
+    use rustix::fs::{Advice, fadvise};
+    // The hints are only an optimization; not all filesystems support them.
+    let _ = fadvise(file, 0, None, Advice::Sequential);
+    let mut hasher = D::new();
+    loop {
+        let n = match file.read(buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buf[..n]);
+    }
+    if drop_cache {
+        let _ = fadvise(file, 0, None, Advice::DontNeed);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FileInfoBuilder, PackageBuilder, packages};
+
+    fn file(contents: &[u8], algo: DigestAlgorithm) -> FileInfoBuilder {
+        let digest = match algo {
+            DigestAlgorithm::Md5 => md5::Md5::digest(contents).to_vec(),
+            DigestAlgorithm::Sha256 => sha2::Sha256::digest(contents).to_vec(),
+            _ => vec![0; algo.digest_len()],
+        };
+        FileInfoBuilder::new()
+            .size(contents.len() as u64)
+            .digest(FileDigest::from_bytes(&digest).unwrap())
+    }
+
+    fn package(name: &str, algo: DigestAlgorithm, files: Vec<(&str, FileInfoBuilder)>) -> Package {
+        (files.into_iter())
+            .fold(PackageBuilder::new(name), |pkg, (path, file)| {
+                pkg.file(path, file)
+            })
+            .digest_algo(Some(algo))
+            .build()
+    }
+
+    #[test]
+    fn test_verify_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let rootfs = Utf8Path::from_path(tmp.path()).unwrap();
+        std::fs::create_dir_all(rootfs.join("usr/bin")).unwrap();
+        let big = vec![7u8; 100_000];
+        for (path, contents) in [
+            ("usr/bin/ok", &b"ok"[..]),
+            ("usr/bin/big", &big),
+            ("usr/bin/changed", b"changed"),
+            ("usr/bin/truncated", b"trunc"),
+            ("usr/bin/md5", b"md5"),
+            ("usr/bin/sha3", b"sha3"),
+        ] {
+            std::fs::write(rootfs.join(path), contents).unwrap();
+        }
+        std::fs::create_dir(rootfs.join("usr/bin/dir")).unwrap();
+        let ghost = file(b"ghost", DigestAlgorithm::Sha256).flags(FileFlags::GHOST);
+
+        let sha256 = DigestAlgorithm::Sha256;
+        let packages = packages([
+            package(
+                "foo",
+                sha256,
+                vec![
+                    ("/usr/bin/ok", file(b"ok", sha256)),
+                    ("/usr/bin/big", file(&big, sha256)),
+                    ("/usr/bin/changed", file(b"CHANGED", sha256)),
+                    ("/usr/bin/truncated", file(b"truncated", sha256)),
+                    ("/usr/bin/missing", file(b"missing", sha256)),
+                    ("/usr/bin/dir", file(b"dir", sha256)),
+                    ("/usr/bin/ghost", ghost),
+                ],
+            ),
+            package(
+                "old",
+                DigestAlgorithm::Md5,
+                vec![("/usr/bin/md5", file(b"md5", DigestAlgorithm::Md5))],
+            ),
+            package(
+                "new",
+                DigestAlgorithm::Sha3_256,
+                vec![("/usr/bin/sha3", file(b"sha3", DigestAlgorithm::Sha3_256))],
+            ),
+        ]);
+
+        for options in [
+            VerifyOptions::new(),
+            VerifyOptions::new().threads(1).buffer_size(1),
+            VerifyOptions::new().drop_cache(true),
+        ] {
+            let report = verify_files(&packages, rootfs, &options).unwrap();
+            let failures: Vec<_> = (report.failures.iter())
+                .map(|f| (f.path.as_str(), f.problem.clone()))
+                .collect();
+            assert_eq!(
+                failures,
+                [
+                    ("/usr/bin/changed", VerifyProblem::Digest),
+                    ("/usr/bin/dir", VerifyProblem::FileType),
+                    ("/usr/bin/missing", VerifyProblem::Missing),
+                    (
+                        "/usr/bin/sha3",
+                        VerifyProblem::UnsupportedDigest(DigestAlgorithm::Sha3_256)
+                    ),
+                    (
+                        "/usr/bin/truncated",
+                        VerifyProblem::Size {
+                            expected: 9,
+                            actual: 5
+                        }
+                    ),
+                ]
+            );
+            assert_eq!(report.files, 8);
+            assert_eq!(report.bytes, 100_000 + 2 + 7 + 3);
+            assert!(report.weak_digests.is_empty());
+        }
+
+        let options = VerifyOptions::new().digest_policy(DigestPolicy::Flag);
+        let report = verify_files(&packages, rootfs, &options).unwrap();
+        assert_eq!(report.weak_digests.len(), 1);
+        assert_eq!(report.weak_digests[0].name, "old");
+        let options = VerifyOptions::new().digest_policy(DigestPolicy::Reject);
+        assert!(matches!(
+            verify_files(&packages, rootfs, &options),
+            Err(Error::WeakDigest(_))
+        ));
+    }
+}
@@ -0,0 +1,312 @@
+use anyhow::anyhow;
+use std::cmp::Ordering;
+
+use crate::*;
+
+/// An epoch, version and release, ordered as rpm orders them, e.g. to tell
+/// whether an installed package is older than a candidate update.
+///
+/// Equality follows the ordering, so e.g. `1.01` equals `1.1` and a missing
+/// epoch equals `0`.
+#[derive(Debug, Clone, Default)]
+pub struct Evr {
+    /// Epoch, if any.
+    pub epoch: Option<u32>,
+    /// Version.
+    pub version: String,
+    /// Release. Empty if unknown, in which case it sorts before any other.
+    pub release: String,
+}
+
+impl Evr {
+    /// Create an EVR from its parts.
+    pub fn new(epoch: Option<u32>, version: impl Into<String>, release: impl Into<String>) -> Self {
+        Self {
+            epoch,
+            version: version.into(),
+            release: release.into(),
+        }
+    }
+}
+
+impl Ord for Evr {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_evr(
+            (self.epoch, &self.version, &self.release),
+            (other.epoch, &other.version, &other.release),
+        )
+    }
+}
+
+impl PartialOrd for Evr {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Evr {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Evr {}
+
+impl std::fmt::Display for Evr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(epoch) = self.epoch {
+            write!(f, "{epoch}:")?;
+        }
+        f.write_str(&self.version)?;
+        if !self.release.is_empty() {
+            write!(f, "-{}", self.release)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Evr {
+    type Err = Error;
+
+    /// Parse an EVR in the usual `[epoch:]version[-release]` form.
+    fn from_str(s: &str) -> Result<Self> {
+        let (epoch, vr) = match s.split_once(':') {
+            Some((epoch, vr)) => {
+                let epoch =
+                    (epoch.parse()).map_err(|_| anyhow!("invalid epoch '{epoch}' in '{s}'"))?;
+                (Some(epoch), vr)
+            }
+            None => (None, s),
+        };
+        let (version, release) = vr.rsplit_once('-').unwrap_or((vr, ""));
+        if version.is_empty() || vr.ends_with('-') {
+            return Err(anyhow!("invalid EVR '{s}'").into());
+        }
+        Ok(Self::new(epoch, version, release))
+    }
+}
+
+/// A package's name, EVR and architecture, ordered by name, then EVR as rpm
+/// orders them, then architecture.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Nevra {
+    /// Package name.
+    pub name: String,
+    /// Epoch, version and release.
+    pub evr: Evr,
+    /// Architecture.
+    pub arch: String,
+}
+
+impl From<&Package> for Nevra {
+    fn from(pkg: &Package) -> Self {
+        Self {
+            name: pkg.name.clone(),
+            evr: pkg.evr(),
+            arch: pkg.arch.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for Nevra {
+    /// Format as `name-[epoch:]version-release.arch`, like
+    /// [`Package::nevra`].
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}.{}", self.name, self.evr, self.arch)
+    }
+}
+
+impl std::str::FromStr for Nevra {
+    type Err = Error;
+
+    /// Parse a NEVRA in the usual `name-[epoch:]version-release.arch` form.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::from(anyhow!("invalid NEVRA '{s}'"));
+        let (nevr, arch) = s.rsplit_once('.').ok_or_else(invalid)?;
+        let (nev, release) = nevr.rsplit_once('-').ok_or_else(invalid)?;
+        let (name, ev) = nev.rsplit_once('-').ok_or_else(invalid)?;
+        if name.is_empty() || release.is_empty() || arch.is_empty() {
+            return Err(invalid());
+        }
+        let evr: Evr = format!("{ev}-{release}").parse()?;
+        Ok(Self {
+            name: name.to_string(),
+            evr,
+            arch: arch.to_string(),
+        })
+    }
+}
+
+/// Compare epochs, versions and releases as rpm does. A missing epoch is the
+/// same as 0.
+pub(crate) fn cmp_evr(a: (Option<u32>, &str, &str), b: (Option<u32>, &str, &str)) -> Ordering {
+    (a.0.unwrap_or(0).cmp(&b.0.unwrap_or(0)))
+        .then_with(|| rpmvercmp(a.1, b.1))
+        .then_with(|| rpmvercmp(a.2, b.2))
+}
+
+/// Compare two version (or release) strings with rpm's algorithm: digit and
+/// letter segments are compared in turn, numerically for digits, with `~`
+/// sorting before anything and `^` after the end of the string.
+pub fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    let is_sep = |c: &u8| !c.is_ascii_alphanumeric() && *c != b'~' && *c != b'^';
+    loop {
+        while a.first().is_some_and(is_sep) {
+            a = &a[1..];
+        }
+        while b.first().is_some_and(is_sep) {
+            b = &b[1..];
+        }
+        // Tilde sorts before anything, even the end of the string.
+        match (a.first(), b.first()) {
+            (Some(b'~'), Some(b'~')) => {
+                a = &a[1..];
+                b = &b[1..];
+                continue;
+            }
+            (Some(b'~'), _) => return Ordering::Less,
+            (_, Some(b'~')) => return Ordering::Greater,
+            _ => {}
+        }
+        // Caret sorts after the end of the string, but before anything else.
+        match (a.first(), b.first()) {
+            (Some(b'^'), Some(b'^')) => {
+                a = &a[1..];
+                b = &b[1..];
+                continue;
+            }
+            (None, Some(b'^')) => return Ordering::Less,
+            (Some(b'^'), None) => return Ordering::Greater,
+            (Some(b'^'), _) => return Ordering::Less,
+            (_, Some(b'^')) => return Ordering::Greater,
+            _ => {}
+        }
+        let (Some(first), Some(_)) = (a.first(), b.first()) else {
+            break;
+        };
+        let numeric = first.is_ascii_digit();
+        let in_segment = |c: &u8| {
+            if numeric {
+                c.is_ascii_digit()
+            } else {
+                c.is_ascii_alphabetic()
+            }
+        };
+        let a_len = a.iter().take_while(|c| in_segment(c)).count();
+        let b_len = b.iter().take_while(|c| in_segment(c)).count();
+        let (mut a_seg, mut b_seg) = (&a[..a_len], &b[..b_len]);
+        (a, b) = (&a[a_len..], &b[b_len..]);
+        if b_seg.is_empty() {
+            // Segments of different types; numeric ones are newer.
+            return if numeric {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+        }
+        let ordering = if numeric {
+            while a_seg.first() == Some(&b'0') {
+                a_seg = &a_seg[1..];
+            }
+            while b_seg.first() == Some(&b'0') {
+                b_seg = &b_seg[1..];
+            }
+            a_seg.len().cmp(&b_seg.len()).then(a_seg.cmp(b_seg))
+        } else {
+            a_seg.cmp(b_seg)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    // Whichever has segments left is newer.
+    match (a.is_empty(), b.is_empty()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Less,
+        _ => Ordering::Greater,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::PackageBuilder;
+
+    #[test]
+    fn test_rpmvercmp() {
+        use Ordering::*;
+        for (a, b, expected) in [
+            ("1.0", "1.0", Equal),
+            ("1.0", "2.0", Less),
+            ("2.0.1", "2.0", Greater),
+            ("1.010", "1.9", Greater),
+            ("1.001", "1.1", Equal),
+            ("5.5p1", "5.5p10", Less),
+            ("10xyz", "10.1xyz", Less),
+            ("xyz10", "xyz10.1", Less),
+            ("1.0a", "1.0", Greater),
+            ("a", "1", Less),
+            ("1_0", "1.0", Equal),
+            ("1.0~rc1", "1.0", Less),
+            ("1.0~rc1", "1.0~rc2", Less),
+            ("1.0~rc1~git1", "1.0~rc1", Less),
+            ("1.0^", "1.0", Greater),
+            ("1.0^git1", "1.0", Greater),
+            ("1.0^git1", "1.01", Less),
+            ("1.0^git1", "1.0^git2", Less),
+            ("1.0~rc1^git1", "1.0~rc1", Greater),
+            ("1.0^git1~pre", "1.0^git1", Less),
+        ] {
+            assert_eq!(rpmvercmp(a, b), expected, "{a} vs {b}");
+            assert_eq!(rpmvercmp(b, a), expected.reverse(), "{b} vs {a}");
+        }
+    }
+
+    #[test]
+    fn test_evr() {
+        let evr = |s: &str| s.parse::<Evr>().unwrap();
+        assert_eq!(evr("1:2.0-3"), Evr::new(Some(1), "2.0", "3"));
+        assert_eq!(evr("2.0"), Evr::new(None, "2.0", ""));
+        assert_eq!(evr("0:1.01-1"), evr("1.1-1"));
+        assert!(evr("1:1.0-1") > evr("2.0-1"));
+        assert!(evr("1.0~rc1-1") < evr("1.0-1"));
+        assert!(evr("1.0-1") < evr("1.0-1.fc43"));
+        assert!(evr("1.0") < evr("1.0-1"));
+        assert_eq!(evr("1:2.0-3").to_string(), "1:2.0-3");
+        assert_eq!(evr("2.0").to_string(), "2.0");
+        for bad in ["", "x:1.0", "-1", "1.0-"] {
+            assert!(bad.parse::<Evr>().is_err(), "{bad}");
+        }
+
+        let nevra: Nevra = "glibc-2:2.42-4.fc43.x86_64".parse().unwrap();
+        assert_eq!(nevra.name, "glibc");
+        assert_eq!(nevra.evr, Evr::new(Some(2), "2.42", "4.fc43"));
+        assert_eq!(nevra.arch, "x86_64");
+        assert_eq!(nevra.to_string(), "glibc-2:2.42-4.fc43.x86_64");
+        let mut nevras: Vec<Nevra> = ["b-1.0-1.noarch", "a-1.10-1.x86_64", "a-1.9-1.x86_64"]
+            .into_iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        nevras.sort();
+        let sorted: Vec<String> = nevras.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            sorted,
+            ["a-1.9-1.x86_64", "a-1.10-1.x86_64", "b-1.0-1.noarch"]
+        );
+        for bad in ["glibc", "glibc-2.42.x86_64", "-1.0-1.x86_64"] {
+            assert!(bad.parse::<Nevra>().is_err(), "{bad}");
+        }
+
+        let pkg = PackageBuilder::new("shadow-utils")
+            .epoch(2)
+            .version("4.17.4")
+            .release("1.fc43")
+            .build();
+        assert_eq!(Nevra::from(&pkg).to_string(), pkg.nevra());
+        assert_eq!(pkg.evr(), Evr::new(Some(2), "4.17.4", "1.fc43"));
+    }
+}
@@ -0,0 +1,145 @@
+#!/usr/bin/env python3
+"""Assemble foo-1.0-1.fc43.x86_64.rpm, a small binary package, without rpmbuild.
+
+The package is laid out as rpmbuild would write it: a lead, a signature header
+with the SIZE and SHA256 digest of the main header, then the main header, both
+with their immutable regions, and a gzipped cpio payload. Everything is
+deterministic, so rerunning this reproduces the fixture byte for byte.
+
+Usage: ./make_rpm.py [OUTPUT]
+"""
+
+import gzip
+import hashlib
+import struct
+import sys
+
+NULL, CHAR, INT8, INT16, INT32, INT64, STRING, BIN, STRING_ARRAY, I18NSTRING = range(10)
+ALIGN = {INT16: 2, INT32: 4, INT64: 8}
+
+MTIME = 1700000000
+BUILDTIME = 1700000100
+
+# (path, mode, contents or symlink target, flags)
+FILES = [
+    ("/usr/bin/foo", 0o100755, b"#!/bin/sh\necho foo\n", 0),
+    ("/usr/bin/foo-link", 0o120777, b"foo", 0),
+    ("/usr/share/doc/foo", 0o040755, b"", 0),
+    ("/usr/share/doc/foo/README", 0o100644, b"foo does foo.\n", 2),  # %doc
+]
+
+
+def header(entries, region_tag):
+    """Serialize a header from (tag, type, values) entries, including the
+    immutable region covering all of them."""
+    entries = sorted(entries)
+    index = []
+    data = b""
+    for tag, kind, values in entries:
+        data += b"\0" * ((-len(data)) % ALIGN.get(kind, 1))
+        if kind in (STRING, I18NSTRING, STRING_ARRAY):
+            if kind == STRING:
+                values = [values]
+            blob = b"".join(v.encode() + b"\0" for v in values)
+        elif kind == BIN:
+            blob = values
+        else:
+            fmt = {INT16: "H", INT32: "I", INT64: "Q"}[kind]
+            blob = struct.pack(f">{len(values)}{fmt}", *values)
+        count = len(values) if kind not in (STRING, BIN) else (1 if kind == STRING else len(blob))
+        index.append(struct.pack(">IIiI", tag, kind, len(data), count))
+        data += blob
+    il = len(index) + 1
+    trailer = struct.pack(">IIiI", region_tag, BIN, -16 * il, 16)
+    region = struct.pack(">IIiI", region_tag, BIN, len(data), 16)
+    data += trailer
+    intro = bytes([0x8E, 0xAD, 0xE8, 0x01, 0, 0, 0, 0]) + struct.pack(">II", il, len(data))
+    return intro + region + b"".join(index) + data
+
+
+def cpio(files):
+    """Build a cpio archive in the newc format, as rpm payloads are."""
+    out = b""
+    for ino, (path, mode, contents, _) in enumerate(files, 1):
+        name = b"." + path.encode() + b"\0"
+        body = b"" if mode & 0o170000 == 0o040000 else contents
+        fields = [ino, mode, 0, 0, 1, MTIME, len(body), 0, 0, 0, 0, len(name), 0]
+        out += b"070701" + b"".join(b"%08X" % f for f in fields) + name
+        out += b"\0" * ((-len(out)) % 4) + body + b"\0" * ((-len(body)) % 4)
+    name = b"TRAILER!!!\0"
+    out += b"070701" + b"%08X" % 0 * 11 + b"%08X" % len(name) + b"%08X" % 0 + name
+    return out + b"\0" * ((-len(out)) % 4)
+
+
+def main():
+    output = sys.argv[1] if len(sys.argv) > 1 else "foo-1.0-1.fc43.x86_64.rpm"
+    dirs = sorted({p.rsplit("/", 1)[0] + "/" for p, *_ in FILES})
+    regular = [(p, m, c) for p, m, c, _ in FILES if m & 0o170000 == 0o100000]
+    size = sum(len(c) for _, _, c in regular)
+    main_header = header(
+        [
+            (100, STRING_ARRAY, ["C"]),  # HEADERI18NTABLE
+            (1000, STRING, "foo"),  # NAME
+            (1001, STRING, "1.0"),  # VERSION
+            (1002, STRING, "1.fc43"),  # RELEASE
+            (1003, INT32, [2]),  # EPOCH
+            (1004, I18NSTRING, ["Does foo"]),  # SUMMARY
+            (1005, I18NSTRING, ["Foo does foo."]),  # DESCRIPTION
+            (1006, INT32, [BUILDTIME]),  # BUILDTIME
+            (1007, STRING, "localhost"),  # BUILDHOST
+            (1009, INT32, [size]),  # SIZE
+            (1014, STRING, "MIT"),  # LICENSE
+            (1021, STRING, "linux"),  # OS
+            (1022, STRING, "x86_64"),  # ARCH
+            (1028, INT32, [len(c) if m & 0o170000 != 0o040000 else 4096 for _, m, c, _ in FILES]),
+            (1030, INT16, [m for _, m, _, _ in FILES]),  # FILEMODES
+            (1033, INT16, [0] * len(FILES)),  # FILERDEVS
+            (1034, INT32, [MTIME] * len(FILES)),  # FILEMTIMES
+            (
+                1035,  # FILEDIGESTS
+                STRING_ARRAY,
+                [hashlib.sha256(c).hexdigest() if m & 0o170000 == 0o100000 else "" for _, m, c, _ in FILES],
+            ),
+            (1036, STRING_ARRAY, [c.decode() if m & 0o170000 == 0o120000 else "" for _, m, c, _ in FILES]),
+            (1037, INT32, [f for *_, f in FILES]),  # FILEFLAGS
+            (1039, STRING_ARRAY, ["root"] * len(FILES)),  # FILEUSERNAME
+            (1040, STRING_ARRAY, ["root"] * len(FILES)),  # FILEGROUPNAME
+            (1044, STRING, "foo-1.0-1.fc43.src.rpm"),  # SOURCERPM
+            (1047, STRING_ARRAY, ["foo", "foo(x86-64)"]),  # PROVIDENAME
+            (1048, INT32, [0, 0x1000000 | 0x2 | 0x8]),  # REQUIREFLAGS
+            (1049, STRING_ARRAY, ["/bin/sh", "rpmlib(PayloadFilesHavePrefix)"]),
+            (1050, STRING_ARRAY, ["", "4.0-1"]),  # REQUIREVERSION
+            (1064, STRING, "4.20.1"),  # RPMVERSION
+            (1095, INT32, [1] * len(FILES)),  # FILEDEVICES
+            (1096, INT32, list(range(1, len(FILES) + 1))),  # FILEINODES
+            (1097, STRING_ARRAY, [""] * len(FILES)),  # FILELANGS
+            (1112, INT32, [8, 8]),  # PROVIDEFLAGS
+            (1113, STRING_ARRAY, ["2:1.0-1.fc43", "2:1.0-1.fc43"]),  # PROVIDEVERSION
+            (1116, INT32, [dirs.index(p.rsplit("/", 1)[0] + "/") for p, *_ in FILES]),
+            (1117, STRING_ARRAY, [p.rsplit("/", 1)[1] for p, *_ in FILES]),  # BASENAMES
+            (1118, STRING_ARRAY, dirs),  # DIRNAMES
+            (1124, STRING, "cpio"),  # PAYLOADFORMAT
+            (1125, STRING, "gzip"),  # PAYLOADCOMPRESSOR
+            (1126, STRING, "9"),  # PAYLOADFLAGS
+            (1140, INT32, [0] * len(FILES)),  # FILECOLORS
+            (5011, INT32, [8]),  # FILEDIGESTALGO (SHA-256)
+        ],
+        63,  # HEADERIMMUTABLE
+    )
+    payload = gzip.compress(cpio(FILES), compresslevel=9, mtime=0)
+    sig_header = header(
+        [
+            (273, STRING, hashlib.sha256(main_header).hexdigest()),  # SHA256
+            (1000, INT32, [len(main_header) + len(payload)]),  # SIZE
+            (1007, INT32, [len(cpio(FILES))]),  # PAYLOADSIZE
+        ],
+        62,  # HEADERSIGNATURES
+    )
+    lead = bytes([0xED, 0xAB, 0xEE, 0xDB, 3, 0]) + struct.pack(">HH", 0, 1)
+    lead += b"foo-1.0-1.fc43".ljust(66, b"\0") + struct.pack(">HH", 1, 5) + b"\0" * 16
+    with open(output, "wb") as f:
+        f.write(lead + sig_header + b"\0" * ((-len(sig_header)) % 8) + main_header + payload)
+
+
+if __name__ == "__main__":
+    main()